@@ -0,0 +1,596 @@
+//! Alerts posted as JSON to webhooks, on monitors that are unreachable,
+//! cross a threshold, are off during business hours or keep getting
+//! replies with a wrong checksum, which points to bad cabling.
+//!
+//! Every check polls the monitors once. An alert fires once its condition
+//! held for `polls` checks in a row, is sent again every `resend_minutes`
+//! while it keeps holding, and is resolved with a last notification once
+//! it no longer does. A notification is sent again on the next check to
+//! the webhooks that did not take it. A condition that cannot be told, a
+//! threshold on an unreachable monitor say, leaves the alert as it was.
+
+use std;
+use std::io;
+use std::fmt;
+use std::error;
+use std::convert::Infallible;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+use std::time;
+
+use chrono::{Datelike, Duration, NaiveDateTime, NaiveTime, Weekday};
+
+use config::Config;
+use group::Group;
+use http;
+use metrics::TransactionStats;
+use monitor;
+use monitor::{Monitor, Opener};
+use property;
+use property::{Kind, Property, Value};
+use schedule::Clock;
+
+/// Conditions an alert can fire on.
+pub const CONDITIONS : [&str; 4] = ["unreachable", "threshold", "power-off", "checksum-error"];
+
+#[derive(Debug)]
+pub enum Error {
+	UnknownTarget(monitor::Error),
+	UnknownProperty(String),
+	InvalidAlert(String, &'static str),
+	WebhookError(String, io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Error::UnknownTarget(ref monitor_error) =>
+				write!(f, "{}", monitor_error),
+			Error::UnknownProperty(ref name) =>
+				write!(f, "unknown property: {}", name),
+			Error::InvalidAlert(ref name, reason) =>
+				write!(f, "{}: {}", name, reason),
+			Error::WebhookError(ref url, ref io_error) =>
+				write!(f, "webhook error: {}: {}", url, io_error),
+		}
+	}
+}
+
+impl error::Error for Error {
+	fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+		match self {
+			Error::UnknownTarget(ref monitor_error) => Some(monitor_error),
+			Error::WebhookError(_, ref io_error) => Some(io_error),
+			_ => None,
+		}
+	}
+}
+
+fn default_polls() -> u32 { 1 }
+
+fn default_timeout_ms() -> u64 { 10000 }
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Alert {
+	pub name: String,
+	/// Monitor names, aliases or group names, every monitor when empty.
+	#[serde(default)]
+	pub targets: Vec<String>,
+	/// One of `CONDITIONS`.
+	pub condition: String,
+	/// Numeric property a threshold applies to, and its bounds.
+	#[serde(default)]
+	pub property: Option<String>,
+	#[serde(default)]
+	pub above: Option<u32>,
+	#[serde(default)]
+	pub below: Option<u32>,
+	/// Local times the condition is checked between, such as `08:00-18:00`,
+	/// always when left out.
+	#[serde(default)]
+	pub hours: Option<String>,
+	/// Days the condition is checked on, such as `mon`, every day when empty.
+	#[serde(default)]
+	pub days: Vec<String>,
+	/// Checks in a row the condition must hold for.
+	#[serde(default = "default_polls")]
+	pub polls: u32,
+	/// `http://` URLs the notifications are posted to.
+	pub webhooks: Vec<String>,
+	/// Minutes between two notifications of a firing alert, none when 0.
+	#[serde(default)]
+	pub resend_minutes: i64,
+	#[serde(default = "default_timeout_ms")]
+	pub timeout_ms: u64,
+}
+
+impl Alert {
+	fn check(&self, config: &Config) -> Result<()> {
+		let invalid = |reason| Err(Error::InvalidAlert(self.name.clone(), reason));
+
+		Group::resolve_all(config, &self.targets).map_err(Error::UnknownTarget)?;
+		match self.condition.as_str() {
+			"threshold" => {
+				let name = match self.property {
+					Some(ref name) => name,
+					None => return invalid("a threshold needs a property"),
+				};
+				property::find(name).filter(|x| x.is_readable() && x.kind() == Kind::Number)
+					.ok_or_else(|| Error::UnknownProperty(name.clone()))?;
+				if self.above.is_none() && self.below.is_none() {
+					return invalid("a threshold needs a bound above or below");
+				}
+			},
+			x if CONDITIONS.contains(&x) => (),
+			_ => return invalid("unknown condition, expected unreachable, threshold, power-off or checksum-error"),
+		}
+		if self.hours.is_some() && self.hours().is_none() {
+			return invalid("expected hours such as 08:00-18:00");
+		}
+		if self.days.iter().any(|x| x.parse::<Weekday>().is_err()) {
+			return invalid("expected days such as mon or tuesday");
+		}
+		if self.polls == 0 {
+			return invalid("polls must be positive");
+		}
+		if self.webhooks.is_empty() {
+			return invalid("no webhooks");
+		}
+		Ok(())
+	}
+
+	fn hours(&self) -> Option<(NaiveTime, NaiveTime)> {
+		let (start, end) = self.hours.as_ref()?.split_once('-')?;
+		let time = |x: &str| NaiveTime::parse_from_str(x.trim(), "%H:%M").ok();
+		Some((time(start)?, time(end)?))
+	}
+
+	/// Whether `t` is within the hours and days, hours may span midnight.
+	fn is_active(&self, t: NaiveDateTime) -> bool {
+		let day = self.days.is_empty() || self.days.iter().any(|x| x.parse() == Ok(t.weekday()));
+		let hours = match self.hours() {
+			Some((start, end)) if start <= end => start <= t.time() && t.time() < end,
+			Some((start, end)) => start <= t.time() || t.time() < end,
+			None => true,
+		};
+		day && hours
+	}
+
+	/// Whether the condition holds, and what was seen.
+	fn evaluate(&self, sample: &Sample, now: NaiveDateTime) -> Option<(bool, String)> {
+		if !self.is_active(now) {
+			return Some((false, String::from("outside the hours")));
+		}
+		match self.condition.as_str() {
+			"unreachable" => Some(match (sample.values.is_empty() && sample.checksum_errors == 0, &sample.error) {
+				(true, Some(error)) => (true, error.clone()),
+				_ => (false, String::from("reachable")),
+			}),
+			"checksum-error" => Some(match sample.checksum_errors {
+				0 => (false, String::from("no checksum error")),
+				n => (true, format!("checksum errors = {}", n)),
+			}),
+			"power-off" => sample.values.get("power").map(|x| (x.matches("off"), format!("power = {}", x))),
+			_ => {
+				let name = self.property.as_ref().unwrap();
+				match sample.values.get(name.as_str()) {
+					Some(&Value::Number(x)) => Some((self.above.is_some_and(|y| x > y) || self.below.is_some_and(|y| x < y),
+						format!("{} = {}", name, x))),
+					_ => None,
+				}
+			},
+		}
+	}
+}
+
+/// What a check read from a monitor.
+#[derive(Debug, Default)]
+struct Sample {
+	values: BTreeMap<&'static str, Value>,
+	/// The first failure of the check.
+	error: Option<String>,
+	/// Replies with a wrong checksum, those a retry made up for included.
+	checksum_errors: u64,
+}
+
+impl Sample {
+	fn read(m: &mut Monitor, properties: &[&'static Property], stats: &TransactionStats) -> Sample {
+		let mut sample = Sample::default();
+		let checksum_errors = stats.checksum_errors();
+		for property in properties {
+			match property.get(m) {
+				Ok(value) => { sample.values.insert(property.name(), value); },
+				Err(err) => { sample.error.get_or_insert(err.to_string()); },
+			}
+		}
+		sample.checksum_errors = stats.checksum_errors() - checksum_errors;
+		sample
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+	Firing,
+	Resolved,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Notification {
+	pub alert: String,
+	pub monitor: String,
+	pub status: Status,
+	pub condition: String,
+	/// Value or failure seen by the check.
+	pub detail: String,
+	/// First check the condition held on.
+	pub since: NaiveDateTime,
+	pub time: NaiveDateTime,
+	/// Sent again while firing.
+	pub resend: bool,
+}
+
+#[derive(Debug, Default)]
+struct State {
+	holding: u32,
+	since: Option<NaiveDateTime>,
+	/// Time of the last notification while firing.
+	sent: Option<NaiveDateTime>,
+	/// Webhooks that took the pending notification of this status while
+	/// others did not.
+	delivered_to: Option<(Status, BTreeSet<String>)>,
+}
+
+pub struct Alerter {
+	config: Config,
+	alerts: Vec<(Alert, Vec<String>)>,
+	properties: Vec<&'static Property>,
+	opener: Opener,
+	stats: Arc<BTreeMap<String, Arc<TransactionStats>>>,
+	states: BTreeMap<(String, String), State>,
+}
+
+impl Alerter {
+	pub fn new(config: Config, alerts: Vec<Alert>) -> Result<Alerter> {
+		Alerter::with_opener(config, alerts, Box::new(Monitor::open))
+	}
+
+	/// Checks the conditions, the targets, the hours and that every alert
+	/// has somewhere to go.
+	pub fn with_opener(config: Config, alerts: Vec<Alert>, opener: Opener) -> Result<Alerter> {
+		let mut properties = BTreeSet::from(["power"]);
+		let mut entries = Vec::new();
+		for alert in alerts {
+			alert.check(&config)?;
+			if let Some(ref name) = alert.property {
+				properties.insert(property::find(name).unwrap().name());
+			}
+			let members = match alert.targets.is_empty() {
+				true => config.monitors.keys().cloned().collect(),
+				false => Group::resolve_all(&config, &alert.targets).unwrap().names().into_iter().map(String::from).collect(),
+			};
+			entries.push((alert, members));
+		}
+		let properties = properties.into_iter().map(|x| property::find(x).unwrap()).collect();
+
+		// Counting every attempt, since retries would hide the checksum errors
+		let stats : Arc<BTreeMap<String, Arc<TransactionStats>>> = Arc::new(config.monitors.keys().map(|x| (x.clone(), Arc::default())).collect());
+		let opener : Opener = {
+			let stats = stats.clone();
+			Box::new(move |name, monitor, config| opener(name, monitor, config).map(|x| x.with_stats(stats[name].clone())))
+		};
+		Ok(Alerter{config, alerts: entries, properties, opener, stats, states: BTreeMap::new()})
+	}
+
+	pub fn alerts(&self) -> impl Iterator<Item = &Alert> {
+		self.alerts.iter().map(|(alert, _)| alert)
+	}
+
+	/// Polls every monitor an alert applies to, returning the notifications
+	/// to send. They are returned again by later checks until delivered.
+	pub fn check(&mut self, now: NaiveDateTime) -> Vec<Notification> {
+		let names : BTreeSet<_> = self.alerts.iter().flat_map(|(_, members)| members.iter().cloned()).collect();
+		let group = Group::resolve_all(&self.config, &names.into_iter().collect::<Vec<_>>()).unwrap();
+		let (properties, stats) = (&self.properties, &self.stats);
+		let report = group.run_with(&self.config, &self.opener, |m| Ok::<_, Infallible>(Sample::read(m, properties, &stats[m.name()])));
+		let samples : BTreeMap<_, _> = report.results.into_iter().map(|(name, result)| {
+			let sample = result.unwrap_or_else(|err| Sample{error: Some(err.to_string()), ..Sample::default()});
+			(name, sample)
+		}).collect();
+
+		let mut notifications = Vec::new();
+		for (alert, members) in &self.alerts {
+			for monitor in members {
+				let (holds, detail) = match alert.evaluate(&samples[monitor], now) {
+					Some(x) => x,
+					None => continue,
+				};
+				let state = self.states.entry((alert.name.clone(), monitor.clone())).or_default();
+				let notify = |status, since, resend| Notification{
+					alert: alert.name.clone(),
+					monitor: monitor.clone(),
+					status,
+					condition: alert.condition.clone(),
+					detail: detail.clone(),
+					since,
+					time: now,
+					resend,
+				};
+
+				if !holds {
+					// Kept until the resolution is delivered to every webhook
+					// that heard of the alert.
+					if state.sent.is_some() || state.delivered_to.is_some() {
+						notifications.push(notify(Status::Resolved, state.since.unwrap(), false));
+						state.holding = 0;
+					} else {
+						*state = State::default();
+					}
+					continue;
+				}
+
+				state.holding += 1;
+				let since = *state.since.get_or_insert(now);
+				let resend = match state.sent {
+					None => state.holding >= alert.polls,
+					Some(sent) => alert.resend_minutes > 0 && now - sent >= Duration::minutes(alert.resend_minutes),
+				};
+				if resend {
+					notifications.push(notify(Status::Firing, since, state.sent.is_some()));
+				}
+			}
+		}
+		notifications
+	}
+
+	/// Posts `notification` to every webhook of its alert that did not take
+	/// it yet, returning the first failure. It counts as sent only once
+	/// every webhook got it.
+	pub fn deliver(&mut self, notification: &Notification) -> Result<()> {
+		let key = (notification.alert.clone(), notification.monitor.clone());
+		let mut delivered_to = match self.states.get(&key).and_then(|x| x.delivered_to.clone()) {
+			Some((status, urls)) if status == notification.status => urls,
+			_ => BTreeSet::new(),
+		};
+
+		let alert = self.alerts().find(|x| x.name == notification.alert).unwrap();
+		let body = serde_json::to_value(notification).unwrap();
+		let timeout = time::Duration::from_millis(alert.timeout_ms);
+		let mut result = Ok(());
+		for url in &alert.webhooks {
+			if delivered_to.contains(url) {
+				continue;
+			}
+			match http::post_json(url, &body, timeout) {
+				Ok(()) => { delivered_to.insert(url.clone()); },
+				Err(err) => if result.is_ok() {
+					result = Err(Error::WebhookError(url.clone(), err));
+				},
+			}
+		}
+
+		match result {
+			Ok(()) => self.delivered(notification),
+			Err(_) => self.states.entry(key).or_default().delivered_to = Some((notification.status, delivered_to)),
+		}
+		result
+	}
+
+	/// Records that `notification` was sent, for notifications delivered
+	/// some other way than `deliver`.
+	pub fn delivered(&mut self, notification: &Notification) {
+		let key = (notification.alert.clone(), notification.monitor.clone());
+		match notification.status {
+			Status::Firing => {
+				let state = self.states.entry(key).or_default();
+				state.sent = Some(notification.time);
+				state.delivered_to = None;
+			},
+			Status::Resolved => { self.states.remove(&key); },
+		}
+	}
+
+	/// Checks every `interval` and delivers the notifications, passing each
+	/// of them to `f` with the outcome of its delivery.
+	pub fn run<C: Clock, F: FnMut(&Notification, Result<()>)>(&mut self, clock: &C, interval: time::Duration, mut f: F) -> ! {
+		loop {
+			for notification in self.check(clock.now()) {
+				let result = self.deliver(&notification);
+				f(&notification, result);
+			}
+			clock.sleep(interval);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io;
+	use std::io::{BufReader, Read, Write};
+	use std::net::TcpListener;
+	use std::thread;
+
+	use chrono::{Duration, NaiveDate, NaiveDateTime};
+
+	use alert::{Alert, Alerter, Notification, Status};
+	use config::RetryPolicy;
+	use http::{Request, Response};
+	use monitor::{Monitor, Opener};
+	use simulator;
+	use simulator::Simulator;
+
+	fn alert(name: &str, condition: &str, webhook: &str) -> Alert {
+		Alert{
+			name: String::from(name),
+			targets: Vec::new(),
+			condition: String::from(condition),
+			property: None,
+			above: None,
+			below: None,
+			hours: None,
+			days: Vec::new(),
+			polls: 1,
+			webhooks: vec![String::from(webhook)],
+			resend_minutes: 0,
+			timeout_ms: 1000,
+		}
+	}
+
+	fn summary(notifications: &[Notification]) -> Vec<String> {
+		notifications.iter().map(|x| format!("{} {:?}{}", x.alert, x.status, if x.resend { " again" } else { "" })).collect()
+	}
+
+	#[test]
+	fn alert_lifecycle() {
+		let simulator = Simulator::new();
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let url = format!("http://{}/alerts", listener.local_addr().unwrap());
+		let (config, opener) = simulator::fixture(&[("lobby", &simulator)]);
+		let alerts = vec![
+			Alert{polls: 2, resend_minutes: 10, ..alert("down", "unreachable", &url)},
+			Alert{property: Some(String::from("backlight-hours")), above: Some(1000), ..alert("worn", "threshold", &url)},
+			Alert{hours: Some(String::from("08:00-18:00")), days: vec![String::from("mon"), String::from("fri")],
+				..alert("off", "power-off", &url)},
+			Alert{polls: 2, ..alert("cable", "checksum-error", &url)},
+		];
+		let mut alerter = Alerter::with_opener(config, alerts, opener).unwrap();
+		// A Monday
+		let at = |minutes| NaiveDate::from_ymd_opt(2024, 1, 1).unwrap().and_hms_opt(9, 0, 0).unwrap() + Duration::minutes(minutes);
+		let mut check = |t: NaiveDateTime| {
+			let notifications = alerter.check(t);
+			notifications.iter().for_each(|x| alerter.delivered(x));
+			summary(&notifications)
+		};
+
+		assert_eq!(vec!["worn Firing"], check(at(0)));
+		assert!(check(at(1)).is_empty());
+		simulator.set_value(0x20, &[0]);
+		assert_eq!(vec!["off Firing"], check(at(2)));
+
+		simulator.set_responding(false);
+		assert!(check(at(3)).is_empty());
+		assert_eq!(vec!["down Firing"], check(at(4)));
+		assert!(check(at(5)).is_empty());
+		assert_eq!(vec!["down Firing again"], check(at(14)));
+		simulator.set_responding(true);
+		assert_eq!(vec!["down Resolved"], check(at(16)));
+		assert_eq!(vec!["off Resolved"], check(at(9 * 60 + 30)));
+
+		simulator.set_corrupting(true);
+		assert!(check(at(9 * 60 + 31)).is_empty());
+		let notifications = alerter.check(at(9 * 60 + 32));
+		assert_eq!(vec!["cable Firing"], summary(&notifications));
+
+		let server = thread::spawn(move || {
+			let (mut stream, _) = listener.accept().unwrap();
+			let request = Request::read_from(&mut BufReader::new(&stream)).unwrap();
+			Response::empty(204).write_to(&mut stream).unwrap();
+			request
+		});
+		alerter.deliver(&notifications[0]).unwrap();
+		let body : serde_json::Value = serde_json::from_slice(&server.join().unwrap().body).unwrap();
+		assert_eq!(("cable", "firing", "lobby"), (body["alert"].as_str().unwrap(), body["status"].as_str().unwrap(), body["monitor"].as_str().unwrap()));
+		assert_eq!(Status::Firing, notifications[0].status);
+	}
+
+	#[test]
+	fn alert_failed_delivery() {
+		let simulator = Simulator::new();
+		// Nothing listens on port 1, so the webhook refuses the connection.
+		let url = "http://127.0.0.1:1/alerts";
+		let (config, opener) = simulator::fixture(&[("lobby", &simulator)]);
+		let mut alerter = Alerter::with_opener(config, vec![alert("off", "power-off", url)], opener).unwrap();
+		let at = |minutes| NaiveDate::from_ymd_opt(2024, 1, 1).unwrap().and_hms_opt(9, 0, 0).unwrap() + Duration::minutes(minutes);
+
+		simulator.set_value(0x20, &[0]);
+		let notifications = alerter.check(at(0));
+		assert_eq!(vec!["off Firing"], summary(&notifications));
+		assert!(alerter.deliver(&notifications[0]).is_err());
+		let notifications = alerter.check(at(1));
+		assert_eq!(vec!["off Firing"], summary(&notifications));
+		alerter.delivered(&notifications[0]);
+		assert!(alerter.check(at(2)).is_empty());
+
+		simulator.set_value(0x20, &[1]);
+		let notifications = alerter.check(at(3));
+		assert_eq!(vec!["off Resolved"], summary(&notifications));
+		assert!(alerter.deliver(&notifications[0]).is_err());
+		let notifications = alerter.check(at(4));
+		assert_eq!(vec!["off Resolved"], summary(&notifications));
+		alerter.delivered(&notifications[0]);
+		assert!(alerter.check(at(5)).is_empty());
+	}
+
+	#[test]
+	fn alert_partial_delivery() {
+		let simulator = Simulator::new();
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let url = format!("http://{}/alerts", listener.local_addr().unwrap());
+		let (config, opener) = simulator::fixture(&[("lobby", &simulator)]);
+		let alerts = vec![Alert{webhooks: vec![url, String::from("http://127.0.0.1:1/alerts")], ..alert("off", "power-off", "")}];
+		let mut alerter = Alerter::with_opener(config, alerts, opener).unwrap();
+		let at = |minutes| NaiveDate::from_ymd_opt(2024, 1, 1).unwrap().and_hms_opt(9, 0, 0).unwrap() + Duration::minutes(minutes);
+
+		simulator.set_value(0x20, &[0]);
+		let notifications = alerter.check(at(0));
+		let server = thread::spawn(move || {
+			let (mut stream, _) = listener.accept().unwrap();
+			Request::read_from(&mut BufReader::new(&stream)).unwrap();
+			Response::empty(204).write_to(&mut stream).unwrap();
+			listener
+		});
+		assert!(alerter.deliver(&notifications[0]).is_err());
+		let listener = server.join().unwrap();
+
+		// Only the webhook that failed gets it again
+		let notifications = alerter.check(at(1));
+		assert_eq!(vec!["off Firing"], summary(&notifications));
+		assert!(alerter.deliver(&notifications[0]).is_err());
+		listener.set_nonblocking(true).unwrap();
+		assert!(listener.accept().is_err());
+
+		// The webhook that heard of it hears of the resolution too
+		simulator.set_value(0x20, &[1]);
+		assert_eq!(vec!["off Resolved"], summary(&alerter.check(at(2))));
+	}
+
+	/// Corrupts the reply to every other request.
+	struct Flaky {
+		simulator: Simulator,
+		requests: usize,
+	}
+
+	impl Read for Flaky {
+		fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+			self.simulator.read(buf)
+		}
+	}
+
+	impl Write for Flaky {
+		fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+			self.simulator.set_corrupting(self.requests % 2 == 0);
+			self.requests += 1;
+			self.simulator.write(buf)
+		}
+		fn flush(&mut self) -> io::Result<()> { Ok(()) }
+	}
+
+	#[test]
+	fn alert_retried_checksum_errors() {
+		let simulator = Simulator::new();
+		let (config, _) = simulator::fixture(&[("lobby", &simulator)]);
+		let opener : Opener = Box::new(move |name, _, _| {
+			let transport = Flaky{simulator: simulator.clone(), requests: 0};
+			Ok(Monitor::new(name, Box::new(transport)).with_retry(RetryPolicy{attempts: 2, delay_ms: 0}))
+		});
+		let mut alerter = Alerter::with_opener(config, vec![alert("cable", "checksum-error", "http://127.0.0.1:1/")], opener).unwrap();
+		let at = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap().and_hms_opt(9, 0, 0).unwrap();
+
+		// Every read succeeds on its retry
+		let notifications = alerter.check(at);
+		assert_eq!(vec!["cable Firing"], summary(&notifications));
+		assert_eq!("checksum errors = 1", notifications[0].detail);
+	}
+}
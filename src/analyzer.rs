@@ -0,0 +1,362 @@
+//! Analysis of RS232 traffic captured on the line, such as with a Y-cable.
+//!
+//! A capture is either raw bytes or a text dump of hexadecimal bytes, one
+//! chunk per line, optionally preceded by a timestamp: seconds with a
+//! fractional part, a time of day or a date and time. The `>` and `<` of
+//! `transport::capture` are skipped, frames tell their direction by their
+//! prefix. Captures are split
+//! into frames on their own, then merged by time, so that each direction
+//! can come from its own capture. Replies are paired with the oldest
+//! request of the same opcode still waiting for one.
+
+use std::fmt;
+use std::collections::VecDeque;
+
+use chrono::{NaiveDateTime, NaiveTime, Timelike};
+use serde::Serializer;
+
+use property;
+use protocol::checksum::{CheckSum, XORCheckSum};
+use protocol::frame::{frame_size, monitor_frame, HOST_PREFIX, MONITOR_PREFIX};
+pub use protocol::frame::hex;
+use protocol::reply::ResultCode;
+
+const READ : u8 = 0xEB;
+const WRITE : u8 = 0xEA;
+
+/// Commands that are not properties.
+const COMMANDS : [(u8, &str); 2] = [(0x2F, "reset-power"), (0xA2, "ddc-ci")];
+
+/// Bytes as they came, with the time they came at.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Chunk {
+	/// Seconds, since midnight or the epoch for times of day and dates.
+	pub time: Option<f64>,
+	pub bytes: Vec<u8>,
+}
+
+pub type Capture = Vec<Chunk>;
+
+fn parse_time(x: &str) -> Option<f64> {
+	if let Ok(t) = NaiveDateTime::parse_from_str(&x.replacen('T', " ", 1), "%Y-%m-%d %H:%M:%S%.f") {
+		return Some(t.and_utc().timestamp_micros() as f64 / 1e6);
+	}
+	if let Ok(t) = NaiveTime::parse_from_str(x, "%H:%M:%S%.f") {
+		return Some(f64::from(t.num_seconds_from_midnight()) + f64::from(t.nanosecond()) / 1e9);
+	}
+	x.parse().ok().filter(|_| x.contains('.'))
+}
+
+fn parse_hex(x: &str) -> Option<Vec<u8>> {
+	if !x.len().is_multiple_of(2) {
+		return None;
+	}
+	(0..x.len()).step_by(2).map(|i| u8::from_str_radix(x.get(i..i + 2)?, 16).ok()).collect()
+}
+
+fn parse_line(line: &str) -> Option<Chunk> {
+	let mut tokens = line.split_whitespace().peekable();
+	let time = match tokens.peek() {
+		Some(x) if parse_hex(x).is_none() => Some(parse_time(tokens.next()?)?),
+		_ => None,
+	};
+	if let Some(&">") | Some(&"<") = tokens.peek() {
+		tokens.next();
+	}
+	let mut bytes = Vec::new();
+	for token in tokens {
+		bytes.extend(parse_hex(token)?);
+	}
+	Some(Chunk{time, bytes})
+}
+
+/// Reads a capture, as a text dump if it is one and as raw bytes otherwise.
+pub fn parse_capture(content: &[u8]) -> Capture {
+	let text = std::str::from_utf8(content).ok().and_then(|text| {
+		text.lines()
+			.map(str::trim)
+			.filter(|x| !x.is_empty() && !x.starts_with('#'))
+			.map(parse_line)
+			.collect::<Option<Vec<_>>>()
+	});
+	match text {
+		Some(chunks) if !chunks.is_empty() => chunks,
+		_ => vec![Chunk{time: None, bytes: content.to_vec()}],
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+	Host,
+	Monitor,
+	/// Bytes outside any frame.
+	Unknown,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Issue {
+	Malformed(String),
+	ChecksumError,
+	UnknownOpcode(u8),
+	/// A request no reply answered.
+	Unanswered,
+	/// A reply to no request.
+	Unsolicited,
+}
+
+impl fmt::Display for Issue {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Issue::Malformed(ref reason) => write!(f, "malformed: {}", reason),
+			Issue::ChecksumError => write!(f, "checksum error"),
+			Issue::UnknownOpcode(opcode) => write!(f, "unknown opcode 0x{:02x}", opcode),
+			Issue::Unanswered => write!(f, "unanswered"),
+			Issue::Unsolicited => write!(f, "unsolicited"),
+		}
+	}
+}
+
+fn serialize_hex<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+	serializer.serialize_str(&hex(bytes))
+}
+
+
+/// A frame, or bytes that are none, as analysed.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Entry {
+	pub time: Option<f64>,
+	pub direction: Direction,
+	#[serde(serialize_with = "serialize_hex")]
+	pub bytes: Vec<u8>,
+	pub opcode: Option<u8>,
+	/// Name of the property or command.
+	pub name: Option<&'static str>,
+	pub summary: String,
+	pub issues: Vec<Issue>,
+	/// Index of the reply to a request, or of the request of a reply.
+	pub pair: Option<usize>,
+}
+
+impl Entry {
+	fn new(time: Option<f64>, direction: Direction, bytes: Vec<u8>) -> Entry {
+		Entry{time, direction, bytes, opcode: None, name: None, summary: String::new(), issues: Vec::new(), pair: None}
+	}
+
+	fn malformed(time: Option<f64>, bytes: Vec<u8>, reason: &str) -> Entry {
+		let direction = match bytes.get(..2) {
+			Some(x) if x == HOST_PREFIX => Direction::Host,
+			Some(x) if x == MONITOR_PREFIX => Direction::Monitor,
+			_ => Direction::Unknown,
+		};
+		let mut entry = Entry::new(time, direction, bytes);
+		entry.summary = String::from(reason);
+		entry.issues.push(Issue::Malformed(String::from(reason)));
+		entry
+	}
+
+	/// Whether the request is a `Set`, for a host frame.
+	fn is_write(&self) -> bool {
+		self.bytes.get(3) == Some(&WRITE)
+	}
+}
+
+fn name_of(opcode: u8) -> Option<&'static str> {
+	property::find_by_opcode(opcode).map(|x| x.name())
+		.or_else(|| COMMANDS.iter().find(|x| x.0 == opcode).map(|x| x.1))
+}
+
+fn prefix_at(bytes: &[u8], i: usize) -> bool {
+	bytes.get(i..i + 2).is_some_and(|x| x == HOST_PREFIX || x == MONITOR_PREFIX)
+}
+
+/// Splits a capture into frames and stray bytes. A frame that is too long
+/// or has a wrong checksum and holds another prefix is cut there, in case
+/// its length byte was the corrupted one.
+pub fn split(capture: &[Chunk]) -> Vec<Entry> {
+	let bytes : Vec<u8> = capture.iter().flat_map(|x| x.bytes.iter().cloned()).collect();
+	let times : Vec<Option<f64>> = capture.iter().flat_map(|x| x.bytes.iter().map(move |_| x.time)).collect();
+	let mut entries = Vec::new();
+	let mut stray = None;
+	let mut i = 0;
+
+	while i < bytes.len() {
+		if !prefix_at(&bytes, i) {
+			stray.get_or_insert(i);
+			i += 1;
+			continue;
+		}
+		if let Some(start) = stray.take() {
+			entries.push(Entry::malformed(times[start], bytes[start..i].to_vec(), "stray bytes"));
+		}
+		let size = bytes.get(i + 2).map(|&x| frame_size(x)).filter(|&x| i + x <= bytes.len());
+		let end = size.map_or(bytes.len(), |x| i + x);
+		let mut c = XORCheckSum::new();
+		c.consume(&bytes[i..end]);
+		if size.is_none() || c.value() != 0 {
+			if let Some(next) = (i + 2..end).find(|&x| prefix_at(&bytes, x)) {
+				entries.push(Entry::malformed(times[i], bytes[i..next].to_vec(), "cut short"));
+				i = next;
+				continue;
+			}
+		}
+		if size.is_none() {
+			entries.push(Entry::malformed(times[i], bytes[i..].to_vec(), "truncated frame"));
+			break;
+		}
+
+		let frame = &bytes[i..end];
+		let mut entry = Entry::new(times[i], Direction::Unknown, frame.to_vec());
+		if c.value() != 0 {
+			entry.issues.push(Issue::ChecksumError);
+		}
+		decode(&mut entry);
+		entries.push(entry);
+		i = end;
+	}
+	if let Some(start) = stray {
+		entries.push(Entry::malformed(times[start], bytes[start..].to_vec(), "stray bytes"));
+	}
+	entries
+}
+
+/// Fills in what a frame says, as far as it can be told without its pair.
+fn decode(entry: &mut Entry) {
+	let bytes = entry.bytes.clone();
+	let host = bytes[..2] == HOST_PREFIX;
+	entry.direction = if host { Direction::Host } else { Direction::Monitor };
+
+	let (opcode, payload) = match (host, bytes.len()) {
+		(true, 6..) => (bytes[4], &bytes[5..bytes.len() - 1]),
+		(false, 7..) => (bytes[5], &bytes[6..bytes.len() - 1]),
+		_ => {
+			entry.issues.push(Issue::Malformed(String::from("too short")));
+			entry.summary = String::from("too short");
+			return;
+		},
+	};
+	entry.opcode = Some(opcode);
+	entry.name = name_of(opcode);
+	let name = entry.name.map_or_else(|| format!("0x{:02x}", opcode), String::from);
+	if entry.name.is_none() {
+		entry.issues.push(Issue::UnknownOpcode(opcode));
+	}
+
+	entry.summary = match (host, bytes[3]) {
+		(true, READ) => format!("get {}", name),
+		(true, WRITE) if payload.is_empty() => name,
+		(true, WRITE) => {
+			let value = property::find_by_opcode(opcode)
+				.and_then(|x| x.decode(&monitor_frame(0, opcode, payload)).ok())
+				.map_or_else(|| hex(payload), |x| x.to_string());
+			format!("set {} = {}", name, value)
+		},
+		(true, x) => {
+			entry.issues.push(Issue::Malformed(format!("unknown direction 0x{:02x}", x)));
+			format!("{} 0x{:02x}", name, x)
+		},
+		(false, _) => match bytes[4] {
+			0 => format!("reply {}", name),
+			x => format!("reply {}: {}", name, num::FromPrimitive::from_u8(x)
+				.map_or_else(|| format!("result code 0x{:02x}", x), |x: ResultCode| x.to_string())),
+		},
+	};
+}
+
+/// Completes a reply to a `Get` with its value.
+fn decode_value(entry: &mut Entry) {
+	if entry.bytes[4] != 0 || !entry.issues.is_empty() {
+		return;
+	}
+	let value = entry.opcode.and_then(property::find_by_opcode).map(|x| x.decode(&entry.bytes));
+	match value {
+		Some(Ok(value)) => entry.summary += &format!(" = {}", value),
+		Some(Err(err)) => entry.issues.push(Issue::Malformed(err.to_string())),
+		None => entry.summary += &format!(" = {}", hex(&entry.bytes[6..entry.bytes.len() - 1])),
+	}
+}
+
+/// Pairs replies with requests, flagging those left without one.
+fn pair(entries: &mut [Entry]) {
+	let mut pending : VecDeque<usize> = VecDeque::new();
+
+	for i in 0..entries.len() {
+		let opcode = match entries[i].opcode {
+			Some(opcode) if !entries[i].issues.contains(&Issue::ChecksumError) => opcode,
+			_ => continue,
+		};
+		match entries[i].direction {
+			Direction::Host => pending.push_back(i),
+			Direction::Monitor => match pending.iter().position(|&x| entries[x].opcode == Some(opcode)) {
+				Some(position) => {
+					let request = pending.remove(position).unwrap();
+					entries[request].pair = Some(i);
+					entries[i].pair = Some(request);
+					if !entries[request].is_write() {
+						decode_value(&mut entries[i]);
+					}
+				},
+				None => {
+					decode_value(&mut entries[i]);
+					entries[i].issues.push(Issue::Unsolicited);
+				},
+			},
+			Direction::Unknown => (),
+		}
+	}
+	for i in pending {
+		entries[i].issues.push(Issue::Unanswered);
+	}
+}
+
+/// Analyses captures of one line, merging their frames by time. Frames
+/// without a time come first, in the order of their capture.
+pub fn analyze(captures: &[Capture]) -> Vec<Entry> {
+	let mut entries : Vec<Entry> = captures.iter().flat_map(|x| split(x)).collect();
+	entries.sort_by(|a, b| a.time.unwrap_or(f64::MIN).total_cmp(&b.time.unwrap_or(f64::MIN)));
+	pair(&mut entries);
+	entries
+}
+
+#[cfg(test)]
+mod tests {
+	use analyzer;
+	use analyzer::{Direction, Issue};
+
+	#[test]
+	fn analyze_split_captures() {
+		let host = analyzer::parse_capture(b"# host side
+12:00:00.000 37 51 02 eb 30 bf
+12:00:01.000 37 51 06 ea 62 02 00 00 00 ea
+12:00:02.000 37515602eb00
+12:00:03.000 37 51 02 eb 99 16
+12:00:04.000 37 51 02 eb 20 af
+");
+		let monitor = analyzer::parse_capture(b"12:00:00.100 6f 37 04 02 00 30 4b 25
+12:00:01.100 6f 37 03 02 00 62 3b
+12:00:04.100 6f 37 04 02 00 20 01 00
+12:00:05.000 6f 37 04 02 00 30 4b 25 6f
+");
+		let entries = analyzer::analyze(&[host, monitor]);
+		let summaries : Vec<_> = entries.iter().map(|x| (x.direction, x.summary.as_str(), x.issues.clone())).collect();
+
+		assert_eq!(vec![
+			(Direction::Host, "get brightness", vec![]),
+			(Direction::Monitor, "reply brightness = 75", vec![]),
+			(Direction::Host, "set input = hdmi2", vec![]),
+			(Direction::Monitor, "reply input", vec![]),
+			(Direction::Host, "cut short", vec![Issue::Malformed(String::from("cut short"))]),
+			(Direction::Host, "get 0x99", vec![Issue::UnknownOpcode(0x99), Issue::Unanswered]),
+			(Direction::Host, "get power", vec![Issue::Unanswered]),
+			(Direction::Monitor, "reply power", vec![Issue::ChecksumError]),
+			(Direction::Monitor, "reply brightness = 75", vec![Issue::Unsolicited]),
+			(Direction::Unknown, "stray bytes", vec![Issue::Malformed(String::from("stray bytes"))]),
+		], summaries);
+		assert_eq!((Some(1), Some(0)), (entries[0].pair, entries[1].pair));
+		assert_eq!(Some(43201.0), entries[2].time);
+
+		let raw = analyzer::parse_capture(&[0x37, 0x51, 0x02, 0xeb, 0x30]);
+		assert_eq!("truncated frame", analyzer::analyze(&[raw])[0].summary);
+	}
+}
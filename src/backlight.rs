@@ -0,0 +1,250 @@
+//! History of the backlight hours of every panel, kept by serial number in
+//! a CSV file, and forecasts of when the panels reach their rated life.
+//!
+//! The monitor counts hours in 16 bits. A reading below the previous one
+//! of the same panel is taken as a wrap past 65535 hours when the time in
+//! between allows for it, and as a reset of the counter otherwise; a panel
+//! that wrapped before its first reading is counted 65536 hours short.
+
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::collections::BTreeMap;
+
+use chrono::{Duration, NaiveDate, NaiveDateTime};
+
+use config::Config;
+use monitor::Monitor;
+use protocol::transaction;
+use protocol::types;
+
+const WRAP : u32 = 65536;
+const HEADER : &str = "time,serial,hours,monitor";
+const TIME_FORMAT : &str = "%Y-%m-%dT%H:%M:%S";
+
+fn default_rated_hours() -> u32 { 30000 }
+
+fn default_window_days() -> i64 { 30 }
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BacklightConfig {
+	/// Hours after which a backlight is due for replacement.
+	#[serde(default = "default_rated_hours")]
+	pub rated_hours: u32,
+	/// Days of history the daily usage is averaged over.
+	#[serde(default = "default_window_days")]
+	pub window_days: i64,
+	/// CSV file of the readings, `$XDG_STATE_HOME/c5517h/backlight.csv` by default.
+	#[serde(default)]
+	pub history: Option<PathBuf>,
+}
+
+impl Default for BacklightConfig {
+	fn default() -> Self {
+		BacklightConfig{rated_hours: default_rated_hours(), window_days: default_window_days(), history: None}
+	}
+}
+
+impl BacklightConfig {
+	pub fn history_path(&self) -> Option<PathBuf> {
+		self.history.clone().or_else(|| Config::state_dir().map(|x| x.join("backlight.csv")))
+	}
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Reading {
+	pub time: NaiveDateTime,
+	pub serial: String,
+	/// As counted by the monitor, wrapping at 65536.
+	pub hours: u16,
+	pub monitor: String,
+}
+
+impl Reading {
+	/// Reads the serial number and the backlight hours of `m`.
+	pub fn read(m: &mut Monitor, time: NaiveDateTime) -> transaction::Result<Reading> {
+		let serial : String = m.get::<types::SerialNumber>()?.into();
+		let hours = u16::from(m.get::<types::BacklightHours>()?);
+		Ok(Reading{time, serial: String::from(serial.trim()), hours, monitor: String::from(m.name())})
+	}
+
+	/// The monitor name comes last, it may contain commas.
+	fn to_line(&self) -> String {
+		format!("{},{},{},{}", self.time.format(TIME_FORMAT), self.serial, self.hours, self.monitor)
+	}
+
+	fn parse(line: &str) -> Option<Reading> {
+		let mut fields = line.splitn(4, ',');
+		Some(Reading{
+			time: NaiveDateTime::parse_from_str(fields.next()?, TIME_FORMAT).ok()?,
+			serial: String::from(fields.next()?),
+			hours: fields.next()?.parse().ok()?,
+			monitor: String::from(fields.next()?),
+		})
+	}
+}
+
+/// Readings kept in `path`, a missing file is an empty history.
+pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Vec<Reading>> {
+	let content = match fs::read_to_string(path) {
+		Ok(content) => content,
+		Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+		Err(err) => return Err(err),
+	};
+
+	content.lines().enumerate()
+		.filter(|&(_, line)| !line.is_empty() && line != HEADER)
+		.map(|(i, line)| Reading::parse(line)
+			.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("line {}: malformed reading", i + 1))))
+		.collect()
+}
+
+/// Appends `readings` to `path`, creating it with a header if needed.
+pub fn append<P: AsRef<Path>>(path: P, readings: &[Reading]) -> io::Result<()> {
+	let path = path.as_ref();
+	if let Some(dir) = path.parent().filter(|x| !x.as_os_str().is_empty()) {
+		fs::create_dir_all(dir)?;
+	}
+	let mut file = fs::OpenOptions::new().create(true).append(true).open(path)?;
+	if file.metadata()?.len() == 0 {
+		writeln!(file, "{}", HEADER)?;
+	}
+	for reading in readings {
+		writeln!(file, "{}", reading.to_line())?;
+	}
+	Ok(())
+}
+
+/// Hours since new of one panel, from its readings in time order. After
+/// a reset, the counter adds up from the total of the last reading.
+pub fn total_hours(readings: &[&Reading]) -> Vec<(NaiveDateTime, u32)> {
+	let mut base = 0;
+	let mut previous : Option<&Reading> = None;
+
+	readings.iter().map(|x| {
+		if let Some(previous) = previous.filter(|previous| x.hours < previous.hours) {
+			let elapsed = (x.time - previous.time).num_seconds().max(0) as u64;
+			match u64::from(previous.hours) + elapsed.div_ceil(3600) >= u64::from(WRAP) {
+				true => base += WRAP,
+				false => base += u32::from(previous.hours),
+			}
+		}
+		previous = Some(x);
+		(x.time, base + u32::from(x.hours))
+	}).collect()
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Forecast {
+	pub serial: String,
+	/// Name of the monitor at the last reading.
+	pub monitor: String,
+	pub last_reading: NaiveDateTime,
+	pub hours: u32,
+	/// `None` without two readings in the window.
+	pub hours_per_day: Option<f64>,
+	/// Negative once past the rated life.
+	pub remaining_hours: i64,
+	/// `None` for a panel not in use.
+	pub end_of_life: Option<NaiveDate>,
+}
+
+/// Forecast of every panel, soonest end of life first. The daily usage is
+/// averaged over the readings within `window` of the last one.
+pub fn forecast(readings: &[Reading], rated_hours: u32, window: Duration) -> Vec<Forecast> {
+	let mut panels : BTreeMap<&str, Vec<&Reading>> = BTreeMap::new();
+	for reading in readings {
+		panels.entry(&reading.serial).or_default().push(reading);
+	}
+
+	let mut forecasts : Vec<_> = panels.into_values().map(|mut readings| {
+		readings.sort_by_key(|x| x.time);
+		let totals = total_hours(&readings);
+		let (last_time, hours) = *totals.last().unwrap();
+		let (first_time, first_hours) = *totals.iter().find(|x| x.0 >= last_time - window).unwrap();
+
+		let days = (last_time - first_time).num_seconds() as f64 / 86400.0;
+		let hours_per_day = Some(f64::from(hours - first_hours) / days).filter(|_| days > 0.0);
+		let remaining_hours = i64::from(rated_hours) - i64::from(hours);
+		let end_of_life = match hours_per_day {
+			_ if remaining_hours <= 0 => Some(last_time.date()),
+			Some(x) if x > 0.0 => Some((last_time + Duration::seconds((remaining_hours as f64 / x * 86400.0) as i64)).date()),
+			_ => None,
+		};
+
+		Forecast{
+			serial: readings[0].serial.clone(),
+			monitor: readings.last().unwrap().monitor.clone(),
+			last_reading: last_time,
+			hours,
+			hours_per_day,
+			remaining_hours,
+			end_of_life,
+		}
+	}).collect();
+
+	forecasts.sort_by_key(|x| (x.end_of_life.is_none(), x.end_of_life));
+	forecasts
+}
+
+#[cfg(test)]
+mod tests {
+	use std::fs;
+	use std::env;
+	use std::process;
+
+	use chrono::{Duration, NaiveDate, NaiveDateTime};
+
+	use backlight;
+	use backlight::Reading;
+
+	fn at(day: u32) -> NaiveDateTime {
+		NaiveDate::from_ymd_opt(2024, 3, day).unwrap().and_hms_opt(12, 0, 0).unwrap()
+	}
+
+	fn reading(day: u32, serial: &str, hours: u16) -> Reading {
+		Reading{time: at(day), serial: String::from(serial), hours, monitor: String::from("lobby, left")}
+	}
+
+	#[test]
+	fn backlight_forecast() {
+		let path = env::temp_dir().join(format!("c5517h-backlight-{}.csv", process::id()));
+		let readings = vec![
+			reading(1, "CN0A", 65530),
+			reading(2, "CN0A", 18),
+			reading(3, "CN0A", 42),
+			reading(1, "CN0B", 100),
+			reading(3, "CN0B", 100),
+			reading(3, "CN0C", 29990),
+		];
+		backlight::append(&path, &readings[..3]).unwrap();
+		backlight::append(&path, &readings[3..]).unwrap();
+		let loaded = backlight::load(&path).unwrap();
+		fs::remove_file(&path).unwrap();
+		assert_eq!(readings, loaded);
+
+		let forecasts = backlight::forecast(&loaded, 70000, Duration::days(30));
+		assert_eq!(vec!["CN0A", "CN0B", "CN0C"], forecasts.iter().map(|x| x.serial.as_str()).collect::<Vec<_>>());
+		// 65578 hours after the wrap, 24 a day: 4422 hours to go
+		assert_eq!((65578, Some(24.0), 4422), (forecasts[0].hours, forecasts[0].hours_per_day, forecasts[0].remaining_hours));
+		assert_eq!(NaiveDate::from_ymd_opt(2024, 9, 3), forecasts[0].end_of_life);
+		assert_eq!((Some(0.0), None), (forecasts[1].hours_per_day, forecasts[1].end_of_life));
+		assert_eq!(None, forecasts[2].hours_per_day);
+		assert_eq!("lobby, left", forecasts[2].monitor);
+
+		let forecasts = backlight::forecast(&loaded, 30000, Duration::days(30));
+		assert_eq!(("CN0A", Some(at(3).date())), (forecasts[0].serial.as_str(), forecasts[0].end_of_life));
+	}
+
+	#[test]
+	fn backlight_reset() {
+		// A day cannot take 5000 hours past 65535, the counter was reset
+		let readings = vec![reading(1, "CN0D", 5000), reading(2, "CN0D", 6), reading(3, "CN0D", 30)];
+		let totals = backlight::total_hours(&readings.iter().collect::<Vec<_>>());
+		assert_eq!(vec![5000, 5006, 5030], totals.iter().map(|x| x.1).collect::<Vec<_>>());
+
+		let forecasts = backlight::forecast(&readings, 30000, Duration::days(30));
+		assert_eq!((5030, Some(15.0)), (forecasts[0].hours, forecasts[0].hours_per_day));
+	}
+}
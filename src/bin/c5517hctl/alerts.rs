@@ -0,0 +1,38 @@
+use std::time::Duration;
+
+use clap::ArgMatches;
+
+use c5517h::alert::{Alerter, Notification, Result};
+use c5517h::schedule::{Clock, LocalClock};
+
+use super::{exit_with, load_config};
+
+fn print(notification: &Notification, result: Result<()>) {
+	let line = serde_json::to_string(notification).unwrap();
+	match result {
+		Ok(()) => println!("{}", line),
+		Err(err) => println!("{} error: {}", line, err),
+	}
+}
+
+pub fn run(matches: &ArgMatches, m: &ArgMatches) {
+	let config = load_config(matches);
+	let alerts = config.alerts.clone();
+	if alerts.is_empty() {
+		exit_with("alerts", "no alerts in the configuration", 2);
+	}
+
+	let interval = m.value_of("interval").unwrap().parse().map(Duration::from_secs)
+		.unwrap_or_else(|err| exit_with("interval", err, 2));
+	let mut alerter = Alerter::new(config, alerts).unwrap_or_else(|err| exit_with("alerts", err, 2));
+
+	if m.is_present("once") {
+		for notification in alerter.check(LocalClock.now()) {
+			let result = alerter.deliver(&notification);
+			print(&notification, result);
+		}
+		return;
+	}
+
+	alerter.run(&LocalClock, interval, print);
+}
@@ -0,0 +1,51 @@
+use std::fs;
+
+use clap::ArgMatches;
+
+use c5517h::analyzer;
+use c5517h::analyzer::{Direction, Entry};
+
+use super::exit_with;
+
+fn print(index: usize, entry: &Entry, start: Option<f64>) {
+	let time = match (entry.time, start) {
+		(Some(time), Some(start)) => format!("{:+10.3}", time - start),
+		_ => format!("{:>10}", "-"),
+	};
+	let direction = match entry.direction {
+		Direction::Host => "host",
+		Direction::Monitor => "monitor",
+		Direction::Unknown => "?",
+	};
+	let mut line = format!("{:4} {} {:7} {}", index, time, direction, entry.summary);
+	if let Some(pair) = entry.pair {
+		line += &format!(" (#{})", pair);
+	}
+	for issue in &entry.issues {
+		line += &format!(" [{}]", issue);
+	}
+	println!("{}\n{:23}{}", line, "", analyzer::hex(&entry.bytes));
+}
+
+pub fn run(_matches: &ArgMatches, m: &ArgMatches) {
+	let captures : Vec<_> = m.values_of("capture").unwrap().map(|path| {
+		let content = fs::read(path).unwrap_or_else(|err| exit_with(path, err, 1));
+		analyzer::parse_capture(&content)
+	}).collect();
+	let entries = analyzer::analyze(&captures);
+
+	if m.is_present("json") {
+		for entry in &entries {
+			println!("{}", serde_json::to_string(entry).unwrap());
+		}
+		return;
+	}
+
+	let start = entries.iter().find_map(|x| x.time);
+	for (i, entry) in entries.iter().enumerate() {
+		print(i, entry, start);
+	}
+	let frames = entries.iter().filter(|x| x.direction != Direction::Unknown).count();
+	let issues : usize = entries.iter().map(|x| x.issues.len()).sum();
+	println!("{} frames, {} issues", frames, issues);
+}
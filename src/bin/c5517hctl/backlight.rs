@@ -0,0 +1,86 @@
+use std::thread;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use chrono::Local;
+use clap::ArgMatches;
+
+use c5517h::backlight;
+use c5517h::backlight::{BacklightConfig, Reading};
+use c5517h::config::Config;
+use c5517h::group::Group;
+
+use super::{exit_with, load_config, open_group};
+
+fn history_path(m: &ArgMatches, backlight: &BacklightConfig) -> PathBuf {
+	m.value_of("history").map(PathBuf::from)
+		.or_else(|| backlight.history_path())
+		.unwrap_or_else(|| exit_with("backlight", "no history file, set HOME or --history", 2))
+}
+
+/// Reads the monitors of `--group`, or all of them, and appends the readings.
+fn record(matches: &ArgMatches, m: &ArgMatches, config: &Config, path: &PathBuf) {
+	let group = open_group(matches, config).unwrap_or_else(|| {
+		let names : Vec<_> = config.monitors.keys().cloned().collect();
+		Group::resolve_all(config, &names).unwrap_or_else(|err| exit_with("backlight", err, 2))
+	});
+	let interval = m.value_of("interval").map(|x| x.parse().map(Duration::from_secs)
+		.unwrap_or_else(|err| exit_with("interval", err, 2)));
+
+	loop {
+		let time = Local::now().naive_local();
+		let report = group.run(config, |monitor| Reading::read(monitor, time));
+		let mut readings = Vec::new();
+		for (name, result) in report.results {
+			match result {
+				Ok(reading) => {
+					println!("{}: {} {} hours", name, reading.serial, reading.hours);
+					readings.push(reading);
+				},
+				Err(err) => eprintln!("{}: error: {}", name, err),
+			}
+		}
+		if let Err(err) = backlight::append(path, &readings) {
+			exit_with(&path.to_string_lossy(), err, 1);
+		}
+
+		match interval {
+			Some(interval) => thread::sleep(interval),
+			None => break,
+		}
+	}
+}
+
+fn report(m: &ArgMatches, backlight: &BacklightConfig, path: &PathBuf) {
+	let rated = m.value_of("rated").map_or(Ok(backlight.rated_hours), |x| x.parse())
+		.unwrap_or_else(|err| exit_with("rated", err, 2));
+	let days = m.value_of("days").map_or(Ok(backlight.window_days), |x| x.parse())
+		.unwrap_or_else(|err| exit_with("days", err, 2));
+	let readings = backlight::load(path).unwrap_or_else(|err| exit_with(&path.to_string_lossy(), err, 1));
+	let forecasts = backlight::forecast(&readings, rated, chrono::Duration::days(days));
+
+	if m.is_present("json") {
+		println!("{}", serde_json::to_string_pretty(&forecasts).unwrap());
+		return;
+	}
+
+	println!("{:<16} {:<16} {:>6} {:>6} {:>9}  end of life", "serial", "monitor", "hours", "h/day", "remaining");
+	for x in forecasts {
+		println!("{:<16} {:<16} {:>6} {:>6} {:>9}  {}", x.serial, x.monitor, x.hours,
+			x.hours_per_day.map_or(String::from("-"), |x| format!("{:.1}", x)),
+			x.remaining_hours,
+			x.end_of_life.map_or(String::from("-"), |x| x.to_string()));
+	}
+}
+
+pub fn run(matches: &ArgMatches, m: &ArgMatches) {
+	let config = load_config(matches);
+	let backlight = config.backlight.clone().unwrap_or_default();
+	let path = history_path(m, &backlight);
+
+	match m.subcommand() {
+		("record", Some(sub)) => record(matches, sub, &config, &path),
+		("report", Some(sub)) => report(sub, &backlight, &path),
+		_ => exit_with("backlight", "expected record or report", 2),
+	}
+}
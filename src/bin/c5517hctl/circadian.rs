@@ -0,0 +1,46 @@
+use chrono::{Duration, Local};
+use clap::ArgMatches;
+
+use c5517h::schedule::LocalClock;
+use c5517h::schedule::circadian::{Adjustment, Controller};
+
+use super::{exit_with, load_config};
+
+fn print_adjustment(adjustment: &Adjustment) {
+	for (name, result) in adjustment.report.results.iter() {
+		match result {
+			Ok(actions) => {
+				let actions : Vec<_> = actions.iter().map(|x| x.to_string()).collect();
+				println!("{} {} {}: {} (target {})", adjustment.time.format("%Y-%m-%d %H:%M:%S"), adjustment.policy, name, actions.join(" "), adjustment.target)
+			},
+			Err(err) => println!("{} {} {}: error: {}", adjustment.time.format("%Y-%m-%d %H:%M:%S"), adjustment.policy, name, err),
+		}
+	}
+}
+
+/// Prints the targets of every policy over today, every half an hour.
+fn preview(controller: &Controller) {
+	let midnight = Local::now().date_naive().and_hms_opt(0, 0, 0).unwrap();
+
+	for policy in controller.policies() {
+		for i in 0..48 {
+			let time = midnight + Duration::minutes(30 * i);
+			println!("{} {} {}", time.format("%H:%M"), policy.name, policy.target(time));
+		}
+	}
+}
+
+pub fn run(matches: &ArgMatches, m: &ArgMatches) {
+	let config = load_config(matches);
+	let policies = config.circadian.clone();
+	if policies.is_empty() {
+		exit_with("circadian", "no circadian policies in the configuration", 2);
+	}
+
+	let mut controller = Controller::new(config, policies).unwrap_or_else(|err| exit_with("circadian", err, 2));
+	if m.is_present("preview") {
+		return preview(&controller);
+	}
+
+	controller.run(&LocalClock, print_adjustment);
+}
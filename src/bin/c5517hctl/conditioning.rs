@@ -0,0 +1,58 @@
+use std::path::PathBuf;
+
+use clap::ArgMatches;
+
+use c5517h::conditioning;
+use c5517h::conditioning::{Conditioner, Record};
+use c5517h::schedule::LocalClock;
+
+use super::{exit_with, load_config};
+
+fn print(record: &Record, log: &Option<PathBuf>) {
+	match record.error {
+		Some(ref err) => println!("{} {}: {} error: {}", record.start.format("%Y-%m-%d %H:%M"), record.routine, record.monitor, err),
+		None => println!("{} {}: {} conditioned until {}", record.start.format("%Y-%m-%d %H:%M"), record.routine, record.monitor, record.end.format("%H:%M")),
+	}
+	if let Some(ref path) = log {
+		if let Err(err) = conditioning::append(path, std::slice::from_ref(record)) {
+			eprintln!("{}: {}", path.display(), err);
+		}
+	}
+}
+
+pub fn run(matches: &ArgMatches, m: &ArgMatches) {
+	let config = load_config(matches);
+	let log = m.value_of("log").map(PathBuf::from).or_else(conditioning::log_path);
+
+	if m.is_present("report") {
+		let path = log.unwrap_or_else(|| exit_with("conditioning", "no log file, set HOME or --log", 2));
+		let records = conditioning::load(&path).unwrap_or_else(|err| exit_with(&path.to_string_lossy(), err, 1));
+		for (monitor, (count, last)) in conditioning::counts(&records) {
+			println!("{}: {} runs, last {}", monitor, count, last.format("%Y-%m-%d %H:%M"));
+		}
+		return;
+	}
+
+	let routines = config.conditioning.clone();
+	if routines.is_empty() {
+		exit_with("conditioning", "no conditioning routines in the configuration", 2);
+	}
+	let calendars = config.calendars.clone();
+	let mut conditioner = Conditioner::new(config, routines)
+		.and_then(|x| x.with_calendars(calendars))
+		.unwrap_or_else(|err| exit_with("conditioning", err, 2));
+
+	if let Some(name) = m.value_of("now") {
+		let routine = conditioner.routines().iter().find(|x| x.name == name).cloned()
+			.unwrap_or_else(|| exit_with(name, "unknown routine", 2));
+		for record in conditioner.condition(&routine, &LocalClock) {
+			print(&record, &log);
+		}
+		return;
+	}
+
+	conditioner.run(&LocalClock, |result| match result {
+		Ok(record) => print(record, &log),
+		Err(err) => eprintln!("conditioning: {}", err),
+	});
+}
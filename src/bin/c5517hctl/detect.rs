@@ -0,0 +1,30 @@
+use std::time::Duration;
+
+use clap::ArgMatches;
+
+use c5517h::port;
+use c5517h::config::Config;
+use c5517h::lock::LockPolicy;
+
+use super::{exit_with, lock_policy};
+
+pub fn run(matches: &ArgMatches, m: &ArgMatches) {
+	let path = matches.value_of("port").unwrap();
+	let timeout = m.value_of("timeout").unwrap().parse()
+		.map(Duration::from_millis)
+		.unwrap_or_else(|err| exit_with("timeout", err, 2));
+
+	let settings = port::detect(path, timeout, &lock_policy(matches, LockPolicy::default())).unwrap_or_else(|err| exit_with(path, err, 1));
+
+	println!("{}: {}", path, settings);
+
+	if let Some(config_path) = Config::user_path() {
+		let result = Config::load_from(&config_path).and_then(|mut config| {
+			config.ports.insert(String::from(path), settings);
+			config.save_to(&config_path)
+		});
+		if let Err(err) = result {
+			exit_with(&config_path.display().to_string(), err, 1);
+		}
+	}
+}
@@ -0,0 +1,18 @@
+use std::time::Duration;
+
+use clap::ArgMatches;
+use serde_json;
+
+use c5517h::discovery;
+
+use super::exit_with;
+
+pub fn run(m: &ArgMatches) {
+	let timeout = m.value_of("timeout").unwrap().parse()
+		.map(Duration::from_millis)
+		.unwrap_or_else(|err| exit_with("timeout", err, 2));
+
+	let reports = discovery::discover(timeout);
+
+	println!("{}", serde_json::to_string_pretty(&reports).unwrap());
+}
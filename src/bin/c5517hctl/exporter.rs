@@ -0,0 +1,23 @@
+use std::net::TcpListener;
+
+use clap::ArgMatches;
+
+use c5517h::http;
+use c5517h::metrics::Exporter;
+
+use super::{exit_with, load_config};
+
+pub fn run(matches: &ArgMatches, m: &ArgMatches) {
+	let config = load_config(matches);
+	if config.monitors.is_empty() {
+		exit_with("exporter", "no monitors in the configuration", 2);
+	}
+
+	let address = m.value_of("listen").unwrap();
+	let listener = TcpListener::bind(address).unwrap_or_else(|err| exit_with(address, err, 1));
+	let exporter = Exporter::new(config);
+
+	if let Err(err) = http::serve(listener, move |request| exporter.handle(request)) {
+		exit_with(address, err, 1);
+	}
+}
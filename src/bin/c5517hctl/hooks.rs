@@ -0,0 +1,44 @@
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use clap::ArgMatches;
+
+use c5517h::hooks::{Dispatcher, Outcome};
+use c5517h::watch::{Interval, Watcher};
+
+use super::{exit_with, load_config};
+
+pub fn run(matches: &ArgMatches, m: &ArgMatches) {
+	let config = load_config(matches);
+	let hooks = config.hooks.clone();
+	if hooks.is_empty() {
+		exit_with("hooks", "no hooks in the configuration", 2);
+	}
+
+	let millis = |name| m.value_of(name).unwrap().parse().map(Duration::from_millis).unwrap_or_else(|err| exit_with(name, err, 2));
+	let interval = Interval{min: millis("min-interval"), max: millis("max-interval")};
+	let mut dispatcher = Dispatcher::new(config.clone(), hooks).unwrap_or_else(|err| exit_with("hooks", err, 2));
+
+	let properties = dispatcher.properties();
+	let (events, receiver) = mpsc::channel();
+	for name in dispatcher.monitors() {
+		let monitor = dispatcher.monitor(name).unwrap_or_else(|err| exit_with(name, err, 1));
+		Watcher::shared(monitor, &properties).with_interval(interval).spawn(events.clone());
+	}
+	drop(events);
+
+	let (outcomes, outcome_receiver) = mpsc::channel::<Outcome>();
+	thread::spawn(move || {
+		for outcome in outcome_receiver {
+			match outcome.result {
+				Ok(()) => println!("{} {}: ok", outcome.hook, outcome.monitor),
+				Err(err) => println!("{} {}: error: {}", outcome.hook, outcome.monitor, err),
+			}
+		}
+	});
+
+	for event in receiver {
+		dispatcher.dispatch(&event, &outcomes);
+	}
+}
@@ -0,0 +1,25 @@
+use std::fs;
+
+use clap::ArgMatches;
+
+use c5517h::group::Group;
+use c5517h::inventory::{Format, Inventory};
+
+use super::{exit_with, load_config, open_group};
+
+pub fn run(matches: &ArgMatches, m: &ArgMatches) {
+	let format : Format = m.value_of("format").unwrap().parse().unwrap_or_else(|err| exit_with("format", err, 2));
+	let config = load_config(matches);
+	let group = open_group(matches, &config).unwrap_or_else(|| {
+		let names : Vec<_> = config.monitors.keys().cloned().collect();
+		Group::resolve_all(&config, &names).unwrap_or_else(|err| exit_with("inventory", err, 2))
+	});
+
+	let inventory = Inventory::collect(&config, &group);
+	let output = inventory.format(format);
+
+	match m.value_of("output") {
+		Some(path) => fs::write(path, output).unwrap_or_else(|err| exit_with(path, err, 1)),
+		None => print!("{}", output),
+	}
+}
@@ -1,28 +1,390 @@
 extern crate c5517h;
-extern crate serialport;
+extern crate clap;
+extern crate chrono;
+extern crate serde_json;
 
-use serialport::prelude::*;
-use std::time::Duration;
-use std::io;
-use std::io::Write;
+mod discover;
+mod detect;
+mod property;
+mod serve;
+mod mqtt;
+mod exporter;
+mod pjlink;
+mod schedule;
+mod circadian;
+mod ramp;
+mod reconcile;
+mod watch;
+mod hooks;
+mod backlight;
+mod inventory;
+mod alerts;
+mod conditioning;
+mod analyze;
 
+use std::fmt;
+use std::process;
+use std::path::PathBuf;
+
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+
+use c5517h::config::{Config, MonitorConfig};
+use c5517h::group::{Group, Report};
+use c5517h::lock::LockPolicy;
+use c5517h::monitor::Monitor;
 use c5517h::protocol::types;
-use c5517h::protocol::command;
-use c5517h::protocol::transaction::transaction;
+use c5517h::transport::Address;
 
-fn main() {
-	let s = SerialPortSettings {
-		baud_rate: 9600,
-		data_bits: DataBits::Eight,
-		flow_control: FlowControl::None,
-		parity: Parity::None,
-		stop_bits: StopBits::One,
-		timeout: Duration::from_secs(1),
+pub fn exit_with<E: fmt::Display>(context: &str, err: E, code: i32) -> ! {
+	eprintln!("{}: {}", context, err);
+	process::exit(code);
+}
+
+pub fn load_config(matches: &ArgMatches) -> Config {
+	let result = match matches.value_of("config") {
+		Some(path) => Config::load_from(path),
+		None => Config::load(),
 	};
-	let mut reader = serialport::open_with_settings("/dev/ttyS1", &s).unwrap();
-	let mut writter = reader.try_clone().unwrap();
+	let mut config = result.unwrap_or_else(|err| exit_with("config", err, 2));
+	if let Some(path) = matches.value_of("capture") {
+		config.set_capture(path);
+	}
+	config
+}
+
+pub fn lock_policy(matches: &ArgMatches, policy: LockPolicy) -> LockPolicy {
+	match matches.value_of("lock-wait") {
+		Some(x) => LockPolicy{wait_ms: x.parse().unwrap_or_else(|err| exit_with("lock-wait", err, 2)), ..policy},
+		None => policy,
+	}
+}
+
+/// Opens the monitor chosen with `--monitor`, or the one attached to `--port`.
+pub fn open_monitor(matches: &ArgMatches, config: &Config) -> Monitor {
+	let (name, mut monitor) = match matches.value_of("monitor") {
+		Some(name) => config.monitor(name)
+			.map(|(name, x)| (name, x.clone()))
+			.unwrap_or_else(|| exit_with(name, "unknown monitor", 2)),
+		None => {
+			let port = matches.value_of("port").unwrap();
+			let address : Address = port.parse().unwrap_or_else(|err| exit_with(port, err, 2));
+			let mut monitor = MonitorConfig::new(address);
+			monitor.capture = matches.value_of("capture").map(PathBuf::from);
+			(port, monitor)
+		},
+	};
+
+	monitor.lock = lock_policy(matches, monitor.lock);
+	Monitor::open(name, &monitor, config).unwrap_or_else(|err| exit_with(name, err, 1))
+}
+
+/// Resolves `--group`, if given.
+pub fn open_group(matches: &ArgMatches, config: &Config) -> Option<Group> {
+	matches.value_of("group").map(|name| {
+		Group::from_config(config, name).unwrap_or_else(|err| exit_with(name, err, 2))
+	})
+}
+
+/// Prints one line per group member and exits with 0 when all of them
+/// succeeded, 3 when some of them did and 1 when none did.
+pub fn exit_with_report<T: fmt::Display, E: fmt::Display>(report: &Report<T, E>) -> ! {
+	for (name, result) in report.results.iter() {
+		match result {
+			Ok(value) => println!("{}: {}", name, value),
+			Err(err) => println!("{}: error: {}", name, err),
+		}
+	}
+
+	process::exit(match (report.is_success(), report.is_partial_failure()) {
+		(true, _) => 0,
+		(false, true) => 3,
+		(false, false) => 1,
+	});
+}
 
-	let state : types::PowerState = transaction(&command::Get::<types::PowerState>::new(), &mut writter, &mut reader).unwrap();
+fn power_state(matches: &ArgMatches) {
+	let config = load_config(matches);
+	let mut monitor = open_monitor(matches, &config);
+
+	let state : types::PowerState = monitor.get().unwrap();
 
 	println!("state = {:?}", state);
 }
+
+fn main() {
+	let matches = App::new("c5517hctl")
+		.about("Controls Dell C5517H monitors over RS232")
+		.setting(AppSettings::VersionlessSubcommands)
+		.arg(Arg::with_name("port")
+			.short("p")
+			.long("port")
+			.takes_value(true)
+			.default_value("/dev/ttyS1")
+			.help("Serial port or address (tcp:host:port, rfc2217:host:port, replay:capture) of the monitor"))
+		.arg(Arg::with_name("monitor")
+			.short("m")
+			.long("monitor")
+			.takes_value(true)
+			.help("Monitor name or alias from the configuration"))
+		.arg(Arg::with_name("group")
+			.short("g")
+			.long("group")
+			.takes_value(true)
+			.conflicts_with("monitor")
+			.help("Group name from the configuration, its monitors are handled in parallel"))
+		.arg(Arg::with_name("lock-wait")
+			.long("lock-wait")
+			.takes_value(true)
+			.help("Milliseconds to wait for another process to release the port"))
+		.arg(Arg::with_name("config")
+			.short("c")
+			.long("config")
+			.takes_value(true)
+			.help("Configuration file to use instead of the default ones"))
+		.arg(Arg::with_name("capture")
+			.long("capture")
+			.takes_value(true)
+			.help("File every byte exchanged with the monitor is appended to, with .NAME appended for configured monitors"))
+		.subcommand(SubCommand::with_name("discover")
+			.about("Probes serial ports for attached monitors and prints a JSON report")
+			.arg(Arg::with_name("timeout")
+				.long("timeout")
+				.takes_value(true)
+				.default_value("300")
+				.help("Reply timeout per port in milliseconds")))
+		.subcommand(SubCommand::with_name("detect")
+			.about("Finds the line settings of the port and caches them in the configuration")
+			.arg(Arg::with_name("timeout")
+				.long("timeout")
+				.takes_value(true)
+				.default_value("300")
+				.help("Reply timeout per attempt in milliseconds")))
+		.subcommand(SubCommand::with_name("get")
+			.about("Reads a property")
+			.arg(Arg::with_name("property")
+				.required(true)
+				.help("Property name, see `properties`")))
+		.subcommand(SubCommand::with_name("set")
+			.about("Writes a property")
+			.arg(Arg::with_name("property")
+				.required(true)
+				.help("Property name, see `properties`"))
+			.arg(Arg::with_name("value")
+				.required(true)))
+		.subcommand(SubCommand::with_name("ramp")
+			.about("Moves brightness, contrast or sharpness to a value gradually")
+			.arg(Arg::with_name("property")
+				.required(true)
+				.possible_values(&["brightness", "contrast", "sharpness"]))
+			.arg(Arg::with_name("target")
+				.required(true))
+			.arg(Arg::with_name("duration")
+				.long("duration")
+				.takes_value(true)
+				.default_value("2000")
+				.help("Milliseconds the ramp takes"))
+			.arg(Arg::with_name("easing")
+				.long("easing")
+				.takes_value(true)
+				.default_value("linear")
+				.possible_values(&["linear", "ease-in", "ease-out", "ease-in-out"])))
+		.subcommand(SubCommand::with_name("watch")
+			.about("Prints changes of properties as JSON lines")
+			.arg(Arg::with_name("property")
+				.multiple(true)
+				.help("Properties to watch, power, input, brightness, contrast, preset and osd-button-lock by default"))
+			.arg(Arg::with_name("min-interval")
+				.long("min-interval")
+				.takes_value(true)
+				.default_value("500")
+				.help("Milliseconds between two polls after a change"))
+			.arg(Arg::with_name("max-interval")
+				.long("max-interval")
+				.takes_value(true)
+				.default_value("10000")
+				.help("Milliseconds between two polls when nothing changes")))
+		.subcommand(SubCommand::with_name("hooks")
+			.about("Watches the monitors and runs the hooks of the configuration")
+			.arg(Arg::with_name("min-interval")
+				.long("min-interval")
+				.takes_value(true)
+				.default_value("500")
+				.help("Milliseconds between two polls after a change"))
+			.arg(Arg::with_name("max-interval")
+				.long("max-interval")
+				.takes_value(true)
+				.default_value("10000")
+				.help("Milliseconds between two polls when nothing changes")))
+		.subcommand(SubCommand::with_name("properties")
+			.about("Lists known properties, whether they can be read and written, and their choices"))
+		.subcommand(SubCommand::with_name("serve")
+			.about("Serves the configured monitors over an HTTP/JSON API")
+			.arg(Arg::with_name("listen")
+				.long("listen")
+				.takes_value(true)
+				.default_value("127.0.0.1:8080")
+				.help("Address to listen on"))
+			.arg(Arg::with_name("openapi")
+				.long("openapi")
+				.help("Prints the OpenAPI document of the API and exits")))
+		.subcommand(SubCommand::with_name("mqtt")
+			.about("Bridges the configured monitors to Home Assistant over MQTT")
+			.arg(Arg::with_name("broker")
+				.long("broker")
+				.takes_value(true)
+				.help("Broker address (host:port), overrides the configuration"))
+			.arg(Arg::with_name("interval")
+				.long("interval")
+				.takes_value(true)
+				.help("Milliseconds between two polls, overrides the configuration")))
+		.subcommand(SubCommand::with_name("exporter")
+			.about("Exports the state of the configured monitors to Prometheus on /metrics")
+			.arg(Arg::with_name("listen")
+				.long("listen")
+				.takes_value(true)
+				.default_value("127.0.0.1:9517")
+				.help("Address to listen on")))
+		.subcommand(SubCommand::with_name("pjlink")
+			.about("Presents the configured monitors as PJLink Class 1 displays")
+			.arg(Arg::with_name("listen")
+				.long("listen")
+				.takes_value(true)
+				.help("Address to serve the monitor chosen with --monitor on, instead of the configured ones"))
+			.arg(Arg::with_name("password")
+				.long("password")
+				.takes_value(true)
+				.help("Password of the PJLink authentication, overrides the configuration")))
+		.subcommand(SubCommand::with_name("schedule")
+			.about("Runs the scheduled rules and calendars of the configuration")
+			.arg(Arg::with_name("state")
+				.long("state")
+				.takes_value(true)
+				.help("File keeping the last run times, for catching up after downtime"))
+			.arg(Arg::with_name("catch-up")
+				.long("catch-up")
+				.takes_value(true)
+				.default_value("24")
+				.help("Hours after which missed runs are no longer caught up"))
+			.arg(Arg::with_name("preview")
+				.long("preview")
+				.help("Lists the planned transitions instead of running them"))
+			.arg(Arg::with_name("days")
+				.long("days")
+				.takes_value(true)
+				.default_value("7")
+				.help("Days covered by the preview")))
+		.subcommand(SubCommand::with_name("circadian")
+			.about("Follows the brightness and colour temperature curves of the configuration")
+			.arg(Arg::with_name("preview")
+				.long("preview")
+				.help("Lists the targets over today instead of following them")))
+		.subcommand(SubCommand::with_name("reconcile")
+			.about("Puts back the values of the configured profiles that drifted, printing every drift as JSON")
+			.arg(Arg::with_name("interval")
+				.long("interval")
+				.takes_value(true)
+				.default_value("300")
+				.help("Seconds between two checks"))
+			.arg(Arg::with_name("once")
+				.long("once")
+				.help("Checks once and exits"))
+			.arg(Arg::with_name("log")
+				.long("log")
+				.takes_value(true)
+				.help("File the drifts are appended to")))
+		.subcommand(SubCommand::with_name("backlight")
+			.about("Records the backlight hours and forecasts the end of life of every panel")
+			.arg(Arg::with_name("history")
+				.long("history")
+				.takes_value(true)
+				.help("CSV file of the readings, overrides the configuration"))
+			.subcommand(SubCommand::with_name("record")
+				.about("Appends a reading of the monitors of --group, or of all of them")
+				.arg(Arg::with_name("interval")
+					.long("interval")
+					.takes_value(true)
+					.help("Seconds between two readings, reads once if not given")))
+			.subcommand(SubCommand::with_name("report")
+				.about("Prints the usage per day and the end of life forecast of every panel")
+				.arg(Arg::with_name("rated")
+					.long("rated")
+					.takes_value(true)
+					.help("Rated backlight life in hours, overrides the configuration"))
+				.arg(Arg::with_name("days")
+					.long("days")
+					.takes_value(true)
+					.help("Days of history the usage per day is averaged over"))
+				.arg(Arg::with_name("json")
+					.long("json")
+					.help("Prints the report as JSON"))))
+		.subcommand(SubCommand::with_name("inventory")
+			.about("Lists the serial number, model, firmware and state of the monitors of --group, or of all of them")
+			.arg(Arg::with_name("format")
+				.long("format")
+				.takes_value(true)
+				.possible_values(&["csv", "json", "markdown"])
+				.default_value("csv")
+				.help("Output format"))
+			.arg(Arg::with_name("output")
+				.short("o")
+				.long("output")
+				.takes_value(true)
+				.help("File to write instead of the standard output")))
+		.subcommand(SubCommand::with_name("alerts")
+			.about("Checks the alerts of the configuration and posts their notifications to webhooks, printing them as JSON")
+			.arg(Arg::with_name("interval")
+				.long("interval")
+				.takes_value(true)
+				.default_value("60")
+				.help("Seconds between two checks"))
+			.arg(Arg::with_name("once")
+				.long("once")
+				.help("Checks once and exits")))
+		.subcommand(SubCommand::with_name("conditioning")
+			.about("Runs the LCD conditioning windows of the configuration on panels the calendars leave free")
+			.arg(Arg::with_name("now")
+				.long("now")
+				.takes_value(true)
+				.help("Runs a window of the named routine right away and exits"))
+			.arg(Arg::with_name("report")
+				.long("report")
+				.help("Prints how often every panel was conditioned"))
+			.arg(Arg::with_name("log")
+				.long("log")
+				.takes_value(true)
+				.help("File the windows are recorded in, overrides the default one")))
+		.subcommand(SubCommand::with_name("analyze")
+			.about("Decodes captured RS232 traffic, pairing requests with replies and flagging bad frames")
+			.arg(Arg::with_name("capture")
+				.required(true)
+				.multiple(true)
+				.help("Captures as raw bytes or hexadecimal dumps, one per direction or both in one, lines may start with a timestamp"))
+			.arg(Arg::with_name("json")
+				.long("json")
+				.help("Prints the frames as JSON lines")))
+		.get_matches();
+
+	match matches.subcommand() {
+		("discover", Some(m)) => discover::run(m),
+		("detect", Some(m)) => detect::run(&matches, m),
+		("get", Some(m)) => property::get(&matches, m),
+		("set", Some(m)) => property::set(&matches, m),
+		("ramp", Some(m)) => ramp::run(&matches, m),
+		("watch", Some(m)) => watch::run(&matches, m),
+		("hooks", Some(m)) => hooks::run(&matches, m),
+		("properties", Some(_)) => property::list(),
+		("serve", Some(m)) => serve::run(&matches, m),
+		("mqtt", Some(m)) => mqtt::run(&matches, m),
+		("exporter", Some(m)) => exporter::run(&matches, m),
+		("pjlink", Some(m)) => pjlink::run(&matches, m),
+		("schedule", Some(m)) => schedule::run(&matches, m),
+		("circadian", Some(m)) => circadian::run(&matches, m),
+		("reconcile", Some(m)) => reconcile::run(&matches, m),
+		("backlight", Some(m)) => backlight::run(&matches, m),
+		("inventory", Some(m)) => inventory::run(&matches, m),
+		("alerts", Some(m)) => alerts::run(&matches, m),
+		("conditioning", Some(m)) => conditioning::run(&matches, m),
+		("analyze", Some(m)) => analyze::run(&matches, m),
+		_ => power_state(&matches),
+	}
+}
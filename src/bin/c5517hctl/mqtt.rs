@@ -0,0 +1,31 @@
+use std::thread;
+use std::time::Duration;
+
+use clap::ArgMatches;
+
+use c5517h::mqtt::bridge::Bridge;
+
+use super::{exit_with, load_config};
+
+pub fn run(matches: &ArgMatches, m: &ArgMatches) {
+	let config = load_config(matches);
+	if config.monitors.is_empty() {
+		exit_with("mqtt", "no monitors in the configuration", 2);
+	}
+
+	let mut mqtt = config.mqtt.clone().unwrap_or_default();
+	if let Some(broker) = m.value_of("broker") {
+		mqtt.broker = String::from(broker);
+	}
+	if let Some(interval) = m.value_of("interval") {
+		mqtt.interval_ms = interval.parse().unwrap_or_else(|err| exit_with("interval", err, 2));
+	}
+
+	let mut bridge = Bridge::new(config, mqtt.clone());
+	loop {
+		if let Err(err) = bridge.run() {
+			eprintln!("{}: {}, reconnecting", mqtt.broker, err);
+		}
+		thread::sleep(Duration::from_secs(5));
+	}
+}
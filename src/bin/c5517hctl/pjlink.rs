@@ -0,0 +1,50 @@
+use std::net::TcpListener;
+use std::sync::Arc;
+use std::thread;
+
+use clap::ArgMatches;
+
+use c5517h::monitor::{Monitor, Opener};
+use c5517h::pjlink;
+use c5517h::pjlink::Server;
+
+use super::{exit_with, load_config};
+
+pub fn run(matches: &ArgMatches, m: &ArgMatches) {
+	let config = load_config(matches);
+	let mut pjlink = config.pjlink.clone().unwrap_or_default();
+
+	if let Some(password) = m.value_of("password") {
+		pjlink.password = Some(String::from(password));
+	}
+	if let Some(address) = m.value_of("listen") {
+		let name = matches.value_of("monitor").unwrap_or_else(|| exit_with("pjlink", "--listen needs --monitor", 2));
+		let (name, _) = config.monitor(name).unwrap_or_else(|| exit_with(name, "unknown monitor", 2));
+		pjlink.listen.clear();
+		pjlink.listen.insert(String::from(name), String::from(address));
+	}
+	if pjlink.listen.is_empty() {
+		exit_with("pjlink", format!("no monitors to serve, use --monitor with --listen (e.g. 0.0.0.0:{})", pjlink::PORT), 2);
+	}
+
+	let config = Arc::new(config);
+	let opener : Arc<Opener> = Arc::new(Box::new(Monitor::open));
+	let servers : Vec<_> = pjlink.listen.iter().map(|(name, address)| {
+		if config.monitor(name).is_none() {
+			exit_with(name, "unknown monitor", 2);
+		}
+		let listener = TcpListener::bind(address).unwrap_or_else(|err| exit_with(address, err, 1));
+		let server = Arc::new(Server::new(name, pjlink.password.clone(), config.clone(), opener.clone()));
+		let address = address.clone();
+
+		thread::spawn(move || {
+			if let Err(err) = server.serve(listener) {
+				exit_with(&address, err, 1);
+			}
+		})
+	}).collect();
+
+	for server in servers {
+		let _ = server.join();
+	}
+}
@@ -0,0 +1,59 @@
+use clap::ArgMatches;
+
+use c5517h::property;
+use c5517h::property::Property;
+
+use super::{exit_with, exit_with_report, load_config, open_group, open_monitor};
+
+fn find(m: &ArgMatches) -> &'static Property {
+	let name = m.value_of("property").unwrap();
+	property::find(name).unwrap_or_else(|| exit_with(name, "unknown property", 2))
+}
+
+pub fn get(matches: &ArgMatches, m: &ArgMatches) {
+	let property = find(m);
+	let config = load_config(matches);
+
+	if let Some(group) = open_group(matches, &config) {
+		exit_with_report(&group.run(&config, |monitor| property.get(monitor)));
+	}
+
+	let mut monitor = open_monitor(matches, &config);
+
+	match property.get(&mut monitor) {
+		Ok(value) => println!("{}", value),
+		Err(err) => exit_with(monitor.name(), err, 1),
+	}
+}
+
+pub fn set(matches: &ArgMatches, m: &ArgMatches) {
+	let property = find(m);
+	let value = m.value_of("value").unwrap();
+	let config = load_config(matches);
+
+	if let Some(group) = open_group(matches, &config) {
+		exit_with_report(&group.run(&config, |monitor| property.set(monitor, value).map(|_| "ok")));
+	}
+
+	let mut monitor = open_monitor(matches, &config);
+
+	if let Err(err) = property.set(&mut monitor, value) {
+		exit_with(monitor.name(), err, 1);
+	}
+}
+
+pub fn list() {
+	for property in property::properties() {
+		let access = match (property.is_readable(), property.is_writable()) {
+			(true, true) => "rw",
+			(true, false) => "r",
+			(false, _) => "w",
+		};
+		let choices = property.choices();
+		if choices.is_empty() {
+			println!("{:<20} {}", property.name(), access);
+		} else {
+			println!("{:<20} {:<3} {}", property.name(), access, choices.join("|"));
+		}
+	}
+}
@@ -0,0 +1,39 @@
+use std::time::Duration;
+
+use clap::ArgMatches;
+
+use c5517h::monitor::Monitor;
+use c5517h::protocol::types;
+use c5517h::ramp;
+use c5517h::ramp::{Easing, Ramp};
+
+use super::{exit_with, exit_with_report, load_config, open_group, open_monitor};
+
+pub fn run(matches: &ArgMatches, m: &ArgMatches) {
+	let property = m.value_of("property").unwrap();
+	let target = m.value_of("target").unwrap();
+	let target = target.parse().ok().filter(|&x| x <= 100).unwrap_or_else(|| exit_with(target, "expected a value in 0 to 100", 2));
+	let duration = m.value_of("duration").unwrap().parse().map(Duration::from_millis)
+		.unwrap_or_else(|err| exit_with("duration", err, 2));
+	let easing : Easing = m.value_of("easing").unwrap().parse().unwrap_or_else(|err| exit_with("easing", err, 2));
+
+	let ramp = Ramp::new(target, duration).with_easing(easing);
+	let f = match property {
+		"brightness" => Monitor::ramp::<types::Brightness>,
+		"contrast" => Monitor::ramp::<types::Contrast>,
+		"sharpness" => Monitor::ramp::<types::Sharpness>,
+		_ => exit_with(property, "expected brightness, contrast or sharpness", 2),
+	};
+	let f = |monitor: &mut Monitor| -> ramp::Result<u8> { f(monitor, &ramp) };
+	let config = load_config(matches);
+
+	if let Some(group) = open_group(matches, &config) {
+		exit_with_report(&group.run(&config, f));
+	}
+
+	let mut monitor = open_monitor(matches, &config);
+
+	if let Err(err) = f(&mut monitor) {
+		exit_with(monitor.name(), err, 1);
+	}
+}
@@ -0,0 +1,60 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::time::Duration;
+
+use clap::ArgMatches;
+
+use c5517h::group::Error;
+use c5517h::reconcile::{Check, Reconciler};
+use c5517h::schedule::{Clock, LocalClock};
+
+use super::{exit_with, load_config};
+
+/// Prints every drift as a JSON line, also appending it to `log` if given.
+fn record(check: &Check, log: &mut Option<std::fs::File>) {
+	for (name, result) in check.report.results.iter() {
+		let drifts = match result {
+			Ok(drifts) => drifts,
+			Err(err) => {
+				eprintln!("{} {}: error: {}", check.profile, name, err);
+				match err {
+					Error::OperationError(interrupted) => &interrupted.drifts,
+					Error::OpenError(_) => continue,
+				}
+			},
+		};
+		for drift in drifts {
+			let line = serde_json::to_string(drift).unwrap();
+			println!("{}", line);
+			if let Some(ref mut file) = log {
+				if let Err(err) = writeln!(file, "{}", line) {
+					eprintln!("log: {}", err);
+				}
+			}
+		}
+	}
+}
+
+pub fn run(matches: &ArgMatches, m: &ArgMatches) {
+	let config = load_config(matches);
+	let profiles = config.profiles.clone();
+	if profiles.is_empty() {
+		exit_with("reconcile", "no profiles in the configuration", 2);
+	}
+
+	let interval = m.value_of("interval").unwrap().parse().map(Duration::from_secs)
+		.unwrap_or_else(|err| exit_with("interval", err, 2));
+	let mut log = m.value_of("log").map(|path| {
+		OpenOptions::new().create(true).append(true).open(path).unwrap_or_else(|err| exit_with(path, err, 1))
+	});
+	let reconciler = Reconciler::new(config, profiles).unwrap_or_else(|err| exit_with("reconcile", err, 2));
+
+	if m.is_present("once") {
+		for check in reconciler.check(LocalClock.now()) {
+			record(&check, &mut log);
+		}
+		return;
+	}
+
+	reconciler.run(&LocalClock, interval, |check| record(check, &mut log));
+}
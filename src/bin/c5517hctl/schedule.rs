@@ -0,0 +1,58 @@
+use chrono::{Duration, Local};
+use clap::ArgMatches;
+
+use c5517h::schedule;
+use c5517h::schedule::{Error, LocalClock, Run, Scheduler};
+
+use super::{exit_with, load_config};
+
+fn print_run(run: Result<&Run, &Error>) {
+	let run = match run {
+		Ok(run) => run,
+		Err(err) => return eprintln!("schedule: {}", err),
+	};
+	for (name, result) in run.report.results.iter() {
+		match result {
+			Ok(()) => println!("{} {} {}: ok", run.time, run.rule, name),
+			Err(err) => println!("{} {} {}: error: {}", run.time, run.rule, name, err),
+		}
+	}
+}
+
+fn preview(scheduler: &Scheduler, days: &str) {
+	let days = days.parse().map(Duration::days).unwrap_or_else(|err| exit_with("days", err, 2));
+	let now = Local::now().naive_local();
+
+	for (name, targets, transition) in scheduler.preview(now, now + days) {
+		let actions : Vec<_> = transition.actions.iter().map(|x| x.to_string()).collect();
+		println!("{} {} [{}] {}  {}", transition.time.format("%Y-%m-%d %H:%M"), name, targets.join(","), actions.join(" "), transition.note);
+	}
+}
+
+pub fn run(matches: &ArgMatches, m: &ArgMatches) {
+	let config = load_config(matches);
+	let rules = config.schedules.clone();
+	let calendars = config.calendars.clone();
+	if rules.is_empty() && calendars.is_empty() {
+		exit_with("schedule", "no schedules or calendars in the configuration", 2);
+	}
+
+	let catch_up = m.value_of("catch-up").unwrap().parse()
+		.map(Duration::hours)
+		.unwrap_or_else(|err| exit_with("catch-up", err, 2));
+	let mut scheduler = Scheduler::new(config, rules)
+		.and_then(|x| x.with_calendars(calendars))
+		.unwrap_or_else(|err| exit_with("schedule", err, 2))
+		.with_catch_up(catch_up);
+
+	if m.is_present("preview") {
+		return preview(&scheduler, m.value_of("days").unwrap());
+	}
+
+	if let Some(path) = m.value_of("state").map(From::from).or_else(schedule::state_path) {
+		scheduler = scheduler.with_state(&path)
+			.unwrap_or_else(|err| exit_with(&path.to_string_lossy(), err, 2));
+	}
+
+	scheduler.run(&LocalClock, print_run);
+}
@@ -0,0 +1,30 @@
+use std::net::TcpListener;
+
+use clap::ArgMatches;
+use serde_json;
+
+use c5517h::http;
+use c5517h::rest;
+use c5517h::rest::Api;
+
+use super::{exit_with, load_config};
+
+pub fn run(matches: &ArgMatches, m: &ArgMatches) {
+	if m.is_present("openapi") {
+		println!("{}", serde_json::to_string_pretty(&rest::openapi()).unwrap());
+		return;
+	}
+
+	let config = load_config(matches);
+	if config.monitors.is_empty() {
+		exit_with("serve", "no monitors in the configuration", 2);
+	}
+
+	let address = m.value_of("listen").unwrap();
+	let listener = TcpListener::bind(address).unwrap_or_else(|err| exit_with(address, err, 1));
+	let api = Api::new(config);
+
+	if let Err(err) = http::serve(listener, move |request| api.handle(request)) {
+		exit_with(address, err, 1);
+	}
+}
@@ -0,0 +1,46 @@
+use std::sync::mpsc;
+use std::time::Duration;
+
+use clap::ArgMatches;
+
+use c5517h::monitor::Monitor;
+use c5517h::property;
+use c5517h::watch;
+use c5517h::watch::{Interval, Watcher};
+
+use super::{exit_with, load_config, open_group, open_monitor};
+
+pub fn run(matches: &ArgMatches, m: &ArgMatches) {
+	let names : Vec<&str> = match m.values_of("property") {
+		Some(values) => values.collect(),
+		None => watch::DEFAULT_PROPERTIES.to_vec(),
+	};
+	let properties : Vec<_> = names.iter().map(|&name| match property::find(name) {
+		Some(property) if property.is_readable() => property,
+		Some(_) => exit_with(name, "not readable", 2),
+		None => exit_with(name, "unknown property", 2),
+	}).collect();
+	let millis = |name| m.value_of(name).unwrap().parse().map(Duration::from_millis).unwrap_or_else(|err| exit_with(name, err, 2));
+	let interval = Interval{min: millis("min-interval"), max: millis("max-interval")};
+
+	let config = load_config(matches);
+	let monitors = match open_group(matches, &config) {
+		Some(group) => group.names().into_iter()
+			.map(|name| Monitor::with_config(&config, name).unwrap_or_else(|err| exit_with(name, err, 1)))
+			.collect(),
+		None => vec![open_monitor(matches, &config)],
+	};
+
+	let (sender, receiver) = mpsc::channel();
+	for monitor in monitors {
+		Watcher::new(monitor, &properties).with_interval(interval).spawn(sender.clone());
+	}
+	drop(sender);
+
+	for event in receiver {
+		match event {
+			Ok(event) => println!("{}", serde_json::to_string(&event).unwrap()),
+			Err(err) => eprintln!("{}", err),
+		}
+	}
+}
@@ -0,0 +1,76 @@
+extern crate c5517h;
+extern crate clap;
+
+use std::fmt;
+use std::process;
+use std::time::Duration;
+
+use clap::{App, Arg};
+
+use c5517h::config::{Config, MonitorConfig};
+use c5517h::daemon;
+use c5517h::daemon::Server;
+use c5517h::transport;
+use c5517h::transport::Address;
+
+fn exit_with<E: fmt::Display>(context: &str, err: E, code: i32) -> ! {
+	eprintln!("{}: {}", context, err);
+	process::exit(code);
+}
+
+fn main() {
+	let matches = App::new("c5517hd")
+		.about("Owns the port of a Dell C5517H monitor and shares it with clients over a Unix socket")
+		.arg(Arg::with_name("port")
+			.short("p")
+			.long("port")
+			.takes_value(true)
+			.default_value("/dev/ttyS1")
+			.help("Serial port or address (tcp:host:port, rfc2217:host:port) of the monitor"))
+		.arg(Arg::with_name("monitor")
+			.short("m")
+			.long("monitor")
+			.takes_value(true)
+			.help("Monitor name or alias from the configuration"))
+		.arg(Arg::with_name("config")
+			.short("c")
+			.long("config")
+			.takes_value(true)
+			.help("Configuration file to use instead of the default ones"))
+		.arg(Arg::with_name("socket")
+			.short("s")
+			.long("socket")
+			.takes_value(true)
+			.help("Socket to listen on instead of the one clients look for"))
+		.get_matches();
+
+	let config = match matches.value_of("config") {
+		Some(path) => Config::load_from(path),
+		None => Config::load(),
+	}.unwrap_or_else(|err| exit_with("config", err, 2));
+
+	let (name, monitor) = match matches.value_of("monitor") {
+		Some(name) => config.monitor(name)
+			.map(|(name, x)| (name, x.clone()))
+			.unwrap_or_else(|| exit_with(name, "unknown monitor", 2)),
+		None => {
+			let port = matches.value_of("port").unwrap();
+			let address : Address = port.parse().unwrap_or_else(|err| exit_with(port, err, 2));
+			(port, MonitorConfig::new(address))
+		},
+	};
+
+	let settings = config.monitor_line_settings(&monitor);
+	let timeout = Duration::from_millis(monitor.timeout_ms);
+	let port = transport::open(&monitor.transport, &settings, timeout, &monitor.lock)
+		.unwrap_or_else(|err| exit_with(name, err, 1));
+
+	let path = matches.value_of("socket")
+		.map(From::from)
+		.unwrap_or_else(|| daemon::socket_path(&monitor.transport));
+	let server = Server::bind(&path, port, timeout).unwrap_or_else(|err| exit_with(&path.display().to_string(), err, 1));
+
+	if let Err(err) = server.run() {
+		exit_with(&path.display().to_string(), err, 1);
+	}
+}
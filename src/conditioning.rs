@@ -0,0 +1,368 @@
+//! LCD conditioning against image retention, in windows when the panels
+//! are not booked.
+//!
+//! A window powers the panel on if needed and turns conditioning on; once
+//! it is over, conditioning is turned off and the input and power state
+//! found before are put back. A panel that a calendar books during the
+//! window is left alone. Every window is recorded, so that the runs of
+//! every panel can be counted.
+
+use std;
+use std::fs;
+use std::io;
+use std::fmt;
+use std::error;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::collections::BTreeMap;
+
+use chrono::{Duration, NaiveDateTime};
+use serde_json;
+
+use config::Config;
+use group;
+use group::{Group, Report};
+use monitor;
+use monitor::{Monitor, Opener};
+use property;
+use property::Value;
+use schedule;
+use schedule::{ical, Calendar, Clock, Cron};
+
+#[derive(Debug)]
+pub enum Error {
+	UnknownTarget(monitor::Error),
+	InvalidRoutine(String, &'static str),
+	ScheduleError(schedule::Error),
+	/// A calendar books the panel during the window, for the named event.
+	InUse(String, String),
+	PropertyError(property::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Error::UnknownTarget(ref monitor_error) =>
+				write!(f, "{}", monitor_error),
+			Error::InvalidRoutine(ref name, reason) =>
+				write!(f, "{}: {}", name, reason),
+			Error::ScheduleError(ref schedule_error) =>
+				write!(f, "{}", schedule_error),
+			Error::InUse(ref calendar, ref summary) =>
+				write!(f, "in use: {}: {}", calendar, summary),
+			Error::PropertyError(ref property_error) =>
+				write!(f, "{}", property_error),
+		}
+	}
+}
+
+impl error::Error for Error {
+	fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+		match self {
+			Error::UnknownTarget(ref monitor_error) => Some(monitor_error),
+			Error::ScheduleError(ref schedule_error) => Some(schedule_error),
+			Error::PropertyError(ref property_error) => Some(property_error),
+			_ => None,
+		}
+	}
+}
+
+fn default_minutes() -> i64 { 60 }
+
+/// Conditioning windows of some monitors.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Routine {
+	pub name: String,
+	/// Start of every window.
+	pub cron: Cron,
+	/// Monitor names, aliases or group names.
+	pub targets: Vec<String>,
+	/// Length of every window.
+	#[serde(default = "default_minutes")]
+	pub minutes: i64,
+}
+
+/// State of a panel before its window, put back afterwards.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Prior {
+	pub power: Value,
+	/// `None` when the panel did not tell.
+	pub input: Option<Value>,
+}
+
+/// A window on one panel.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Record {
+	pub routine: String,
+	pub monitor: String,
+	pub start: NaiveDateTime,
+	pub end: NaiveDateTime,
+	/// Why conditioning did not start, or the panel was not put back.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub error: Option<String>,
+}
+
+/// Where the records are kept: `$XDG_STATE_HOME/c5517h/conditioning.log`.
+pub fn log_path() -> Option<PathBuf> {
+	Config::state_dir().map(|x| x.join("conditioning.log"))
+}
+
+/// Appends `records` to `path` as JSON lines.
+pub fn append<P: AsRef<Path>>(path: P, records: &[Record]) -> io::Result<()> {
+	let path = path.as_ref();
+	if let Some(dir) = path.parent().filter(|x| !x.as_os_str().is_empty()) {
+		fs::create_dir_all(dir)?;
+	}
+	let mut file = fs::OpenOptions::new().create(true).append(true).open(path)?;
+	for record in records {
+		writeln!(file, "{}", serde_json::to_string(record).unwrap())?;
+	}
+	Ok(())
+}
+
+/// Records kept in `path`, a missing file is an empty log.
+pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Vec<Record>> {
+	match fs::read_to_string(path) {
+		Ok(content) => content.lines().filter(|x| !x.is_empty())
+			.map(|x| serde_json::from_str(x).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)))
+			.collect(),
+		Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+		Err(err) => Err(err),
+	}
+}
+
+/// Windows that ran on every panel, and the last of them.
+pub fn counts(records: &[Record]) -> BTreeMap<&str, (usize, NaiveDateTime)> {
+	let mut counts = BTreeMap::new();
+	for record in records.iter().filter(|x| x.error.is_none()) {
+		let count = counts.entry(record.monitor.as_str()).or_insert((0, record.start));
+		count.0 += 1;
+		count.1 = count.1.max(record.start);
+	}
+	counts
+}
+
+fn set(m: &mut Monitor, name: &str, value: &str) -> Result<()> {
+	property::find(name).unwrap().set(m, value).map_err(Error::PropertyError)
+}
+
+/// Turns conditioning off and puts back `prior`, trying every step.
+fn restore(m: &mut Monitor, prior: &Prior) -> Result<()> {
+	let mut results = vec![set(m, "lcd-conditioning", "disabled")];
+	if let Some(ref input) = prior.input {
+		results.push(set(m, "input", &input.to_string()));
+	}
+	if prior.power.matches("off") {
+		results.push(set(m, "power", "off"));
+	}
+	results.into_iter().collect()
+}
+
+pub struct Conditioner {
+	config: Config,
+	routines: Vec<Routine>,
+	calendars: Vec<(Calendar, Vec<ical::Event>)>,
+	opener: Opener,
+}
+
+impl Conditioner {
+	pub fn new(config: Config, routines: Vec<Routine>) -> Result<Conditioner> {
+		Conditioner::with_opener(config, routines, Box::new(Monitor::open))
+	}
+
+	pub fn with_opener(config: Config, routines: Vec<Routine>, opener: Opener) -> Result<Conditioner> {
+		for routine in &routines {
+			Group::resolve_all(&config, &routine.targets).map_err(Error::UnknownTarget)?;
+			if routine.minutes <= 0 {
+				return Err(Error::InvalidRoutine(routine.name.clone(), "minutes must be positive"));
+			}
+		}
+		Ok(Conditioner{config, routines, calendars: Vec::new(), opener})
+	}
+
+	/// Adds the calendars telling when panels are in use, reading their events.
+	pub fn with_calendars(mut self, calendars: Vec<Calendar>) -> Result<Conditioner> {
+		for calendar in calendars {
+			Group::resolve_all(&self.config, &calendar.targets).map_err(Error::UnknownTarget)?;
+			let events = calendar.load().map_err(Error::ScheduleError)?;
+			self.calendars.push((calendar, events));
+		}
+		Ok(self)
+	}
+
+	/// Reads the calendars again, those that fail to read keep their events.
+	pub fn reload_calendars(&mut self) -> Result<()> {
+		let mut result = Ok(());
+		for (calendar, events) in &mut self.calendars {
+			match calendar.load() {
+				Ok(x) => *events = x,
+				Err(err) => result = Err(Error::ScheduleError(err)),
+			}
+		}
+		result
+	}
+
+	pub fn routines(&self) -> &[Routine] {
+		&self.routines
+	}
+
+	/// Calendar and event booking `monitor` during `[from, to)`.
+	fn booking(&self, monitor: &str, from: NaiveDateTime, to: NaiveDateTime) -> Option<(String, String)> {
+		self.calendars.iter()
+			.filter(|(calendar, _)| Group::resolve_all(&self.config, &calendar.targets).unwrap().names().contains(&monitor))
+			.find_map(|(calendar, events)| calendar.booking(events, from, to).map(|x| (calendar.name.clone(), x)))
+	}
+
+	/// Starts a window of `routine` at `now` on every panel not booked
+	/// until its end, returning what to put back.
+	pub fn start(&self, routine: &Routine, now: NaiveDateTime) -> Report<Prior, Error> {
+		let end = now + Duration::minutes(routine.minutes);
+		let group = Group::resolve_all(&self.config, &routine.targets).unwrap();
+
+		group.run_with(&self.config, &self.opener, |m| {
+			if let Some((calendar, summary)) = self.booking(m.name(), now, end) {
+				return Err(Error::InUse(calendar, summary));
+			}
+			let power = property::find("power").unwrap().get(m).map_err(Error::PropertyError)?;
+			let input = property::find("input").unwrap().get(m).ok();
+			let prior = Prior{power, input};
+
+			if prior.power.matches("off") {
+				set(m, "power", "on")?;
+			}
+			if let Err(err) = set(m, "lcd-conditioning", "enabled") {
+				let _ = restore(m, &prior);
+				return Err(err);
+			}
+			Ok(prior)
+		})
+	}
+
+	/// Ends the window on the panels of `priors`, putting them back.
+	pub fn finish(&self, priors: &BTreeMap<String, Prior>) -> Report<(), Error> {
+		let names : Vec<_> = priors.keys().cloned().collect();
+		let group = Group::resolve_all(&self.config, &names).unwrap();
+		group.run_with(&self.config, &self.opener, |m| restore(m, &priors[m.name()]))
+	}
+
+	/// Runs a whole window of `routine`, from now until its end.
+	pub fn condition<C: Clock>(&self, routine: &Routine, clock: &C) -> Vec<Record> {
+		let start = clock.now();
+		let mut records = Vec::new();
+		let mut priors = BTreeMap::new();
+		let record = |monitor: String, end, error: Option<String>| Record{routine: routine.name.clone(), monitor, start, end, error};
+
+		for (name, result) in self.start(routine, start).results {
+			match result {
+				Ok(prior) => { priors.insert(name, prior); },
+				Err(err) => records.push(record(name, start, Some(err.to_string()))),
+			}
+		}
+		if priors.is_empty() {
+			return records;
+		}
+
+		clock.sleep(Duration::minutes(routine.minutes).to_std().unwrap());
+		let end = clock.now();
+		for (name, result) in self.finish(&priors).results {
+			records.push(record(name, end, result.err().map(|x: group::Error<Error>| x.to_string())));
+		}
+		records
+	}
+
+	/// Runs the windows as they come, passing every record to `f`.
+	pub fn run<C: Clock, F: FnMut(std::result::Result<&Record, &Error>)>(&mut self, clock: &C, mut f: F) -> ! {
+		loop {
+			let now = clock.now();
+			let next = self.routines.iter()
+				.filter_map(|x| x.cron.next_after(now).map(|t| (t, x)))
+				.min_by_key(|&(t, _)| t);
+			let (time, routine) = match next {
+				Some((time, routine)) => (time, routine.clone()),
+				None => {
+					clock.sleep(std::time::Duration::from_secs(3600));
+					continue;
+				},
+			};
+
+			clock.sleep((time - now).to_std().unwrap_or_default());
+			if let Err(err) = self.reload_calendars() {
+				f(Err(&err));
+			}
+			self.condition(&routine, clock).iter().for_each(|x| f(Ok(x)));
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::fs;
+	use std::env;
+	use std::process;
+	use std::cell::Cell;
+
+	use chrono::{Duration, NaiveDate, NaiveDateTime};
+
+	use conditioning;
+	use conditioning::{Conditioner, Routine};
+	use schedule::{Calendar, Clock};
+	use simulator;
+	use simulator::Simulator;
+
+	struct FakeClock(Cell<NaiveDateTime>);
+
+	impl Clock for FakeClock {
+		fn now(&self) -> NaiveDateTime {
+			self.0.get()
+		}
+
+		fn sleep(&self, duration: std::time::Duration) {
+			self.0.set(self.0.get() + Duration::from_std(duration).unwrap());
+		}
+	}
+
+	#[test]
+	fn conditioning_window() {
+		let lobby = Simulator::new();
+		let atrium = Simulator::new();
+		let (config, opener) = simulator::fixture(&[("lobby", &lobby), ("atrium", &atrium)]);
+		let path = env::temp_dir().join(format!("c5517h-conditioning-{}.ics", process::id()));
+		fs::write(&path, "BEGIN:VCALENDAR\nBEGIN:VEVENT\nUID:a\nSUMMARY:Night shift\nDTSTART:20240102T023000\nDTEND:20240102T050000\nEND:VEVENT\nEND:VCALENDAR\n").unwrap();
+		let calendar = Calendar{name: String::from("ops"), path: path.clone(), targets: vec![String::from("atrium")], lead_minutes: 10};
+		let routine = Routine{name: String::from("nightly"), cron: "0 2 * * *".parse().unwrap(),
+			targets: vec![String::from("lobby"), String::from("atrium")], minutes: 30};
+
+		let conditioner = Conditioner::with_opener(config, vec![routine.clone()], opener).unwrap()
+			.with_calendars(vec![calendar]).unwrap();
+		fs::remove_file(&path).unwrap();
+
+		// The lobby is off on DP, the atrium is booked from 02:20 with the lead
+		lobby.set_value(0x20, &[0]);
+		lobby.set_value(0x62, &[0x08, 0, 0, 0]);
+		let night = |day| NaiveDate::from_ymd_opt(2024, 1, day).unwrap().and_hms_opt(2, 0, 0).unwrap();
+		let started = conditioner.start(&routine, night(2));
+		assert_eq!(Some(vec![1]), lobby.value(0x20));
+		assert_eq!(Some(vec![1]), lobby.value(0xA3));
+		assert_eq!("in use: ops: Night shift", started.results[1].1.as_ref().unwrap_err().to_string());
+		assert_eq!(Some(vec![0]), atrium.value(0xA3));
+
+		let priors = started.results.into_iter().filter_map(|(name, x)| x.ok().map(|x| (name, x))).collect();
+		assert!(conditioner.finish(&priors).is_success());
+		assert_eq!((Some(vec![0]), Some(vec![0]), Some(vec![0x08, 0, 0, 0])), (lobby.value(0xA3), lobby.value(0x20), lobby.value(0x62)));
+
+		// The next night the atrium is free
+		let clock = FakeClock(Cell::new(night(3)));
+		let records = conditioner.condition(&routine, &clock);
+		assert_eq!(vec![("atrium", None), ("lobby", None)], records.iter().map(|x| (x.monitor.as_str(), x.error.clone())).collect::<Vec<_>>());
+		assert_eq!(night(3) + Duration::minutes(30), records[0].end);
+		assert_eq!(Some(vec![0]), lobby.value(0x20));
+		let log = env::temp_dir().join(format!("c5517h-conditioning-{}.log", process::id()));
+		conditioning::append(&log, &records).unwrap();
+		let loaded = conditioning::load(&log).unwrap();
+		fs::remove_file(&log).unwrap();
+		assert_eq!(records, loaded);
+		assert_eq!(Some(&(1, night(3))), conditioning::counts(&loaded).get("atrium"));
+	}
+}
@@ -0,0 +1,341 @@
+use std;
+use std::fs;
+use std::io;
+use std::fmt;
+use std::env;
+use std::error;
+use std::path::{Path, PathBuf};
+use std::collections::BTreeMap;
+
+use toml;
+
+use alert::Alert;
+use backlight::BacklightConfig;
+use conditioning::Routine;
+use hooks::Hook;
+use lock::LockPolicy;
+use mqtt::MqttConfig;
+use pjlink::PjlinkConfig;
+use reconcile::Profile;
+use schedule::{Calendar, Policy, Rule};
+use port::LineSettings;
+use transport::Address;
+
+/// System-wide configuration file, read before the per-user one.
+pub const SYSTEM_PATH : &str = "/etc/c5517h.toml";
+
+#[derive(Debug)]
+pub enum Error {
+	IoError(io::Error),
+	ParseError(toml::de::Error),
+	SerializeError(toml::ser::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Error::IoError(ref io_error) =>
+				write!(f, "io error: {}", io_error),
+			Error::ParseError(ref parse_error) =>
+				write!(f, "parse error: {}", parse_error),
+			Error::SerializeError(ref serialize_error) =>
+				write!(f, "serialize error: {}", serialize_error),
+		}
+	}
+}
+
+impl error::Error for Error {
+	fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+		match self {
+			Error::IoError(ref io_error) => Some(io_error),
+			Error::ParseError(ref parse_error) => Some(parse_error),
+			Error::SerializeError(ref serialize_error) => Some(serialize_error),
+		}
+	}
+}
+
+fn default_timeout_ms() -> u64 { 1000 }
+fn default_attempts() -> u32 { 1 }
+fn default_delay_ms() -> u64 { 100 }
+
+/// How often and how fast a failed transaction is repeated.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RetryPolicy {
+	/// Total number of attempts, the first one included.
+	#[serde(default = "default_attempts")]
+	pub attempts: u32,
+	#[serde(default = "default_delay_ms")]
+	pub delay_ms: u64,
+}
+
+impl Default for RetryPolicy {
+	fn default() -> Self {
+		RetryPolicy{attempts: default_attempts(), delay_ms: default_delay_ms()}
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MonitorConfig {
+	pub transport: Address,
+	/// Line settings, falls back to `ports` and then to 9600 8N1.
+	#[serde(default)]
+	pub settings: Option<LineSettings>,
+	#[serde(default)]
+	pub aliases: Vec<String>,
+	#[serde(default = "default_timeout_ms")]
+	pub timeout_ms: u64,
+	#[serde(default)]
+	pub retry: RetryPolicy,
+	#[serde(default)]
+	pub lock: LockPolicy,
+	/// File every byte exchanged with the monitor is recorded to, one per
+	/// monitor since captures carry no monitor name.
+	#[serde(default)]
+	pub capture: Option<PathBuf>,
+}
+
+impl MonitorConfig {
+	pub fn new(transport: Address) -> MonitorConfig {
+		MonitorConfig{
+			transport,
+			settings: None,
+			aliases: Vec::new(),
+			timeout_ms: default_timeout_ms(),
+			retry: RetryPolicy::default(),
+			lock: LockPolicy::default(),
+			capture: None,
+		}
+	}
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Config {
+	/// Line settings found by `port::detect`, keyed by port path.
+	#[serde(default)]
+	pub ports: BTreeMap<String, LineSettings>,
+	#[serde(default)]
+	pub monitors: BTreeMap<String, MonitorConfig>,
+	/// Named lists of monitor names or aliases.
+	#[serde(default)]
+	pub groups: BTreeMap<String, Vec<String>>,
+	/// Broker of the Home Assistant bridge.
+	#[serde(default)]
+	pub mqtt: Option<MqttConfig>,
+	#[serde(default)]
+	pub pjlink: Option<PjlinkConfig>,
+	/// Rules of the scheduler.
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub schedules: Vec<Rule>,
+	/// Calendars of the scheduler.
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub calendars: Vec<Calendar>,
+	/// Brightness and colour temperature curves.
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub circadian: Vec<Policy>,
+	/// Desired states kept by the reconciler.
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub profiles: Vec<Profile>,
+	/// Commands, writes and webhooks run on property changes and failures.
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub hooks: Vec<Hook>,
+	/// Conditions posted to webhooks.
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub alerts: Vec<Alert>,
+	/// LCD conditioning windows.
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub conditioning: Vec<Routine>,
+	/// Rated life and history file of the backlight hours.
+	#[serde(default)]
+	pub backlight: Option<BacklightConfig>,
+}
+
+impl Config {
+	/// Per-user configuration file: `$XDG_CONFIG_HOME/c5517h/config.toml`.
+	pub fn user_path() -> Option<PathBuf> {
+		env::var_os("XDG_CONFIG_HOME")
+			.map(PathBuf::from)
+			.or_else(|| env::var_os("HOME").map(|x| Path::new(&x).join(".config")))
+			.map(|x| x.join("c5517h").join("config.toml"))
+	}
+
+	/// Per-user directory of kept state: `$XDG_STATE_HOME/c5517h`.
+	pub fn state_dir() -> Option<PathBuf> {
+		env::var_os("XDG_STATE_HOME")
+			.map(PathBuf::from)
+			.or_else(|| env::var_os("HOME").map(|x| Path::new(&x).join(".local").join("state")))
+			.map(|x| x.join("c5517h"))
+	}
+
+	/// Reads the system-wide file, then lets the per-user one override its entries.
+	pub fn load() -> Result<Config> {
+		let mut config = Config::load_from(SYSTEM_PATH)?;
+		if let Some(path) = Config::user_path() {
+			config.merge(Config::load_from(path)?);
+		}
+		Ok(config)
+	}
+
+	pub fn merge(&mut self, other: Config) {
+		self.ports.extend(other.ports);
+		self.monitors.extend(other.monitors);
+		self.groups.extend(other.groups);
+		if other.mqtt.is_some() {
+			self.mqtt = other.mqtt;
+		}
+		if other.pjlink.is_some() {
+			self.pjlink = other.pjlink;
+		}
+		for rule in other.schedules {
+			self.schedules.retain(|x| x.name != rule.name);
+			self.schedules.push(rule);
+		}
+		for calendar in other.calendars {
+			self.calendars.retain(|x| x.name != calendar.name);
+			self.calendars.push(calendar);
+		}
+		for policy in other.circadian {
+			self.circadian.retain(|x| x.name != policy.name);
+			self.circadian.push(policy);
+		}
+		for profile in other.profiles {
+			self.profiles.retain(|x| x.name != profile.name);
+			self.profiles.push(profile);
+		}
+		for hook in other.hooks {
+			self.hooks.retain(|x| x.name != hook.name);
+			self.hooks.push(hook);
+		}
+		for alert in other.alerts {
+			self.alerts.retain(|x| x.name != alert.name);
+			self.alerts.push(alert);
+		}
+		for routine in other.conditioning {
+			self.conditioning.retain(|x| x.name != routine.name);
+			self.conditioning.push(routine);
+		}
+		if other.backlight.is_some() {
+			self.backlight = other.backlight;
+		}
+	}
+
+	/// Reads `path`, a missing file is an empty configuration.
+	pub fn load_from<P: AsRef<Path>>(path: P) -> Result<Config> {
+		match fs::read_to_string(path) {
+			Ok(content) => toml::from_str(&content).map_err(Error::ParseError),
+			Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(Config::default()),
+			Err(err) => Err(Error::IoError(err)),
+		}
+	}
+
+	pub fn save_to<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+		let content = toml::to_string(self).map_err(Error::SerializeError)?;
+		if let Some(dir) = path.as_ref().parent() {
+			fs::create_dir_all(dir).map_err(Error::IoError)?;
+		}
+		fs::write(path, content).map_err(Error::IoError)
+	}
+
+	/// Records the traffic of every monitor to `<path>.<name>`, a capture
+	/// of its own so that replies are replayed to the monitor they came from.
+	pub fn set_capture<P: AsRef<Path>>(&mut self, path: P) {
+		for (name, monitor) in self.monitors.iter_mut() {
+			let mut file = path.as_ref().as_os_str().to_owned();
+			file.push(format!(".{}", name));
+			monitor.capture = Some(PathBuf::from(file));
+		}
+	}
+
+	pub fn line_settings(&self, port: &str) -> LineSettings {
+		self.ports.get(port).cloned().unwrap_or_default()
+	}
+
+	/// Finds a monitor by its name or one of its aliases, returns the name too.
+	pub fn monitor(&self, name: &str) -> Option<(&str, &MonitorConfig)> {
+		self.monitors.get_key_value(name)
+			.or_else(|| self.monitors.iter().find(|&(_, x)| x.aliases.iter().any(|alias| alias == name)))
+			.map(|(name, x)| (name.as_str(), x))
+	}
+
+	/// Line settings of `monitor`: its own ones, then the cached ones of its port.
+	pub fn monitor_line_settings(&self, monitor: &MonitorConfig) -> LineSettings {
+		match (monitor.settings, &monitor.transport) {
+			(Some(settings), _) => settings,
+			(None, Address::Serial(path)) => self.line_settings(path),
+			(None, _) => LineSettings::default(),
+		}
+	}
+
+	/// Members of the group `name`, `None` when there is no such group.
+	pub fn group(&self, name: &str) -> Option<&[String]> {
+		self.groups.get(name).map(|x| &x[..])
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::env;
+	use std::fs;
+
+	use toml;
+
+	use config::{Config, RetryPolicy};
+	use lock::LockPolicy;
+	use port::{LineSettings, LineParity};
+	use transport::Address;
+
+	#[test]
+	fn config_missing_file() {
+		let path = env::temp_dir().join("c5517h-config-missing.toml");
+		assert_eq!(Config::default(), Config::load_from(&path).unwrap());
+	}
+
+	#[test]
+	fn config_ports_roundtrip() {
+		let path = env::temp_dir().join("c5517h-config-ports.toml");
+		let mut config = Config::default();
+		config.ports.insert(String::from("/dev/ttyUSB0"), LineSettings{baud_rate: 19200, parity: LineParity::Even, stop_bits: 1});
+		config.save_to(&path).unwrap();
+
+		let loaded = Config::load_from(&path).unwrap();
+		fs::remove_file(&path).unwrap();
+		assert_eq!(config, loaded);
+		assert_eq!(19200, loaded.line_settings("/dev/ttyUSB0").baud_rate);
+		assert_eq!(LineSettings::default(), loaded.line_settings("/dev/ttyUSB1"));
+	}
+
+	#[test]
+	fn config_monitors() {
+		let config : Config = toml::from_str(r#"
+			[monitors.lobby]
+			transport = "/dev/serial/by-id/usb-FTDI_FT232R_USB_UART_A50285BI-if00-port0"
+			aliases = ["entrance"]
+			retry = { attempts = 3 }
+			lock = { wait_ms = 5000 }
+
+			[monitors.atrium]
+			transport = "rfc2217:moxa:950"
+			settings = { baud_rate = 19200, parity = "none", stop_bits = 1 }
+			timeout_ms = 500
+
+			[groups]
+			room12 = ["lobby", "atrium"]
+		"#).unwrap();
+
+		let (name, lobby) = config.monitor("entrance").unwrap();
+		assert_eq!("lobby", name);
+		assert_eq!(Address::Serial(String::from("/dev/serial/by-id/usb-FTDI_FT232R_USB_UART_A50285BI-if00-port0")), lobby.transport);
+		assert_eq!(RetryPolicy{attempts: 3, delay_ms: 100}, lobby.retry);
+		assert_eq!(1000, lobby.timeout_ms);
+		assert_eq!(LockPolicy{enabled: true, wait_ms: 5000}, lobby.lock);
+		assert_eq!(LineSettings::default(), config.monitor_line_settings(lobby));
+
+		let (_, atrium) = config.monitor("atrium").unwrap();
+		assert_eq!(19200, config.monitor_line_settings(atrium).baud_rate);
+		assert_eq!(500, atrium.timeout_ms);
+
+		assert!(config.monitor("lounge").is_none());
+		assert_eq!(Some(&[String::from("lobby"), String::from("atrium")][..]), config.group("room12"));
+	}
+}
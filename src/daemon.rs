@@ -0,0 +1,434 @@
+//! Sharing one monitor between processes through a daemon that owns its port.
+//!
+//! Clients talk to the daemon over a Unix socket with the very frames they
+//! would send to the monitor, so a connected socket is a transport like any
+//! other. The daemon relays one whole request and reply at a time, requests
+//! of different clients never interleave on the line, and a reply that comes
+//! too late for its client is discarded rather than handed to the next one.
+
+use std::fs;
+use std::env;
+use std::io;
+use std::io::{Read, Write};
+use std::thread;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+
+use libc;
+
+use protocol::frame::{read_frame, HOST_PREFIX, MONITOR_PREFIX};
+use transport::{Address, Transport};
+
+/// Default directory for daemon sockets, `C5517H_RUNTIME_DIR` overrides it.
+pub const RUNTIME_DIR : &str = "/run/c5517h";
+
+pub fn runtime_dir() -> PathBuf {
+	env::var_os("C5517H_RUNTIME_DIR").map(PathBuf::from).unwrap_or_else(|| PathBuf::from(RUNTIME_DIR))
+}
+
+/// Socket of the daemon owning `address`.
+///
+/// Serial ports are named after the device they resolve to, so that a
+/// `/dev/serial/by-id` link and the tty it points to share the daemon.
+pub fn socket_path(address: &Address) -> PathBuf {
+	let name = match address {
+		Address::Serial(ref path) => fs::canonicalize(path)
+			.unwrap_or_else(|_| PathBuf::from(path))
+			.file_name()
+			.map(|x| x.to_string_lossy().into_owned())
+			.unwrap_or_default(),
+		address => address.to_string().replace(|c: char| !c.is_ascii_alphanumeric() && c != '.' && c != '-', "_"),
+	};
+
+	runtime_dir().join(format!("{}.sock", name))
+}
+
+/// Connects to the daemon owning `address`, `None` when there is none.
+pub fn connect(address: &Address, timeout: Duration) -> Option<Client> {
+	Client::connect(socket_path(address), timeout).ok()
+}
+
+/// A connection to a daemon, as a transport.
+///
+/// A reply the daemon sent after the client stopped waiting for it is
+/// discarded before the next request goes out.
+pub struct Client {
+	stream: UnixStream,
+}
+
+impl Client {
+	pub fn connect<P : AsRef<Path>>(path: P, timeout: Duration) -> io::Result<Client> {
+		let stream = UnixStream::connect(path)?;
+		stream.set_read_timeout(Some(timeout))?;
+		stream.set_write_timeout(Some(timeout))?;
+		Ok(Client{stream})
+	}
+}
+
+impl Read for Client {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		self.stream.read(buf)
+	}
+}
+
+impl Write for Client {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		self.stream.set_nonblocking(true)?;
+		let mut discarded = [0u8; 64];
+		let pending = loop {
+			match self.stream.read(&mut discarded) {
+				Ok(0) => break Ok(()),
+				Ok(_) => continue,
+				Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => break Ok(()),
+				Err(err) => break Err(err),
+			}
+		};
+		self.stream.set_nonblocking(false)?;
+		pending?;
+		self.stream.write(buf)
+	}
+	fn flush(&mut self) -> io::Result<()> {
+		self.stream.flush()
+	}
+}
+
+/// Most bytes discarded from a line before a request, so that one which
+/// never goes quiet does not stall its clients.
+const MAX_DRAIN : usize = 1024;
+
+/// The port, and whether a reply may still be on its way through it.
+struct Line {
+	port: Box<dyn Transport>,
+	stale: bool,
+}
+
+impl Line {
+	/// Discards input until the port has nothing more to read, for at most
+	/// `MAX_DRAIN` bytes and until `deadline`.
+	fn drain(&mut self, deadline: Instant) {
+		let mut buf = [0u8; 64];
+		let mut drained = 0;
+		while drained < MAX_DRAIN && Instant::now() < deadline {
+			match self.port.read(&mut buf) {
+				Ok(0) | Err(_) => break,
+				Ok(n) => drained += n,
+			}
+		}
+	}
+
+	/// Relays `request`, skipping replies to other opcodes until `deadline`.
+	fn exchange(&mut self, request: &[u8], deadline: Instant) -> io::Result<Vec<u8>> {
+		if self.stale {
+			self.drain(deadline);
+		}
+		self.stale = true;
+		self.port.write_all(request)?;
+		self.port.flush()?;
+
+		loop {
+			let reply = read_frame(&mut *self.port, MONITOR_PREFIX)?;
+			if reply.get(5) == request.get(4) {
+				self.stale = false;
+				return Ok(reply);
+			}
+			if Instant::now() >= deadline {
+				return Err(io::Error::new(io::ErrorKind::TimedOut, "no reply to the request"));
+			}
+		}
+	}
+}
+
+/// The line, lent to one client at a time.
+struct Port {
+	line: Mutex<Option<Line>>,
+	returned: Condvar,
+}
+
+impl Port {
+	fn new(port: Box<dyn Transport>) -> Port {
+		Port{line: Mutex::new(Some(Line{port, stale: false})), returned: Condvar::new()}
+	}
+
+	/// Relays `request` unless the line stays busy with other clients until `deadline`.
+	fn exchange(&self, request: &[u8], deadline: Instant) -> io::Result<Vec<u8>> {
+		let mut line = {
+			let mut line = self.line.lock().unwrap();
+			loop {
+				if let Some(line) = line.take() {
+					break line;
+				}
+				let now = Instant::now();
+				if now >= deadline {
+					return Err(io::Error::new(io::ErrorKind::TimedOut, "the line is busy"));
+				}
+				line = self.returned.wait_timeout(line, deadline - now).unwrap().0;
+			}
+		};
+
+		let reply = line.exchange(request, deadline);
+		*self.line.lock().unwrap() = Some(line);
+		self.returned.notify_one();
+		reply
+	}
+}
+
+/// Whether `client` sent more than what was read from it.
+fn has_pending(client: &UnixStream) -> io::Result<bool> {
+	let mut byte = 0u8;
+	let n = unsafe {
+		libc::recv(client.as_raw_fd(), &mut byte as *mut u8 as *mut libc::c_void, 1, libc::MSG_PEEK | libc::MSG_DONTWAIT)
+	};
+	if n >= 0 {
+		return Ok(n > 0);
+	}
+	match io::Error::last_os_error() {
+		ref err if err.kind() == io::ErrorKind::WouldBlock => Ok(false),
+		err => Err(err),
+	}
+}
+
+fn serve(mut client: UnixStream, port: Arc<Port>, timeout: Duration) -> io::Result<()> {
+	loop {
+		let request = match read_frame(&mut client, HOST_PREFIX) {
+			Ok(x) => x,
+			Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+			Err(err) => return Err(err),
+		};
+		let deadline = Instant::now() + timeout;
+
+		// On failure the client is left to its own read timeout and retry
+		// policy, just as if it owned the port. A reply past that timeout,
+		// or once the client sent its next request, is not waited for anymore.
+		if let Ok(reply) = port.exchange(&request, deadline) {
+			if Instant::now() < deadline && !has_pending(&client)? {
+				client.write_all(&reply)?;
+			}
+		}
+	}
+}
+
+pub struct Server {
+	listener: UnixListener,
+	path: PathBuf,
+	port: Arc<Port>,
+	timeout: Duration,
+}
+
+impl Server {
+	/// Listens on `path` for clients of `port`, whose replies take at
+	/// most `timeout`. Clients are not expected to wait any longer.
+	///
+	/// A socket left behind by a daemon that is gone is replaced, a live one
+	/// is an `AddrInUse` error.
+	pub fn bind<P : AsRef<Path>>(path: P, port: Box<dyn Transport>, timeout: Duration) -> io::Result<Server> {
+		let path = path.as_ref().to_path_buf();
+
+		if path.exists() {
+			if UnixStream::connect(&path).is_ok() {
+				return Err(io::Error::new(io::ErrorKind::AddrInUse, format!("{} is served by another daemon", path.display())));
+			}
+			fs::remove_file(&path)?;
+		}
+		if let Some(dir) = path.parent() {
+			fs::create_dir_all(dir)?;
+		}
+
+		let listener = UnixListener::bind(&path)?;
+
+		Ok(Server{listener, path, port: Arc::new(Port::new(port)), timeout})
+	}
+
+	pub fn path(&self) -> &Path {
+		&self.path
+	}
+
+	/// Accepts clients forever, each one served by its own thread.
+	pub fn run(&self) -> io::Result<()> {
+		for client in self.listener.incoming() {
+			let client = client?;
+			let port = self.port.clone();
+			let timeout = self.timeout;
+			thread::spawn(move || serve(client, port, timeout));
+		}
+		Ok(())
+	}
+}
+
+impl Drop for Server {
+	fn drop(&mut self) {
+		let _ = fs::remove_file(&self.path);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::env;
+	use std::io;
+	use std::io::{Read, Write};
+	use std::thread;
+	use std::sync::Arc;
+	use std::time::Duration;
+	use std::os::unix::net::UnixStream;
+
+	use daemon::{Client, Server};
+	use monitor::Monitor;
+	use protocol::types;
+
+	/// Answers every request for the power state, after some line noise.
+	struct Device {
+		pending: Vec<u8>,
+	}
+
+	impl Read for Device {
+		fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+			let n = (&self.pending[..]).read(buf)?;
+			self.pending.drain(..n);
+			Ok(n)
+		}
+	}
+
+	impl Write for Device {
+		fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+			assert_eq!([0x37 as u8, 0x51, 0x02, 0xeb, 0x20, 175], buf);
+			self.pending.extend_from_slice(&[0x00, 0x6f, 0x37, 0x04, 0x02, 0x00, 0x20, 0x01, 127]);
+			Ok(buf.len())
+		}
+		fn flush(&mut self) -> io::Result<()> { Ok(()) }
+	}
+
+	#[test]
+	fn server_relays_transactions() {
+		let path = env::temp_dir().join("c5517h-daemon-test.sock");
+		let server = Arc::new(Server::bind(&path, Box::new(Device{pending: Vec::new()}), Duration::from_millis(100)).unwrap());
+		let s = server.clone();
+		thread::spawn(move || s.run());
+
+		let clients : Vec<_> = (0..4).map(|_| {
+			let path = path.clone();
+			thread::spawn(move || {
+				let mut m = Monitor::new("test", Box::new(UnixStream::connect(path).unwrap()));
+				for _ in 0..10 {
+					assert_eq!(types::PowerState::On, m.get::<types::PowerState>().unwrap());
+				}
+			})
+		}).collect();
+
+		for client in clients {
+			client.join().unwrap();
+		}
+	}
+
+	/// Answers the first request only after `delay` reads timed out.
+	struct LateDevice {
+		pending: Vec<u8>,
+		late: Option<Vec<u8>>,
+		delay: usize,
+	}
+
+	impl Read for LateDevice {
+		fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+			if self.pending.is_empty() {
+				if self.late.is_some() {
+					self.delay -= 1;
+					if self.delay == 0 {
+						self.pending = self.late.take().unwrap();
+					}
+				}
+				return Err(io::ErrorKind::TimedOut.into());
+			}
+			let n = (&self.pending[..]).read(buf)?;
+			self.pending.drain(..n);
+			Ok(n)
+		}
+	}
+
+	impl Write for LateDevice {
+		fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+			match buf[4] {
+				0x20 => self.late = Some(vec![0x6f, 0x37, 0x04, 0x02, 0x00, 0x20, 0x01, 127]),
+				_ => self.pending.extend_from_slice(&[0x6f, 0x37, 0x04, 0x02, 0x00, 0x30, 0x4b, 0x25]),
+			}
+			Ok(buf.len())
+		}
+		fn flush(&mut self) -> io::Result<()> { Ok(()) }
+	}
+
+	#[test]
+	fn server_discards_late_replies() {
+		// The late reply comes before the next request, then after it.
+		for delay in 1..3 {
+			let path = env::temp_dir().join(format!("c5517h-daemon-late-{}.sock", delay));
+			let device = LateDevice{pending: Vec::new(), late: None, delay};
+			let server = Arc::new(Server::bind(&path, Box::new(device), Duration::from_millis(100)).unwrap());
+			let s = server.clone();
+			thread::spawn(move || s.run());
+
+			let stream = UnixStream::connect(&path).unwrap();
+			stream.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+			let mut m = Monitor::new("test", Box::new(stream));
+			assert!(m.get::<types::PowerState>().is_err());
+			assert_eq!(types::Brightness::from(75), m.get::<types::Brightness>().unwrap());
+		}
+	}
+
+	/// Answers the power state and the brightness, the first request only after `delay`.
+	struct SlowDevice {
+		pending: Vec<u8>,
+		delay: Option<Duration>,
+	}
+
+	impl Read for SlowDevice {
+		fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+			if self.pending.is_empty() {
+				return Err(io::ErrorKind::TimedOut.into());
+			}
+			let n = (&self.pending[..]).read(buf)?;
+			self.pending.drain(..n);
+			Ok(n)
+		}
+	}
+
+	impl Write for SlowDevice {
+		fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+			if let Some(delay) = self.delay.take() {
+				thread::sleep(delay);
+			}
+			match buf[4] {
+				0x20 => self.pending.extend_from_slice(&[0x6f, 0x37, 0x04, 0x02, 0x00, 0x20, 0x01, 127]),
+				_ => self.pending.extend_from_slice(&[0x6f, 0x37, 0x04, 0x02, 0x00, 0x30, 0x4b, 0x25]),
+			}
+			Ok(buf.len())
+		}
+		fn flush(&mut self) -> io::Result<()> { Ok(()) }
+	}
+
+	#[test]
+	fn server_discards_replies_to_queued_clients() {
+		// The queued client sends its next request before its late reply
+		// is ready, then only after it.
+		for pause in [50, 350].iter() {
+			let path = env::temp_dir().join(format!("c5517h-daemon-queued-{}.sock", pause));
+			let device = SlowDevice{pending: Vec::new(), delay: Some(Duration::from_millis(400))};
+			let server = Arc::new(Server::bind(&path, Box::new(device), Duration::from_millis(1000)).unwrap());
+			let s = server.clone();
+			thread::spawn(move || s.run());
+
+			let p = path.clone();
+			let slow = thread::spawn(move || {
+				let mut m = Monitor::new("slow", Box::new(Client::connect(p, Duration::from_millis(1000)).unwrap()));
+				assert_eq!(types::PowerState::On, m.get::<types::PowerState>().unwrap());
+			});
+
+			thread::sleep(Duration::from_millis(30));
+			let mut m = Monitor::new("queued", Box::new(Client::connect(&path, Duration::from_millis(200)).unwrap()));
+			assert!(m.get::<types::PowerState>().is_err());
+			thread::sleep(Duration::from_millis(*pause));
+			assert_eq!(types::Brightness::from(75), m.get::<types::Brightness>().unwrap());
+
+			slow.join().unwrap();
+		}
+	}
+}
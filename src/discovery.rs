@@ -0,0 +1,146 @@
+use std::fs;
+use std::io::{Read, Write};
+use std::thread;
+use std::time::Duration;
+use std::collections::BTreeMap;
+
+use serialport;
+use serialport::SerialPortType;
+
+use port;
+use lock;
+use lock::LockPolicy;
+use protocol::types;
+use protocol::command::Get;
+use protocol::transaction::stream_transaction;
+
+pub use port::{Error, Result};
+
+const SERIAL_BY_ID : &str = "/dev/serial/by-id";
+const MODEL : &str = "C5517H";
+
+/// A serial port that may have a monitor attached.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Candidate {
+	pub port: String,
+	/// Stable `/dev/serial/by-id` link pointing to `port`, if any.
+	pub by_id: Option<String>,
+	/// Serial number of the USB-serial adapter, if any.
+	pub usb_serial_number: Option<String>,
+}
+
+/// What a monitor told about itself while being probed.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Identity {
+	pub power_state: String,
+	pub monitor_name: String,
+	pub serial_number: Option<String>,
+}
+
+impl Identity {
+	pub fn is_c5517h(&self) -> bool {
+		self.monitor_name.contains(MODEL)
+	}
+}
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+	#[serde(flatten)]
+	pub candidate: Candidate,
+	pub found: bool,
+	pub identity: Option<Identity>,
+	pub error: Option<String>,
+}
+
+/// Lists the ports known to the system together with `/dev/serial/by-id` links.
+pub fn candidate_ports() -> Vec<Candidate> {
+	let mut ports = BTreeMap::new();
+
+	for info in serialport::available_ports().unwrap_or_default() {
+		let usb_serial_number = match info.port_type {
+			SerialPortType::UsbPort(usb) => usb.serial_number,
+			_ => None,
+		};
+		ports.insert(info.port_name.clone(), Candidate{port: info.port_name, by_id: None, usb_serial_number});
+	}
+
+	if let Ok(entries) = fs::read_dir(SERIAL_BY_ID) {
+		for link in entries.filter_map(|x| x.ok()).map(|x| x.path()) {
+			let target = match fs::canonicalize(&link) {
+				Ok(target) => target.to_string_lossy().into_owned(),
+				Err(_) => continue,
+			};
+			ports.entry(target.clone())
+				.or_insert_with(|| Candidate{port: target, by_id: None, usb_serial_number: None})
+				.by_id = Some(link.to_string_lossy().into_owned());
+		}
+	}
+
+	ports.into_values().collect()
+}
+
+/// Asks whatever is attached to `port` for its power state, name and serial number.
+///
+/// Ports locked by other processes are not waited for.
+pub fn probe(port: &str, timeout: Duration) -> Result<Identity> {
+	let mut port = lock::open(port, &port::settings(timeout), &LockPolicy::default()).map_err(Error::OpenError)?;
+	identify(&mut port)
+}
+
+/// Same as `probe` over an already open line.
+///
+/// The serial number is optional since not every firmware answers it.
+pub fn identify<S : Read + Write>(line: &mut S) -> Result<Identity> {
+	let power_state : types::PowerState = stream_transaction(&Get::<types::PowerState>::new(), line)
+		.map_err(Error::TransactionError)?;
+	let monitor_name : types::MonitorName = stream_transaction(&Get::<types::MonitorName>::new(), line)
+		.map_err(Error::TransactionError)?;
+	let serial_number = stream_transaction::<types::SerialNumber, _, _>(&Get::<types::SerialNumber>::new(), line)
+		.ok()
+		.map(String::from);
+
+	Ok(Identity{
+		power_state: format!("{:?}", power_state),
+		monitor_name: String::from(monitor_name),
+		serial_number,
+	})
+}
+
+/// Probes every candidate port in parallel.
+pub fn discover(timeout: Duration) -> Vec<Report> {
+	let probes : Vec<_> = candidate_ports().into_iter().map(|candidate| {
+		let port = candidate.port.clone();
+		(candidate, thread::spawn(move || probe(&port, timeout)))
+	}).collect();
+
+	probes.into_iter().map(|(candidate, handle)| {
+		match handle.join().expect("probe thread panicked") {
+			Ok(identity) => Report{candidate, found: identity.is_c5517h(), identity: Some(identity), error: None},
+			Err(err) => Report{candidate, found: false, identity: None, error: Some(err.to_string())},
+		}
+	}).collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use discovery::identify;
+	use port::Error;
+	use simulator::Simulator;
+
+	#[test]
+	fn identify_answering_and_silent() {
+		let mut answering = Simulator::new();
+		let identity = identify(&mut answering).unwrap();
+		assert_eq!("On", identity.power_state);
+		assert_eq!("DELL C5517H", identity.monitor_name);
+		assert_eq!(Some(String::from("CN0ABC123456")), identity.serial_number);
+		assert!(identity.is_c5517h());
+
+		let mut silent = Simulator::new();
+		silent.set_responding(false);
+		match identify(&mut silent) {
+			Err(Error::TransactionError(_)) => (),
+			other => panic!("unexpected {:?}", other),
+		}
+	}
+}
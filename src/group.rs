@@ -0,0 +1,178 @@
+//! The same operation on several monitors at once, one thread per monitor.
+
+use std;
+use std::fmt;
+use std::error;
+use std::thread;
+
+use config::{Config, MonitorConfig};
+use monitor;
+use monitor::{Monitor, Opener};
+
+#[derive(Debug)]
+pub enum Error<E> {
+	OpenError(monitor::Error),
+	OperationError(E),
+}
+
+impl<E : fmt::Display> fmt::Display for Error<E> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Error::OpenError(ref open_error) => write!(f, "{}", open_error),
+			Error::OperationError(ref operation_error) => write!(f, "{}", operation_error),
+		}
+	}
+}
+
+impl<E : error::Error + 'static> error::Error for Error<E> {
+	fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+		match self {
+			Error::OpenError(ref open_error) => Some(open_error),
+			Error::OperationError(ref operation_error) => Some(operation_error),
+		}
+	}
+}
+
+/// Outcome of an operation on every member of a group, in member order.
+#[derive(Debug)]
+pub struct Report<T, E> {
+	pub results: Vec<(String, std::result::Result<T, Error<E>>)>,
+}
+
+impl<T, E> Report<T, E> {
+	pub fn succeeded(&self) -> usize {
+		self.results.iter().filter(|&(_, x)| x.is_ok()).count()
+	}
+
+	pub fn failed(&self) -> usize {
+		self.results.len() - self.succeeded()
+	}
+
+	pub fn is_success(&self) -> bool {
+		self.failed() == 0
+	}
+
+	/// Some members failed while others did not.
+	pub fn is_partial_failure(&self) -> bool {
+		self.failed() > 0 && self.succeeded() > 0
+	}
+}
+
+pub struct Group {
+	members: Vec<(String, MonitorConfig)>,
+}
+
+impl Group {
+	/// Resolves the group `name`, its members may be monitor names or aliases.
+	pub fn from_config(config: &Config, name: &str) -> monitor::Result<Group> {
+		let members = config.group(name).ok_or_else(|| monitor::Error::UnknownGroup(String::from(name)))?;
+
+		let members = members.iter().map(|member| {
+			config.monitor(member)
+				.map(|(name, x)| (String::from(name), x.clone()))
+				.ok_or_else(|| monitor::Error::UnknownMonitor(member.clone()))
+		}).collect::<monitor::Result<Vec<_>>>()?;
+
+		Ok(Group{members})
+	}
+
+	/// Resolves a group name, or a monitor name or alias as a group of one.
+	pub fn resolve(config: &Config, name: &str) -> monitor::Result<Group> {
+		match config.monitor(name) {
+			Some((name, monitor)) => Ok(Group{members: vec![(String::from(name), monitor.clone())]}),
+			None => Group::from_config(config, name),
+		}
+	}
+
+	/// Members of all the groups or monitors in `names`, each monitor once.
+	pub fn resolve_all(config: &Config, names: &[String]) -> monitor::Result<Group> {
+		let mut members : Vec<(String, MonitorConfig)> = Vec::new();
+
+		for name in names {
+			for member in Group::resolve(config, name)?.members {
+				if !members.iter().any(|x| x.0 == member.0) {
+					members.push(member);
+				}
+			}
+		}
+
+		Ok(Group{members})
+	}
+
+	pub fn names(&self) -> Vec<&str> {
+		self.members.iter().map(|(name, _)| name.as_str()).collect()
+	}
+
+	/// Opens every member and applies `f` to it concurrently.
+	pub fn run<T, E, F>(&self, config: &Config, f: F) -> Report<T, E>
+		where T : Send, E : Send, F : Fn(&mut Monitor) -> std::result::Result<T, E> + Sync {
+		self.run_with(config, &(Box::new(Monitor::open) as Opener), f)
+	}
+
+	/// Same as `run`, opening the members with `opener`.
+	pub fn run_with<T, E, F>(&self, config: &Config, opener: &Opener, f: F) -> Report<T, E>
+		where T : Send, E : Send, F : Fn(&mut Monitor) -> std::result::Result<T, E> + Sync {
+		let f = &f;
+
+		let results = thread::scope(|scope| {
+			let handles : Vec<_> = self.members.iter().map(|(name, monitor)| {
+				scope.spawn(move || {
+					let mut monitor = opener(name, monitor, config).map_err(Error::OpenError)?;
+					f(&mut monitor).map_err(Error::OperationError)
+				})
+			}).collect();
+
+			handles.into_iter().map(|x| x.join().expect("group member thread panicked")).collect::<Vec<_>>()
+		});
+
+		Report{results: self.members.iter().map(|(name, _)| name.clone()).zip(results).collect()}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::{Read, Write};
+	use std::net::TcpListener;
+	use std::thread;
+
+	use config::{Config, MonitorConfig};
+	use group::Group;
+	use protocol::types;
+
+	#[test]
+	fn group_unknown_member() {
+		let mut config = Config::default();
+		config.monitors.insert(String::from("lobby"), MonitorConfig::new("tcp:127.0.0.1:1".parse().unwrap()));
+		config.groups.insert(String::from("room12"), vec![String::from("lobby"), String::from("lounge")]);
+
+		assert!(Group::from_config(&config, "room12").is_err());
+		assert!(Group::from_config(&config, "room13").is_err());
+	}
+
+	#[test]
+	fn group_partial_failure() {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let address = listener.local_addr().unwrap();
+		let device = thread::spawn(move || {
+			let (mut stream, _) = listener.accept().unwrap();
+			let mut request = [0 as u8; 6];
+			stream.read_exact(&mut request).unwrap();
+			stream.write_all(&[0x6f, 0x37, 0x04, 0x02, 0x00, 0x20, 0x01, 127]).unwrap();
+		});
+
+		let mut config = Config::default();
+		config.monitors.insert(String::from("lobby"), MonitorConfig::new(format!("tcp:{}", address).parse().unwrap()));
+		config.monitors.insert(String::from("atrium"), MonitorConfig::new("/nonexistent/tty".parse().unwrap()));
+		config.groups.insert(String::from("room12"), vec![String::from("lobby"), String::from("atrium")]);
+
+		let group = Group::from_config(&config, "room12").unwrap();
+		let report = group.run(&config, |m| m.get::<types::PowerState>());
+		device.join().unwrap();
+
+		assert_eq!(vec!["lobby", "atrium"], group.names());
+		assert_eq!("lobby", report.results[0].0);
+		assert_eq!(types::PowerState::On, *report.results[0].1.as_ref().unwrap());
+		assert!(report.results[1].1.is_err());
+		assert!(report.is_partial_failure());
+	}
+}
@@ -0,0 +1,555 @@
+//! Commands, property writes and webhooks run on watcher events.
+//!
+//! A hook triggers either on a property change, optionally from and to
+//! given values, or on a monitor failing to answer. Failures trigger once
+//! until the monitor answers again. Every run gets the event as `C5517H_*`
+//! environment variables and as JSON on the standard input, or as the
+//! body of the webhook.
+
+use std;
+use std::io;
+use std::fmt;
+use std::error;
+use std::thread;
+use std::io::Write;
+use std::process;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc;
+use std::sync::mpsc::{Receiver, Sender, SyncSender};
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::{Duration, Instant};
+
+use chrono::Local;
+use serde_json;
+
+use config::Config;
+use group::Group;
+use http;
+use monitor;
+use monitor::{Monitor, Opener};
+use property;
+use protocol::decoder;
+use protocol::reply::ResultCode;
+use protocol::transaction;
+use schedule::Action;
+use watch;
+
+/// Failures a hook can trigger on.
+pub const ERRORS : [&str; 4] = ["not-connected", "timeout", "no-reply", "any"];
+
+#[derive(Debug)]
+pub enum Error {
+	UnknownTarget(monitor::Error),
+	UnknownProperty(String),
+	NotWritable(String),
+	InvalidHook(String, &'static str),
+	OpenError(monitor::Error),
+	SetError(property::Error),
+	CommandError(io::Error),
+	CommandFailed(process::ExitStatus),
+	CommandTimeout,
+	WebhookError(io::Error),
+	Busy,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Error::UnknownTarget(ref monitor_error) =>
+				write!(f, "{}", monitor_error),
+			Error::UnknownProperty(ref name) =>
+				write!(f, "unknown property: {}", name),
+			Error::NotWritable(ref name) =>
+				write!(f, "{} is not writable", name),
+			Error::InvalidHook(ref name, reason) =>
+				write!(f, "{}: {}", name, reason),
+			Error::OpenError(ref monitor_error) =>
+				write!(f, "{}", monitor_error),
+			Error::SetError(ref property_error) =>
+				write!(f, "{}", property_error),
+			Error::CommandError(ref io_error) =>
+				write!(f, "command error: {}", io_error),
+			Error::CommandFailed(status) =>
+				write!(f, "command failed: {}", status),
+			Error::CommandTimeout =>
+				write!(f, "command timed out"),
+			Error::WebhookError(ref io_error) =>
+				write!(f, "webhook error: {}", io_error),
+			Error::Busy =>
+				write!(f, "too many runs waiting, event dropped"),
+		}
+	}
+}
+
+impl error::Error for Error {
+	fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+		match self {
+			Error::UnknownTarget(ref monitor_error) | Error::OpenError(ref monitor_error) => Some(monitor_error),
+			Error::SetError(ref property_error) => Some(property_error),
+			Error::CommandError(ref io_error) | Error::WebhookError(ref io_error) => Some(io_error),
+			_ => None,
+		}
+	}
+}
+
+fn default_timeout_ms() -> u64 { 10000 }
+
+fn default_concurrency() -> usize { 1 }
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Hook {
+	pub name: String,
+	/// Monitor names, aliases or group names, every monitor when empty.
+	#[serde(default)]
+	pub targets: Vec<String>,
+	/// Property whose change triggers the hook.
+	#[serde(default)]
+	pub property: Option<String>,
+	/// Values before and after the change, any when left out.
+	#[serde(default)]
+	pub from: Option<String>,
+	#[serde(default)]
+	pub to: Option<String>,
+	/// Failure triggering the hook instead, one of `ERRORS`.
+	#[serde(default)]
+	pub error: Option<String>,
+	/// Program and its arguments.
+	#[serde(default)]
+	pub command: Vec<String>,
+	/// Writes on the monitor of the event.
+	#[serde(default)]
+	pub set: Vec<Action>,
+	/// `http://` URL the event is posted to.
+	#[serde(default)]
+	pub webhook: Option<String>,
+	/// Time the command and the webhook are given.
+	#[serde(default = "default_timeout_ms")]
+	pub timeout_ms: u64,
+	/// Runs of the hook at the same time, as many more wait for their turn
+	/// and further events are dropped.
+	#[serde(default = "default_concurrency")]
+	pub concurrency: usize,
+}
+
+impl Hook {
+	fn check(&self, config: &Config) -> Result<()> {
+		let invalid = |reason| Err(Error::InvalidHook(self.name.clone(), reason));
+
+		Group::resolve_all(config, &self.targets).map_err(Error::UnknownTarget)?;
+		match (&self.property, &self.error) {
+			(Some(name), None) => {
+				property::find(name).filter(|x| x.is_readable()).ok_or_else(|| Error::UnknownProperty(name.clone()))?;
+			},
+			(None, Some(error)) if ERRORS.contains(&error.as_str()) => (),
+			(None, Some(_)) => return invalid("unknown error, expected not-connected, timeout, no-reply or any"),
+			_ => return invalid("expected either a property or an error"),
+		}
+		for action in &self.set {
+			let property = property::find(&action.property).ok_or_else(|| Error::UnknownProperty(action.property.clone()))?;
+			if !property.is_writable() {
+				return Err(Error::NotWritable(action.property.clone()));
+			}
+		}
+		if self.command.is_empty() && self.set.is_empty() && self.webhook.is_none() {
+			return invalid("nothing to run");
+		}
+		if self.concurrency == 0 {
+			return invalid("concurrency must be positive");
+		}
+		Ok(())
+	}
+
+	fn matches_change(&self, event: &watch::Event) -> bool {
+		let old = match event.old {
+			Some(ref old) => old,
+			None => return false,
+		};
+		self.property.as_ref().is_some_and(|x| x == event.property)
+			&& self.from.as_ref().is_none_or(|x| old.matches(x))
+			&& self.to.as_ref().is_none_or(|x| event.new.matches(x))
+	}
+
+	fn matches_error(&self, kind: &str) -> bool {
+		self.error.as_ref().is_some_and(|x| x == "any" || x == kind)
+	}
+}
+
+/// Kind of a failed poll, as named in `ERRORS`: `no-reply` covers a
+/// silent monitor and a broken link alike.
+pub fn error_kind(error: &property::Error) -> &'static str {
+	match error {
+		property::Error::TransactionError(transaction::Error::DecodeError(decoder::Error::DeviceError(ResultCode::NotConnected), _)) => "not-connected",
+		property::Error::TransactionError(transaction::Error::DecodeError(decoder::Error::DeviceError(ResultCode::Timeout), _)) => "timeout",
+		property::Error::TransactionError(transaction::Error::ReadError(_)) |
+		property::Error::TransactionError(transaction::Error::WriteError(_)) => "no-reply",
+		_ => "other",
+	}
+}
+
+/// Result of one run of a hook.
+#[derive(Debug)]
+pub struct Outcome {
+	pub hook: String,
+	pub monitor: String,
+	pub result: Result<()>,
+}
+
+/// Event waiting for a worker of a hook.
+struct Job {
+	monitor: String,
+	payload: serde_json::Value,
+	outcomes: Sender<Outcome>,
+}
+
+struct Entry {
+	hook: Arc<Hook>,
+	members: Vec<String>,
+	jobs: SyncSender<Job>,
+}
+
+/// What the workers of every hook share.
+struct Shared {
+	config: Config,
+	opener: Opener,
+	monitors: Mutex<BTreeMap<String, Arc<Mutex<Monitor>>>>,
+}
+
+impl Shared {
+	fn monitor(&self, name: &str) -> Result<Arc<Mutex<Monitor>>> {
+		let (name, config) = self.config.monitor(name)
+			.ok_or_else(|| Error::UnknownTarget(monitor::Error::UnknownMonitor(String::from(name))))?;
+		let mut monitors = self.monitors.lock().unwrap();
+
+		if let Some(monitor) = monitors.get(name) {
+			return Ok(monitor.clone());
+		}
+
+		let monitor = (self.opener)(name, config, &self.config).map_err(Error::OpenError)?;
+		let monitor = Arc::new(Mutex::new(monitor));
+		monitors.insert(String::from(name), monitor.clone());
+		Ok(monitor)
+	}
+}
+
+pub struct Dispatcher {
+	entries: Vec<Entry>,
+	failing: BTreeSet<String>,
+	shared: Arc<Shared>,
+}
+
+fn run_command(hook: &Hook, event: &serde_json::Value) -> Result<()> {
+	let variable = |name| match event[name] {
+		serde_json::Value::Null => String::new(),
+		serde_json::Value::String(ref x) => x.clone(),
+		ref x => x.to_string(),
+	};
+	let mut child = Command::new(&hook.command[0])
+		.args(&hook.command[1..])
+		.envs(["hook", "monitor", "property", "old", "new", "error", "time"].iter()
+			.map(|&name| (format!("C5517H_{}", name.to_ascii_uppercase()), variable(name))))
+		.stdin(Stdio::piped())
+		.stdout(Stdio::null())
+		.spawn()
+		.map_err(Error::CommandError)?;
+
+	// The command may well not read it
+	let _ = child.stdin.take().unwrap().write_all(event.to_string().as_bytes());
+
+	let deadline = Instant::now() + Duration::from_millis(hook.timeout_ms);
+	loop {
+		match child.try_wait().map_err(Error::CommandError)? {
+			Some(status) if status.success() => return Ok(()),
+			Some(status) => return Err(Error::CommandFailed(status)),
+			None if Instant::now() >= deadline => {
+				let _ = child.kill();
+				let _ = child.wait();
+				return Err(Error::CommandTimeout);
+			},
+			None => thread::sleep(Duration::from_millis(10)),
+		}
+	}
+}
+
+impl Dispatcher {
+	pub fn new(config: Config, hooks: Vec<Hook>) -> Result<Dispatcher> {
+		Dispatcher::with_opener(config, hooks, Box::new(Monitor::open))
+	}
+
+	/// Checks that every hook has one trigger, something to run, and
+	/// existing targets and properties.
+	pub fn with_opener(config: Config, hooks: Vec<Hook>, opener: Opener) -> Result<Dispatcher> {
+		for hook in &hooks {
+			hook.check(&config)?;
+		}
+
+		let shared = Arc::new(Shared{config, opener, monitors: Mutex::new(BTreeMap::new())});
+		let mut entries = Vec::new();
+		for hook in hooks {
+			let members = match hook.targets.is_empty() {
+				true => shared.config.monitors.keys().cloned().collect(),
+				false => Group::resolve_all(&shared.config, &hook.targets).unwrap().names().into_iter().map(String::from).collect(),
+			};
+			let (jobs, receiver) = mpsc::sync_channel(hook.concurrency);
+			let (hook, receiver) = (Arc::new(hook), Arc::new(Mutex::new(receiver)));
+			for _ in 0..hook.concurrency {
+				let (hook, receiver, shared) = (hook.clone(), receiver.clone(), shared.clone());
+				thread::spawn(move || Dispatcher::work(&hook, &receiver, &shared));
+			}
+			entries.push(Entry{hook, members, jobs});
+		}
+		Ok(Dispatcher{entries, failing: BTreeSet::new(), shared})
+	}
+
+	/// The monitor named `name`, opened once for the hooks writing to it
+	/// and for whatever watches it, since a serial port opens only once.
+	pub fn monitor(&self, name: &str) -> Result<Arc<Mutex<Monitor>>> {
+		self.shared.monitor(name)
+	}
+
+	/// Monitors some hook applies to.
+	pub fn monitors(&self) -> Vec<&str> {
+		let monitors : BTreeSet<_> = self.entries.iter().flat_map(|x| x.members.iter().map(|x| x.as_str())).collect();
+		monitors.into_iter().collect()
+	}
+
+	/// Properties the hooks watch, the power alone when they only watch failures.
+	pub fn properties(&self) -> Vec<&'static property::Property> {
+		let names : BTreeSet<_> = self.entries.iter().filter_map(|x| x.hook.property.as_ref()).collect();
+		match names.is_empty() {
+			true => vec![property::find("power").unwrap()],
+			false => names.into_iter().map(|x| property::find(x).unwrap()).collect(),
+		}
+	}
+
+	/// Queues the hooks matching `event` for their workers, which send the
+	/// outcomes to `outcomes`. A hook with a full queue fails at once.
+	pub fn dispatch(&mut self, event: &watch::Result<watch::Event>, outcomes: &Sender<Outcome>) {
+		let time = Local::now();
+		let (monitor, matches, payload) = match event {
+			Ok(event) => {
+				self.failing.remove(&event.monitor);
+				let payload = json!({
+					"monitor": event.monitor,
+					"property": event.property,
+					"old": event.old,
+					"new": event.new,
+					"time": event.time,
+				});
+				(&event.monitor, self.entries.iter().filter(|x| x.hook.matches_change(event)).collect::<Vec<_>>(), payload)
+			},
+			Err(watch::Error::PollError(monitor, property, error)) => {
+				if !self.failing.insert(monitor.clone()) {
+					return;
+				}
+				let kind = error_kind(error);
+				let payload = json!({
+					"monitor": monitor,
+					"property": property,
+					"error": kind,
+					"message": error.to_string(),
+					"time": time,
+				});
+				(monitor, self.entries.iter().filter(|x| x.hook.matches_error(kind)).collect(), payload)
+			},
+		};
+
+		for entry in matches.into_iter().filter(|x| x.members.contains(monitor)) {
+			let mut payload = payload.clone();
+			payload["hook"] = json!(entry.hook.name);
+
+			let job = Job{monitor: monitor.clone(), payload, outcomes: outcomes.clone()};
+			if let Err(mpsc::TrySendError::Full(job)) = entry.jobs.try_send(job) {
+				let _ = outcomes.send(Outcome{hook: entry.hook.name.clone(), monitor: job.monitor, result: Err(Error::Busy)});
+			}
+		}
+	}
+
+	/// Runs the queued events of `hook` until the dispatcher is dropped.
+	fn work(hook: &Hook, jobs: &Mutex<Receiver<Job>>, shared: &Shared) {
+		loop {
+			let job = jobs.lock().unwrap().recv();
+			let job = match job {
+				Ok(job) => job,
+				Err(_) => return,
+			};
+			let result = Dispatcher::run(hook, &job.monitor, &job.payload, shared);
+			let _ = job.outcomes.send(Outcome{hook: hook.name.clone(), monitor: job.monitor, result});
+		}
+	}
+
+	/// Writes, then runs the command, then calls the webhook, stopping at the first failure.
+	fn run(hook: &Hook, monitor: &str, payload: &serde_json::Value, shared: &Shared) -> Result<()> {
+		if !hook.set.is_empty() {
+			let monitor = shared.monitor(monitor)?;
+			let mut m = monitor.lock().unwrap();
+			for action in &hook.set {
+				property::find(&action.property).unwrap().set(&mut m, &action.value).map_err(Error::SetError)?;
+			}
+		}
+		if !hook.command.is_empty() {
+			run_command(hook, payload)?;
+		}
+		if let Some(ref url) = hook.webhook {
+			http::post_json(url, payload, Duration::from_millis(hook.timeout_ms)).map_err(Error::WebhookError)?;
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::fs;
+	use std::env;
+	use std::process;
+	use std::io::BufReader;
+	use std::net::TcpListener;
+	use std::thread;
+	use std::sync::mpsc;
+	use std::sync::atomic::{AtomicBool, Ordering};
+	use std::time::Duration;
+
+	use chrono::Local;
+
+	use hooks::{Dispatcher, Error, Hook};
+	use http::{Request, Response};
+	use monitor;
+	use property;
+	use property::Value;
+	use protocol::decoder;
+	use protocol::reply::ResultCode;
+	use protocol::transaction;
+	use schedule::Action;
+	use simulator;
+	use simulator::Simulator;
+	use watch;
+	use watch::Watcher;
+
+	fn hook(name: &str) -> Hook {
+		Hook{
+			name: String::from(name),
+			targets: Vec::new(),
+			property: None,
+			from: None,
+			to: None,
+			error: None,
+			command: Vec::new(),
+			set: Vec::new(),
+			webhook: None,
+			timeout_ms: 1000,
+			concurrency: 1,
+		}
+	}
+
+	fn change(property: &'static str, old: Option<&'static str>, new: &'static str) -> watch::Result<watch::Event> {
+		Ok(watch::Event{
+			monitor: String::from("lobby"),
+			property,
+			old: old.map(Value::Choice),
+			new: Value::Choice(new),
+			time: Local::now(),
+		})
+	}
+
+	#[test]
+	fn hooks_dispatch() {
+		let simulator = Simulator::new();
+		let (config, opener) = simulator::fixture(&[("lobby", &simulator)]);
+
+		let output = env::temp_dir().join(format!("c5517h-hooks-{}", process::id()));
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+
+		let hooks = vec![
+			Hook{property: Some(String::from("power")), to: Some(String::from("off")),
+				command: vec![String::from("sh"), String::from("-c"), format!("cat > {0}; echo \" $C5517H_OLD\" >> {0}", output.display())],
+				..hook("off")},
+			Hook{property: Some(String::from("input")), to: Some(String::from("DP1")),
+				set: vec![Action::new("preset", "custom-color")], ..hook("cad")},
+			Hook{error: Some(String::from("not-connected")),
+				webhook: Some(format!("http://{}/gone", listener.local_addr().unwrap())), ..hook("gone")},
+			Hook{property: Some(String::from("power")), command: vec![String::from("sleep"), String::from("5")],
+				timeout_ms: 50, ..hook("slow")},
+		];
+		let mut dispatcher = Dispatcher::with_opener(config, hooks, opener).unwrap();
+		assert_eq!(vec!["lobby"], dispatcher.monitors());
+		assert_eq!(vec!["input", "power"], dispatcher.properties().iter().map(|x| x.name()).collect::<Vec<_>>());
+		let (sender, receiver) = mpsc::channel();
+
+		// The first value of a property is not a change
+		dispatcher.dispatch(&change("power", None, "off"), &sender);
+		dispatcher.dispatch(&change("power", Some("on"), "off"), &sender);
+		let mut outcomes : Vec<_> = (0..2).map(|_| receiver.recv().unwrap()).collect();
+		outcomes.sort_by(|a, b| a.hook.cmp(&b.hook));
+		assert!(outcomes[0].result.is_ok());
+		assert!(matches!(outcomes[1].result, Err(Error::CommandTimeout)));
+		let content = fs::read_to_string(&output).unwrap();
+		fs::remove_file(&output).unwrap();
+		assert!(content.starts_with("{\"hook\":\"off\""), "{}", content);
+		assert!(content.ends_with(" on\n"), "{}", content);
+
+		dispatcher.dispatch(&change("input", Some("hdmi1"), "dp1"), &sender);
+		assert!(receiver.recv().unwrap().result.is_ok());
+		assert_eq!(Some(vec![0x80, 0, 0, 0]), simulator.value(0x48));
+
+		// Only the first of consecutive failures triggers
+		let failure = || Err(watch::Error::PollError(String::from("lobby"), "power",
+			property::Error::TransactionError(transaction::Error::DecodeError(decoder::Error::DeviceError(ResultCode::NotConnected), Vec::new()))));
+		dispatcher.dispatch(&failure(), &sender);
+		dispatcher.dispatch(&failure(), &sender);
+		let (mut stream, _) = listener.accept().unwrap();
+		let request = Request::read_from(&mut BufReader::new(&stream)).unwrap();
+		Response::empty(204).write_to(&mut stream).unwrap();
+		let body : serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+		assert_eq!(("gone", "not-connected"), (body["hook"].as_str().unwrap(), body["error"].as_str().unwrap()));
+		assert_eq!("gone", receiver.recv().unwrap().hook);
+		assert!(receiver.recv_timeout(Duration::from_millis(100)).is_err());
+	}
+
+	#[test]
+	fn hooks_dispatch_busy() {
+		let (config, opener) = simulator::fixture(&[("lobby", &Simulator::new())]);
+		let hooks = vec![
+			Hook{property: Some(String::from("power")), command: vec![String::from("sleep"), String::from("5")],
+				timeout_ms: 200, ..hook("slow")},
+		];
+		let mut dispatcher = Dispatcher::with_opener(config, hooks, opener).unwrap();
+		let (sender, receiver) = mpsc::channel();
+
+		// One event runs, one waits and the third is dropped
+		dispatcher.dispatch(&change("power", Some("on"), "off"), &sender);
+		thread::sleep(Duration::from_millis(50));
+		dispatcher.dispatch(&change("power", Some("off"), "on"), &sender);
+		dispatcher.dispatch(&change("power", Some("on"), "off"), &sender);
+		assert!(matches!(receiver.recv().unwrap().result, Err(Error::Busy)));
+		assert!(matches!(receiver.recv().unwrap().result, Err(Error::CommandTimeout)));
+		assert!(matches!(receiver.recv().unwrap().result, Err(Error::CommandTimeout)));
+		assert!(receiver.recv_timeout(Duration::from_millis(100)).is_err());
+	}
+
+	#[test]
+	fn hooks_share_monitors() {
+		let simulator = Simulator::new();
+		let (config, fixture) = simulator::fixture(&[("lobby", &simulator)]);
+
+		// Like a serial port, the monitor opens only once
+		let opened = AtomicBool::new(false);
+		let opener : monitor::Opener = Box::new(move |name, monitor, config| match opened.swap(true, Ordering::SeqCst) {
+			false => fixture(name, monitor, config),
+			true => Err(monitor::Error::UnknownMonitor(String::from(name))),
+		});
+		let hooks = vec![
+			Hook{property: Some(String::from("input")), to: Some(String::from("DP1")),
+				set: vec![Action::new("preset", "custom-color")], ..hook("cad")},
+		];
+		let mut dispatcher = Dispatcher::with_opener(config, hooks, opener).unwrap();
+		let mut watcher = Watcher::shared(dispatcher.monitor("lobby").unwrap(), &dispatcher.properties());
+		let (sender, receiver) = mpsc::channel();
+
+		assert!(watcher.next().unwrap().is_ok());
+		dispatcher.dispatch(&change("input", Some("hdmi1"), "dp1"), &sender);
+		assert!(receiver.recv().unwrap().result.is_ok());
+		assert_eq!(Some(vec![0x80, 0, 0, 0]), simulator.value(0x48));
+	}
+}
@@ -0,0 +1,250 @@
+//! Just enough HTTP/1.1 to serve a JSON API and to post to webhooks: one
+//! request per connection, bodies sized by `Content-Length`.
+
+use std::io;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use serde_json;
+
+const MAX_HEADERS : usize = 64;
+const MAX_BODY : usize = 64 * 1024;
+
+#[derive(Debug, PartialEq)]
+pub struct Request {
+	pub method: String,
+	pub path: String,
+	pub headers: Vec<(String, String)>,
+	pub body: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Response {
+	pub status: u16,
+	pub content_type: &'static str,
+	pub body: Vec<u8>,
+}
+
+fn invalid_data(message: &str) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn read_line<R: BufRead>(r: &mut R) -> io::Result<String> {
+	let mut line = String::new();
+	r.by_ref().take(8192).read_line(&mut line)?;
+	if !line.ends_with('\n') {
+		return Err(invalid_data("truncated request"));
+	}
+	Ok(String::from(line.trim_end()))
+}
+
+impl Request {
+	pub fn new(method: &str, path: &str, body: &[u8]) -> Request {
+		Request{method: String::from(method), path: String::from(path), headers: Vec::new(), body: body.to_vec()}
+	}
+
+	pub fn read_from<R: BufRead>(r: &mut R) -> io::Result<Request> {
+		let line = read_line(r)?;
+		let mut parts = line.split(' ');
+		let (method, path) = match (parts.next(), parts.next(), parts.next()) {
+			(Some(method), Some(path), Some(version)) if version.starts_with("HTTP/1.") => (method, path),
+			_ => return Err(invalid_data("malformed request line")),
+		};
+
+		let mut headers = Vec::new();
+		loop {
+			let line = read_line(r)?;
+			if line.is_empty() {
+				break;
+			}
+			if headers.len() == MAX_HEADERS {
+				return Err(invalid_data("too many headers"));
+			}
+			let colon = line.find(':').ok_or_else(|| invalid_data("malformed header"))?;
+			headers.push((line[..colon].trim().to_ascii_lowercase(), String::from(line[colon + 1..].trim())));
+		}
+
+		let mut request = Request{method: String::from(method), path: String::from(path), headers, body: Vec::new()};
+		let length = match request.header("content-length") {
+			Some(x) => x.parse::<usize>().map_err(|_| invalid_data("malformed content-length"))?,
+			None => 0,
+		};
+		if length > MAX_BODY {
+			return Err(invalid_data("body too large"));
+		}
+		request.body.resize(length, 0);
+		r.read_exact(&mut request.body)?;
+
+		Ok(request)
+	}
+
+	/// Value of the header `name`, which must be in lower case.
+	pub fn header(&self, name: &str) -> Option<&str> {
+		self.headers.iter().find(|(x, _)| x == name).map(|(_, value)| value.as_str())
+	}
+
+	/// Path split into its segments, query string dropped.
+	pub fn segments(&self) -> Vec<&str> {
+		let path = self.path.split('?').next().unwrap();
+		path.split('/').filter(|x| !x.is_empty()).collect()
+	}
+}
+
+fn reason(status: u16) -> &'static str {
+	match status {
+		200 => "OK",
+		204 => "No Content",
+		400 => "Bad Request",
+		404 => "Not Found",
+		405 => "Method Not Allowed",
+		422 => "Unprocessable Entity",
+		500 => "Internal Server Error",
+		502 => "Bad Gateway",
+		503 => "Service Unavailable",
+		504 => "Gateway Timeout",
+		_ => "",
+	}
+}
+
+impl Response {
+	pub fn json(status: u16, value: &serde_json::Value) -> Response {
+		Response{status, content_type: "application/json", body: value.to_string().into_bytes()}
+	}
+
+	pub fn text(status: u16, body: &str) -> Response {
+		Response{status, content_type: "text/plain; charset=utf-8", body: body.as_bytes().to_vec()}
+	}
+
+	pub fn empty(status: u16) -> Response {
+		Response{status, content_type: "", body: Vec::new()}
+	}
+
+	/// Writes the response at once, a client such as `post_json` may close
+	/// the connection as soon as it has the status line.
+	pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+		let mut buf = format!("HTTP/1.1 {} {}\r\n", self.status, reason(self.status)).into_bytes();
+		if !self.content_type.is_empty() {
+			write!(buf, "Content-Type: {}\r\n", self.content_type)?;
+		}
+		write!(buf, "Content-Length: {}\r\nConnection: close\r\n\r\n", self.body.len())?;
+		buf.extend_from_slice(&self.body);
+		w.write_all(&buf)?;
+		w.flush()
+	}
+}
+
+/// Serves every connection accepted on `listener` in its own thread.
+pub fn serve<F>(listener: TcpListener, handler: F) -> io::Result<()>
+	where F: Fn(&Request) -> Response + Send + Sync + 'static {
+	let handler = Arc::new(handler);
+
+	for stream in listener.incoming() {
+		let mut stream = match stream {
+			Ok(stream) => stream,
+			Err(_) => continue,
+		};
+		let handler = handler.clone();
+
+		thread::spawn(move || {
+			let _ = stream.set_read_timeout(Some(Duration::from_secs(10)));
+			let response = match Request::read_from(&mut BufReader::new(&stream)) {
+				Ok(request) => handler(&request),
+				Err(err) => Response::text(400, &err.to_string()),
+			};
+			let _ = response.write_to(&mut stream);
+		});
+	}
+
+	Ok(())
+}
+
+/// Host, port and path of an `http://` URL.
+fn split_url(url: &str) -> io::Result<(&str, u16, &str)> {
+	let rest = url.strip_prefix("http://").ok_or_else(|| invalid_data("only http:// URLs are supported"))?;
+	let (authority, path) = match rest.find('/') {
+		Some(i) => (&rest[..i], &rest[i..]),
+		None => (rest, "/"),
+	};
+	match authority.rfind(':') {
+		Some(i) => {
+			let port = authority[i + 1..].parse().map_err(|_| invalid_data("malformed port"))?;
+			Ok((&authority[..i], port, path))
+		},
+		None => Ok((authority, 80, path)),
+	}
+}
+
+/// Posts `value` to `url`, a status other than 2xx is an error.
+pub fn post_json(url: &str, value: &serde_json::Value, timeout: Duration) -> io::Result<()> {
+	let (host, port, path) = split_url(url)?;
+	let address = (host, port).to_socket_addrs()?.next().ok_or_else(|| invalid_data("no address"))?;
+	let mut stream = TcpStream::connect_timeout(&address, timeout)?;
+	stream.set_read_timeout(Some(timeout))?;
+	stream.set_write_timeout(Some(timeout))?;
+
+	let body = value.to_string();
+	write!(stream, "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+		path, host, body.len(), body)?;
+	stream.flush()?;
+
+	let line = read_line(&mut BufReader::new(&stream))?;
+	let status : u16 = line.split(' ').nth(1).and_then(|x| x.parse().ok()).ok_or_else(|| invalid_data("malformed status line"))?;
+	match status {
+		200..=299 => Ok(()),
+		_ => Err(io::Error::other(format!("{}: HTTP status {}", url, status))),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::BufReader;
+	use std::net::TcpListener;
+	use std::thread;
+	use std::time::Duration;
+
+	use http;
+	use http::{Request, Response};
+
+	#[test]
+	fn read_request() {
+		let mut input : &[u8] = b"PUT /monitors/lobby/input?x=1 HTTP/1.1\r\nHost: x\r\nContent-Length: 17\r\n\r\n{\"value\":\"HDMI2\"}";
+		let request = Request::read_from(&mut input).unwrap();
+
+		assert_eq!("PUT", request.method);
+		assert_eq!(vec!["monitors", "lobby", "input"], request.segments());
+		assert_eq!(Some("x"), request.header("host"));
+		assert_eq!(b"{\"value\":\"HDMI2\"}".to_vec(), request.body);
+
+		let mut input : &[u8] = b"GET / HTTP/1.1\r\nHost";
+		assert!(Request::read_from(&mut input).is_err());
+	}
+
+	#[test]
+	fn write_response() {
+		let mut output = Vec::new();
+		Response::empty(204).write_to(&mut output).unwrap();
+		assert_eq!(b"HTTP/1.1 204 No Content\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_vec(), output);
+	}
+
+	#[test]
+	fn post_json() {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let url = format!("http://{}/hooks/lobby", listener.local_addr().unwrap());
+		let server = thread::spawn(move || {
+			let (mut stream, _) = listener.accept().unwrap();
+			let request = Request::read_from(&mut BufReader::new(&stream)).unwrap();
+			Response::empty(204).write_to(&mut stream).unwrap();
+			request
+		});
+
+		http::post_json(&url, &json!({"power": "off"}), Duration::from_secs(1)).unwrap();
+		let request = server.join().unwrap();
+		assert_eq!(("POST", "/hooks/lobby"), (request.method.as_str(), request.path.as_str()));
+		assert_eq!(b"{\"power\":\"off\"}".to_vec(), request.body);
+
+		assert!(http::post_json("https://example.com/", &json!({}), Duration::from_secs(1)).is_err());
+	}
+}
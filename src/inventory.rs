@@ -0,0 +1,247 @@
+//! Inventory of the configured monitors, for asset management.
+//!
+//! Every monitor is read concurrently. One that cannot be opened or does
+//! not report its power state is listed as unreachable, other values it
+//! fails to report are left empty.
+
+use std::fmt;
+use std::str::FromStr;
+use std::collections::BTreeMap;
+
+use config::Config;
+use group::Group;
+use monitor::{Monitor, Opener};
+use property;
+
+const COLUMNS : [&str; 10] = ["monitor", "reachable", "serial", "model", "firmware", "backlight_hours", "input", "power", "flags", "error"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Flag {
+	/// Another monitor reports the same serial number.
+	DuplicateSerial,
+	/// The firmware differs from the one most of the fleet runs.
+	FirmwareOutlier,
+}
+
+impl fmt::Display for Flag {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(match self {
+			Flag::DuplicateSerial => "duplicate-serial",
+			Flag::FirmwareOutlier => "firmware-outlier",
+		})
+	}
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Entry {
+	pub monitor: String,
+	pub reachable: bool,
+	pub serial: Option<String>,
+	pub model: Option<String>,
+	pub firmware: Option<String>,
+	pub backlight_hours: Option<u16>,
+	pub input: Option<String>,
+	pub power: Option<String>,
+	pub flags: Vec<Flag>,
+	/// Why the monitor is unreachable.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub error: Option<String>,
+}
+
+impl Entry {
+	fn read(m: &mut Monitor) -> property::Result<Entry> {
+		let mut get = |name| property::find(name).unwrap().get(m);
+		let power = get("power")?;
+		let mut text = |name| get(name).ok().map(|x| String::from(x.to_string().trim()));
+
+		Ok(Entry{
+			reachable: true,
+			power: Some(power.to_string()),
+			serial: text("serial-number"),
+			model: text("name"),
+			firmware: text("firmware"),
+			backlight_hours: text("backlight-hours").and_then(|x| x.parse().ok()),
+			input: text("input"),
+			..Entry::default()
+		})
+	}
+
+	fn fields(&self) -> [String; 10] {
+		let text = |x: &Option<String>| x.clone().unwrap_or_default();
+		[
+			self.monitor.clone(),
+			self.reachable.to_string(),
+			text(&self.serial),
+			text(&self.model),
+			text(&self.firmware),
+			self.backlight_hours.map(|x| x.to_string()).unwrap_or_default(),
+			text(&self.input),
+			text(&self.power),
+			self.flags.iter().map(|x| x.to_string()).collect::<Vec<_>>().join(" "),
+			text(&self.error),
+		]
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+	Csv,
+	Json,
+	Markdown,
+}
+
+impl FromStr for Format {
+	type Err = String;
+
+	fn from_str(x: &str) -> Result<Self, Self::Err> {
+		match x {
+			"csv" => Ok(Format::Csv),
+			"json" => Ok(Format::Json),
+			"markdown" | "md" => Ok(Format::Markdown),
+			_ => Err(format!("{}: expected csv, json or markdown", x)),
+		}
+	}
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Inventory {
+	pub entries: Vec<Entry>,
+}
+
+impl Inventory {
+	/// Reads every monitor of `group`.
+	pub fn collect(config: &Config, group: &Group) -> Inventory {
+		Inventory::collect_with(config, group, &(Box::new(Monitor::open) as Opener))
+	}
+
+	/// Same as `collect`, opening the monitors with `opener`.
+	pub fn collect_with(config: &Config, group: &Group, opener: &Opener) -> Inventory {
+		let report = group.run_with(config, opener, Entry::read);
+		let entries = report.results.into_iter().map(|(monitor, result)| match result {
+			Ok(entry) => Entry{monitor, ..entry},
+			Err(err) => Entry{monitor, error: Some(err.to_string()), ..Entry::default()},
+		}).collect();
+
+		let mut inventory = Inventory{entries};
+		inventory.flag();
+		inventory
+	}
+
+	/// Flags the duplicate serial numbers and the firmware outliers. There
+	/// are no outliers when no firmware is more common than all others.
+	fn flag(&mut self) {
+		let mut serials = BTreeMap::new();
+		let mut firmwares = BTreeMap::new();
+		for entry in &self.entries {
+			if let Some(ref serial) = entry.serial {
+				*serials.entry(serial.clone()).or_insert(0) += 1;
+			}
+			if let Some(ref firmware) = entry.firmware {
+				*firmwares.entry(firmware.clone()).or_insert(0) += 1;
+			}
+		}
+		let majority = firmwares.values().copied().max().unwrap_or(0);
+		let majority = match firmwares.values().filter(|&&x| x == majority).count() {
+			1 => majority,
+			_ => 0,
+		};
+
+		for entry in self.entries.iter_mut() {
+			entry.flags.clear();
+			if entry.serial.as_ref().is_some_and(|x| serials[x] > 1) {
+				entry.flags.push(Flag::DuplicateSerial);
+			}
+			if entry.firmware.as_ref().is_some_and(|x| firmwares[x] < majority) {
+				entry.flags.push(Flag::FirmwareOutlier);
+			}
+		}
+	}
+
+	pub fn format(&self, format: Format) -> String {
+		match format {
+			Format::Csv => self.to_csv(),
+			Format::Json => serde_json::to_string_pretty(self).unwrap(),
+			Format::Markdown => self.to_markdown(),
+		}
+	}
+
+	/// RFC 4180, fields quoted when needed.
+	pub fn to_csv(&self) -> String {
+		let quote = |x: &str| match x.contains([',', '"', '\n', '\r']) {
+			true => format!("\"{}\"", x.replace('"', "\"\"")),
+			false => String::from(x),
+		};
+
+		let mut csv = COLUMNS.join(",") + "\r\n";
+		for entry in &self.entries {
+			csv += &entry.fields().iter().map(|x| quote(x)).collect::<Vec<_>>().join(",");
+			csv += "\r\n";
+		}
+		csv
+	}
+
+	pub fn to_markdown(&self) -> String {
+		let row = |fields: &[String]| format!("| {} |\n", fields.join(" | "));
+
+		let mut markdown = row(&COLUMNS.map(String::from));
+		markdown += &row(&COLUMNS.map(|_| String::from("---")));
+		for entry in &self.entries {
+			markdown += &row(&entry.fields().map(|x| x.replace('|', "\\|").replace('\n', " ")));
+		}
+		markdown
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io;
+
+	use group::Group;
+	use inventory::{Entry, Flag, Inventory};
+	use monitor;
+	use monitor::Opener;
+	use simulator;
+	use simulator::Simulator;
+
+	#[test]
+	fn inventory_flags() {
+		let outlier = Simulator::new();
+		outlier.set_value(0xA0, b"M2T101");
+		outlier.set_value(0x02, b"CN0DEF, 7");
+		let (config, opener) = simulator::fixture(&[
+			("lobby", &Simulator::new()), ("atrium", &Simulator::new()), ("foyer", &outlier), ("hall", &Simulator::new())]);
+
+		let names = ["lobby", "atrium", "foyer", "hall"].map(String::from);
+		let group = Group::resolve_all(&config, &names).unwrap();
+		let opener : Opener = Box::new(move |name, monitor, config| match name {
+			"hall" => Err(monitor::Error::OpenError(io::Error::new(io::ErrorKind::ConnectionRefused, "refused"))),
+			_ => opener(name, monitor, config),
+		});
+		let inventory = Inventory::collect_with(&config, &group, &opener);
+
+		let entries : Vec<_> = inventory.entries.iter().map(|x| (x.monitor.as_str(), x.reachable, x.flags.clone())).collect();
+		assert_eq!(vec![
+			("lobby", true, vec![Flag::DuplicateSerial]),
+			("atrium", true, vec![Flag::DuplicateSerial]),
+			("foyer", true, vec![Flag::FirmwareOutlier]),
+			("hall", false, vec![]),
+		], entries);
+		assert_eq!((Some(1234), Some("hdmi1"), Some("on")), (inventory.entries[0].backlight_hours,
+			inventory.entries[0].input.as_deref(), inventory.entries[0].power.as_deref()));
+
+		let csv = inventory.to_csv();
+		assert!(csv.starts_with("monitor,reachable,serial,model,firmware,backlight_hours,input,power,flags,error\r\n"));
+		assert!(csv.contains("foyer,true,\"CN0DEF, 7\",DELL C5517H,M2T101,1234,hdmi1,on,firmware-outlier,\r\n"));
+		let markdown = inventory.to_markdown();
+		assert_eq!(6, markdown.lines().count());
+		assert!(markdown.contains("| lobby | true | CN0ABC123456 | DELL C5517H | M2T104 | 1234 | hdmi1 | on | duplicate-serial |  |"));
+
+		// No firmware is more common than all others with two of A, two of B and one of C
+		let mut inventory = Inventory{entries: ["A", "A", "B", "B", "C"].iter()
+			.map(|&x| Entry{firmware: Some(String::from(x)), ..Entry::default()})
+			.collect()};
+		inventory.flag();
+		assert!(inventory.entries.iter().all(|x| x.flags.is_empty()));
+	}
+}
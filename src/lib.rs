@@ -2,5 +2,43 @@ extern crate nom;
 extern crate num;
 #[macro_use]
 extern crate num_derive;
+extern crate serialport;
+#[cfg(unix)]
+extern crate libc;
+extern crate serde;
+#[macro_use]
+extern crate serde_json;
+extern crate toml;
+extern crate md5;
+extern crate chrono;
+extern crate tracing;
+#[macro_use]
+extern crate serde_derive;
 
 pub mod protocol;
+pub mod port;
+pub mod lock;
+pub mod discovery;
+pub mod config;
+pub mod transport;
+pub mod monitor;
+pub mod property;
+pub mod group;
+pub mod ramp;
+pub mod simulator;
+pub mod http;
+pub mod rest;
+pub mod mqtt;
+pub mod metrics;
+pub mod pjlink;
+pub mod schedule;
+pub mod reconcile;
+pub mod watch;
+pub mod hooks;
+pub mod backlight;
+pub mod inventory;
+pub mod alert;
+pub mod conditioning;
+pub mod analyzer;
+#[cfg(unix)]
+pub mod daemon;
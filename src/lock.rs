@@ -0,0 +1,301 @@
+//! Advisory locking of serial ports shared between processes.
+//!
+//! A port is locked twice: with a UUCP-style lock file `/var/lock/LCK..ttyUSB0`
+//! holding the PID of the owner, which minicom, picocom and friends respect
+//! too, and with `flock` on the opened descriptor. serialport additionally
+//! opens the tty with `TIOCEXCL`. Elsewhere than on Unix only the lock
+//! file is used.
+
+use std;
+use std::fs;
+use std::io;
+use std::io::{Read, Write};
+use std::fmt;
+use std::error;
+use std::thread;
+use std::process;
+use std::path::{Path, PathBuf};
+use std::ops::{Deref, DerefMut};
+use std::time::{Duration, Instant};
+#[cfg(unix)]
+use std::os::unix::io::AsRawFd;
+
+#[cfg(unix)]
+use libc;
+use serialport;
+#[cfg(unix)]
+use serialport::posix::TTYPort;
+use serialport::{SerialPort, SerialPortSettings};
+
+pub const LOCK_DIR : &str = "/var/lock";
+
+const POLL_INTERVAL : Duration = Duration::from_millis(100);
+
+fn default_enabled() -> bool { true }
+
+/// Whether to lock a port and how long to wait for its current owner.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LockPolicy {
+	#[serde(default = "default_enabled")]
+	pub enabled: bool,
+	/// How long to wait for the port to be released, zero fails at once.
+	#[serde(default)]
+	pub wait_ms: u64,
+}
+
+impl Default for LockPolicy {
+	fn default() -> Self {
+		LockPolicy{enabled: default_enabled(), wait_ms: 0}
+	}
+}
+
+#[derive(Debug)]
+pub enum Error {
+	Locked{ port : String, pid : Option<u32> },
+	OpenError(serialport::Error),
+	IoError(io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Error::Locked{ref port, pid: Some(pid)} =>
+				write!(f, "{} is locked by process {}", port, pid),
+			Error::Locked{ref port, pid: None} =>
+				write!(f, "{} is locked by another process", port),
+			Error::OpenError(ref open_error) =>
+				write!(f, "open error: {}", open_error),
+			Error::IoError(ref io_error) =>
+				write!(f, "io error: {}", io_error),
+		}
+	}
+}
+
+impl error::Error for Error {
+	fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+		match self {
+			Error::Locked{..} => None,
+			Error::OpenError(ref open_error) => Some(open_error),
+			Error::IoError(ref io_error) => Some(io_error),
+		}
+	}
+}
+
+impl From<serialport::Error> for Error {
+	fn from(error: serialport::Error) -> Error {
+		Error::OpenError(error)
+	}
+}
+
+impl From<Error> for io::Error {
+	fn from(error: Error) -> io::Error {
+		match error {
+			Error::IoError(io_error) => io_error,
+			Error::OpenError(open_error) => open_error.into(),
+			locked => io::Error::new(io::ErrorKind::WouldBlock, locked),
+		}
+	}
+}
+
+#[cfg(unix)]
+fn is_alive(pid: u32) -> bool {
+	// Signal 0 only checks whether the process exists. EPERM means it does
+	// but belongs to somebody else.
+	let ret = unsafe { libc::kill(pid as libc::pid_t, 0) };
+	ret == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(not(unix))]
+fn is_alive(_pid: u32) -> bool {
+	true
+}
+
+fn read_pid(path: &Path) -> Option<u32> {
+	fs::read_to_string(path).ok().and_then(|x| x.trim().parse().ok())
+}
+
+/// UUCP lock file, removed when dropped.
+#[derive(Debug)]
+pub struct LockFile {
+	path: PathBuf,
+}
+
+impl LockFile {
+	/// Lock file name for `port`, symlinks such as `/dev/serial/by-id` resolved.
+	pub fn path_for(dir: &Path, port: &str) -> PathBuf {
+		let port = fs::canonicalize(port).unwrap_or_else(|_| PathBuf::from(port));
+		let name = port.file_name().map(|x| x.to_string_lossy().into_owned()).unwrap_or_default();
+		dir.join(format!("LCK..{}", name))
+	}
+
+	/// Creates the lock file for `port` in `dir`, replacing stale ones.
+	///
+	/// `None` is returned when `dir` is missing or not writable to us, flock
+	/// is then the only protection.
+	pub fn acquire(dir: &Path, port: &str) -> Result<Option<LockFile>> {
+		let path = LockFile::path_for(dir, port);
+
+		loop {
+			match fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+				Ok(mut file) => {
+					let lock = LockFile{path};
+					writeln!(file, "{:>10}", process::id()).map_err(Error::IoError)?;
+					return Ok(Some(lock));
+				},
+				Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists => {
+					match read_pid(&path) {
+						Some(pid) if !is_alive(pid) => match fs::remove_file(&path) {
+							Err(ref err) if err.kind() != io::ErrorKind::NotFound => return Ok(None),
+							_ => continue,
+						},
+						// A file without a PID may be in the middle of being written
+						pid => return Err(Error::Locked{port: String::from(port), pid}),
+					}
+				},
+				Err(ref err) if err.kind() == io::ErrorKind::NotFound || err.kind() == io::ErrorKind::PermissionDenied =>
+					return Ok(None),
+				Err(err) => return Err(Error::IoError(err)),
+			}
+		}
+	}
+
+	pub fn path(&self) -> &Path {
+		&self.path
+	}
+}
+
+impl Drop for LockFile {
+	fn drop(&mut self) {
+		let _ = fs::remove_file(&self.path);
+	}
+}
+
+/// A serial port owned by this process until dropped.
+pub struct LockedPort {
+	port: Box<dyn SerialPort>,
+	_lock: Option<LockFile>,
+}
+
+impl Deref for LockedPort {
+	type Target = dyn SerialPort;
+
+	fn deref(&self) -> &Self::Target {
+		&*self.port
+	}
+}
+
+impl DerefMut for LockedPort {
+	fn deref_mut(&mut self) -> &mut Self::Target {
+		&mut *self.port
+	}
+}
+
+impl Read for LockedPort {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		self.port.read(buf)
+	}
+}
+
+impl Write for LockedPort {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		self.port.write(buf)
+	}
+	fn flush(&mut self) -> io::Result<()> {
+		self.port.flush()
+	}
+}
+
+#[cfg(unix)]
+fn open_port(path: &str, settings: &SerialPortSettings) -> Result<Box<dyn SerialPort>> {
+	let port = TTYPort::open(Path::new(path), settings).map_err(Error::OpenError)?;
+
+	if unsafe { libc::flock(port.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+		let err = io::Error::last_os_error();
+		return Err(match err.raw_os_error() {
+			Some(libc::EWOULDBLOCK) => Error::Locked{
+				port: String::from(path),
+				pid: read_pid(&LockFile::path_for(Path::new(LOCK_DIR), path)),
+			},
+			_ => Error::IoError(err),
+		});
+	}
+
+	Ok(Box::new(port))
+}
+
+#[cfg(not(unix))]
+fn open_port(path: &str, settings: &SerialPortSettings) -> Result<Box<dyn SerialPort>> {
+	serialport::open_with_settings(path, settings).map_err(Error::OpenError)
+}
+
+fn try_open(path: &str, settings: &SerialPortSettings) -> Result<LockedPort> {
+	let lock = LockFile::acquire(Path::new(LOCK_DIR), path)?;
+	let port = open_port(path, settings)?;
+
+	Ok(LockedPort{port, _lock: lock})
+}
+
+/// Opens and locks the serial port at `path` according to `policy`.
+pub fn open(path: &str, settings: &SerialPortSettings, policy: &LockPolicy) -> Result<LockedPort> {
+	if !policy.enabled {
+		return serialport::open_with_settings(path, settings)
+			.map(|port| LockedPort{port, _lock: None})
+			.map_err(Error::OpenError);
+	}
+
+	let deadline = Instant::now() + Duration::from_millis(policy.wait_ms);
+
+	loop {
+		match try_open(path, settings) {
+			Err(Error::Locked{..}) if Instant::now() < deadline => thread::sleep(POLL_INTERVAL),
+			result => return result,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::env;
+	use std::fs;
+	use std::process;
+
+	use lock::{Error, LockFile};
+
+	#[test]
+	fn lock_file_acquire() {
+		let dir = env::temp_dir().join("c5517h-lock-acquire");
+		fs::create_dir_all(&dir).unwrap();
+
+		let lock = LockFile::acquire(&dir, "/nonexistent/ttyLOCK0").unwrap().unwrap();
+		assert_eq!(dir.join("LCK..ttyLOCK0"), lock.path());
+		assert_eq!(format!("{:>10}\n", process::id()), fs::read_to_string(lock.path()).unwrap());
+
+		match LockFile::acquire(&dir, "/nonexistent/ttyLOCK0") {
+			Err(Error::Locked{pid: Some(pid), ..}) => assert_eq!(process::id(), pid),
+			x => panic!("unexpected {:?}", x),
+		}
+
+		let path = lock.path().to_path_buf();
+		drop(lock);
+		assert!(!path.exists());
+	}
+
+	#[test]
+	fn lock_file_stale() {
+		let dir = env::temp_dir().join("c5517h-lock-stale");
+		fs::create_dir_all(&dir).unwrap();
+		// Larger than any pid_max
+		fs::write(dir.join("LCK..ttyLOCK1"), "2147483000\n").unwrap();
+
+		let lock = LockFile::acquire(&dir, "/nonexistent/ttyLOCK1").unwrap().unwrap();
+		assert_eq!(format!("{:>10}\n", process::id()), fs::read_to_string(lock.path()).unwrap());
+	}
+
+	#[test]
+	fn lock_file_missing_dir() {
+		let dir = env::temp_dir().join("c5517h-lock-missing");
+		assert!(LockFile::acquire(&dir, "/nonexistent/ttyLOCK2").unwrap().is_none());
+	}
+}
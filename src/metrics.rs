@@ -0,0 +1,324 @@
+//! Prometheus exporter: `GET /metrics` reads every configured monitor and
+//! reports its state together with transaction counters and latencies.
+
+use std::io;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use std::collections::BTreeMap;
+
+use config::Config;
+use http::{Request, Response};
+use monitor::{Monitor, Opener};
+use property;
+use property::{Choice, Value};
+use protocol::decoder;
+use protocol::reply::ResultCode;
+use protocol::transaction;
+use protocol::types;
+
+/// Upper bounds of the latency histogram buckets, in seconds.
+pub const LATENCY_BUCKETS : [f64; 9] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5];
+
+const RESULT_CODES : [(ResultCode, &str); 4] = [
+	(ResultCode::Timeout, "timeout"),
+	(ResultCode::ParametersError, "parameters-error"),
+	(ResultCode::NotConnected, "not-connected"),
+	(ResultCode::Other, "other"),
+];
+
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Counts {
+	transactions: u64,
+	result_codes: [u64; 4],
+	checksum_errors: u64,
+	timeouts: u64,
+	buckets: [u64; 9],
+	latency_sum: f64,
+}
+
+/// Transaction counters of one monitor, shared with the `Monitor` feeding them.
+#[derive(Debug, Default)]
+pub struct TransactionStats {
+	counts: Mutex<Counts>,
+}
+
+impl TransactionStats {
+	pub fn record<R>(&self, elapsed: Duration, result: &transaction::Result<R>) {
+		let mut counts = self.counts.lock().unwrap();
+		let seconds = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+
+		counts.transactions += 1;
+		counts.latency_sum += seconds;
+		for (bucket, &bound) in counts.buckets.iter_mut().zip(LATENCY_BUCKETS.iter()) {
+			if seconds <= bound {
+				*bucket += 1;
+			}
+		}
+
+		match result {
+			Err(transaction::Error::DecodeError(decoder::Error::DeviceError(ref result_code), _)) => {
+				let index = RESULT_CODES.iter().position(|(x, _)| x == result_code).unwrap();
+				counts.result_codes[index] += 1;
+			},
+			Err(transaction::Error::DecodeError(decoder::Error::ChecksumError{..}, _)) => counts.checksum_errors += 1,
+			Err(transaction::Error::ReadError(ref err)) if err.kind() == io::ErrorKind::TimedOut
+				|| err.kind() == io::ErrorKind::WouldBlock => counts.timeouts += 1,
+			_ => (),
+		}
+	}
+
+	/// Replies with a wrong checksum so far, retried ones included.
+	pub fn checksum_errors(&self) -> u64 {
+		self.counts.lock().unwrap().checksum_errors
+	}
+}
+
+/// One metric family in the text exposition format.
+struct Family {
+	name: &'static str,
+	kind: &'static str,
+	help: &'static str,
+	samples: Vec<(String, String, f64)>,
+}
+
+impl Family {
+	fn new(name: &'static str, kind: &'static str, help: &'static str) -> Family {
+		Family{name, kind, help, samples: Vec::new()}
+	}
+
+	fn add(&mut self, suffix: &str, labels: &[(&str, &str)], value: f64) {
+		let labels = labels.iter()
+			.map(|&(name, value)| format!("{}=\"{}\"", name, escape(value)))
+			.collect::<Vec<_>>()
+			.join(",");
+		self.samples.push((String::from(suffix), labels, value));
+	}
+
+	fn render(&self, out: &mut String) {
+		let _ = writeln!(out, "# HELP {} {}", self.name, self.help);
+		let _ = writeln!(out, "# TYPE {} {}", self.name, self.kind);
+		for (suffix, labels, value) in &self.samples {
+			let _ = writeln!(out, "{}{}{{{}}} {}", self.name, suffix, labels, value);
+		}
+	}
+}
+
+fn escape(x: &str) -> String {
+	x.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+struct Target {
+	monitor: Option<Monitor>,
+	serial_number: Option<String>,
+	stats: Arc<TransactionStats>,
+}
+
+/// State of one monitor read during a scrape, `None` when its power could
+/// not be read. Other gauges are left out while they cannot be read.
+struct Sample {
+	serial_number: Option<String>,
+	values: Option<BTreeMap<&'static str, Value>>,
+	counts: Counts,
+}
+
+pub struct Exporter {
+	config: Config,
+	opener: Opener,
+	targets: BTreeMap<String, Mutex<Target>>,
+}
+
+/// Properties exported as gauges, the choice ones with one series per choice.
+const GAUGES : [&str; 6] = ["power", "backlight-hours", "brightness", "contrast", "input", "preset"];
+
+fn scrape(config: &Config, opener: &Opener, name: &str, target: &mut Target) -> Sample {
+	if target.monitor.is_none() {
+		let (name, monitor) = config.monitor(name).unwrap();
+		target.monitor = opener(name, monitor, config).ok()
+			.map(|x| x.with_stats(target.stats.clone()));
+	}
+
+	let mut values = None;
+	if let Some(ref mut monitor) = target.monitor {
+		if target.serial_number.is_none() {
+			target.serial_number = monitor.get::<types::SerialNumber>().ok().map(String::from);
+		}
+		let mut gauges = GAUGES.iter().map(|&x| (x, property::find(x).unwrap().get(monitor)));
+		if let Some((_, Ok(power))) = gauges.next() {
+			let mut read = BTreeMap::new();
+			read.insert("power", power);
+			read.extend(gauges.filter_map(|(x, value)| value.ok().map(|value| (x, value))));
+			values = Some(read);
+		}
+	}
+	if values.is_none() {
+		target.monitor = None;
+	}
+
+	Sample{
+		serial_number: target.serial_number.clone(),
+		values,
+		counts: target.stats.counts.lock().unwrap().clone(),
+	}
+}
+
+impl Exporter {
+	pub fn new(config: Config) -> Exporter {
+		Exporter::with_opener(config, Box::new(Monitor::open))
+	}
+
+	pub fn with_opener(config: Config, opener: Opener) -> Exporter {
+		let targets = config.monitors.keys()
+			.map(|x| (x.clone(), Mutex::new(Target{monitor: None, serial_number: None, stats: Arc::default()})))
+			.collect();
+		Exporter{config, opener, targets}
+	}
+
+	/// Reads all monitors in parallel and renders the metrics.
+	pub fn render(&self) -> String {
+		let samples : Vec<(&String, Sample)> = thread::scope(|s| {
+			let handles : Vec<_> = self.targets.iter()
+				.map(|(name, target)| (name, s.spawn(move || {
+					scrape(&self.config, &self.opener, name, &mut target.lock().unwrap())
+				})))
+				.collect();
+			handles.into_iter().map(|(name, handle)| (name, handle.join().unwrap())).collect()
+		});
+
+		let mut info = Family::new("c5517h_info", "gauge", "Serial number of the monitor, once it could be read.");
+		let mut up = Family::new("c5517h_up", "gauge", "Whether the power of the monitor could be read in the last scrape.");
+		let mut hours = Family::new("c5517h_backlight_hours", "gauge", "Backlight usage in hours.");
+		let mut power = Family::new("c5517h_power_on", "gauge", "Whether the monitor is on.");
+		let mut brightness = Family::new("c5517h_brightness", "gauge", "Brightness in percent.");
+		let mut contrast = Family::new("c5517h_contrast", "gauge", "Contrast in percent.");
+		let mut input = Family::new("c5517h_input", "gauge", "Current video input, 1 for the selected one.");
+		let mut preset = Family::new("c5517h_preset", "gauge", "Current color preset, 1 for the selected one.");
+		let mut transactions = Family::new("c5517h_transactions_total", "counter", "Transactions attempted.");
+		let mut result_codes = Family::new("c5517h_result_code_errors_total", "counter", "Error result codes replied by the monitor.");
+		let mut checksum_errors = Family::new("c5517h_checksum_errors_total", "counter", "Replies with a bad checksum.");
+		let mut timeouts = Family::new("c5517h_timeouts_total", "counter", "Transactions without a reply in time.");
+		let mut latency = Family::new("c5517h_transaction_duration_seconds", "histogram", "Transaction latency.");
+
+		for (name, sample) in samples {
+			let monitor = [("monitor", name.as_str())];
+
+			if let Some(ref serial_number) = sample.serial_number {
+				info.add("", &[monitor[0], ("serial_number", serial_number)], 1.0);
+			}
+			up.add("", &monitor, if sample.values.is_some() { 1.0 } else { 0.0 });
+			if let Some(ref values) = sample.values {
+				let number = |family: &mut Family, x: &str| if let Some(&Value::Number(value)) = values.get(x) {
+					family.add("", &monitor, f64::from(value));
+				};
+				number(&mut hours, "backlight-hours");
+				number(&mut brightness, "brightness");
+				number(&mut contrast, "contrast");
+				power.add("", &monitor, if values["power"] == Value::Choice(types::PowerState::On.choice_name()) { 1.0 } else { 0.0 });
+
+				for (family, property) in [(&mut input, "input"), (&mut preset, "preset")] {
+					let value = match values.get(property) {
+						Some(value) => value,
+						None => continue,
+					};
+					for choice in property::find(property).unwrap().choices() {
+						let selected = *value == Value::Choice(choice);
+						family.add("", &[monitor[0], (property, choice)], if selected { 1.0 } else { 0.0 });
+					}
+				}
+			}
+
+			let counts = &sample.counts;
+			transactions.add("", &monitor, counts.transactions as f64);
+			for (&(_, code), &count) in RESULT_CODES.iter().zip(counts.result_codes.iter()) {
+				result_codes.add("", &[monitor[0], ("code", code)], count as f64);
+			}
+			checksum_errors.add("", &monitor, counts.checksum_errors as f64);
+			timeouts.add("", &monitor, counts.timeouts as f64);
+			for (&bound, &count) in LATENCY_BUCKETS.iter().zip(counts.buckets.iter()) {
+				latency.add("_bucket", &[monitor[0], ("le", &bound.to_string())], count as f64);
+			}
+			latency.add("_bucket", &[monitor[0], ("le", "+Inf")], counts.transactions as f64);
+			latency.add("_sum", &monitor, counts.latency_sum);
+			latency.add("_count", &monitor, counts.transactions as f64);
+		}
+
+		let mut out = String::new();
+		for family in &[info, up, hours, power, brightness, contrast, input, preset,
+			transactions, result_codes, checksum_errors, timeouts, latency] {
+			family.render(&mut out);
+		}
+		out
+	}
+
+	pub fn handle(&self, request: &Request) -> Response {
+		match (request.method.as_str(), &request.segments()[..]) {
+			("GET", ["metrics"]) => Response{
+				status: 200,
+				content_type: "text/plain; version=0.0.4",
+				body: self.render().into_bytes(),
+			},
+			_ => Response::text(404, "not found"),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use metrics::Exporter;
+	use protocol::reply::ResultCode;
+	use simulator;
+	use simulator::Simulator;
+
+	fn exporter(simulator: &Simulator) -> Exporter {
+		let (config, opener) = simulator::fixture(&[("lobby", simulator)]);
+		Exporter::with_opener(config, opener)
+	}
+
+	#[test]
+	fn exporter_render() {
+		let simulator = Simulator::new();
+		let exporter = exporter(&simulator);
+		let metrics = exporter.render();
+		let labels = "monitor=\"lobby\"";
+
+		assert!(metrics.contains("c5517h_info{monitor=\"lobby\",serial_number=\"CN0ABC123456\"} 1\n"));
+		assert!(metrics.contains(&format!("c5517h_up{{{}}} 1\n", labels)));
+		assert!(metrics.contains(&format!("c5517h_backlight_hours{{{}}} 1234\n", labels)));
+		assert!(metrics.contains(&format!("c5517h_power_on{{{}}} 1\n", labels)));
+		assert!(metrics.contains(&format!("c5517h_input{{{},input=\"hdmi1\"}} 1\n", labels)));
+		assert!(metrics.contains(&format!("c5517h_input{{{},input=\"dp1\"}} 0\n", labels)));
+		assert!(metrics.contains("# TYPE c5517h_transaction_duration_seconds histogram\n"));
+		assert!(metrics.contains("c5517h_transaction_duration_seconds_bucket{monitor=\"lobby\",le=\"+Inf\"} 7\n"));
+
+		simulator.fail_next(ResultCode::NotConnected);
+		let metrics = exporter.render();
+		assert!(metrics.contains(&format!("c5517h_up{{{}}} 0\n", labels)));
+		assert!(metrics.contains("c5517h_result_code_errors_total{monitor=\"lobby\",code=\"not-connected\"} 1\n"));
+
+		simulator.set_responding(false);
+		let metrics = exporter.render();
+		assert!(metrics.contains("c5517h_timeouts_total{monitor=\"lobby\"} 1\n"));
+	}
+
+	#[test]
+	fn exporter_render_partial() {
+		let simulator = Simulator::new();
+		let exporter = exporter(&simulator);
+
+		// No serial number yet, and a brightness that comes back garbled
+		simulator.fail_next(ResultCode::Other);
+		simulator.set_value(0x30, &[]);
+		let metrics = exporter.render();
+		assert!(!metrics.contains("c5517h_info{"));
+		assert!(metrics.contains("c5517h_up{monitor=\"lobby\"} 1\n"));
+		assert!(metrics.contains("c5517h_power_on{monitor=\"lobby\"} 1\n"));
+		assert!(metrics.contains("c5517h_contrast{monitor=\"lobby\"} 75\n"));
+		assert!(!metrics.contains("c5517h_brightness{"));
+
+		simulator.set_value(0x30, &[75]);
+		let metrics = exporter.render();
+		assert!(metrics.contains("c5517h_info{monitor=\"lobby\",serial_number=\"CN0ABC123456\"} 1\n"));
+		assert!(metrics.contains("c5517h_brightness{monitor=\"lobby\"} 75\n"));
+	}
+}
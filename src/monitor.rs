@@ -0,0 +1,295 @@
+use std;
+use std::io;
+use std::sync::Arc;
+use std::fmt;
+use std::error;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tracing;
+
+use config;
+use config::{Config, MonitorConfig, RetryPolicy};
+use metrics::TransactionStats;
+use transport;
+use transport::{Address, Transport};
+use transport::capture::Recorder;
+#[cfg(unix)]
+use daemon;
+use protocol::HasCommandOpcode;
+use protocol::command::{Command, Get, Set, Serialize, ResetPower};
+use protocol::reply::{Reply, Parse, NullaryReply};
+use protocol::transaction;
+use protocol::transaction::stream_transaction;
+use protocol::types;
+use ramp;
+use ramp::{Level, Ramp};
+
+#[derive(Debug)]
+pub enum Error {
+	ConfigError(config::Error),
+	UnknownMonitor(String),
+	UnknownGroup(String),
+	OpenError(io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Error::ConfigError(ref config_error) =>
+				write!(f, "config error: {}", config_error),
+			Error::UnknownMonitor(ref name) =>
+				write!(f, "unknown monitor: {}", name),
+			Error::UnknownGroup(ref name) =>
+				write!(f, "unknown group: {}", name),
+			Error::OpenError(ref open_error) =>
+				write!(f, "open error: {}", open_error),
+		}
+	}
+}
+
+impl error::Error for Error {
+	fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+		match self {
+			Error::ConfigError(ref config_error) => Some(config_error),
+			Error::UnknownMonitor(_) | Error::UnknownGroup(_) => None,
+			Error::OpenError(ref open_error) => Some(open_error),
+		}
+	}
+}
+
+#[cfg(unix)]
+fn connect_daemon(address: &Address, timeout: Duration) -> Option<Box<dyn Transport>> {
+	daemon::connect(address, timeout).map(|x| Box::new(x) as Box<dyn Transport>)
+}
+
+#[cfg(not(unix))]
+fn connect_daemon(_address: &Address, _timeout: Duration) -> Option<Box<dyn Transport>> {
+	None
+}
+
+/// Opens a configured monitor, `Monitor::open` unless a front-end is under test.
+pub type Opener = Box<dyn Fn(&str, &MonitorConfig, &Config) -> Result<Monitor> + Send + Sync>;
+
+/// An opened monitor together with its retry policy.
+pub struct Monitor {
+	name: String,
+	transport: Box<dyn Transport>,
+	retry: RetryPolicy,
+	stats: Option<Arc<TransactionStats>>,
+}
+
+impl Monitor {
+	pub fn new(name: &str, transport: Box<dyn Transport>) -> Monitor {
+		Monitor{name: String::from(name), transport, retry: RetryPolicy::default(), stats: None}
+	}
+
+	/// Opens the monitor named `name` (or aliased so) in the default configuration files.
+	pub fn from_config(name: &str) -> Result<Monitor> {
+		let config = Config::load().map_err(Error::ConfigError)?;
+		Monitor::with_config(&config, name)
+	}
+
+	pub fn with_config(config: &Config, name: &str) -> Result<Monitor> {
+		let (name, monitor) = config.monitor(name).ok_or_else(|| Error::UnknownMonitor(String::from(name)))?;
+		Monitor::open(name, monitor, config)
+	}
+
+	/// Opens the monitor through the daemon owning its port when there is
+	/// one, directly otherwise, recording its traffic if it has a capture.
+	pub fn open(name: &str, monitor: &MonitorConfig, config: &Config) -> Result<Monitor> {
+		let settings = config.monitor_line_settings(monitor);
+		let timeout = Duration::from_millis(monitor.timeout_ms);
+		let transport = match connect_daemon(&monitor.transport, timeout) {
+			Some(transport) => transport,
+			None => transport::open(&monitor.transport, &settings, timeout, &monitor.lock).map_err(Error::OpenError)?,
+		};
+		let transport = match monitor.capture {
+			Some(ref path) => Box::new(Recorder::create(transport, path).map_err(Error::OpenError)?),
+			None => transport,
+		};
+
+		Ok(Monitor::new(name, transport).with_retry(monitor.retry))
+	}
+
+	pub fn with_retry(mut self, retry: RetryPolicy) -> Monitor {
+		self.retry = retry;
+		self
+	}
+
+	/// Counts every transaction attempt into `stats`.
+	pub fn with_stats(mut self, stats: Arc<TransactionStats>) -> Monitor {
+		self.stats = Some(stats);
+		self
+	}
+
+	pub fn name(&self) -> &str {
+		&self.name
+	}
+
+	/// Runs a transaction, repeating it as the retry policy permits. Its
+	/// attempts are traced in a span naming the monitor.
+	pub fn transaction<R : Reply, T : Command>(&mut self, cmd: &T) -> transaction::Result<R> {
+		let span = tracing::debug_span!("monitor", name = %self.name);
+		let _entered = span.enter();
+		let mut attempt = 1;
+
+		loop {
+			let start = Instant::now();
+			let result = stream_transaction(cmd, &mut self.transport);
+			if let Some(ref stats) = self.stats {
+				stats.record(start.elapsed(), &result);
+			}
+
+			match result {
+				Err(ref err) if attempt < self.retry.attempts && err.is_retryable() => {
+					tracing::debug!(attempt, attempts = self.retry.attempts, error = %err, "retrying");
+					thread::sleep(Duration::from_millis(self.retry.delay_ms));
+					attempt += 1;
+				},
+				result => return result,
+			}
+		}
+	}
+
+	pub fn get<T : HasCommandOpcode + Parse>(&mut self) -> transaction::Result<T> {
+		self.transaction(&Get::<T>::new())
+	}
+
+	pub fn set<T : HasCommandOpcode + Serialize>(&mut self, x: T) -> transaction::Result<()> {
+		self.transaction::<NullaryReply<T>, _>(&Set::new(x)).map(|_| ())
+	}
+
+	pub fn reset_power(&mut self) -> transaction::Result<()> {
+		self.transaction::<NullaryReply<ResetPower>, _>(&ResetPower()).map(|_| ())
+	}
+
+	/// Steps `T` from its current value to `ramp.target`, writing the next
+	/// value only once the previous write is acknowledged. Returns the last
+	/// value written, short of the target when the ramp was cancelled.
+	pub fn ramp<T : Level>(&mut self, ramp: &Ramp) -> ramp::Result<u8> {
+		T::level(ramp.target).map_err(ramp::Error::OutOfRange)?;
+		let start = self.get::<T>().map_err(ramp::Error::TransactionError)?.into();
+		let tick = ramp.tick(start);
+		let begin = Instant::now();
+		let mut current = start;
+
+		while current != ramp.target && !ramp.cancel.is_cancelled() {
+			let value = ramp.value_at(start, begin.elapsed());
+			if value != current {
+				self.set(T::from(value)).map_err(ramp::Error::TransactionError)?;
+				current = value;
+			}
+			if current != ramp.target {
+				thread::sleep(tick);
+			}
+		}
+
+		Ok(current)
+	}
+
+	pub fn ramp_brightness(&mut self, target: u8, duration: Duration) -> ramp::Result<u8> {
+		self.ramp::<types::Brightness>(&Ramp::new(target, duration))
+	}
+
+	pub fn ramp_contrast(&mut self, target: u8, duration: Duration) -> ramp::Result<u8> {
+		self.ramp::<types::Contrast>(&Ramp::new(target, duration))
+	}
+
+	pub fn ramp_sharpness(&mut self, target: u8, duration: Duration) -> ramp::Result<u8> {
+		self.ramp::<types::Sharpness>(&Ramp::new(target, duration))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io;
+	use std::io::{Read, Write};
+	use std::collections::VecDeque;
+	use std::time::Duration;
+
+	use config::RetryPolicy;
+	use monitor::Monitor;
+	use protocol::types;
+	use ramp;
+	use ramp::{Cancel, Easing, Ramp};
+	use simulator::Simulator;
+
+	/// Hands out one reply per read call, like a line that goes quiet between frames.
+	struct Duplex {
+		replies: VecDeque<Vec<u8>>,
+		output: Vec<u8>,
+	}
+
+	impl Read for Duplex {
+		fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+			match self.replies.pop_front() {
+				Some(reply) => (&reply[..]).read(buf),
+				None => Ok(0),
+			}
+		}
+	}
+
+	impl Write for Duplex {
+		fn write(&mut self, buf: &[u8]) -> io::Result<usize> { self.output.write(buf) }
+		fn flush(&mut self) -> io::Result<()> { Ok(()) }
+	}
+
+	fn monitor(replies: &[&[u8]]) -> Monitor {
+		let duplex = Duplex{replies: replies.iter().map(|x| x.to_vec()).collect(), output: Vec::new()};
+		Monitor::new("test", Box::new(duplex))
+	}
+
+	#[test]
+	fn monitor_get() {
+		let mut m = monitor(&[&[0x6f, 0x37, 0x04, 0x02, 0x00, 0x20, 0x01, 127]]);
+		assert_eq!(types::PowerState::On, m.get::<types::PowerState>().unwrap());
+	}
+
+	#[test]
+	fn monitor_set() {
+		let mut m = monitor(&[&[0x6f, 0x37, 0x03, 0x02, 0x00, 0x30, 105]]);
+		m.set(types::Brightness::new(64).unwrap()).unwrap();
+	}
+
+	#[test]
+	fn monitor_retry() {
+		// A corrupted reply followed by a good one
+		let mut m = monitor(&[
+			&[0x6f, 0x37, 0x04, 0x02, 0x00, 0x20, 0x01, 126],
+			&[0x6f, 0x37, 0x04, 0x02, 0x00, 0x20, 0x01, 127]]).with_retry(RetryPolicy{attempts: 2, delay_ms: 0});
+		assert_eq!(types::PowerState::On, m.get::<types::PowerState>().unwrap());
+	}
+
+	#[test]
+	fn monitor_no_retry_on_parameters_error() {
+		let mut m = monitor(&[
+			&[0x6f, 0x37, 0x03, 0x02, 0x02, 0x30, 107],
+			&[0x6f, 0x37, 0x03, 0x02, 0x00, 0x30, 105]]).with_retry(RetryPolicy{attempts: 2, delay_ms: 0});
+		assert!(m.set(types::Brightness::new(64).unwrap()).is_err());
+	}
+
+	#[test]
+	fn monitor_ramp() {
+		let simulator = Simulator::new();
+		let mut m = Monitor::new("sim", Box::new(simulator.clone()));
+
+		assert_eq!(70, m.ramp_contrast(70, Duration::from_millis(50)).unwrap());
+		assert_eq!(Some(vec![70]), simulator.value(0x31));
+		// One get, then at most one set per value
+		assert!((2..=6).contains(&simulator.requests()));
+
+		let cancel = Cancel::new();
+		cancel.cancel();
+		let ramp = Ramp::new(20, Duration::from_millis(50)).with_easing(Easing::EaseInOut).with_cancel(cancel);
+		assert_eq!(50, m.ramp::<types::Sharpness>(&ramp).unwrap());
+		assert_eq!(Some(vec![50]), simulator.value(0x34));
+
+		// Out of range targets are not even read
+		let requests = simulator.requests();
+		assert!(matches!(m.ramp_brightness(150, Duration::from_millis(50)), Err(ramp::Error::OutOfRange(_))));
+		assert_eq!(requests, simulator.requests());
+	}
+}
@@ -0,0 +1,300 @@
+//! Home Assistant bridge: every configured monitor becomes a device with a
+//! power switch, brightness and contrast numbers and an input select.
+//!
+//! State is polled and published when it changes, `.../set` topics are
+//! turned into writes, and a monitor is available as long as its last
+//! transaction succeeded.
+
+use std::io;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use config::Config;
+use monitor::{Monitor, Opener};
+use mqtt::{Client, Message, MqttConfig};
+use property;
+use property::Property;
+
+/// Home Assistant component and property of every entity.
+const ENTITIES : [(&str, &str); 4] = [
+	("switch", "power"),
+	("number", "brightness"),
+	("number", "contrast"),
+	("select", "input"),
+];
+
+#[derive(Default)]
+struct Device {
+	monitor: Option<Monitor>,
+	states: BTreeMap<&'static str, String>,
+	available: Option<bool>,
+}
+
+pub struct Bridge {
+	config: Config,
+	mqtt: MqttConfig,
+	opener: Opener,
+	devices: BTreeMap<String, Device>,
+}
+
+fn object_id(name: &str) -> String {
+	name.chars().map(|x| if x.is_ascii_alphanumeric() { x.to_ascii_lowercase() } else { '_' }).collect()
+}
+
+fn entity(property: &str) -> Option<&'static Property> {
+	ENTITIES.iter().find(|&&(_, x)| x == property).and_then(|&(_, x)| property::find(x))
+}
+
+impl Bridge {
+	pub fn new(config: Config, mqtt: MqttConfig) -> Bridge {
+		Bridge::with_opener(config, mqtt, Box::new(Monitor::open))
+	}
+
+	pub fn with_opener(config: Config, mqtt: MqttConfig, opener: Opener) -> Bridge {
+		let devices = config.monitors.keys().map(|x| (x.clone(), Device::default())).collect();
+		Bridge{config, mqtt, opener, devices}
+	}
+
+	fn topic(&self, name: &str, suffix: &str) -> String {
+		format!("{}/{}/{}", self.mqtt.base_topic, name, suffix)
+	}
+
+	/// Availability of the bridge itself, the broker reports it offline when the bridge dies.
+	pub fn status_topic(&self) -> String {
+		format!("{}/status", self.mqtt.base_topic)
+	}
+
+	pub fn command_filter(&self) -> String {
+		format!("{}/+/+/set", self.mqtt.base_topic)
+	}
+
+	/// Retained discovery configs of every entity of every monitor.
+	pub fn discovery(&self) -> Vec<Message> {
+		let mut messages = Vec::new();
+
+		for name in self.devices.keys() {
+			let device = format!("c5517h_{}", object_id(name));
+
+			for &(component, property) in ENTITIES.iter() {
+				let mut payload = json!({
+					"name": property,
+					"unique_id": format!("{}_{}", device, object_id(property)),
+					"state_topic": self.topic(name, property),
+					"command_topic": self.topic(name, &format!("{}/set", property)),
+					"availability": [{"topic": self.status_topic()}, {"topic": self.topic(name, "availability")}],
+					"availability_mode": "all",
+					"device": {"identifiers": [device], "name": name, "manufacturer": "Dell", "model": "C5517H"},
+				});
+				match component {
+					"switch" => {
+						payload["payload_on"] = json!("on");
+						payload["payload_off"] = json!("off");
+						payload["state_on"] = json!("on");
+						payload["state_off"] = json!("off");
+					},
+					"number" => {
+						payload["min"] = json!(0);
+						payload["max"] = json!(100);
+						payload["step"] = json!(1);
+					},
+					_ => payload["options"] = json!(entity(property).unwrap().choices()),
+				}
+
+				let topic = format!("{}/{}/{}/{}/config", self.mqtt.discovery_prefix, component, device, property);
+				messages.push(Message::new(topic, &payload.to_string(), true));
+			}
+		}
+
+		messages
+	}
+
+	fn set_available(&mut self, name: &str, available: bool, messages: &mut Vec<Message>) {
+		let topic = self.topic(name, "availability");
+		let device = self.devices.get_mut(name).unwrap();
+
+		if !available {
+			device.monitor = None;
+		}
+		if device.available != Some(available) {
+			device.available = Some(available);
+			messages.push(Message::new(topic, if available { "online" } else { "offline" }, true));
+		}
+	}
+
+	/// Opens the monitor unless it is already open.
+	fn open(&mut self, name: &str) -> bool {
+		if self.devices[name].monitor.is_some() {
+			return true;
+		}
+		let (name, monitor) = self.config.monitor(name).unwrap();
+		match (self.opener)(name, monitor, &self.config) {
+			Ok(monitor) => {
+				self.devices.get_mut(name).unwrap().monitor = Some(monitor);
+				true
+			},
+			Err(_) => false,
+		}
+	}
+
+	/// Reads `properties` of `name`, publishing the values that changed.
+	fn refresh(&mut self, name: &str, properties: &[&'static Property], messages: &mut Vec<Message>) {
+		if !self.open(name) {
+			return self.set_available(name, false, messages);
+		}
+
+		for property in properties {
+			let topic = self.topic(name, property.name());
+			let device = self.devices.get_mut(name).unwrap();
+			let value = match property.get(device.monitor.as_mut().unwrap()) {
+				Ok(value) => value.to_string(),
+				Err(_) => return self.set_available(name, false, messages),
+			};
+			if device.states.get(property.name()) != Some(&value) {
+				messages.push(Message::new(topic, &value, true));
+				device.states.insert(property.name(), value);
+			}
+		}
+
+		self.set_available(name, true, messages);
+	}
+
+	/// Polls every monitor, returns what is to be published.
+	pub fn poll(&mut self) -> Vec<Message> {
+		let properties : Vec<_> = ENTITIES.iter().filter_map(|&(_, x)| property::find(x)).collect();
+		let names : Vec<_> = self.devices.keys().cloned().collect();
+		let mut messages = Vec::new();
+
+		for name in names {
+			self.refresh(&name, &properties, &mut messages);
+		}
+		messages
+	}
+
+	/// Carries out a message received on a command topic, returns what is to be published.
+	pub fn command(&mut self, message: &Message) -> Vec<Message> {
+		let mut messages = Vec::new();
+		let prefix = format!("{}/", self.mqtt.base_topic);
+		let path : Vec<_> = match message.topic.starts_with(&prefix) {
+			true => message.topic[prefix.len()..].split('/').collect(),
+			false => return messages,
+		};
+		let (name, property) = match (&path[..], String::from_utf8(message.payload.clone())) {
+			(&[name, property, "set"], Ok(_)) if self.devices.contains_key(name) => match entity(property) {
+				Some(property) => (String::from(name), property),
+				None => return messages,
+			},
+			_ => return messages,
+		};
+		let value = String::from_utf8_lossy(&message.payload);
+
+		if !self.open(&name) {
+			self.set_available(&name, false, &mut messages);
+			return messages;
+		}
+		let device = self.devices.get_mut(&name).unwrap();
+		match property.set(device.monitor.as_mut().unwrap(), value.trim()) {
+			Ok(()) => self.refresh(&name, &[property], &mut messages),
+			Err(property::Error::TransactionError(_)) => self.set_available(&name, false, &mut messages),
+			Err(_) => (),
+		}
+		messages
+	}
+
+	/// Connects to the broker and bridges until the connection fails.
+	pub fn run(&mut self) -> io::Result<()> {
+		let will = Message::new(self.status_topic(), "offline", true);
+		let mut client = Client::connect(&self.mqtt, &will)?;
+		let interval = Duration::from_millis(self.mqtt.interval_ms);
+
+		client.subscribe(&self.command_filter())?;
+		for message in self.discovery() {
+			client.publish(&message)?;
+		}
+		client.publish(&Message::new(self.status_topic(), "online", true))?;
+
+		// Publish everything again to the new session
+		for device in self.devices.values_mut() {
+			device.states.clear();
+			device.available = None;
+		}
+
+		let mut next_poll = Instant::now();
+		loop {
+			let now = Instant::now();
+			if now >= next_poll {
+				for message in self.poll() {
+					client.publish(&message)?;
+				}
+				next_poll = now + interval;
+			}
+
+			let timeout = next_poll.saturating_duration_since(Instant::now()).min(Duration::from_secs(1));
+			if let Some(message) = client.receive(timeout)? {
+				for message in self.command(&message) {
+					client.publish(&message)?;
+				}
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use serde_json;
+	use serde_json::Value as Json;
+
+	use mqtt::{Message, MqttConfig};
+	use mqtt::bridge::Bridge;
+	use simulator;
+	use simulator::Simulator;
+
+	fn bridge(simulator: &Simulator) -> Bridge {
+		let (config, opener) = simulator::fixture(&[("lobby", simulator)]);
+		Bridge::with_opener(config, MqttConfig::default(), opener)
+	}
+
+	fn message(topic: &str, payload: &str) -> Message {
+		Message::new(String::from(topic), payload, true)
+	}
+
+	#[test]
+	fn bridge_discovery() {
+		let bridge = bridge(&Simulator::new());
+		let discovery = bridge.discovery();
+
+		assert_eq!(4, discovery.len());
+		assert_eq!("homeassistant/select/c5517h_lobby/input/config", discovery[3].topic);
+		let config : Json = serde_json::from_slice(&discovery[3].payload).unwrap();
+		assert_eq!(json!("c5517h/lobby/input/set"), config["command_topic"]);
+		assert_eq!(json!("hdmi2"), config["options"][1]);
+	}
+
+	#[test]
+	fn bridge_poll_and_command() {
+		let simulator = Simulator::new();
+		let mut bridge = bridge(&simulator);
+
+		assert_eq!(vec![
+			message("c5517h/lobby/power", "on"),
+			message("c5517h/lobby/brightness", "75"),
+			message("c5517h/lobby/contrast", "75"),
+			message("c5517h/lobby/input", "hdmi1"),
+			message("c5517h/lobby/availability", "online"),
+		], bridge.poll());
+		assert!(bridge.poll().is_empty());
+
+		simulator.set_value(0x30, &[10]);
+		assert_eq!(vec![message("c5517h/lobby/brightness", "10")], bridge.poll());
+
+		assert_eq!(vec![message("c5517h/lobby/input", "dp1")],
+			bridge.command(&message("c5517h/lobby/input/set", "DP1")));
+		assert_eq!(Some(vec![0x08, 0, 0, 0]), simulator.value(0x62));
+		assert!(bridge.command(&message("c5517h/lobby/input/set", "scart")).is_empty());
+		assert!(bridge.command(&message("c5517h/atrium/input/set", "dp1")).is_empty());
+
+		simulator.set_responding(false);
+		assert_eq!(vec![message("c5517h/lobby/availability", "offline")], bridge.poll());
+		simulator.set_responding(true);
+		assert_eq!(vec![message("c5517h/lobby/availability", "online")], bridge.poll());
+	}
+}
@@ -0,0 +1,298 @@
+//! A minimal MQTT 3.1.1 client: QoS 0 publish and subscribe, keep-alive
+//! and a last will, which is all the Home Assistant bridge needs.
+
+pub mod bridge;
+
+use std::io;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+fn default_broker() -> String { String::from("127.0.0.1:1883") }
+fn default_client_id() -> String { String::from("c5517h") }
+fn default_base_topic() -> String { String::from("c5517h") }
+fn default_discovery_prefix() -> String { String::from("homeassistant") }
+fn default_interval_ms() -> u64 { 10000 }
+fn default_keep_alive() -> u16 { 60 }
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MqttConfig {
+	/// Broker address as `host:port`.
+	#[serde(default = "default_broker")]
+	pub broker: String,
+	#[serde(default = "default_client_id")]
+	pub client_id: String,
+	#[serde(default)]
+	pub username: Option<String>,
+	#[serde(default)]
+	pub password: Option<String>,
+	/// Prefix of state, command and availability topics.
+	#[serde(default = "default_base_topic")]
+	pub base_topic: String,
+	#[serde(default = "default_discovery_prefix")]
+	pub discovery_prefix: String,
+	/// Time between two polls of the monitors.
+	#[serde(default = "default_interval_ms")]
+	pub interval_ms: u64,
+	/// Keep-alive in seconds.
+	#[serde(default = "default_keep_alive")]
+	pub keep_alive: u16,
+}
+
+impl Default for MqttConfig {
+	fn default() -> Self {
+		MqttConfig{
+			broker: default_broker(),
+			client_id: default_client_id(),
+			username: None,
+			password: None,
+			base_topic: default_base_topic(),
+			discovery_prefix: default_discovery_prefix(),
+			interval_ms: default_interval_ms(),
+			keep_alive: default_keep_alive(),
+		}
+	}
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Message {
+	pub topic: String,
+	pub payload: Vec<u8>,
+	pub retain: bool,
+}
+
+impl Message {
+	pub fn new(topic: String, payload: &str, retain: bool) -> Message {
+		Message{topic, payload: payload.as_bytes().to_vec(), retain}
+	}
+}
+
+const CONNECT : u8 = 0x10;
+const CONNACK : u8 = 0x20;
+const PUBLISH : u8 = 0x30;
+const PUBACK : u8 = 0x40;
+const SUBSCRIBE : u8 = 0x82;
+const PINGREQ : u8 = 0xC0;
+const DISCONNECT : u8 = 0xE0;
+
+fn invalid_data(message: &str) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn put_string(buf: &mut Vec<u8>, x: &[u8]) {
+	buf.extend_from_slice(&[(x.len() >> 8) as u8, x.len() as u8]);
+	buf.extend_from_slice(x);
+}
+
+fn get_string(buf: &[u8]) -> io::Result<(&[u8], &[u8])> {
+	if buf.len() < 2 {
+		return Err(invalid_data("truncated string"));
+	}
+	let len = (buf[0] as usize) << 8 | buf[1] as usize;
+	if buf.len() < len + 2 {
+		return Err(invalid_data("truncated string"));
+	}
+	Ok((&buf[2..len + 2], &buf[len + 2..]))
+}
+
+/// Fixed header followed by `body`.
+pub fn packet(header: u8, body: &[u8]) -> Vec<u8> {
+	let mut buf = vec![header];
+	let mut len = body.len();
+	loop {
+		let byte = (len % 128) as u8;
+		len /= 128;
+		if len == 0 {
+			buf.push(byte);
+			break;
+		}
+		buf.push(byte | 0x80);
+	}
+	buf.extend_from_slice(body);
+	buf
+}
+
+pub fn connect_packet(config: &MqttConfig, will: &Message) -> Vec<u8> {
+	let mut flags = 0x02 | 0x04;
+	if will.retain {
+		flags |= 0x20;
+	}
+	if config.password.is_some() {
+		flags |= 0x40;
+	}
+	if config.username.is_some() {
+		flags |= 0x80;
+	}
+
+	let mut body = Vec::new();
+	put_string(&mut body, b"MQTT");
+	body.extend_from_slice(&[4, flags, (config.keep_alive >> 8) as u8, config.keep_alive as u8]);
+	put_string(&mut body, config.client_id.as_bytes());
+	put_string(&mut body, will.topic.as_bytes());
+	put_string(&mut body, &will.payload);
+	if let Some(ref username) = config.username {
+		put_string(&mut body, username.as_bytes());
+	}
+	if let Some(ref password) = config.password {
+		put_string(&mut body, password.as_bytes());
+	}
+	packet(CONNECT, &body)
+}
+
+pub fn publish_packet(message: &Message) -> Vec<u8> {
+	let mut body = Vec::new();
+	put_string(&mut body, message.topic.as_bytes());
+	body.extend_from_slice(&message.payload);
+	packet(PUBLISH | message.retain as u8, &body)
+}
+
+pub fn subscribe_packet(packet_id: u16, filter: &str) -> Vec<u8> {
+	let mut body = vec![(packet_id >> 8) as u8, packet_id as u8];
+	put_string(&mut body, filter.as_bytes());
+	body.push(0);
+	packet(SUBSCRIBE, &body)
+}
+
+/// Topic and payload of a PUBLISH packet body, and its packet id if it needs an acknowledgement.
+pub fn parse_publish(header: u8, body: &[u8]) -> io::Result<(Message, Option<u16>)> {
+	let (topic, rest) = get_string(body)?;
+	let topic = String::from_utf8(topic.to_vec()).map_err(|_| invalid_data("topic is not UTF-8"))?;
+	let (packet_id, payload) = match (header >> 1) & 0x03 {
+		0 => (None, rest),
+		_ if rest.len() >= 2 => (Some((rest[0] as u16) << 8 | rest[1] as u16), &rest[2..]),
+		_ => return Err(invalid_data("truncated publish")),
+	};
+	Ok((Message{topic, payload: payload.to_vec(), retain: header & 0x01 != 0}, packet_id))
+}
+
+pub struct Client {
+	stream: TcpStream,
+	keep_alive: Duration,
+	last_sent: Instant,
+	packet_id: u16,
+}
+
+impl Client {
+	/// Connects to the broker, which publishes `will` when the connection is lost.
+	pub fn connect(config: &MqttConfig, will: &Message) -> io::Result<Client> {
+		let address = config.broker.to_socket_addrs()?.next()
+			.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "broker address does not resolve"))?;
+		let stream = TcpStream::connect_timeout(&address, Duration::from_secs(10))?;
+		stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+		stream.set_nodelay(true)?;
+
+		let mut client = Client{
+			stream,
+			keep_alive: Duration::from_secs(u64::from(config.keep_alive)),
+			last_sent: Instant::now(),
+			packet_id: 0,
+		};
+		client.send(&connect_packet(config, will))?;
+
+		match client.read_packet()? {
+			(CONNACK, ref body) if body.len() == 2 && body[1] == 0 => Ok(client),
+			(CONNACK, ref body) if body.len() == 2 => Err(io::Error::new(io::ErrorKind::ConnectionRefused,
+				format!("broker refused the connection with code {}", body[1]))),
+			_ => Err(invalid_data("expected CONNACK")),
+		}
+	}
+
+	fn send(&mut self, packet: &[u8]) -> io::Result<()> {
+		self.stream.write_all(packet)?;
+		self.last_sent = Instant::now();
+		Ok(())
+	}
+
+	fn read_packet(&mut self) -> io::Result<(u8, Vec<u8>)> {
+		let mut header = [0u8];
+		self.stream.read_exact(&mut header)?;
+
+		let mut len = 0usize;
+		for shift in 0..4 {
+			let mut byte = [0u8];
+			self.stream.read_exact(&mut byte)?;
+			len |= ((byte[0] & 0x7f) as usize) << (7 * shift);
+			if byte[0] & 0x80 == 0 {
+				let mut body = vec![0u8; len];
+				self.stream.read_exact(&mut body)?;
+				return Ok((header[0], body));
+			}
+		}
+		Err(invalid_data("malformed remaining length"))
+	}
+
+	pub fn publish(&mut self, message: &Message) -> io::Result<()> {
+		self.send(&publish_packet(message))
+	}
+
+	pub fn subscribe(&mut self, filter: &str) -> io::Result<()> {
+		self.packet_id = self.packet_id.wrapping_add(1).max(1);
+		let packet = subscribe_packet(self.packet_id, filter);
+		self.send(&packet)
+	}
+
+	/// Waits up to `timeout` for a message, pinging the broker when the
+	/// connection has been quiet for half the keep-alive.
+	pub fn receive(&mut self, timeout: Duration) -> io::Result<Option<Message>> {
+		if self.last_sent.elapsed() >= self.keep_alive / 2 {
+			self.send(&[PINGREQ, 0])?;
+		}
+
+		self.stream.set_read_timeout(Some(timeout.max(Duration::from_millis(1))))?;
+		let mut header = [0u8];
+		let peeked = self.stream.peek(&mut header);
+		self.stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+		match peeked {
+			Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "broker closed the connection")),
+			Ok(_) => (),
+			Err(ref err) if err.kind() == io::ErrorKind::WouldBlock || err.kind() == io::ErrorKind::TimedOut => return Ok(None),
+			Err(err) => return Err(err),
+		}
+
+		let (header, body) = self.read_packet()?;
+		if header & 0xf0 != PUBLISH {
+			return Ok(None);
+		}
+
+		let (message, packet_id) = parse_publish(header, &body)?;
+		if let Some(packet_id) = packet_id {
+			self.send(&packet(PUBACK, &[(packet_id >> 8) as u8, packet_id as u8]))?;
+		}
+		Ok(Some(message))
+	}
+
+	pub fn disconnect(mut self) -> io::Result<()> {
+		self.send(&[DISCONNECT, 0])
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use mqtt;
+	use mqtt::{Message, MqttConfig};
+
+	#[test]
+	fn mqtt_packets() {
+		assert_eq!(vec![0x30, 0x80, 0x01], mqtt::packet(0x30, &[0; 128])[..3].to_vec());
+
+		let will = Message::new(String::from("c/s"), "offline", true);
+		assert_eq!(b"\x10\x20\x00\x04MQTT\x04\x26\x00\x3c\x00\x06c5517h\x00\x03c/s\x00\x07offline".to_vec(),
+			mqtt::connect_packet(&MqttConfig::default(), &will));
+
+		assert_eq!(b"\x31\x06\x00\x01tabc".to_vec(), mqtt::publish_packet(&Message::new(String::from("t"), "abc", true)));
+		assert_eq!(b"\x82\x08\x00\x01\x00\x03a/#\x00".to_vec(), mqtt::subscribe_packet(1, "a/#"));
+	}
+
+	#[test]
+	fn mqtt_parse_publish() {
+		let (message, packet_id) = mqtt::parse_publish(0x30, b"\x00\x03a/bon").unwrap();
+		assert_eq!(Message::new(String::from("a/b"), "on", false), message);
+		assert_eq!(None, packet_id);
+
+		let (message, packet_id) = mqtt::parse_publish(0x33, b"\x00\x01t\x00\x07on").unwrap();
+		assert_eq!(Message::new(String::from("t"), "on", true), message);
+		assert_eq!(Some(7), packet_id);
+
+		assert!(mqtt::parse_publish(0x30, b"\x00\x09a").is_err());
+	}
+}
@@ -0,0 +1,287 @@
+//! PJLink Class 1 server, so that AV control systems can drive a monitor
+//! as if it were a PJLink display.
+//!
+//! Every monitor listens on its own address. With a password set, the
+//! controller must prefix its first command with the MD5 digest of the
+//! random challenge followed by the password.
+
+use std::io;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::collections::BTreeMap;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+use md5;
+
+use config::Config;
+use monitor::{Monitor, Opener};
+use protocol::decoder;
+use protocol::reply::ResultCode;
+use protocol::transaction;
+use protocol::types;
+
+/// The port PJLink controllers connect to.
+pub const PORT : u16 = 4352;
+
+/// Longest command line: a digest, the header, the command, a parameter of up to 128 bytes.
+const MAX_LINE : u64 = 32 + 7 + 128 + 1;
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct PjlinkConfig {
+	/// Password of the optional authentication.
+	#[serde(default)]
+	pub password: Option<String>,
+	/// Listening address of every monitor, such as `192.168.1.10:4352`.
+	#[serde(default)]
+	pub listen: BTreeMap<String, String>,
+}
+
+/// PJLink inputs in display order, as a type and number digit pair.
+const INPUTS : [(&str, types::VideoInput); 4] = [
+	("11", types::VideoInput::VGA1),
+	("31", types::VideoInput::HDMI1),
+	("32", types::VideoInput::HDMI2),
+	("33", types::VideoInput::DP1),
+];
+
+const ERR1 : &str = "ERR1";
+const ERR2 : &str = "ERR2";
+const ERR3 : &str = "ERR3";
+const ERR4 : &str = "ERR4";
+
+type Reply = Result<String, &'static str>;
+
+/// Undefined commands and parameters are ERR1 and ERR2, a monitor that is
+/// busy or off is ERR3 and anything else is a display failure.
+fn error(err: transaction::Error) -> &'static str {
+	match err {
+		transaction::Error::DecodeError(decoder::Error::DeviceError(ResultCode::ParametersError), _) => ERR2,
+		transaction::Error::DecodeError(decoder::Error::DeviceError(ResultCode::Timeout), _) |
+		transaction::Error::DecodeError(decoder::Error::DeviceError(ResultCode::NotConnected), _) => ERR3,
+		_ => ERR4,
+	}
+}
+
+fn text<T: Into<String>>(x: transaction::Result<T>) -> Reply {
+	x.map(|x| x.into()).map_err(error)
+}
+
+fn ok(x: transaction::Result<()>) -> Reply {
+	x.map(|_| String::from("OK")).map_err(error)
+}
+
+/// Error status of the six PJLink categories, only the last one, "other", is known.
+fn error_status(monitor: &mut Monitor) -> Reply {
+	match monitor.get::<types::PowerState>() {
+		Ok(_) => Ok(String::from("000000")),
+		Err(transaction::Error::DecodeError(decoder::Error::DeviceError(ResultCode::Timeout), _)) => Ok(String::from("000001")),
+		Err(transaction::Error::DecodeError(decoder::Error::DeviceError(_), _)) => Ok(String::from("000002")),
+		Err(err) => Err(error(err)),
+	}
+}
+
+/// Executes a Class 1 command such as `POWR 1`, returns what follows `=` in the response.
+pub fn execute(monitor: &mut Monitor, name: &str, command: &str, parameter: &str) -> Reply {
+	match (command, parameter) {
+		("POWR", "?") => monitor.get::<types::PowerState>()
+			.map(|x| String::from(if x == types::PowerState::On { "1" } else { "0" }))
+			.map_err(error),
+		("POWR", "0") => ok(monitor.set(types::PowerState::Off)),
+		("POWR", "1") => ok(monitor.set(types::PowerState::On)),
+		("INPT", "?") => {
+			let input = monitor.get::<types::VideoInput>().map_err(error)?;
+			INPUTS.iter().find(|&&(_, x)| x == input).map(|&(code, _)| String::from(code)).ok_or(ERR4)
+		},
+		("INPT", code) => match INPUTS.iter().find(|&&(x, _)| x == code) {
+			Some(&(_, input)) => ok(monitor.set(input)),
+			None => Err(ERR2),
+		},
+		("INST", "?") => Ok(INPUTS.iter().map(|&(code, _)| code).collect::<Vec<_>>().join(" ")),
+		("LAMP", "?") => {
+			let hours = monitor.get::<types::BacklightHours>().map_err(error)?;
+			let power = monitor.get::<types::PowerState>().map_err(error)?;
+			Ok(format!("{} {}", u16::from(hours), if power == types::PowerState::On { 1 } else { 0 }))
+		},
+		("ERST", "?") => error_status(monitor),
+		("NAME", "?") => Ok(String::from(name)),
+		("INF1", "?") => text(monitor.get::<types::MonitorName>()),
+		("INF2", "?") => text(monitor.get::<types::SerialNumber>()),
+		("INFO", "?") => text(monitor.get::<types::VersionFirmware>()),
+		("CLSS", "?") => Ok(String::from("1")),
+		("POWR", _) | ("LAMP", _) | ("ERST", _) | ("NAME", _) | ("INF1", _) | ("INF2", _) |
+		("INFO", _) | ("CLSS", _) | ("INST", _) => Err(ERR2),
+		_ => Err(ERR1),
+	}
+}
+
+/// Random challenge of the authentication.
+fn challenge() -> String {
+	let mut hasher = RandomState::new().build_hasher();
+	hasher.write_u128(SystemTime::now().duration_since(UNIX_EPOCH).map(|x| x.as_nanos()).unwrap_or(0));
+	format!("{:08x}", hasher.finish() as u32)
+}
+
+pub fn digest(challenge: &str, password: &str) -> String {
+	format!("{:x}", md5::compute(format!("{}{}", challenge, password)))
+}
+
+/// One monitor presented as a PJLink display.
+pub struct Server {
+	name: String,
+	password: Option<String>,
+	config: Arc<Config>,
+	opener: Arc<Opener>,
+	monitor: Mutex<Option<Monitor>>,
+}
+
+impl Server {
+	pub fn new(name: &str, password: Option<String>, config: Arc<Config>, opener: Arc<Opener>) -> Server {
+		Server{name: String::from(name), password, config, opener, monitor: Mutex::new(None)}
+	}
+
+	/// Response to one command line, without the digest.
+	fn respond(&self, line: &str) -> String {
+		let (command, parameter) = match (line.get(..2), line.get(2..6), line.get(6..7), line.get(7..)) {
+			(Some("%1"), Some(command), Some(" "), Some(parameter)) => (command, parameter),
+			(Some("%1"), Some(command), _, _) => return format!("%1{}={}\r", command, ERR2),
+			_ => return String::from("%1ERR1\r"),
+		};
+		let command = command.to_ascii_uppercase();
+
+		let mut guard = self.monitor.lock().unwrap();
+		if guard.is_none() {
+			let (name, monitor) = self.config.monitor(&self.name).unwrap();
+			*guard = (self.opener)(name, monitor, &self.config).ok();
+		}
+		let reply = match *guard {
+			Some(ref mut monitor) => execute(monitor, &self.name, &command, parameter),
+			None => Err(ERR4),
+		};
+		if reply == Err(ERR4) {
+			*guard = None;
+		}
+
+		format!("%1{}={}\r", command, reply.unwrap_or_else(String::from))
+	}
+
+	/// Talks to one controller until it disconnects or fails to authenticate.
+	pub fn session<S: Read + Write>(&self, stream: S) -> io::Result<()> {
+		let mut stream = BufReader::new(stream);
+		let challenge = challenge();
+		let mut authenticated = match self.password {
+			Some(_) => {
+				write!(stream.get_mut(), "PJLINK 1 {}\r", challenge)?;
+				false
+			},
+			None => {
+				write!(stream.get_mut(), "PJLINK 0\r")?;
+				true
+			},
+		};
+
+		loop {
+			let mut line = Vec::new();
+			if stream.by_ref().take(MAX_LINE).read_until(b'\r', &mut line)? == 0 {
+				return Ok(());
+			}
+			let line = String::from_utf8_lossy(&line);
+			let mut line = line.trim_matches(&['\r', '\n'][..]);
+
+			if !authenticated {
+				let expected = digest(&challenge, self.password.as_ref().unwrap());
+				match line.get(..32) {
+					Some(x) if x.eq_ignore_ascii_case(&expected) => line = &line[32..],
+					_ => return write!(stream.get_mut(), "PJLINK ERRA\r"),
+				}
+				authenticated = true;
+			}
+
+			let response = self.respond(line);
+			stream.get_mut().write_all(response.as_bytes())?;
+		}
+	}
+
+	pub fn serve(self: Arc<Self>, listener: TcpListener) -> io::Result<()> {
+		for stream in listener.incoming() {
+			let stream : TcpStream = match stream {
+				Ok(stream) => stream,
+				Err(_) => continue,
+			};
+			let server = self.clone();
+
+			thread::spawn(move || {
+				// Controllers are dropped after 30 seconds of silence
+				let _ = stream.set_read_timeout(Some(Duration::from_secs(30)));
+				let _ = server.session(&stream);
+			});
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io;
+	use std::io::{Read, Write};
+	use std::sync::Arc;
+
+	use pjlink;
+	use pjlink::Server;
+	use protocol::reply::ResultCode;
+	use simulator;
+	use simulator::Simulator;
+
+	struct Controller {
+		input: io::Cursor<Vec<u8>>,
+		output: Vec<u8>,
+	}
+
+	impl Read for Controller {
+		fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> { self.input.read(buf) }
+	}
+
+	impl Write for Controller {
+		fn write(&mut self, buf: &[u8]) -> io::Result<usize> { self.output.write(buf) }
+		fn flush(&mut self) -> io::Result<()> { Ok(()) }
+	}
+
+	fn server(simulator: &Simulator, password: Option<&str>) -> Server {
+		let (config, opener) = simulator::fixture(&[("lobby", simulator)]);
+		Server::new("lobby", password.map(String::from), Arc::new(config), Arc::new(opener))
+	}
+
+	fn session(server: &Server, input: &str) -> String {
+		let mut controller = Controller{input: io::Cursor::new(input.as_bytes().to_vec()), output: Vec::new()};
+		server.session(&mut controller).unwrap();
+		String::from_utf8(controller.output).unwrap()
+	}
+
+	#[test]
+	fn pjlink_commands() {
+		let simulator = Simulator::new();
+		let server = server(&simulator, None);
+
+		assert_eq!("PJLINK 0\r%1POWR=1\r%1INPT=OK\r%1INPT=32\r%1INPT=ERR2\r%1NAME=lobby\r%1INF1=DELL C5517H\r%1INFO=M2T104\r%1LAMP=1234 1\r%1AVMT=ERR1\r",
+			session(&server, "%1POWR ?\r%1INPT 32\r%1INPT ?\r%1INPT 59\r%1NAME ?\r%1INF1 ?\r%1INFO ?\r%1LAMP ?\r%1AVMT ?\r"));
+
+		simulator.fail_next(ResultCode::Other);
+		assert_eq!("PJLINK 0\r%1ERST=000002\r%1ERST=000000\r", session(&server, "%1ERST ?\r%1ERST ?\r"));
+
+		simulator.set_responding(false);
+		assert_eq!("PJLINK 0\r%1POWR=ERR4\r", session(&server, "%1POWR 0\r"));
+	}
+
+	#[test]
+	fn pjlink_authentication() {
+		let server = server(&Simulator::new(), Some("JBMIAProjectorLink"));
+
+		assert_eq!("5d8409bc1c3fa39749434aa3a5c38682", pjlink::digest("498e4a67", "JBMIAProjectorLink"));
+		let output = session(&server, "00000000000000000000000000000000%1POWR ?\r");
+		assert!(output.starts_with("PJLINK 1 "));
+		assert!(output.ends_with("\rPJLINK ERRA\r"));
+	}
+}
@@ -0,0 +1,212 @@
+use std;
+use std::fmt;
+use std::error;
+use std::str::FromStr;
+use std::time::Duration;
+
+use serde::{Deserialize, Deserializer};
+use serde::de;
+use serialport::prelude::*;
+
+use lock;
+use lock::LockPolicy;
+
+use protocol::types;
+use protocol::command::Get;
+use protocol::transaction;
+use protocol::transaction::transaction;
+
+#[derive(Debug)]
+pub enum Error {
+	OpenError(lock::Error),
+	TransactionError(transaction::Error),
+	NotDetected,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Error::OpenError(ref open_error) =>
+				write!(f, "open error: {}", open_error),
+			Error::TransactionError(ref transaction_error) =>
+				write!(f, "transaction error: {}", transaction_error),
+			Error::NotDetected =>
+				write!(f, "no line settings yield a valid reply"),
+		}
+	}
+}
+
+impl error::Error for Error {
+	fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+		match self {
+			Error::OpenError(ref open_error) => Some(open_error),
+			Error::TransactionError(ref transaction_error) => Some(transaction_error),
+			Error::NotDetected => None,
+		}
+	}
+}
+
+/// Baud rates tried by `detect`, the factory default first.
+pub const BAUD_RATES : [u32; 7] = [9600, 19200, 38400, 57600, 115200, 4800, 2400];
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LineParity {
+	None,
+	Odd,
+	Even,
+}
+
+/// Line settings of a port, the protocol always uses eight data bits.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LineSettings {
+	pub baud_rate: u32,
+	pub parity: LineParity,
+	/// 1 or 2.
+	#[serde(deserialize_with = "deserialize_stop_bits")]
+	pub stop_bits: u8,
+}
+
+fn deserialize_stop_bits<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<u8, D::Error> {
+	match u8::deserialize(deserializer)? {
+		x @ 1..=2 => Ok(x),
+		x => Err(de::Error::invalid_value(de::Unexpected::Unsigned(u64::from(x)), &"1 or 2 stop bits")),
+	}
+}
+
+impl Default for LineSettings {
+	fn default() -> Self {
+		LineSettings{baud_rate: 9600, parity: LineParity::None, stop_bits: 1}
+	}
+}
+
+impl fmt::Display for LineSettings {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let parity = match self.parity {
+			LineParity::None => 'N',
+			LineParity::Odd => 'O',
+			LineParity::Even => 'E',
+		};
+		write!(f, "{} 8{}{}", self.baud_rate, parity, self.stop_bits)
+	}
+}
+
+/// Parses settings as displayed, such as `19200 8E1`.
+impl FromStr for LineSettings {
+	type Err = String;
+
+	fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+		let invalid = || format!("{}: expected line settings such as 9600 8N1", s);
+		let (baud_rate, frame) = s.trim().split_once(' ').ok_or_else(invalid)?;
+		let baud_rate = baud_rate.parse().map_err(|_| invalid())?;
+		let (parity, stop_bits) = match frame.trim().as_bytes() {
+			&[b'8', parity, stop_bits] => (parity, stop_bits),
+			_ => return Err(invalid()),
+		};
+		let parity = match parity.to_ascii_uppercase() {
+			b'N' => LineParity::None,
+			b'O' => LineParity::Odd,
+			b'E' => LineParity::Even,
+			_ => return Err(invalid()),
+		};
+		let stop_bits = match stop_bits {
+			b'1' => 1,
+			b'2' => 2,
+			_ => return Err(invalid()),
+		};
+		Ok(LineSettings{baud_rate, parity, stop_bits})
+	}
+}
+
+impl LineSettings {
+	pub fn to_serialport(&self, timeout: Duration) -> SerialPortSettings {
+		SerialPortSettings {
+			baud_rate: self.baud_rate,
+			data_bits: DataBits::Eight,
+			flow_control: FlowControl::None,
+			parity: match self.parity {
+				LineParity::None => Parity::None,
+				LineParity::Odd => Parity::Odd,
+				LineParity::Even => Parity::Even,
+			},
+			stop_bits: match self.stop_bits {
+				2 => StopBits::Two,
+				_ => StopBits::One,
+			},
+			timeout,
+		}
+	}
+
+	/// All combinations tried by `detect`, in order.
+	pub fn candidates() -> Vec<LineSettings> {
+		let mut ret = Vec::new();
+		for &parity in &[LineParity::None, LineParity::Even, LineParity::Odd] {
+			for &stop_bits in &[1, 2] {
+				for &baud_rate in BAUD_RATES.iter() {
+					ret.push(LineSettings{baud_rate, parity, stop_bits});
+				}
+			}
+		}
+		ret
+	}
+}
+
+/// Line settings the monitor uses out of the box: 9600 8N1, no flow control.
+pub fn settings(timeout: Duration) -> SerialPortSettings {
+	LineSettings::default().to_serialport(timeout)
+}
+
+/// Finds the line settings the monitor at `path` answers to.
+///
+/// Every candidate is tried with a harmless `Get<PowerState>`, the first one
+/// that brings back a well-formed reply with a valid checksum wins.
+pub fn detect(path: &str, timeout: Duration, policy: &LockPolicy) -> Result<LineSettings> {
+	let mut reader = lock::open(path, &settings(timeout), policy).map_err(Error::OpenError)?;
+	let mut writer = reader.try_clone().map_err(|x| Error::OpenError(x.into()))?;
+
+	for candidate in LineSettings::candidates() {
+		reader.set_all(&candidate.to_serialport(timeout)).map_err(|x| Error::OpenError(x.into()))?;
+		// Garbage received at a wrong rate must not leak into the next attempt
+		reader.clear(ClearBuffer::All).map_err(|x| Error::OpenError(x.into()))?;
+
+		if transaction::<types::PowerState, _>(&Get::<types::PowerState>::new(), &mut writer, &mut reader).is_ok() {
+			return Ok(candidate);
+		}
+	}
+
+	Err(Error::NotDetected)
+}
+
+#[cfg(test)]
+mod tests {
+	use toml;
+
+	use port::{LineParity, LineSettings};
+
+	#[test]
+	fn line_settings_candidates() {
+		let candidates = LineSettings::candidates();
+		assert_eq!(42, candidates.len());
+		// The factory default first, then every rate before another framing
+		assert_eq!(LineSettings::default(), candidates[0]);
+		assert_eq!(vec!["9600 8N1", "19200 8N1", "38400 8N1", "57600 8N1", "115200 8N1", "4800 8N1", "2400 8N1", "9600 8N2"],
+			candidates[..8].iter().map(|x| x.to_string()).collect::<Vec<_>>());
+		assert_eq!("2400 8O2", candidates[41].to_string());
+
+		for candidate in &candidates {
+			assert_eq!(*candidate, candidate.to_string().parse().unwrap());
+		}
+		assert_eq!(LineSettings{baud_rate: 19200, parity: LineParity::Even, stop_bits: 1}, "19200 8e1".parse().unwrap());
+		for x in ["9600", "9600 8N3", "9600 7N1", "fast 8N1"] {
+			assert!(x.parse::<LineSettings>().is_err(), "{}", x);
+		}
+	}
+
+	#[test]
+	fn line_settings_stop_bits() {
+		assert!(toml::from_str::<LineSettings>("baud_rate = 9600\nparity = \"none\"\nstop_bits = 2").is_ok());
+		assert!(toml::from_str::<LineSettings>("baud_rate = 9600\nparity = \"none\"\nstop_bits = 3").is_err());
+	}
+}
@@ -0,0 +1,459 @@
+//! Monitor properties addressable by name, for front-ends that only have
+//! strings to work with.
+
+use std;
+use std::fmt;
+use std::error;
+
+use monitor::Monitor;
+use nom::error::ErrorKind;
+
+use protocol::HasCommandOpcode;
+use protocol::command::Serialize;
+use protocol::decoder;
+use protocol::reply::Parse;
+use protocol::transaction;
+use protocol::types;
+use protocol::types::TypesError;
+
+#[derive(Debug)]
+pub enum Error {
+	TransactionError(transaction::Error),
+	InvalidValue{ property : &'static str, value : String },
+	OutOfRange(TypesError),
+	NotReadable(&'static str),
+	NotWritable(&'static str),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Error::TransactionError(ref transaction_error) =>
+				write!(f, "transaction error: {}", transaction_error),
+			Error::InvalidValue{property, ref value} =>
+				write!(f, "invalid value for {}: {}", property, value),
+			Error::OutOfRange(ref types_error) =>
+				write!(f, "{}", types_error),
+			Error::NotReadable(property) =>
+				write!(f, "{} is not readable", property),
+			Error::NotWritable(property) =>
+				write!(f, "{} is not writable", property),
+		}
+	}
+}
+
+impl error::Error for Error {
+	fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+		match self {
+			Error::TransactionError(ref transaction_error) => Some(transaction_error),
+			Error::OutOfRange(ref types_error) => Some(types_error),
+			_ => None,
+		}
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum Value {
+	Number(u32),
+	Text(String),
+	Choice(&'static str),
+}
+
+impl fmt::Display for Value {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Value::Number(x) => write!(f, "{}", x),
+			Value::Text(ref x) => write!(f, "{}", x),
+			Value::Choice(x) => write!(f, "{}", x),
+		}
+	}
+}
+
+impl Value {
+	/// Whether `x`, as written in a configuration, names this value.
+	pub fn matches(&self, x: &str) -> bool {
+		match self {
+			Value::Number(n) => x.trim().parse() == Ok(*n),
+			Value::Text(ref text) => text == x,
+			Value::Choice(name) => name.eq_ignore_ascii_case(x.trim()),
+		}
+	}
+}
+
+/// Enumerations with a name for every variant.
+pub trait Choice : Sized + Copy + PartialEq + 'static {
+	fn choices() -> &'static [(&'static str, Self)];
+
+	fn choice_name(&self) -> &'static str {
+		Self::choices().iter().find(|&&(_, x)| x == *self).map(|&(name, _)| name).unwrap()
+	}
+
+	fn from_choice_name(name: &str) -> Option<Self> {
+		Self::choices().iter().find(|&&(x, _)| x.eq_ignore_ascii_case(name)).map(|&(_, x)| x)
+	}
+}
+
+/// Values set from a bounded number.
+pub trait Level : Sized {
+	fn level(x: u8) -> std::result::Result<Self, TypesError>;
+}
+
+impl Choice for types::PowerState {
+	fn choices() -> &'static [(&'static str, Self)] {
+		&[("off", types::PowerState::Off), ("on", types::PowerState::On)]
+	}
+}
+
+impl Choice for types::PowerLED {
+	fn choices() -> &'static [(&'static str, Self)] {
+		&[("off", types::PowerLED::Off), ("on", types::PowerLED::On)]
+	}
+}
+
+impl Choice for types::PowerUSB {
+	fn choices() -> &'static [(&'static str, Self)] {
+		&[("off", types::PowerUSB::Off), ("on", types::PowerUSB::On)]
+	}
+}
+
+impl Choice for types::AspectRatio {
+	fn choices() -> &'static [(&'static str, Self)] {
+		&[("16x9", types::AspectRatio::_16X9), ("4x3", types::AspectRatio::_4X3), ("5x4", types::AspectRatio::_5X4)]
+	}
+}
+
+impl Choice for types::ColorTemperature {
+	fn choices() -> &'static [(&'static str, Self)] {
+		&[
+			("5000k", types::ColorTemperature::_5000K),
+			("5700k", types::ColorTemperature::_5700K),
+			("6500k", types::ColorTemperature::_6500K),
+			("7500k", types::ColorTemperature::_7500K),
+			("9300k", types::ColorTemperature::_9300K),
+			("10000k", types::ColorTemperature::_10000K),
+		]
+	}
+}
+
+impl Choice for types::ColorFormat {
+	fn choices() -> &'static [(&'static str, Self)] {
+		&[("rgb", types::ColorFormat::RGB), ("ypbpr", types::ColorFormat::YPbPr)]
+	}
+}
+
+impl Choice for types::ColorPreset {
+	fn choices() -> &'static [(&'static str, Self)] {
+		&[
+			("standard", types::ColorPreset::Standard),
+			("multimedia", types::ColorPreset::Multimedia),
+			("color-temp", types::ColorPreset::ColorTemp),
+			("custom-color", types::ColorPreset::CustomColor),
+		]
+	}
+}
+
+impl Choice for types::AutoSelect {
+	fn choices() -> &'static [(&'static str, Self)] {
+		&[("off", types::AutoSelect::Off), ("on", types::AutoSelect::On)]
+	}
+}
+
+impl Choice for types::VideoInput {
+	fn choices() -> &'static [(&'static str, Self)] {
+		&[
+			("hdmi1", types::VideoInput::HDMI1),
+			("hdmi2", types::VideoInput::HDMI2),
+			("dp1", types::VideoInput::DP1),
+			("vga1", types::VideoInput::VGA1),
+		]
+	}
+}
+
+impl Choice for types::OSDButtonLock {
+	fn choices() -> &'static [(&'static str, Self)] {
+		&[("unlock", types::OSDButtonLock::Unlock), ("lock", types::OSDButtonLock::Lock)]
+	}
+}
+
+impl Choice for types::LCDConditioning {
+	fn choices() -> &'static [(&'static str, Self)] {
+		&[("disabled", types::LCDConditioning::Disabled), ("enabled", types::LCDConditioning::Enabled)]
+	}
+}
+
+impl Level for types::Brightness {
+	fn level(x: u8) -> std::result::Result<Self, TypesError> { types::Brightness::new(x) }
+}
+
+impl Level for types::Contrast {
+	fn level(x: u8) -> std::result::Result<Self, TypesError> { types::Contrast::new(x) }
+}
+
+impl Level for types::Sharpness {
+	fn level(x: u8) -> std::result::Result<Self, TypesError> { types::Sharpness::new(x) }
+}
+
+impl Level for types::OSDTransparency {
+	fn level(x: u8) -> std::result::Result<Self, TypesError> { types::OSDTransparency::new(x) }
+}
+
+impl Level for types::OSDTimer {
+	fn level(x: u8) -> std::result::Result<Self, TypesError> { types::OSDTimer::new(x) }
+}
+
+type Getter = fn(&mut Monitor) -> Result<Value>;
+type Decoder = fn(&[u8]) -> Result<Value>;
+type Setter = fn(&mut Monitor, &str) -> Result<()>;
+type Checker = fn(&str) -> Result<()>;
+
+/// Shape of a property value.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+	Number,
+	Text,
+	Choice,
+}
+
+pub struct Property {
+	name: &'static str,
+	opcode: fn() -> u8,
+	kind: Kind,
+	choices: fn() -> Vec<&'static str>,
+	get: Option<Getter>,
+	decode: Option<Decoder>,
+	set: Option<Setter>,
+	check: Option<Checker>,
+}
+
+fn text<T : Into<String>>(x: T) -> Value {
+	Value::Text(x.into())
+}
+
+fn number<T, N : From<T>>(x: T) -> Value
+	where u32 : From<N> {
+	Value::Number(u32::from(N::from(x)))
+}
+
+fn choice<T : Choice>(x: T) -> Value {
+	Value::Choice(x.choice_name())
+}
+
+/// Reply to a `T` command in a frame of the monitor.
+fn decode<T : HasCommandOpcode + Parse>(reply: &[u8]) -> Result<T> {
+	decoder::decode::<T, (&[u8], ErrorKind)>(reply).map_err(|err| {
+		Error::TransactionError(transaction::Error::DecodeError(err.map(|(_, kind)| kind), reply.to_vec()))
+	})
+}
+
+fn get_text<T : HasCommandOpcode + Parse + Into<String>>(m: &mut Monitor) -> Result<Value> {
+	m.get::<T>().map(text).map_err(Error::TransactionError)
+}
+
+fn decode_text<T : HasCommandOpcode + Parse + Into<String>>(reply: &[u8]) -> Result<Value> {
+	decode::<T>(reply).map(text)
+}
+
+fn get_number<T : HasCommandOpcode + Parse, N : From<T>>(m: &mut Monitor) -> Result<Value>
+	where u32 : From<N> {
+	m.get::<T>().map(number::<T, N>).map_err(Error::TransactionError)
+}
+
+fn decode_number<T : HasCommandOpcode + Parse, N : From<T>>(reply: &[u8]) -> Result<Value>
+	where u32 : From<N> {
+	decode::<T>(reply).map(number::<T, N>)
+}
+
+fn get_choice<T : HasCommandOpcode + Parse + Choice>(m: &mut Monitor) -> Result<Value> {
+	m.get::<T>().map(choice).map_err(Error::TransactionError)
+}
+
+fn decode_choice<T : HasCommandOpcode + Parse + Choice>(reply: &[u8]) -> Result<Value> {
+	decode::<T>(reply).map(choice)
+}
+
+fn parse_level<T : Level>(value: &str) -> Result<T> {
+	let x = value.parse::<u8>().map_err(|_| Error::InvalidValue{property: "level", value: String::from(value)})?;
+	T::level(x).map_err(Error::OutOfRange)
+}
+
+fn parse_choice<T : Choice>(value: &str) -> Result<T> {
+	T::from_choice_name(value).ok_or_else(|| Error::InvalidValue{property: "choice", value: String::from(value)})
+}
+
+fn set_level<T : HasCommandOpcode + Serialize + Level>(m: &mut Monitor, value: &str) -> Result<()> {
+	let x = parse_level::<T>(value)?;
+	m.set(x).map_err(Error::TransactionError)
+}
+
+fn check_level<T : Level>(value: &str) -> Result<()> {
+	parse_level::<T>(value).map(|_| ())
+}
+
+fn set_choice<T : HasCommandOpcode + Serialize + Choice>(m: &mut Monitor, value: &str) -> Result<()> {
+	let x = parse_choice::<T>(value)?;
+	m.set(x).map_err(Error::TransactionError)
+}
+
+fn check_choice<T : Choice>(value: &str) -> Result<()> {
+	parse_choice::<T>(value).map(|_| ())
+}
+
+fn no_choices() -> Vec<&'static str> {
+	Vec::new()
+}
+
+fn choice_names<T : Choice>() -> Vec<&'static str> {
+	T::choices().iter().map(|&(name, _)| name).collect()
+}
+
+static PROPERTIES : [Property; 20] = [
+	Property{name: "name", opcode: types::MonitorName::opcode, kind: Kind::Text, choices: no_choices,
+		get: Some(get_text::<types::MonitorName>), decode: Some(decode_text::<types::MonitorName>), set: None, check: None},
+	Property{name: "serial-number", opcode: types::SerialNumber::opcode, kind: Kind::Text, choices: no_choices,
+		get: Some(get_text::<types::SerialNumber>), decode: Some(decode_text::<types::SerialNumber>), set: None, check: None},
+	Property{name: "firmware", opcode: types::VersionFirmware::opcode, kind: Kind::Text, choices: no_choices,
+		get: Some(get_text::<types::VersionFirmware>), decode: Some(decode_text::<types::VersionFirmware>), set: None, check: None},
+	Property{name: "backlight-hours", opcode: types::BacklightHours::opcode, kind: Kind::Number, choices: no_choices,
+		get: Some(get_number::<types::BacklightHours, u16>), decode: Some(decode_number::<types::BacklightHours, u16>), set: None, check: None},
+	Property{name: "power", opcode: types::PowerState::opcode, kind: Kind::Choice, choices: choice_names::<types::PowerState>,
+		get: Some(get_choice::<types::PowerState>), decode: Some(decode_choice::<types::PowerState>), set: Some(set_choice::<types::PowerState>), check: Some(check_choice::<types::PowerState>)},
+	Property{name: "power-led", opcode: types::PowerLED::opcode, kind: Kind::Choice, choices: choice_names::<types::PowerLED>,
+		get: Some(get_choice::<types::PowerLED>), decode: Some(decode_choice::<types::PowerLED>), set: Some(set_choice::<types::PowerLED>), check: Some(check_choice::<types::PowerLED>)},
+	Property{name: "power-usb", opcode: types::PowerUSB::opcode, kind: Kind::Choice, choices: choice_names::<types::PowerUSB>,
+		get: Some(get_choice::<types::PowerUSB>), decode: Some(decode_choice::<types::PowerUSB>), set: Some(set_choice::<types::PowerUSB>), check: Some(check_choice::<types::PowerUSB>)},
+	Property{name: "brightness", opcode: types::Brightness::opcode, kind: Kind::Number, choices: no_choices,
+		get: Some(get_number::<types::Brightness, u8>), decode: Some(decode_number::<types::Brightness, u8>), set: Some(set_level::<types::Brightness>), check: Some(check_level::<types::Brightness>)},
+	Property{name: "contrast", opcode: types::Contrast::opcode, kind: Kind::Number, choices: no_choices,
+		get: Some(get_number::<types::Contrast, u8>), decode: Some(decode_number::<types::Contrast, u8>), set: Some(set_level::<types::Contrast>), check: Some(check_level::<types::Contrast>)},
+	Property{name: "aspect-ratio", opcode: types::AspectRatio::opcode, kind: Kind::Choice, choices: choice_names::<types::AspectRatio>,
+		get: Some(get_choice::<types::AspectRatio>), decode: Some(decode_choice::<types::AspectRatio>), set: Some(set_choice::<types::AspectRatio>), check: Some(check_choice::<types::AspectRatio>)},
+	Property{name: "sharpness", opcode: types::Sharpness::opcode, kind: Kind::Number, choices: no_choices,
+		get: Some(get_number::<types::Sharpness, u8>), decode: Some(decode_number::<types::Sharpness, u8>), set: Some(set_level::<types::Sharpness>), check: Some(check_level::<types::Sharpness>)},
+	Property{name: "color-temperature", opcode: types::ColorTemperature::opcode, kind: Kind::Choice, choices: choice_names::<types::ColorTemperature>,
+		get: Some(get_choice::<types::ColorTemperature>), decode: Some(decode_choice::<types::ColorTemperature>), set: Some(set_choice::<types::ColorTemperature>), check: Some(check_choice::<types::ColorTemperature>)},
+	Property{name: "color-format", opcode: types::ColorFormat::opcode, kind: Kind::Choice, choices: choice_names::<types::ColorFormat>,
+		get: Some(get_choice::<types::ColorFormat>), decode: Some(decode_choice::<types::ColorFormat>), set: Some(set_choice::<types::ColorFormat>), check: Some(check_choice::<types::ColorFormat>)},
+	Property{name: "preset", opcode: types::ColorPreset::opcode, kind: Kind::Choice, choices: choice_names::<types::ColorPreset>,
+		get: Some(get_choice::<types::ColorPreset>), decode: Some(decode_choice::<types::ColorPreset>), set: Some(set_choice::<types::ColorPreset>), check: Some(check_choice::<types::ColorPreset>)},
+	Property{name: "auto-select", opcode: types::AutoSelect::opcode, kind: Kind::Choice, choices: choice_names::<types::AutoSelect>,
+		get: Some(get_choice::<types::AutoSelect>), decode: Some(decode_choice::<types::AutoSelect>), set: Some(set_choice::<types::AutoSelect>), check: Some(check_choice::<types::AutoSelect>)},
+	Property{name: "input", opcode: types::VideoInput::opcode, kind: Kind::Choice, choices: choice_names::<types::VideoInput>,
+		get: Some(get_choice::<types::VideoInput>), decode: Some(decode_choice::<types::VideoInput>), set: Some(set_choice::<types::VideoInput>), check: Some(check_choice::<types::VideoInput>)},
+	Property{name: "osd-transparency", opcode: types::OSDTransparency::opcode, kind: Kind::Number, choices: no_choices,
+		get: None, decode: None, set: Some(set_level::<types::OSDTransparency>), check: Some(check_level::<types::OSDTransparency>)},
+	Property{name: "osd-timer", opcode: types::OSDTimer::opcode, kind: Kind::Number, choices: no_choices,
+		get: None, decode: None, set: Some(set_level::<types::OSDTimer>), check: Some(check_level::<types::OSDTimer>)},
+	Property{name: "osd-button-lock", opcode: types::OSDButtonLock::opcode, kind: Kind::Choice, choices: choice_names::<types::OSDButtonLock>,
+		get: Some(get_choice::<types::OSDButtonLock>), decode: Some(decode_choice::<types::OSDButtonLock>), set: Some(set_choice::<types::OSDButtonLock>), check: Some(check_choice::<types::OSDButtonLock>)},
+	Property{name: "lcd-conditioning", opcode: types::LCDConditioning::opcode, kind: Kind::Choice, choices: choice_names::<types::LCDConditioning>,
+		get: Some(get_choice::<types::LCDConditioning>), decode: Some(decode_choice::<types::LCDConditioning>), set: Some(set_choice::<types::LCDConditioning>), check: Some(check_choice::<types::LCDConditioning>)},
+];
+
+impl Property {
+	pub fn name(&self) -> &'static str {
+		self.name
+	}
+
+	pub fn opcode(&self) -> u8 {
+		(self.opcode)()
+	}
+
+	pub fn kind(&self) -> Kind {
+		self.kind
+	}
+
+	/// Accepted values of an enumeration, empty for numbers and text.
+	pub fn choices(&self) -> Vec<&'static str> {
+		(self.choices)()
+	}
+
+	pub fn is_readable(&self) -> bool {
+		self.get.is_some()
+	}
+
+	pub fn is_writable(&self) -> bool {
+		self.set.is_some()
+	}
+
+	pub fn get(&self, m: &mut Monitor) -> Result<Value> {
+		match self.get {
+			Some(get) => get(m),
+			None => Err(Error::NotReadable(self.name)),
+		}
+	}
+
+	/// Value of a reply frame of the monitor, decoded the way `get` does.
+	pub fn decode(&self, reply: &[u8]) -> Result<Value> {
+		let decode = self.decode.ok_or(Error::NotReadable(self.name))?;
+		decode(reply)
+	}
+
+	pub fn set(&self, m: &mut Monitor, value: &str) -> Result<()> {
+		let set = self.set.ok_or(Error::NotWritable(self.name))?;
+		set(m, value).map_err(|err| self.named(err))
+	}
+
+	/// Whether `set` would accept `value`, without writing it.
+	pub fn check(&self, value: &str) -> Result<()> {
+		let check = self.check.ok_or(Error::NotWritable(self.name))?;
+		check(value).map_err(|err| self.named(err))
+	}
+
+	fn named(&self, err: Error) -> Error {
+		match err {
+			Error::InvalidValue{value, ..} => Error::InvalidValue{property: self.name, value},
+			err => err,
+		}
+	}
+}
+
+pub fn properties() -> &'static [Property] {
+	&PROPERTIES
+}
+
+pub fn find(name: &str) -> Option<&'static Property> {
+	PROPERTIES.iter().find(|x| x.name == name)
+}
+
+pub fn find_by_opcode(opcode: u8) -> Option<&'static Property> {
+	PROPERTIES.iter().find(|x| x.opcode() == opcode)
+}
+
+#[cfg(test)]
+mod tests {
+	use property;
+	use property::{Choice, Error, Value};
+	use protocol::decoder;
+	use protocol::frame::monitor_frame;
+	use protocol::transaction;
+	use protocol::types;
+
+	#[test]
+	fn choice_names() {
+		assert_eq!("hdmi2", types::VideoInput::HDMI2.choice_name());
+		assert_eq!(Some(types::VideoInput::HDMI2), types::VideoInput::from_choice_name("HDMI2"));
+		assert_eq!(None, types::VideoInput::from_choice_name("hdmi3"));
+	}
+
+	#[test]
+	fn find_property() {
+		let input = property::find("input").unwrap();
+		assert_eq!(0x62, input.opcode());
+		assert!(input.is_readable() && input.is_writable());
+		assert_eq!(vec!["hdmi1", "hdmi2", "dp1", "vga1"], input.choices());
+		assert_eq!("brightness", property::find_by_opcode(0x30).unwrap().name());
+		assert!(property::find("volume").is_none());
+	}
+
+	#[test]
+	fn decode_property() {
+		let brightness = property::find("brightness").unwrap();
+		assert_eq!(Value::Number(42), brightness.decode(&monitor_frame(0, 0x30, &[42])).unwrap());
+		assert!(matches!(brightness.decode(&monitor_frame(0, 0x31, &[42])),
+			Err(Error::TransactionError(transaction::Error::DecodeError(decoder::Error::OpcodeMismatch{expected: 0x30, actual: 0x31}, _)))));
+		assert!(matches!(property::find("osd-timer").unwrap().decode(&monitor_frame(0, 0x83, &[20])), Err(Error::NotReadable("osd-timer"))));
+	}
+}
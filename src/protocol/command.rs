@@ -72,8 +72,12 @@ impl<T: HasCommandOpcode + Serialize> Command for Set<T> {
 	fn dump<U: Write>(&self, w: U) -> io::Result<u8> { <T as Serialize>::dump(&self.object, w) }
 }
 
-impl NullaryCommand for ResetPower {
+impl HasCommandOpcode for ResetPower {
 	fn opcode() -> u8 { 0x2F }
+}
+
+impl NullaryCommand for ResetPower {
+	fn opcode() -> u8 { <ResetPower as HasCommandOpcode>::opcode() }
 	fn direction() -> Direction { Direction::Write }
 }
 
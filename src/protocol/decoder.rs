@@ -13,21 +13,47 @@ use nom::IResult;
 use nom;
 
 use num;
+use tracing;
 
 use protocol::checksum::{CheckSum, XORCheckSum};
+use protocol::frame::MONITOR_PREFIX;
 use protocol::reply::{Reply, ResultCode, NullaryReply, Parse};
 
 use super::HasCommandOpcode;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Error<E = ()> {
-	ChecksumError,
+	/// The checksum byte of the frame and the one its bytes add up to.
+	ChecksumError{received: u8, computed: u8},
+	/// A reply to another command than the one sent.
+	OpcodeMismatch{expected: u8, actual: u8},
 	ParseError(nom::Err<E>),
 	DeviceError(ResultCode),
 }
 
 pub type Result<T, E = ()> = std::result::Result<T, Error<E>>;
 
+impl<E> Error<E> {
+	/// Whether repeating the request may succeed. Only a request the device
+	/// rejected is fatal, anything else may be noise on the line or a reply
+	/// left over from an earlier request.
+	pub fn is_retryable(&self) -> bool {
+		!matches!(self, Error::DeviceError(ResultCode::ParametersError))
+	}
+
+	/// Converts the parser error, such as to drop the input it refers to.
+	pub fn map<E2, F : FnOnce(E) -> E2>(self, f: F) -> Error<E2> {
+		match self {
+			Error::ChecksumError{received, computed} => Error::ChecksumError{received, computed},
+			Error::OpcodeMismatch{expected, actual} => Error::OpcodeMismatch{expected, actual},
+			Error::ParseError(nom::Err::Incomplete(needed)) => Error::ParseError(nom::Err::Incomplete(needed)),
+			Error::ParseError(nom::Err::Error(e)) => Error::ParseError(nom::Err::Error(f(e))),
+			Error::ParseError(nom::Err::Failure(e)) => Error::ParseError(nom::Err::Failure(f(e))),
+			Error::DeviceError(result_code) => Error::DeviceError(result_code),
+		}
+	}
+}
+
 impl<E> From<nom::Err<E>> for Error<E> {
 	fn from(error: nom::Err<E>) -> Self {
 		Error::ParseError(error)
@@ -38,8 +64,10 @@ impl<E> fmt::Display for Error<E>
 	where E : std::fmt::Debug {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
 		match self {
-			Error::ChecksumError =>
-				write!(f, "incorrect checksum"),
+			Error::ChecksumError{received, computed} =>
+				write!(f, "incorrect checksum 0x{:02x}, computed 0x{:02x}", received, computed),
+			Error::OpcodeMismatch{expected, actual} =>
+				write!(f, "reply to opcode 0x{:02x} instead of 0x{:02x}", actual, expected),
 			Error::ParseError(nom) =>
 				write!(f, "parse error: {}", nom),
 			Error::DeviceError(result_code) =>
@@ -59,12 +87,12 @@ impl<E> error::Error for Error<E>
 }
 
 fn validate_checksum<'a, E : ParseError<&'a [u8]>>(input: &'a [u8], end: &'a [u8]) -> Result<(), E> {
-	let len = input.offset(end) + 1;
+	let received = *end.first().ok_or(Error::ParseError(nom::Err::Incomplete(nom::Needed::Size(1))))?;
 	let mut c = XORCheckSum::new();
-	c.consume(&input[..len]);
+	c.consume(&input[..input.offset(end)]);
 	match c.value() {
-		0 => Ok(()),
-		_ => Err(Error::ChecksumError),
+		computed if computed == received => Ok(()),
+		computed => Err(Error::ChecksumError{received, computed}),
 	}
 }
 
@@ -78,9 +106,7 @@ fn do_decode_payload<'a, T : Reply, E : ParseError<&'a [u8]>>(i: &'a [u8]) -> IR
 }
 
 fn do_decode<'a, T : Reply, E : ParseError<&'a [u8]>>(i: &'a [u8]) -> IResult<&'a [u8], (u8, &'a[u8]), E> {
-	const PREFIX : [u8; 2] = [0x6f as u8, 0x37];
-
-	let (i, _) = tag(PREFIX)(i)?;
+	let (i, _) = tag(MONITOR_PREFIX)(i)?;
 	let (i, length) = be_u8(i)?;
 	let (i, _) = tag(&[0x02 as u8][..])(i)?;
 	let (i, result_code) = verify(be_u8, |val: &u8| *val < 5)(i)?;
@@ -91,12 +117,14 @@ fn do_decode<'a, T : Reply, E : ParseError<&'a [u8]>>(i: &'a [u8]) -> IResult<&'
 
 pub fn decode<'a, T : Reply, E : ParseError<&'a [u8]>>(input: &'a [u8]) -> Result<T, E> {
 	let (i, (result_code, bytes)) = do_decode::<T, E>(input)?;
+	tracing::trace!(opcode = T::opcode(), actual_opcode = bytes.first().cloned(), result_code, "reply");
 
 	match result_code {
-		0 => validate_checksum(input, i).and_then(|_| {
-			do_decode_payload::<T, E>(bytes)
+		0 => validate_checksum(input, i).and_then(|_| match bytes.first() {
+			Some(&actual) if actual != T::opcode() => Err(Error::OpcodeMismatch{expected: T::opcode(), actual}),
+			_ => do_decode_payload::<T, E>(bytes)
 				.map(|(_, payload)| payload)
-				.map_err(Error::ParseError)
+				.map_err(Error::ParseError),
 		}),
 		_ => Err(Error::DeviceError(num::FromPrimitive::from_u8(result_code).unwrap()))
 	}
@@ -1,24 +1,33 @@
 use std::io::Write;
 use std::io::Result;
 
-use protocol::command::{Command};
+use tracing;
+
+use protocol::command::{Command, Direction};
 use protocol::checksum::{CheckSum,CheckSumWriter,XORCheckSum};
+use protocol::frame::{hex, HOST_PREFIX};
 
 pub type Error = std::io::Error;
 
-pub fn encode<T : Command, U: Write>(c: &T, w: U) -> Result<usize> {
-	const PREFIX : [u8; 2] = [0x37 as u8, 0x51];
-
-	let mut size = 0;
-	let mut cw = CheckSumWriter::new(XORCheckSum::new(), w);
+/// Writes the frame of `c` at once, tracing it.
+pub fn encode<T : Command, U: Write>(c: &T, mut w: U) -> Result<usize> {
+	let mut cw = CheckSumWriter::new(XORCheckSum::new(), Vec::new());
 
-	size += cw.write(&PREFIX)?;
-	size += cw.write(&[c.length() + 2])?;
-	size += cw.write(&[T::direction() as u8])?;
-	size += cw.write(&[T::opcode()])?;
-	size += c.dump(&mut cw).map(|x| x as usize)?;
+	cw.write_all(&HOST_PREFIX)?;
+	cw.write_all(&[c.length() + 2])?;
+	cw.write_all(&[T::direction() as u8])?;
+	cw.write_all(&[T::opcode()])?;
+	c.dump(&mut cw)?;
 	let checksum = cw.checksum().value();
-	size += cw.inner().write(&[checksum])?;
+	let frame = cw.inner();
+	frame.push(checksum);
+
+	let command = match T::direction() {
+		Direction::Read => "get",
+		Direction::Write => "set",
+	};
+	tracing::trace!(direction = "sent", command, opcode = T::opcode(), frame = %hex(frame), "frame");
+	w.write_all(frame)?;
 
-	Ok(size)
+	Ok(frame.len())
 }
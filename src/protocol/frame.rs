@@ -0,0 +1,74 @@
+//! Whole frames as raw bytes, for code that forwards or inspects traffic
+//! without decoding it.
+//!
+//! Both directions share the layout: two prefix bytes, a length byte, as
+//! many bytes as the length says and a checksum byte.
+
+use std::io;
+use std::io::Read;
+
+use protocol::checksum::{CheckSum, XORCheckSum};
+
+/// Prefix of frames sent by the host.
+pub const HOST_PREFIX : [u8; 2] = [0x37, 0x51];
+/// Prefix of frames sent by the monitor.
+pub const MONITOR_PREFIX : [u8; 2] = [0x6f, 0x37];
+
+/// Bytes as space separated hexadecimal.
+pub fn hex(bytes: &[u8]) -> String {
+	bytes.iter().map(|x| format!("{:02x}", x)).collect::<Vec<_>>().join(" ")
+}
+
+/// Total frame size for the given length byte.
+pub fn frame_size(length: u8) -> usize {
+	length as usize + 4
+}
+
+/// A reply frame of the monitor, with its checksum.
+pub fn monitor_frame(result_code: u8, opcode: u8, payload: &[u8]) -> Vec<u8> {
+	let mut frame = MONITOR_PREFIX.to_vec();
+	frame.extend_from_slice(&[payload.len() as u8 + 3, 0x02, result_code, opcode]);
+	frame.extend_from_slice(payload);
+
+	let mut c = XORCheckSum::new();
+	c.consume(&frame);
+	frame.push(c.value());
+	frame
+}
+
+/// Reads one whole frame starting with `prefix`, anything before it is skipped.
+pub fn read_frame<R : Read + ?Sized>(r: &mut R, prefix: [u8; 2]) -> io::Result<Vec<u8>> {
+	let mut frame = vec![0u8; 3];
+
+	r.read_exact(&mut frame[..2])?;
+	while frame[..2] != prefix {
+		frame[0] = frame[1];
+		r.read_exact(&mut frame[1..2])?;
+	}
+
+	r.read_exact(&mut frame[2..3])?;
+	let size = frame_size(frame[2]);
+	frame.resize(size, 0);
+	r.read_exact(&mut frame[3..])?;
+
+	Ok(frame)
+}
+
+#[cfg(test)]
+mod tests {
+	use protocol::frame::{read_frame, HOST_PREFIX, MONITOR_PREFIX};
+
+	#[test]
+	fn read_frame_skips_garbage() {
+		let x = [0x00 as u8, 0x6f, 0x6f, 0x37, 0x04, 0x02, 0x00, 0x20, 0x01, 127, 0x6f];
+		let mut r = &x[..];
+		assert_eq!(x[2..10], read_frame(&mut r, MONITOR_PREFIX).unwrap()[..]);
+		assert_eq!([0x6f as u8], r);
+	}
+
+	#[test]
+	fn read_frame_truncated() {
+		let x = [0x37 as u8, 0x51, 0x03, 0xea, 0x20];
+		assert!(read_frame(&mut &x[..], HOST_PREFIX).is_err());
+	}
+}
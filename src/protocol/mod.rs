@@ -1,10 +1,11 @@
-mod checksum;
-mod decoder;
+pub(crate) mod checksum;
+pub mod decoder;
 mod encoder;
 pub mod reply;
 pub mod transaction;
 pub mod types;
 pub mod command;
+pub mod frame;
 
 pub trait HasCommandOpcode {
 	fn opcode() -> u8;
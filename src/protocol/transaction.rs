@@ -3,8 +3,12 @@ use std::error;
 use std::io::Write;
 use std::io::Read;
 use std::cmp;
+use std::time::Instant;
+
+use tracing;
 
 use protocol::command::Command;
+use protocol::frame::hex;
 use protocol::reply::Reply;
 use protocol::encoder;
 use protocol::decoder;
@@ -16,11 +20,23 @@ use nom::error::ErrorKind;
 pub enum Error {
 	WriteError(encoder::Error),
 	ReadError(std::io::Error),
-	DecodeError(decoder::Error<()>)
+	/// A reply that could not be decoded, with the bytes read so far.
+	DecodeError(decoder::Error<ErrorKind>, Vec<u8>)
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+impl Error {
+	/// Whether repeating the transaction may succeed, as it may unless the
+	/// device rejected the request.
+	pub fn is_retryable(&self) -> bool {
+		match self {
+			Error::WriteError(_) | Error::ReadError(_) => true,
+			Error::DecodeError(ref decode_error, _) => decode_error.is_retryable(),
+		}
+	}
+}
+
 impl fmt::Display for Error {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
 		match self {
@@ -28,8 +44,8 @@ impl fmt::Display for Error {
 				write!(f, "write error: {}", write_error),
 			Error::ReadError(ref read_error) =>
 				write!(f, "read error: {}", read_error),
-			Error::DecodeError(ref decode_error) =>
-				write!(f, "decode error: {}", decode_error),
+			Error::DecodeError(ref decode_error, ref frame) =>
+				write!(f, "decode error: {} in {}", decode_error, hex(frame)),
 		}
 	}
 }
@@ -39,7 +55,7 @@ impl error::Error for Error {
 		match self {
 			Error::WriteError(ref write_error) => Some(write_error),
 			Error::ReadError(ref read_error) => Some(read_error),
-			Error::DecodeError(ref decode_error) => Some(decode_error)
+			Error::DecodeError(ref decode_error, _) => Some(decode_error)
 		}
 	}
 }
@@ -55,6 +71,7 @@ impl<T: Read> ReadAtLeast for T {
 
 		while read < limit {
 			read += match self.read(&mut buf[read..]) {
+				Ok(0) => return Err(std::io::ErrorKind::UnexpectedEof.into()),
 				Ok(n) => n,
 				Err(err) => match err.kind() {
 					std::io::ErrorKind::Interrupted => 0,
@@ -75,30 +92,114 @@ fn complete_transaction<R : Reply>(mut r : &mut dyn Read) -> Result<R> {
 	let mut read : usize = 0;
 
 	loop {
-		read += r.read_at_least(&mut buf[..], to_read).map_err(|x| Error::ReadError(x))?;
-		let to_read = match decoder::decode(&buf[..read]) {
-			Ok(x) => return Ok(x),
-			Err(decoder::Error::ParseError(nom::Err::Incomplete(needed))) => match needed {
-				nom::Needed::Unknown => 1,
-				nom::Needed::Size(len) => len,
+		read += r.read_at_least(&mut buf[read..], to_read).map_err(|x| {
+			tracing::debug!(direction = "received", frame = %hex(&buf[..read]), error = %x, "read failed");
+			Error::ReadError(x)
+		})?;
+		to_read = match decoder::decode::<R, (&[u8], ErrorKind)>(&buf[..read]) {
+			Ok(x) => {
+				tracing::trace!(direction = "received", frame = %hex(&buf[..read]), "frame");
+				return Ok(x)
 			},
-			Err(x) => return Err(Error::DecodeError(x))
+			// Needed is counted from the failing sub-parser, not from the
+			// end of buf, so just take whatever arrives next.
+			Err(decoder::Error::ParseError(nom::Err::Incomplete(_))) if read < buf.len() => 1,
+			Err(x) => {
+				let x = x.map(|(_, kind)| kind);
+				tracing::debug!(direction = "received", frame = %hex(&buf[..read]), error = %x, "undecodable frame");
+				return Err(Error::DecodeError(x, buf[..read].to_vec()))
+			}
 		};
 	}
 }
 
+/// Runs `f` in a span of its own, tracing how long it took.
+fn traced<R, F : FnOnce() -> Result<R>>(opcode: u8, f: F) -> Result<R> {
+	let span = tracing::debug_span!("transaction", opcode);
+	let _entered = span.enter();
+	let start = Instant::now();
+	let result = f();
+	let elapsed_us = start.elapsed().as_micros() as u64;
+
+	match result {
+		Ok(_) => tracing::debug!(elapsed_us, "done"),
+		Err(ref err) => tracing::debug!(elapsed_us, error = %err, "failed"),
+	}
+	result
+}
+
 pub fn transaction<R : Reply, T : Command>(cmd : &T, w : &mut dyn Write, r : &mut dyn Read) -> Result<R> {
-	encoder::encode(cmd, w)
-		.map_err(|x| Error::WriteError(x))
-		.and_then(|_| complete_transaction::<R>(r))
+	traced(T::opcode(), || {
+		encoder::encode(cmd, w)
+			.map_err(|x| Error::WriteError(x))
+			.and_then(|_| complete_transaction::<R>(r))
+	})
+}
+
+/// Same as `transaction` for a single stream that is both read and written.
+pub fn stream_transaction<R : Reply, T : Command, S : Read + Write>(cmd : &T, s : &mut S) -> Result<R> {
+	traced(T::opcode(), || {
+		encoder::encode(cmd, &mut *s)
+			.map_err(Error::WriteError)
+			.and_then(|_| complete_transaction::<R>(s))
+	})
 }
 
 #[cfg(test)]
 mod tests {
 	use protocol::types;
 	use protocol::command;
-	use protocol::transaction::transaction;
+	use protocol::decoder;
+	use protocol::transaction::{transaction, Error};
 	use protocol::transaction::ReadAtLeast;
+	use std::io::Read;
+	use std::error;
+	use std::fmt;
+	use std::sync::{Arc, Mutex};
+
+	use tracing;
+	use tracing::{Event, Metadata};
+	use tracing::field::{Field, Visit};
+	use tracing::span::{Attributes, Id, Record};
+
+	/// Keeps events as `span: field=value...` lines.
+	#[derive(Clone, Default)]
+	struct Collector {
+		spans: Arc<Mutex<Vec<&'static str>>>,
+		current: Arc<Mutex<Vec<usize>>>,
+		lines: Arc<Mutex<Vec<String>>>,
+	}
+
+	struct Line(String);
+
+	impl Visit for Line {
+		fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+			self.0 += &format!(" {}={:?}", field.name(), value);
+		}
+	}
+
+	impl tracing::Subscriber for Collector {
+		fn enabled(&self, _: &Metadata) -> bool { true }
+		fn new_span(&self, span: &Attributes) -> Id {
+			let mut spans = self.spans.lock().unwrap();
+			spans.push(span.metadata().name());
+			Id::from_u64(spans.len() as u64)
+		}
+		fn record(&self, _: &Id, _: &Record) {}
+		fn record_follows_from(&self, _: &Id, _: &Id) {}
+		fn event(&self, event: &Event) {
+			let span = self.current.lock().unwrap().last().map_or("", |&x| self.spans.lock().unwrap()[x - 1]);
+			let mut line = Line(format!("{}:", span));
+			event.record(&mut line);
+			self.lines.lock().unwrap().push(line.0);
+		}
+		fn enter(&self, span: &Id) {
+			self.current.lock().unwrap().push(span.into_u64() as usize);
+		}
+		fn exit(&self, _: &Id) {
+			self.current.lock().unwrap().pop();
+		}
+	}
 
 	#[test]
 	fn read_at_least() {
@@ -116,5 +217,50 @@ mod tests {
 
 		assert_eq!(types::PowerState::On, transaction(&command::Get::<types::PowerState>::new(), &mut w, &mut r).unwrap());
 	}
-}
 
+	#[test]
+	fn transaction_get_monitor_name_chunked() {
+		let resp = [0x6f as u8, 0x37, 0x0a, 0x02, 0x00, 0x01, b'C', b'5', b'5', b'1', b'7', b'H', 0x00, 0x5c];
+		let mut w = Vec::new();
+		let mut r = (&resp[..7]).chain(&resp[7..10]).chain(&resp[10..]);
+
+		assert_eq!(types::MonitorName::from(String::from("C5517H")), transaction(&command::Get::<types::MonitorName>::new(), &mut w, &mut r).unwrap());
+	}
+
+	#[test]
+	fn transaction_traces_frames() {
+		let resp = [0x6f as u8, 0x37, 0x04, 0x02, 0x00, 0x20, 0x01, 127];
+		let mut w = Vec::new();
+		let mut r = &resp[..];
+		let collector = Collector::default();
+
+		tracing::subscriber::with_default(collector.clone(), || {
+			transaction::<types::PowerState, _>(&command::Get::<types::PowerState>::new(), &mut w, &mut r).unwrap();
+		});
+		let lines = collector.lines.lock().unwrap();
+		assert!(lines[0].starts_with("transaction: message=frame direction=\"sent\" command=\"get\" opcode=32 frame=37 51 02 eb 20 af"), "{}", lines[0]);
+		assert!(lines.iter().any(|x| x.starts_with("transaction: message=frame direction=\"received\" frame=6f 37 04 02 00 20 01 7f")));
+		assert!(lines.last().unwrap().starts_with("transaction: message=done elapsed_us="));
+	}
+
+	#[test]
+	fn transaction_decode_errors() {
+		let get = || command::Get::<types::PowerState>::new();
+
+		let resp = [0x6f as u8, 0x37, 0x04, 0x02, 0x00, 0x20, 0x01, 0x00];
+		let err = transaction::<types::PowerState, _>(&get(), &mut Vec::new(), &mut &resp[..]).unwrap_err();
+		assert!(matches!(err, Error::DecodeError(decoder::Error::ChecksumError{received: 0x00, computed: 0x7f}, ref frame) if frame[..] == resp[..]));
+		assert_eq!("decode error: incorrect checksum 0x00, computed 0x7f in 6f 37 04 02 00 20 01 00", err.to_string());
+		assert!(err.is_retryable());
+
+		let resp = [0x6f as u8, 0x37, 0x04, 0x02, 0x00, 0x30, 0x01, 0x6f];
+		let err = transaction::<types::PowerState, _>(&get(), &mut Vec::new(), &mut &resp[..]).unwrap_err();
+		assert!(matches!(err, Error::DecodeError(decoder::Error::OpcodeMismatch{expected: 0x20, actual: 0x30}, _)));
+		assert!(err.is_retryable());
+
+		let resp = [0x6f as u8, 0x37, 0x03, 0x02, 0x02, 0x20, 0x7b];
+		let err = transaction::<types::PowerState, _>(&get(), &mut Vec::new(), &mut &resp[..]).unwrap_err();
+		assert!(!err.is_retryable());
+		assert!(error::Error::source(&err).is_some());
+	}
+}
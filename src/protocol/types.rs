@@ -7,8 +7,8 @@ use std::option::Option;
 
 use nom::IResult;
 use nom::error::ParseError;
-use nom::number::streaming::{be_u8, be_u16, be_u32};
-use nom::combinator::{map, map_opt};
+use nom::number::streaming::{be_u8, be_u16, le_u32};
+use nom::combinator::{map, map_opt, rest};
 
 use protocol::HasCommandOpcode;
 use protocol::command::{Serialize};
@@ -24,12 +24,17 @@ fn parse_from_u16<'a, T : From<u16>, E : ParseError<&'a [u8]>>(input : &'a [u8])
 	map(be_u16, T::from)(input)
 }
 
+fn parse_string<'a, T : From<String>, E : ParseError<&'a [u8]>>(input : &'a [u8]) -> IResult<&'a [u8], T, E> {
+	map(rest, |x : &[u8]| T::from(String::from_utf8_lossy(x).trim_end_matches(&['\0', ' '][..]).to_string()))(input)
+}
+
 fn parse_enum_from_u8<'a, T : num::FromPrimitive, E : ParseError<&'a [u8]>>(input : &'a [u8]) -> IResult<&'a [u8], T, E> {
 	map_opt(be_u8, num::FromPrimitive::from_u8)(input)
 }
 
+/// Little-endian, the way `u32` is dumped into requests.
 fn parse_enum_from_u32<'a, T : num::FromPrimitive, E : ParseError<&'a [u8]>>(input : &'a [u8]) -> IResult<&'a [u8], T, E> {
-	map_opt(be_u32, num::FromPrimitive::from_u32)(input)
+	map_opt(le_u32, num::FromPrimitive::from_u32)(input)
 }
 
 #[derive(Debug, Clone)]
@@ -60,16 +65,37 @@ impl std::error::Error for TypesError {
 }
 
 
+#[derive(Clone,Debug,PartialEq)]
 pub struct MonitorName(String);
 impl HasCommandOpcode for MonitorName {
 	fn opcode() -> u8 { 0x01 }
 }
+impl From<String> for MonitorName {
+	fn from(x : String) -> Self { Self(x) }
+}
+impl From<MonitorName> for String {
+	fn from(x : MonitorName) -> Self { x.0 }
+}
+impl Parse for MonitorName {
+	fn parse<'a, E : ParseError<&'a [u8]>>(input: &'a [u8]) -> IResult<&'a [u8], Self, E> { parse_string(input) }
+}
 
+#[derive(Clone,Debug,PartialEq)]
 pub struct SerialNumber(String);
 impl HasCommandOpcode for SerialNumber {
 	fn opcode() -> u8 { 0x02 }
 }
+impl From<String> for SerialNumber {
+	fn from(x : String) -> Self { Self(x) }
+}
+impl From<SerialNumber> for String {
+	fn from(x : SerialNumber) -> Self { x.0 }
+}
+impl Parse for SerialNumber {
+	fn parse<'a, E : ParseError<&'a [u8]>>(input: &'a [u8]) -> IResult<&'a [u8], Self, E> { parse_string(input) }
+}
 
+#[derive(Clone,Copy,Debug,PartialEq)]
 pub struct BacklightHours(u16);
 impl HasCommandOpcode for BacklightHours {
 	fn opcode() -> u8 { 0x04 }
@@ -77,6 +103,9 @@ impl HasCommandOpcode for BacklightHours {
 impl From<u16> for BacklightHours {
 	fn from(x : u16) -> Self { Self(x) }
 }
+impl From<BacklightHours> for u16 {
+	fn from(x : BacklightHours) -> Self { x.0 }
+}
 impl Parse for BacklightHours {
 	fn parse<'a, E : ParseError<&'a [u8]>>(input: &'a [u8]) -> IResult<&'a [u8], Self, E> { parse_from_u16(input) }
 }
@@ -141,7 +170,7 @@ impl Parse for PowerUSB {
 	fn parse<'a, E : ParseError<&'a [u8]>>(input: &'a [u8]) -> IResult<&'a [u8], Self, E> { parse_enum_from_u8(input) }
 }
 
-#[derive(Debug,PartialEq)]
+#[derive(Clone,Copy,Debug,PartialEq)]
 pub struct Brightness(u8);
 impl HasCommandOpcode for Brightness {
 	fn opcode() -> u8 { 0x30 }
@@ -153,11 +182,14 @@ impl Serialize for Brightness {
 impl From<u8> for Brightness {
 	fn from(x : u8) -> Self { Self(x) }
 }
+impl From<Brightness> for u8 {
+	fn from(x : Brightness) -> Self { x.0 }
+}
 impl Parse for Brightness {
 	fn parse<'a, E : ParseError<&'a [u8]>>(input: &'a [u8]) -> IResult<&'a [u8], Self, E> { parse_from_u8(input) }
 }
 
-#[derive(Debug,PartialEq)]
+#[derive(Clone,Copy,Debug,PartialEq)]
 pub struct Contrast(u8);
 impl HasCommandOpcode for Contrast {
 	fn opcode() -> u8 { 0x31 }
@@ -169,6 +201,9 @@ impl Serialize for Contrast {
 impl From<u8> for Contrast {
 	fn from(x : u8) -> Self { Self(x) }
 }
+impl From<Contrast> for u8 {
+	fn from(x : Contrast) -> Self { x.0 }
+}
 impl Parse for Contrast {
 	fn parse<'a, E : ParseError<&'a [u8]>>(input: &'a [u8]) -> IResult<&'a [u8], Self, E> { parse_from_u8(input) }
 }
@@ -194,7 +229,7 @@ impl Parse for AspectRatio {
 	fn parse<'a, E : ParseError<&'a [u8]>>(input: &'a [u8]) -> IResult<&'a [u8], Self, E> { parse_enum_from_u8(input) }
 }
 
-#[derive(Debug,PartialEq)]
+#[derive(Clone,Copy,Debug,PartialEq)]
 pub struct Sharpness(u8);
 impl HasCommandOpcode for Sharpness {
 	fn opcode() -> u8 { 0x34 }
@@ -206,12 +241,15 @@ impl Serialize for Sharpness {
 impl From<u8> for Sharpness {
 	fn from(x : u8) -> Self { Self(x) }
 }
+impl From<Sharpness> for u8 {
+	fn from(x : Sharpness) -> Self { x.0 }
+}
 impl Parse for Sharpness {
 	fn parse<'a, E : ParseError<&'a [u8]>>(input: &'a [u8]) -> IResult<&'a [u8], Self, E> { parse_from_u8(input) }
 }
 
 #[repr(u32)]
-#[derive(Clone,Copy,Debug,PartialEq)]
+#[derive(Clone,Copy,Debug,FromPrimitive,PartialEq)]
 pub enum ColorTemperature {
 	_5000K  = 0x01,
 	_5700K  = 0x02,
@@ -230,9 +268,12 @@ impl Serialize for ColorTemperature {
 	fn dump<U: Write>(&self, w : U) -> io::Result<u8> { u32::from(*self).dump(w) }
 	fn length(&self) -> u8 { u32::from(*self).length() }
 }
+impl Parse for ColorTemperature {
+	fn parse<'a, E : ParseError<&'a [u8]>>(input: &'a [u8]) -> IResult<&'a [u8], Self, E> { parse_enum_from_u32(input) }
+}
 
 #[repr(u8)]
-#[derive(Clone,Copy,Debug,PartialEq)]
+#[derive(Clone,Copy,Debug,FromPrimitive,PartialEq)]
 pub enum ColorFormat {
 	RGB = 0,
 	YPbPr = 1,
@@ -247,9 +288,12 @@ impl Serialize for ColorFormat {
 	fn dump<U: Write>(&self, w : U) -> io::Result<u8> { u8::from(*self).dump(w) }
 	fn length(&self) -> u8 { u8::from(*self).length() }
 }
+impl Parse for ColorFormat {
+	fn parse<'a, E : ParseError<&'a [u8]>>(input: &'a [u8]) -> IResult<&'a [u8], Self, E> { parse_enum_from_u8(input) }
+}
 
 #[repr(u32)]
-#[derive(Clone,Copy,Debug,PartialEq)]
+#[derive(Clone,Copy,Debug,FromPrimitive,PartialEq)]
 pub enum ColorPreset {
 	Standard    = 0x01,
 	Multimedia  = 0x02,
@@ -266,6 +310,9 @@ impl Serialize for ColorPreset {
 	fn dump<U: Write>(&self, w : U) -> io::Result<u8> { u32::from(*self).dump(w) }
 	fn length(&self) -> u8 { u32::from(*self).length() }
 }
+impl Parse for ColorPreset {
+	fn parse<'a, E : ParseError<&'a [u8]>>(input: &'a [u8]) -> IResult<&'a [u8], Self, E> { parse_enum_from_u32(input) }
+}
 
 pub struct RGB {
 	r : u8,
@@ -288,7 +335,7 @@ impl Serialize for CustomColor {
 }
 
 #[repr(u8)]
-#[derive(Clone,Copy,Debug,PartialEq)]
+#[derive(Clone,Copy,Debug,FromPrimitive,PartialEq)]
 pub enum AutoSelect {
 	Off = 0,
 	On = 1,
@@ -303,9 +350,12 @@ impl Serialize for AutoSelect {
 	fn dump<U: Write>(&self, w : U) -> io::Result<u8> { u8::from(*self).dump(w) }
 	fn length(&self) -> u8 { u8::from(*self).length() }
 }
+impl Parse for AutoSelect {
+	fn parse<'a, E : ParseError<&'a [u8]>>(input: &'a [u8]) -> IResult<&'a [u8], Self, E> { parse_enum_from_u8(input) }
+}
 
 #[repr(u32)]
-#[derive(Clone,Copy,Debug,PartialEq)]
+#[derive(Clone,Copy,Debug,FromPrimitive,PartialEq)]
 pub enum VideoInput {
 	HDMI1 = 0x01,
 	HDMI2 = 0x02,
@@ -322,6 +372,9 @@ impl Serialize for VideoInput {
 	fn dump<U: Write>(&self, w : U) -> io::Result<u8> { u32::from(*self).dump(w) }
 	fn length(&self) -> u8 { u32::from(*self).length() }
 }
+impl Parse for VideoInput {
+	fn parse<'a, E : ParseError<&'a [u8]>>(input: &'a [u8]) -> IResult<&'a [u8], Self, E> { parse_enum_from_u32(input) }
+}
 
 pub struct OSDTransparency(u8);
 impl HasCommandOpcode for OSDTransparency {
@@ -357,6 +410,7 @@ impl Serialize for OSDTimer {
 }
 
 #[repr(u8)]
+#[derive(Clone,Copy,Debug,FromPrimitive,PartialEq)]
 pub enum OSDButtonLock {
 	Unlock = 0,
 	Lock = 1,
@@ -364,11 +418,31 @@ pub enum OSDButtonLock {
 impl HasCommandOpcode for OSDButtonLock {
 	fn opcode() -> u8 { 0x84 }
 }
+impl From<OSDButtonLock> for u8 {
+	fn from(x : OSDButtonLock) -> Self { x as u8 }
+}
+impl Serialize for OSDButtonLock {
+	fn dump<U: Write>(&self, w : U) -> io::Result<u8> { u8::from(*self).dump(w) }
+	fn length(&self) -> u8 { u8::from(*self).length() }
+}
+impl Parse for OSDButtonLock {
+	fn parse<'a, E : ParseError<&'a [u8]>>(input: &'a [u8]) -> IResult<&'a [u8], Self, E> { parse_enum_from_u8(input) }
+}
 
+#[derive(Clone,Debug,PartialEq)]
 pub struct VersionFirmware(String);
 impl HasCommandOpcode for VersionFirmware {
 	fn opcode() -> u8 { 0xA0 }
 }
+impl From<String> for VersionFirmware {
+	fn from(x : String) -> Self { Self(x) }
+}
+impl From<VersionFirmware> for String {
+	fn from(x : VersionFirmware) -> Self { x.0 }
+}
+impl Parse for VersionFirmware {
+	fn parse<'a, E : ParseError<&'a [u8]>>(input: &'a [u8]) -> IResult<&'a [u8], Self, E> { parse_string(input) }
+}
 
 #[repr(u8)]
 pub enum DDCCI {
@@ -380,6 +454,7 @@ impl HasCommandOpcode for DDCCI {
 }
 
 #[repr(u8)]
+#[derive(Clone,Copy,Debug,FromPrimitive,PartialEq)]
 pub enum LCDConditioning {
 	Disabled = 0,
 	Enabled = 1,
@@ -387,6 +462,16 @@ pub enum LCDConditioning {
 impl HasCommandOpcode for LCDConditioning {
 	fn opcode() -> u8 { 0xA3 }
 }
+impl From<LCDConditioning> for u8 {
+	fn from(x : LCDConditioning) -> Self { x as u8 }
+}
+impl Serialize for LCDConditioning {
+	fn dump<U: Write>(&self, w : U) -> io::Result<u8> { u8::from(*self).dump(w) }
+	fn length(&self) -> u8 { u8::from(*self).length() }
+}
+impl Parse for LCDConditioning {
+	fn parse<'a, E : ParseError<&'a [u8]>>(input: &'a [u8]) -> IResult<&'a [u8], Self, E> { parse_enum_from_u8(input) }
+}
 
 
 fn clamp<T : Ord + Sized>(value: T, min: T, max: T) -> Option<T> {
@@ -457,6 +542,50 @@ mod tests {
 		assert_eq!([0x37 as u8, 0x51, 0x02, 0xeb, 0x02, 141], &x[..]);
 	}
 
+	#[test]
+	fn decode_get_monitor_name() {
+		let x = [0x6f as u8, 0x37, 0x0a, 0x02, 0x00, 0x01, b'C', b'5', b'5', b'1', b'7', b'H', 0x00, 0x5c];
+		assert_eq!(Result::<_>::Ok(types::MonitorName(String::from("C5517H"))), decode(&x));
+	}
+
+	#[test]
+	fn decode_get_serial_number() {
+		let x = [0x6f as u8, 0x37, 0x09, 0x02, 0x00, 0x02, b'A', b'B', b'C', b'1', b'2', b'3', 0x21];
+		assert_eq!(Result::<_>::Ok(types::SerialNumber(String::from("ABC123"))), decode(&x));
+	}
+
+	#[test]
+	fn decode_get_version_firmware() {
+		let x = [0x6f as u8, 0x37, 0x09, 0x02, 0x00, 0xa0, b'M', b'2', b'T', b'1', b'0', b'4', 0xed];
+		assert_eq!(Result::<_>::Ok(types::VersionFirmware(String::from("M2T104"))), decode(&x));
+	}
+
+	#[test]
+	fn encode_set_osd_button_lock() {
+		let mut x = Vec::new();
+		encode(&command::Set::new(types::OSDButtonLock::Lock), &mut x).unwrap();
+		assert_eq!([0x37 as u8, 0x51, 0x03, 0xea, 0x84, 0x01, 0x0a], &x[..]);
+	}
+
+	#[test]
+	fn decode_get_osd_button_lock() {
+		let x = [0x6f as u8, 0x37, 0x04, 0x02, 0x00, 0x84, 0x01, 0xdb];
+		assert_eq!(Result::<_>::Ok(types::OSDButtonLock::Lock), decode(&x));
+	}
+
+	#[test]
+	fn encode_set_lcd_conditioning() {
+		let mut x = Vec::new();
+		encode(&command::Set::new(types::LCDConditioning::Enabled), &mut x).unwrap();
+		assert_eq!([0x37 as u8, 0x51, 0x03, 0xea, 0xa3, 0x01, 0x2d], &x[..]);
+	}
+
+	#[test]
+	fn decode_get_lcd_conditioning() {
+		let x = [0x6f as u8, 0x37, 0x04, 0x02, 0x00, 0xa3, 0x01, 0xfc];
+		assert_eq!(Result::<_>::Ok(types::LCDConditioning::Enabled), decode(&x));
+	}
+
 	#[test]
 	fn encode_get_backlight_hours() {
 		let mut x = Vec::new();
@@ -740,6 +869,12 @@ mod tests {
 		assert_eq!([0x37 as u8, 0x51, 0x02, 0xeb, 0x62, 237], &x[..]);
 	}
 
+	#[test]
+	fn decode_get_color_temperature() {
+		let x = [0x6f as u8, 0x37, 0x07, 0x02, 0x00, 0x43, 0x10, 0x00, 0x00, 0x00, 0x0e];
+		assert_eq!(Result::<_>::Ok(types::ColorTemperature::_9300K), decode(&x));
+	}
+
 	#[test]
 	fn encode_set_video_input() {
 		let mut x = Vec::new();
@@ -747,6 +882,15 @@ mod tests {
 		assert_eq!([0x37 as u8, 0x51, 0x06, 0xea, 0x62, 0x40, 0, 0, 0, 168], &x[..]);
 	}
 
+	#[test]
+	fn decode_get_video_input() {
+		// Replies carry the least significant byte first, as requests do.
+		let x = [0x6f as u8, 0x37, 0x07, 0x02, 0x00, 0x62, 0x08, 0x00, 0x00, 0x00, 0x37];
+		assert_eq!(Result::<_>::Ok(types::VideoInput::DP1), decode(&x));
+		let x = [0x6f as u8, 0x37, 0x07, 0x02, 0x00, 0x62, 0x40, 0x00, 0x00, 0x00, 0x7f];
+		assert_eq!(Result::<_>::Ok(types::VideoInput::VGA1), decode(&x));
+	}
+
 	#[test]
 	fn decode_set_video_input() {
 		let x = [0x6f as u8, 0x37, 0x03, 0x02, 0x00, 0x62, 59];
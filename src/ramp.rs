@@ -0,0 +1,189 @@
+//! Gradual changes of brightness, contrast and sharpness.
+//!
+//! A ramp writes the intermediate values one `Set` at a time, the next
+//! one only after the reply to the previous one. Values follow an easing
+//! curve over the duration, those that fall behind are skipped.
+
+use std;
+use std::fmt;
+use std::error;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use property;
+use protocol::HasCommandOpcode;
+use protocol::command::Serialize;
+use protocol::reply::Parse;
+use protocol::transaction;
+use protocol::types;
+use protocol::types::TypesError;
+
+#[derive(Debug)]
+pub enum Error {
+	TransactionError(transaction::Error),
+	OutOfRange(TypesError),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Error::TransactionError(ref transaction_error) =>
+				write!(f, "transaction error: {}", transaction_error),
+			Error::OutOfRange(ref types_error) =>
+				write!(f, "{}", types_error),
+		}
+	}
+}
+
+impl error::Error for Error {
+	fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+		match self {
+			Error::TransactionError(ref transaction_error) => Some(transaction_error),
+			Error::OutOfRange(ref types_error) => Some(types_error),
+		}
+	}
+}
+
+/// Settings in 0 to 100 that can be ramped.
+pub trait Level : HasCommandOpcode + Parse + Serialize + property::Level + From<u8> + Into<u8> + Copy {}
+
+impl Level for types::Brightness {}
+impl Level for types::Contrast {}
+impl Level for types::Sharpness {}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Easing {
+	Linear,
+	EaseIn,
+	EaseOut,
+	EaseInOut,
+}
+
+impl Easing {
+	/// Progress of the value at the fraction `t` of the duration, both in 0 to 1.
+	pub fn apply(&self, t: f64) -> f64 {
+		let t = t.clamp(0.0, 1.0);
+		match self {
+			Easing::Linear => t,
+			Easing::EaseIn => t * t * t,
+			Easing::EaseOut => 1.0 - (1.0 - t).powi(3),
+			Easing::EaseInOut => match t < 0.5 {
+				true => 4.0 * t * t * t,
+				false => 1.0 - (2.0 - 2.0 * t).powi(3) / 2.0,
+			},
+		}
+	}
+}
+
+impl FromStr for Easing {
+	type Err = String;
+
+	fn from_str(x: &str) -> std::result::Result<Self, Self::Err> {
+		match x {
+			"linear" => Ok(Easing::Linear),
+			"ease-in" => Ok(Easing::EaseIn),
+			"ease-out" => Ok(Easing::EaseOut),
+			"ease-in-out" => Ok(Easing::EaseInOut),
+			_ => Err(format!("{}: expected linear, ease-in, ease-out or ease-in-out", x)),
+		}
+	}
+}
+
+impl fmt::Display for Easing {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(match self {
+			Easing::Linear => "linear",
+			Easing::EaseIn => "ease-in",
+			Easing::EaseOut => "ease-out",
+			Easing::EaseInOut => "ease-in-out",
+		})
+	}
+}
+
+/// Stops a ramp from another thread, clones share the flag.
+#[derive(Clone, Debug, Default)]
+pub struct Cancel(Arc<AtomicBool>);
+
+impl Cancel {
+	pub fn new() -> Cancel {
+		Cancel::default()
+	}
+
+	pub fn cancel(&self) {
+		self.0.store(true, Ordering::SeqCst)
+	}
+
+	pub fn is_cancelled(&self) -> bool {
+		self.0.load(Ordering::SeqCst)
+	}
+}
+
+#[derive(Clone, Debug)]
+pub struct Ramp {
+	pub target: u8,
+	pub duration: Duration,
+	pub easing: Easing,
+	pub cancel: Cancel,
+}
+
+impl Ramp {
+	/// A linear ramp to `target`.
+	pub fn new(target: u8, duration: Duration) -> Ramp {
+		Ramp{target, duration, easing: Easing::Linear, cancel: Cancel::new()}
+	}
+
+	pub fn with_easing(mut self, easing: Easing) -> Ramp {
+		self.easing = easing;
+		self
+	}
+
+	pub fn with_cancel(mut self, cancel: Cancel) -> Ramp {
+		self.cancel = cancel;
+		self
+	}
+
+	/// Value at `elapsed` of a ramp from `start`.
+	pub fn value_at(&self, start: u8, elapsed: Duration) -> u8 {
+		let t = match self.duration.is_zero() {
+			true => 1.0,
+			false => elapsed.as_secs_f64() / self.duration.as_secs_f64(),
+		};
+		let delta = f64::from(self.target) - f64::from(start);
+		(f64::from(start) + delta * self.easing.apply(t)).round() as u8
+	}
+
+	/// Time between two checks of the progress, enough for every value of a linear ramp.
+	pub fn tick(&self, start: u8) -> Duration {
+		let steps = u32::from(start.abs_diff(self.target)).max(1);
+		(self.duration / steps).max(Duration::from_millis(1))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::time::Duration;
+
+	use ramp::{Easing, Ramp};
+
+	#[test]
+	fn ramp_easing() {
+		for easing in [Easing::Linear, Easing::EaseIn, Easing::EaseOut, Easing::EaseInOut] {
+			assert_eq!(0.0, easing.apply(0.0));
+			assert_eq!(1.0, easing.apply(1.0));
+			assert_eq!(easing, easing.to_string().parse().unwrap());
+		}
+		assert_eq!(0.5, Easing::EaseInOut.apply(0.5));
+		assert!(Easing::EaseIn.apply(0.25) < 0.25);
+		assert!(Easing::EaseOut.apply(0.25) > 0.25);
+
+		let ramp = Ramp::new(90, Duration::from_secs(7)).with_easing(Easing::Linear);
+		assert_eq!(20, ramp.value_at(20, Duration::from_secs(0)));
+		assert_eq!(55, ramp.value_at(20, Duration::from_millis(3500)));
+		assert_eq!(90, ramp.value_at(20, Duration::from_secs(8)));
+		assert_eq!(Duration::from_millis(100), ramp.tick(20));
+	}
+}
@@ -0,0 +1,286 @@
+//! Keeps monitors at a declared profile against changes made on the OSD.
+//!
+//! Every check reads the properties of the profile and writes back those
+//! that drifted, in the order they are declared, so that a preset can be
+//! put back before the brightness it resets.
+
+use std;
+use std::fmt;
+use std::error;
+use std::time::Duration;
+
+use chrono::NaiveDateTime;
+
+use config::Config;
+use group::{Group, Report};
+use monitor;
+use monitor::{Monitor, Opener};
+use property;
+use schedule::{Action, Clock};
+
+#[derive(Debug)]
+pub enum Error {
+	UnknownTarget(monitor::Error),
+	UnknownProperty(String),
+	/// The property cannot be both read and written.
+	NotReconcilable(String),
+	InvalidValue(property::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Error::UnknownTarget(ref monitor_error) =>
+				write!(f, "{}", monitor_error),
+			Error::UnknownProperty(ref name) =>
+				write!(f, "unknown property: {}", name),
+			Error::NotReconcilable(ref name) =>
+				write!(f, "{} cannot be read and written", name),
+			Error::InvalidValue(ref property_error) =>
+				write!(f, "{}", property_error),
+		}
+	}
+}
+
+impl error::Error for Error {
+	fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+		match self {
+			Error::UnknownTarget(ref monitor_error) => Some(monitor_error),
+			Error::InvalidValue(ref property_error) => Some(property_error),
+			_ => None,
+		}
+	}
+}
+
+/// Desired values of some monitors.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Profile {
+	pub name: String,
+	/// Monitor names, aliases or group names.
+	pub targets: Vec<String>,
+	/// Checked and written back in order.
+	pub desired: Vec<Action>,
+	/// Locks the OSD buttons after the other values, so that they stay put.
+	#[serde(default)]
+	pub relock: bool,
+}
+
+impl Profile {
+	/// `desired`, followed by the OSD lock when `relock` is set and the lock
+	/// is not declared already.
+	pub fn actions(&self) -> Vec<Action> {
+		let mut actions = self.desired.clone();
+		if self.relock && !actions.iter().any(|x| x.property == "osd-button-lock") {
+			actions.push(Action::new("osd-button-lock", "lock"));
+		}
+		actions
+	}
+}
+
+/// A property found away from its desired value.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Drift {
+	pub profile: String,
+	pub monitor: String,
+	pub time: NaiveDateTime,
+	pub property: String,
+	pub actual: String,
+	pub desired: String,
+	/// Why the desired value could not be written back.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub error: Option<String>,
+}
+
+impl Drift {
+	pub fn is_corrected(&self) -> bool {
+		self.error.is_none()
+	}
+}
+
+/// A check stopped by a failed read, with the drifts found before it.
+#[derive(Debug)]
+pub struct Interrupted {
+	pub drifts: Vec<Drift>,
+	pub error: property::Error,
+}
+
+impl fmt::Display for Interrupted {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", self.error)
+	}
+}
+
+impl error::Error for Interrupted {
+	fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+		Some(&self.error)
+	}
+}
+
+/// Drifts found on the monitors of a profile, or why a monitor could not be checked.
+#[derive(Debug)]
+pub struct Check {
+	pub profile: String,
+	pub report: Report<Vec<Drift>, Interrupted>,
+}
+
+pub struct Reconciler {
+	config: Config,
+	profiles: Vec<Profile>,
+	opener: Opener,
+}
+
+impl Reconciler {
+	pub fn new(config: Config, profiles: Vec<Profile>) -> Result<Reconciler> {
+		Reconciler::with_opener(config, profiles, Box::new(Monitor::open))
+	}
+
+	/// Checks that the targets exist and that every property can be both
+	/// read and written, with a value it accepts.
+	pub fn with_opener(config: Config, profiles: Vec<Profile>, opener: Opener) -> Result<Reconciler> {
+		for profile in &profiles {
+			Group::resolve_all(&config, &profile.targets).map_err(Error::UnknownTarget)?;
+			for action in profile.actions() {
+				let property = property::find(&action.property).ok_or_else(|| Error::UnknownProperty(action.property.clone()))?;
+				if !property.is_readable() || !property.is_writable() {
+					return Err(Error::NotReconcilable(action.property.clone()));
+				}
+				property.check(&action.value).map_err(Error::InvalidValue)?;
+			}
+		}
+		Ok(Reconciler{config, profiles, opener})
+	}
+
+	pub fn profiles(&self) -> &[Profile] {
+		&self.profiles
+	}
+
+	/// Reads every monitor and writes back the values that drifted. A
+	/// failed read stops the check of that monitor, keeping the drifts
+	/// found until then, a failed write is kept in its drift.
+	pub fn check(&self, now: NaiveDateTime) -> Vec<Check> {
+		self.profiles.iter().map(|profile| {
+			let actions : Vec<_> = profile.actions().into_iter()
+				.map(|x| (property::find(&x.property).unwrap(), x))
+				.collect();
+			let group = Group::resolve_all(&self.config, &profile.targets).unwrap();
+
+			let report = group.run_with(&self.config, &self.opener, |m| {
+				let mut drifts = Vec::new();
+				for (property, action) in &actions {
+					let actual = match property.get(m) {
+						Ok(actual) => actual,
+						Err(error) => return Err(Interrupted{drifts, error}),
+					};
+					if actual.matches(&action.value) {
+						continue;
+					}
+					drifts.push(Drift{
+						profile: profile.name.clone(),
+						monitor: String::from(m.name()),
+						time: now,
+						property: action.property.clone(),
+						actual: actual.to_string(),
+						desired: action.value.clone(),
+						error: property.set(m, &action.value).err().map(|x| x.to_string()),
+					});
+				}
+				Ok(drifts)
+			});
+
+			Check{profile: profile.name.clone(), report}
+		}).collect()
+	}
+
+	/// Checks the monitors every `interval`, passing every check to `f`.
+	pub fn run<C: Clock, F: FnMut(&Check)>(&self, clock: &C, interval: Duration, mut f: F) -> ! {
+		loop {
+			self.check(clock.now()).iter().for_each(&mut f);
+			clock.sleep(interval);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use chrono::NaiveDate;
+
+	use group;
+	use reconcile::{Check, Error, Profile, Reconciler};
+	use schedule::Action;
+	use simulator;
+	use simulator::Simulator;
+
+	fn drifts(checks: &[Check]) -> Vec<String> {
+		checks[0].report.results[0].1.as_ref().unwrap().iter()
+			.map(|x| format!("{}: {} -> {}", x.property, x.actual, x.desired))
+			.collect()
+	}
+
+	#[test]
+	fn reconcile_drift() {
+		let simulator = Simulator::new();
+		let (config, opener) = simulator::fixture(&[("lobby", &simulator)]);
+		let profile = Profile{
+			name: String::from("signage"),
+			targets: vec![String::from("lobby")],
+			desired: vec![Action::new("input", "HDMI2"), Action::new("brightness", "60"), Action::new("power-led", "on")],
+			relock: true,
+		};
+		let reconciler = Reconciler::with_opener(config, vec![profile], opener).unwrap();
+		let now = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap().and_hms_opt(8, 0, 0).unwrap();
+
+		assert_eq!(vec!["input: hdmi1 -> HDMI2", "brightness: 75 -> 60", "osd-button-lock: unlock -> lock"],
+			drifts(&reconciler.check(now)));
+		assert_eq!(Some(vec![1]), simulator.value(0x84));
+		assert!(drifts(&reconciler.check(now)).is_empty());
+
+		// Someone turns the brightness up on the OSD
+		simulator.set_value(0x30, &[90]);
+		assert_eq!(vec!["brightness: 90 -> 60"], drifts(&reconciler.check(now)));
+		assert_eq!(Some(vec![60]), simulator.value(0x30));
+	}
+
+	#[test]
+	fn reconcile_keeps_drifts_of_interrupted_checks() {
+		let simulator = Simulator::new();
+		let (config, opener) = simulator::fixture(&[("lobby", &simulator)]);
+		let profile = Profile{
+			name: String::from("signage"),
+			targets: vec![String::from("lobby")],
+			desired: vec![Action::new("input", "HDMI2"), Action::new("brightness", "60"), Action::new("contrast", "50")],
+			relock: false,
+		};
+		let reconciler = Reconciler::with_opener(config, vec![profile], opener).unwrap();
+		let now = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap().and_hms_opt(8, 0, 0).unwrap();
+
+		// The brightness comes back garbled
+		simulator.set_value(0x30, &[]);
+		let checks = reconciler.check(now);
+		let interrupted = match checks[0].report.results[0].1 {
+			Err(group::Error::OperationError(ref x)) => x,
+			ref other => panic!("unexpected {:?}", other),
+		};
+		assert_eq!(vec![("input", "hdmi1")], interrupted.drifts.iter().map(|x| (x.property.as_str(), x.actual.as_str())).collect::<Vec<_>>());
+		assert!(interrupted.drifts[0].is_corrected());
+		assert_eq!(Some(vec![0x02, 0, 0, 0]), simulator.value(0x62));
+	}
+
+	#[test]
+	fn reconcile_rejects_invalid_values() {
+		let (config, _) = simulator::fixture(&[("lobby", &Simulator::new())]);
+		let profile = |property, value| Profile{
+			name: String::from("signage"),
+			targets: vec![String::from("lobby")],
+			desired: vec![Action::new(property, value)],
+			relock: false,
+		};
+
+		for (property, value) in [("brightness", "150"), ("brightness", "dim"), ("input", "hdmi9")] {
+			let result = Reconciler::new(config.clone(), vec![profile(property, value)]);
+			assert!(matches!(result, Err(Error::InvalidValue(_))), "{}={}", property, value);
+		}
+		assert!(Reconciler::new(config, vec![profile("input", "DP1")]).is_ok());
+	}
+}
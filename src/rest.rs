@@ -0,0 +1,357 @@
+//! HTTP/JSON front-end to the property registry.
+//!
+//! `GET /monitors/{name}` reads every readable property,
+//! `GET` and `PUT /monitors/{name}/{property}` read and write one,
+//! `POST /monitors/{name}/reset-power` resets the power and
+//! `GET /openapi.json` describes all of that.
+
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::collections::BTreeMap;
+
+use serde_json;
+use serde_json::Value as Json;
+
+use config::Config;
+use http::{Request, Response};
+use monitor::{Monitor, Opener};
+use property;
+use property::{Kind, Property};
+use protocol::decoder;
+use protocol::reply::ResultCode;
+use protocol::transaction;
+
+/// Monitors are opened on first use and kept open until their line fails.
+pub struct Api {
+	config: Config,
+	opener: Opener,
+	monitors: Mutex<BTreeMap<String, Arc<Mutex<Monitor>>>>,
+}
+
+fn error(status: u16, code: &str, message: &str) -> Response {
+	Response::json(status, &json!({"error": message, "code": code}))
+}
+
+fn device_error(result_code: &ResultCode) -> (u16, &'static str) {
+	match result_code {
+		ResultCode::Timeout => (504, "device-timeout"),
+		ResultCode::ParametersError => (422, "device-parameters-error"),
+		ResultCode::NotConnected => (503, "device-not-connected"),
+		ResultCode::Other => (502, "device-error"),
+	}
+}
+
+/// Status and code for a failed transaction, and whether the line is still usable.
+fn transaction_error(err: &transaction::Error) -> (u16, &'static str, bool) {
+	match err {
+		transaction::Error::DecodeError(decoder::Error::DeviceError(ref result_code), _) => {
+			let (status, code) = device_error(result_code);
+			(status, code, true)
+		},
+		transaction::Error::DecodeError(..) => (502, "protocol-error", true),
+		transaction::Error::ReadError(ref io_error) if io_error.kind() == io::ErrorKind::TimedOut
+			|| io_error.kind() == io::ErrorKind::WouldBlock => (504, "monitor-timeout", true),
+		transaction::Error::ReadError(_) | transaction::Error::WriteError(_) => (502, "io-error", false),
+	}
+}
+
+fn property_error(err: &property::Error) -> (u16, &'static str, bool) {
+	match err {
+		property::Error::TransactionError(ref transaction_error_) => transaction_error(transaction_error_),
+		property::Error::InvalidValue{..} => (400, "invalid-value", true),
+		property::Error::OutOfRange(_) => (400, "out-of-range", true),
+		property::Error::NotReadable(_) => (405, "not-readable", true),
+		property::Error::NotWritable(_) => (405, "not-writable", true),
+	}
+}
+
+fn value_schema(property: &Property) -> Json {
+	match property.kind() {
+		Kind::Number => json!({"type": "integer", "minimum": 0}),
+		Kind::Text => json!({"type": "string"}),
+		Kind::Choice => json!({"type": "string", "enum": property.choices()}),
+	}
+}
+
+fn error_responses() -> Json {
+	let reference = json!({"content": {"application/json": {"schema": {"$ref": "#/components/schemas/Error"}}}});
+	let mut responses = serde_json::Map::new();
+	for &(status, description) in &[("400", "Invalid value"), ("404", "Unknown monitor or property"),
+		("422", "Parameters rejected by the monitor"), ("502", "Monitor failure"),
+		("503", "Monitor not reachable"), ("504", "Monitor timed out")] {
+		let mut response = reference.clone();
+		response["description"] = json!(description);
+		responses.insert(String::from(status), response);
+	}
+	Json::Object(responses)
+}
+
+fn with_responses(mut responses: Json, success: (&str, Json)) -> Json {
+	responses[success.0] = success.1;
+	responses
+}
+
+/// OpenAPI 3 description of the API, generated from the property registry.
+pub fn openapi() -> Json {
+	let name = json!([{"name": "name", "in": "path", "required": true,
+		"description": "Monitor name or alias", "schema": {"type": "string"}}]);
+	let mut paths = serde_json::Map::new();
+
+	paths.insert(String::from("/monitors"), json!({"get": {
+		"summary": "List configured monitors",
+		"responses": {"200": {"description": "Monitor names",
+			"content": {"application/json": {"schema": {"type": "array", "items": {"type": "string"}}}}}},
+	}}));
+
+	let readable : serde_json::Map<String, Json> = property::properties().iter()
+		.filter(|x| x.is_readable())
+		.map(|x| (String::from(x.name()), value_schema(x)))
+		.collect();
+	paths.insert(String::from("/monitors/{name}"), json!({"parameters": name, "get": {
+		"summary": "Read all readable properties, null when the monitor does not support one",
+		"responses": with_responses(error_responses(), ("200", json!({"description": "Property values",
+			"content": {"application/json": {"schema": {"type": "object", "properties": readable}}}}))),
+	}}));
+
+	paths.insert(String::from("/monitors/{name}/reset-power"), json!({"parameters": name, "post": {
+		"summary": "Reset the power",
+		"responses": with_responses(error_responses(), ("204", json!({"description": "Done"}))),
+	}}));
+
+	for property in property::properties() {
+		let schema = json!({"type": "object", "required": ["value"], "properties": {"value": value_schema(property)}});
+		let mut item = json!({"parameters": name});
+
+		if property.is_readable() {
+			item["get"] = json!({
+				"summary": format!("Read {}", property.name()),
+				"responses": with_responses(error_responses(), ("200", json!({"description": "Current value",
+					"content": {"application/json": {"schema": schema}}}))),
+			});
+		}
+		if property.is_writable() {
+			item["put"] = json!({
+				"summary": format!("Write {}", property.name()),
+				"requestBody": {"required": true, "content": {"application/json": {"schema": schema}}},
+				"responses": with_responses(error_responses(), ("204", json!({"description": "Done"}))),
+			});
+		}
+		paths.insert(format!("/monitors/{{name}}/{}", property.name()), item);
+	}
+
+	json!({
+		"openapi": "3.0.3",
+		"info": {"title": "c5517h", "version": env!("CARGO_PKG_VERSION")},
+		"paths": paths,
+		"components": {"schemas": {"Error": {"type": "object", "required": ["error", "code"], "properties": {
+			"error": {"type": "string"},
+			"code": {"type": "string"},
+		}}}},
+	})
+}
+
+/// Value of a `{"value": ...}` body in the form `Property::set` takes.
+fn body_value(body: &[u8]) -> Option<String> {
+	match serde_json::from_slice::<Json>(body).ok()?.get("value")? {
+		Json::String(x) => Some(x.clone()),
+		Json::Number(x) => Some(x.to_string()),
+		_ => None,
+	}
+}
+
+impl Api {
+	pub fn new(config: Config) -> Api {
+		Api::with_opener(config, Box::new(Monitor::open))
+	}
+
+	/// Uses `opener` instead of `Monitor::open` to reach the monitors.
+	pub fn with_opener(config: Config, opener: Opener) -> Api {
+		Api{config, opener, monitors: Mutex::new(BTreeMap::new())}
+	}
+
+	fn monitor(&self, name: &str) -> Result<(String, Arc<Mutex<Monitor>>), Response> {
+		let (name, config) = self.config.monitor(name)
+			.ok_or_else(|| error(404, "unknown-monitor", &format!("unknown monitor: {}", name)))?;
+		let mut monitors = self.monitors.lock().unwrap();
+
+		if let Some(monitor) = monitors.get(name) {
+			return Ok((String::from(name), monitor.clone()));
+		}
+
+		let monitor = (self.opener)(name, config, &self.config)
+			.map_err(|err| error(503, "open-error", &err.to_string()))?;
+		let monitor = Arc::new(Mutex::new(monitor));
+		monitors.insert(String::from(name), monitor.clone());
+		Ok((String::from(name), monitor))
+	}
+
+	/// Turns a failure into a response, forgetting the monitor when its line is broken.
+	fn failure(&self, name: &str, (status, code, usable): (u16, &str, bool), message: &str) -> Response {
+		if !usable {
+			self.monitors.lock().unwrap().remove(name);
+		}
+		error(status, code, message)
+	}
+
+	fn get_all(&self, name: &str) -> Response {
+		let (name, monitor) = match self.monitor(name) {
+			Ok(x) => x,
+			Err(response) => return response,
+		};
+		let mut monitor = monitor.lock().unwrap();
+		let mut values = serde_json::Map::new();
+
+		for property in property::properties().iter().filter(|x| x.is_readable()) {
+			let value = match property.get(&mut monitor) {
+				Ok(value) => json!(value),
+				Err(property::Error::TransactionError(transaction::Error::DecodeError(
+					decoder::Error::DeviceError(ResultCode::ParametersError), _))) => Json::Null,
+				Err(err) => return self.failure(&name, property_error(&err), &format!("{}: {}", property.name(), err)),
+			};
+			values.insert(String::from(property.name()), value);
+		}
+
+		Response::json(200, &Json::Object(values))
+	}
+
+	fn get(&self, name: &str, property: &Property) -> Response {
+		let (name, monitor) = match self.monitor(name) {
+			Ok(x) => x,
+			Err(response) => return response,
+		};
+		let result = property.get(&mut monitor.lock().unwrap());
+
+		match result {
+			Ok(value) => Response::json(200, &json!({"value": value})),
+			Err(err) => self.failure(&name, property_error(&err), &err.to_string()),
+		}
+	}
+
+	fn set(&self, name: &str, property: &Property, body: &[u8]) -> Response {
+		let value = match body_value(body) {
+			Some(value) => value,
+			None => return error(400, "bad-request", "expected {\"value\": ...}"),
+		};
+		let (name, monitor) = match self.monitor(name) {
+			Ok(x) => x,
+			Err(response) => return response,
+		};
+		let result = property.set(&mut monitor.lock().unwrap(), &value);
+
+		match result {
+			Ok(()) => Response::empty(204),
+			Err(err) => self.failure(&name, property_error(&err), &err.to_string()),
+		}
+	}
+
+	fn reset_power(&self, name: &str) -> Response {
+		let (name, monitor) = match self.monitor(name) {
+			Ok(x) => x,
+			Err(response) => return response,
+		};
+		let result = monitor.lock().unwrap().reset_power();
+
+		match result {
+			Ok(()) => Response::empty(204),
+			Err(err) => self.failure(&name, transaction_error(&err), &err.to_string()),
+		}
+	}
+
+	pub fn handle(&self, request: &Request) -> Response {
+		let method = request.method.as_str();
+
+		match (method, &request.segments()[..]) {
+			("GET", ["openapi.json"]) => Response::json(200, &openapi()),
+			("GET", ["monitors"]) => Response::json(200, &json!(self.config.monitors.keys().collect::<Vec<_>>())),
+			("GET", ["monitors", name]) => self.get_all(name),
+			("POST", ["monitors", name, "reset-power"]) => self.reset_power(name),
+			(_, ["monitors", _, "reset-power"]) => error(405, "method-not-allowed", "use POST"),
+			(_, ["monitors", name, property]) => match (method, property::find(property)) {
+				(_, None) => error(404, "unknown-property", &format!("unknown property: {}", property)),
+				("GET", Some(property)) => self.get(name, property),
+				("PUT", Some(property)) => self.set(name, property, &request.body),
+				_ => error(405, "method-not-allowed", "use GET or PUT"),
+			},
+			_ => error(404, "not-found", "not found"),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use serde_json;
+	use serde_json::Value as Json;
+
+	use http::Request;
+	use protocol::reply::ResultCode;
+	use rest::Api;
+	use simulator;
+	use simulator::Simulator;
+
+	fn api(simulator: &Simulator) -> Api {
+		let (mut config, opener) = simulator::fixture(&[("lobby", simulator)]);
+		config.monitors.get_mut("lobby").unwrap().aliases.push(String::from("entrance"));
+		Api::with_opener(config, opener)
+	}
+
+	fn call(api: &Api, method: &str, path: &str, body: &str) -> (u16, Json) {
+		let response = api.handle(&Request::new(method, path, body.as_bytes()));
+		let body = serde_json::from_slice(&response.body).unwrap_or(Json::Null);
+		(response.status, body)
+	}
+
+	#[test]
+	fn rest_get_set() {
+		let simulator = Simulator::new();
+		let api = api(&simulator);
+
+		assert_eq!((200, json!({"value": 75})), call(&api, "GET", "/monitors/lobby/brightness", ""));
+		assert_eq!(204, call(&api, "PUT", "/monitors/entrance/input", "{\"value\":\"HDMI2\"}").0);
+		assert_eq!(Some(vec![0x02, 0, 0, 0]), simulator.value(0x62));
+		assert_eq!(204, call(&api, "PUT", "/monitors/lobby/brightness", "{\"value\":40}").0);
+		assert_eq!(204, call(&api, "POST", "/monitors/lobby/reset-power", "").0);
+
+		let (status, all) = call(&api, "GET", "/monitors/lobby", "");
+		assert_eq!(200, status);
+		assert_eq!(json!("DELL C5517H"), all["name"]);
+		assert_eq!(json!(40), all["brightness"]);
+		assert_eq!(json!("hdmi2"), all["input"]);
+		assert!(all.get("osd-timer").is_none());
+	}
+
+	#[test]
+	fn rest_errors() {
+		let simulator = Simulator::new();
+		let api = api(&simulator);
+
+		assert_eq!(404, call(&api, "GET", "/monitors/atrium/brightness", "").0);
+		assert_eq!(404, call(&api, "GET", "/monitors/lobby/volume", "").0);
+		assert_eq!(405, call(&api, "GET", "/monitors/lobby/osd-timer", "").0);
+		assert_eq!(405, call(&api, "PUT", "/monitors/lobby/name", "{\"value\":\"x\"}").0);
+		assert_eq!(400, call(&api, "PUT", "/monitors/lobby/input", "{}").0);
+		assert_eq!(400, call(&api, "PUT", "/monitors/lobby/input", "{\"value\":\"HDMI9\"}").0);
+		assert_eq!(400, call(&api, "PUT", "/monitors/lobby/brightness", "{\"value\":101}").0);
+
+		simulator.fail_next(ResultCode::NotConnected);
+		let (status, body) = call(&api, "GET", "/monitors/lobby/power", "");
+		assert_eq!(503, status);
+		assert_eq!(json!("device-not-connected"), body["code"]);
+
+		simulator.fail_next(ResultCode::Timeout);
+		assert_eq!(504, call(&api, "GET", "/monitors/lobby/power", "").0);
+		simulator.fail_next(ResultCode::ParametersError);
+		assert_eq!(422, call(&api, "GET", "/monitors/lobby/power", "").0);
+	}
+
+	#[test]
+	fn rest_openapi() {
+		let api = api(&Simulator::new());
+		let (status, doc) = call(&api, "GET", "/openapi.json", "");
+
+		assert_eq!(200, status);
+		assert!(doc["paths"]["/monitors/{name}/input"]["put"].is_object());
+		assert!(doc["paths"]["/monitors/{name}/name"]["put"].is_null());
+		assert_eq!(json!("hdmi2"), doc["paths"]["/monitors/{name}/input"]["get"]["responses"]["200"]
+			["content"]["application/json"]["schema"]["properties"]["value"]["enum"][1]);
+	}
+}
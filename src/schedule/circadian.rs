@@ -0,0 +1,502 @@
+//! Brightness and colour temperature following the time of day.
+//!
+//! A policy is a daily curve through points given at clock times or
+//! relative to sunrise, solar noon and sunset, which are computed from a
+//! latitude and longitude. Brightness is interpolated linearly between the
+//! points, colour temperature is interpolated in kelvin and rounded to the
+//! nearest setting of the monitor. Monitors approach the curve `step` by
+//! `step`, with at most `max_per_minute` transactions a minute each. A
+//! monitor follows one policy at most.
+
+use std;
+use std::fmt;
+use std::f64::consts::PI;
+use std::str::FromStr;
+use std::convert::TryFrom;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
+use chrono::{Datelike, Duration, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
+
+use config::Config;
+use group;
+use group::{Group, Report};
+use monitor::{Monitor, Opener};
+use protocol::transaction;
+use protocol::types;
+use schedule::{check_targets, Action, Clock, Error, Result};
+
+const TEMPERATURES : [(u32, types::ColorTemperature); 6] = [
+	(5000, types::ColorTemperature::_5000K),
+	(5700, types::ColorTemperature::_5700K),
+	(6500, types::ColorTemperature::_6500K),
+	(7500, types::ColorTemperature::_7500K),
+	(9300, types::ColorTemperature::_9300K),
+	(10000, types::ColorTemperature::_10000K),
+];
+
+fn kelvin(x: types::ColorTemperature) -> u32 {
+	TEMPERATURES.iter().find(|y| y.1 == x).unwrap().0
+}
+
+/// Setting closest to `kelvin`.
+fn nearest_temperature(kelvin: f64) -> types::ColorTemperature {
+	TEMPERATURES.iter().min_by_key(|x| (f64::from(x.0) - kelvin).abs() as u32).unwrap().1
+}
+
+/// Sunrise, solar noon and sunset of a day, in UTC.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sun {
+	pub sunrise: NaiveDateTime,
+	pub noon: NaiveDateTime,
+	pub sunset: NaiveDateTime,
+}
+
+/// Sun times of `date` at a latitude and longitude in degrees, east and
+/// north positive, after the NOAA approximations. `None` when the sun
+/// does not rise or does not set that day.
+pub fn sun(date: NaiveDate, latitude: f64, longitude: f64) -> Option<Sun> {
+	let gamma = 2.0 * PI / 365.0 * f64::from(date.ordinal0());
+	let equation_of_time = 229.18 * (0.000075 + 0.001868 * gamma.cos() - 0.032077 * gamma.sin()
+		- 0.014615 * (2.0 * gamma).cos() - 0.040849 * (2.0 * gamma).sin());
+	let declination = 0.006918 - 0.399912 * gamma.cos() + 0.070257 * gamma.sin()
+		- 0.006758 * (2.0 * gamma).cos() + 0.000907 * (2.0 * gamma).sin()
+		- 0.002697 * (3.0 * gamma).cos() + 0.00148 * (3.0 * gamma).sin();
+
+	let latitude = latitude.to_radians();
+	// Zenith of the upper limb at the horizon, refraction included
+	let cos_hour_angle = 90.833f64.to_radians().cos() / (latitude.cos() * declination.cos()) - latitude.tan() * declination.tan();
+	if !(-1.0..=1.0).contains(&cos_hour_angle) {
+		return None;
+	}
+	let hour_angle = cos_hour_angle.acos().to_degrees();
+
+	let at = |minutes: f64| date.and_hms_opt(0, 0, 0).unwrap() + Duration::seconds((minutes * 60.0).round() as i64);
+	Some(Sun{
+		sunrise: at(720.0 - 4.0 * (longitude + hour_angle) - equation_of_time),
+		noon: at(720.0 - 4.0 * longitude - equation_of_time),
+		sunset: at(720.0 - 4.0 * (longitude - hour_angle) - equation_of_time),
+	})
+}
+
+fn local(utc: NaiveDateTime) -> NaiveDateTime {
+	Utc.from_utc_datetime(&utc).with_timezone(&Local).naive_local()
+}
+
+/// Time of day of a point: `07:30`, or `sunrise`, `noon` and `sunset`
+/// followed by an offset in minutes such as `sunset-45`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Anchor {
+	Time(NaiveTime),
+	Sunrise(i64),
+	Noon(i64),
+	Sunset(i64),
+}
+
+impl Anchor {
+	fn is_solar(&self) -> bool {
+		!matches!(self, Anchor::Time(_))
+	}
+}
+
+impl FromStr for Anchor {
+	type Err = String;
+
+	fn from_str(text: &str) -> std::result::Result<Self, Self::Err> {
+		let invalid = || format!("{}: expected HH:MM, sunrise, noon or sunset with an offset in minutes", text);
+		if let Ok(time) = NaiveTime::parse_from_str(text, "%H:%M") {
+			return Ok(Anchor::Time(time));
+		}
+
+		let (name, offset) = match text.find(['+', '-']) {
+			Some(i) => (&text[..i], text[i..].trim_start_matches('+').parse().map_err(|_| invalid())?),
+			None => (text, 0),
+		};
+		match name {
+			"sunrise" => Ok(Anchor::Sunrise(offset)),
+			"noon" => Ok(Anchor::Noon(offset)),
+			"sunset" => Ok(Anchor::Sunset(offset)),
+			_ => Err(invalid()),
+		}
+	}
+}
+
+impl TryFrom<String> for Anchor {
+	type Error = String;
+
+	fn try_from(x: String) -> std::result::Result<Self, Self::Error> {
+		x.parse()
+	}
+}
+
+impl From<Anchor> for String {
+	fn from(x: Anchor) -> Self {
+		x.to_string()
+	}
+}
+
+impl fmt::Display for Anchor {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let (name, offset) = match *self {
+			Anchor::Time(time) => return write!(f, "{}", time.format("%H:%M")),
+			Anchor::Sunrise(offset) => ("sunrise", offset),
+			Anchor::Noon(offset) => ("noon", offset),
+			Anchor::Sunset(offset) => ("sunset", offset),
+		};
+		match offset {
+			0 => write!(f, "{}", name),
+			_ => write!(f, "{}{:+}", name, offset),
+		}
+	}
+}
+
+/// A point of the curve, either value may be left out.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Point {
+	pub at: Anchor,
+	#[serde(default)]
+	pub brightness: Option<u8>,
+	/// Kelvin.
+	#[serde(default)]
+	pub color_temperature: Option<u32>,
+}
+
+fn default_step() -> u8 { 5 }
+
+fn default_max_per_minute() -> usize { 6 }
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Policy {
+	pub name: String,
+	/// Monitor names, aliases or group names.
+	pub targets: Vec<String>,
+	/// Degrees, needed by points relative to the sun.
+	#[serde(default)]
+	pub latitude: Option<f64>,
+	#[serde(default)]
+	pub longitude: Option<f64>,
+	pub points: Vec<Point>,
+	/// Largest brightness change of one write.
+	#[serde(default = "default_step")]
+	pub step: u8,
+	/// Transactions a minute on every monitor.
+	#[serde(default = "default_max_per_minute")]
+	pub max_per_minute: usize,
+}
+
+/// Values of the curve at some time.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Target {
+	pub brightness: Option<u8>,
+	pub color_temperature: Option<types::ColorTemperature>,
+}
+
+impl fmt::Display for Target {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let brightness = self.brightness.map(|x| format!("brightness={}", x));
+		let color_temperature = self.color_temperature.map(|x| format!("color-temperature={}k", kelvin(x)));
+		let values : Vec<_> = brightness.into_iter().chain(color_temperature).collect();
+		write!(f, "{}", values.join(" "))
+	}
+}
+
+impl Policy {
+	fn check(&self, config: &Config) -> Result<()> {
+		check_targets(config, &self.targets)?;
+		let invalid = |reason| Err(Error::InvalidPolicy(self.name.clone(), reason));
+
+		if self.points.is_empty() {
+			return invalid("no points");
+		}
+		if self.step == 0 || self.max_per_minute == 0 {
+			return invalid("step and max_per_minute must be positive");
+		}
+		if self.points.iter().any(|x| x.at.is_solar()) && (self.latitude.is_none() || self.longitude.is_none()) {
+			return invalid("points relative to the sun need a latitude and a longitude");
+		}
+		if self.points.iter().any(|x| x.brightness.is_some_and(|x| x > 100)) {
+			return invalid("brightness above 100");
+		}
+		Ok(())
+	}
+
+	/// Local time of `anchor` on `date`, `None` for solar points on days
+	/// without sunrise or sunset.
+	fn resolve(&self, anchor: Anchor, date: NaiveDate) -> Option<NaiveDateTime> {
+		let sun = || sun(date, self.latitude?, self.longitude?);
+		match anchor {
+			Anchor::Time(time) => Some(date.and_time(time)),
+			Anchor::Sunrise(offset) => sun().map(|x| local(x.sunrise) + Duration::minutes(offset)),
+			Anchor::Noon(offset) => sun().map(|x| local(x.noon) + Duration::minutes(offset)),
+			Anchor::Sunset(offset) => sun().map(|x| local(x.sunset) + Duration::minutes(offset)),
+		}
+	}
+
+	/// Value at `t` of the points having one, linear between the points
+	/// around `t` and across midnight.
+	fn interpolate(&self, t: NaiveDateTime, value: &dyn Fn(&Point) -> Option<f64>) -> Option<f64> {
+		let mut points : Vec<(NaiveDateTime, f64)> = [-1, 0, 1].iter()
+			.map(|&days| t.date() + Duration::days(days))
+			.flat_map(|date| self.points.iter().filter_map(move |x| Some((self.resolve(x.at, date)?, value(x)?))))
+			.collect();
+		points.sort_by_key(|x| x.0);
+
+		match points.iter().position(|x| x.0 > t) {
+			None => points.last().map(|x| x.1),
+			Some(0) => Some(points[0].1),
+			Some(i) => {
+				let (t0, v0) = points[i - 1];
+				let (t1, v1) = points[i];
+				let f = (t - t0).num_seconds() as f64 / (t1 - t0).num_seconds() as f64;
+				Some(v0 + (v1 - v0) * f)
+			},
+		}
+	}
+
+	pub fn target(&self, t: NaiveDateTime) -> Target {
+		Target{
+			brightness: self.interpolate(t, &|x| x.brightness.map(f64::from)).map(|x| x.round() as u8),
+			color_temperature: self.interpolate(t, &|x| x.color_temperature.map(f64::from)).map(nearest_temperature),
+		}
+	}
+}
+
+/// What is known of a monitor, and its recent transactions.
+#[derive(Default)]
+struct MonitorState {
+	brightness: Option<u8>,
+	color_temperature: Option<types::ColorTemperature>,
+	sent: VecDeque<NaiveDateTime>,
+}
+
+impl MonitorState {
+	fn has_budget(&mut self, now: NaiveDateTime, max_per_minute: usize) -> bool {
+		while self.sent.front().is_some_and(|&x| x <= now - Duration::minutes(1)) {
+			self.sent.pop_front();
+		}
+		self.sent.len() < max_per_minute
+	}
+
+	fn spend(&mut self, now: NaiveDateTime, max_per_minute: usize) -> bool {
+		let allowed = self.has_budget(now, max_per_minute);
+		if allowed {
+			self.sent.push_back(now);
+		}
+		allowed
+	}
+
+	fn is_at(&self, target: &Target) -> bool {
+		target.brightness.is_none_or(|x| self.brightness == Some(x))
+			&& target.color_temperature.is_none_or(|x| self.color_temperature == Some(x))
+	}
+
+	/// Moves `m` one step toward `target`, reading the brightness first
+	/// when it is not known.
+	fn adjust(&mut self, m: &mut Monitor, policy: &Policy, target: &Target, now: NaiveDateTime) -> transaction::Result<Vec<Action>> {
+		let mut actions = Vec::new();
+
+		if let Some(brightness) = target.brightness {
+			if self.brightness.is_none() && self.spend(now, policy.max_per_minute) {
+				self.brightness = Some(u8::from(m.get::<types::Brightness>()?));
+			}
+			if let Some(current) = self.brightness.filter(|&x| x != brightness) {
+				if self.spend(now, policy.max_per_minute) {
+					let next = match brightness > current {
+						true => current.saturating_add(policy.step).min(brightness),
+						false => current.saturating_sub(policy.step).max(brightness),
+					};
+					m.set(types::Brightness::from(next))?;
+					self.brightness = Some(next);
+					actions.push(Action::new("brightness", &next.to_string()));
+				}
+			}
+		}
+
+		if let Some(color_temperature) = target.color_temperature.filter(|&x| self.color_temperature != Some(x)) {
+			if self.spend(now, policy.max_per_minute) {
+				m.set(color_temperature)?;
+				self.color_temperature = Some(color_temperature);
+				actions.push(Action::new("color-temperature", &format!("{}k", kelvin(color_temperature))));
+			}
+		}
+
+		Ok(actions)
+	}
+}
+
+/// Writes of one policy at `time`, for every monitor that was opened.
+#[derive(Debug)]
+pub struct Adjustment {
+	pub policy: String,
+	pub time: NaiveDateTime,
+	pub target: Target,
+	pub report: Report<Vec<Action>, transaction::Error>,
+}
+
+pub struct Controller {
+	config: Config,
+	policies: Vec<Policy>,
+	opener: Opener,
+	states: BTreeMap<String, MonitorState>,
+}
+
+impl Controller {
+	pub fn new(config: Config, policies: Vec<Policy>) -> Result<Controller> {
+		Controller::with_opener(config, policies, Box::new(Monitor::open))
+	}
+
+	/// Checks the targets and points of every policy, and that no monitor
+	/// is the target of two policies.
+	pub fn with_opener(config: Config, policies: Vec<Policy>, opener: Opener) -> Result<Controller> {
+		let mut members = BTreeSet::new();
+		for policy in &policies {
+			policy.check(&config)?;
+			let group = Group::resolve_all(&config, &policy.targets).unwrap();
+			if !group.names().into_iter().all(|x| members.insert(String::from(x))) {
+				return Err(Error::InvalidPolicy(policy.name.clone(), "a target already follows another policy"));
+			}
+		}
+		Ok(Controller{config, policies, opener, states: BTreeMap::new()})
+	}
+
+	pub fn policies(&self) -> &[Policy] {
+		&self.policies
+	}
+
+	/// Moves every monitor one step toward the target of its policy, as far
+	/// as its transaction budget allows. Monitors at their target or out of
+	/// budget are not opened. The colour temperature is written once at
+	/// start, since reading it would cost as much.
+	pub fn tick(&mut self, now: NaiveDateTime) -> Vec<Adjustment> {
+		let mut adjustments = Vec::new();
+
+		for policy in &self.policies {
+			let target = policy.target(now);
+			let group = Group::resolve_all(&self.config, &policy.targets).unwrap();
+			let mut results = Vec::new();
+
+			for name in group.names() {
+				let state = self.states.entry(String::from(name)).or_default();
+				if state.is_at(&target) || !state.has_budget(now, policy.max_per_minute) {
+					continue;
+				}
+
+				let (name, monitor) = self.config.monitor(name).unwrap();
+				let result = (self.opener)(name, monitor, &self.config)
+					.map_err(group::Error::OpenError)
+					.and_then(|mut m| state.adjust(&mut m, policy, &target, now).map_err(group::Error::OperationError));
+				if result.is_err() {
+					// The write may or may not have happened
+					state.brightness = None;
+					state.color_temperature = None;
+				}
+				results.push((String::from(name), result));
+			}
+
+			if !results.is_empty() {
+				adjustments.push(Adjustment{policy: policy.name.clone(), time: now, target, report: Report{results}});
+			}
+		}
+
+		adjustments
+	}
+
+	/// Spreads the transactions of the busiest policy over the minute.
+	fn interval(&self) -> std::time::Duration {
+		let max_per_minute = self.policies.iter().map(|x| x.max_per_minute).max().unwrap_or(1);
+		std::time::Duration::from_millis(60_000 / max_per_minute as u64).max(std::time::Duration::from_secs(1))
+	}
+
+	/// Follows the policies, passing every adjustment to `f`.
+	pub fn run<C: Clock, F: FnMut(&Adjustment)>(&mut self, clock: &C, mut f: F) -> ! {
+		let interval = self.interval();
+		loop {
+			self.tick(clock.now()).iter().for_each(&mut f);
+			clock.sleep(interval);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use chrono::{NaiveDate, NaiveDateTime};
+
+	use protocol::types;
+	use schedule::circadian;
+	use schedule::circadian::{Anchor, Controller, Point, Policy};
+	use simulator;
+	use simulator::Simulator;
+
+	fn at(hour: u32, minute: u32, second: u32) -> NaiveDateTime {
+		NaiveDate::from_ymd_opt(2024, 6, 21).unwrap().and_hms_opt(hour, minute, second).unwrap()
+	}
+
+	fn policy(points: &[(&str, u8, u32)]) -> Policy {
+		Policy{
+			name: String::from("atrium"),
+			targets: vec![String::from("lobby")],
+			latitude: None,
+			longitude: None,
+			points: points.iter().map(|&(at, brightness, color_temperature)| Point{
+				at: at.parse().unwrap(),
+				brightness: Some(brightness),
+				color_temperature: Some(color_temperature),
+			}).collect(),
+			step: 10,
+			max_per_minute: 2,
+		}
+	}
+
+	#[test]
+	fn circadian_sun_times() {
+		// Berlin at the summer solstice: 02:43 and 19:33 UTC
+		let sun = circadian::sun(NaiveDate::from_ymd_opt(2024, 6, 21).unwrap(), 52.52, 13.405).unwrap();
+		assert!((sun.sunrise - at(2, 43, 0)).num_minutes().abs() <= 2, "{}", sun.sunrise);
+		assert!((sun.sunset - at(19, 33, 0)).num_minutes().abs() <= 2, "{}", sun.sunset);
+		assert!((sun.noon - at(11, 8, 0)).num_minutes().abs() <= 2, "{}", sun.noon);
+		// Polar day at Longyearbyen
+		assert_eq!(None, circadian::sun(NaiveDate::from_ymd_opt(2024, 6, 21).unwrap(), 78.22, 15.65));
+
+		assert_eq!(Anchor::Sunset(-45), "sunset-45".parse().unwrap());
+		assert_eq!("sunrise+30", "sunrise+30".parse::<Anchor>().unwrap().to_string());
+		assert!("dusk".parse::<Anchor>().is_err());
+	}
+
+	#[test]
+	fn circadian_follows_curve() {
+		let policy = policy(&[("00:00", 20, 5000), ("12:00", 80, 9300), ("18:00", 50, 6500)]);
+		assert_eq!(Some(50), policy.target(at(6, 0, 0)).brightness);
+		assert_eq!(Some(types::ColorTemperature::_7500K), policy.target(at(6, 0, 0)).color_temperature);
+		// Across midnight, from 50 at 18:00 to 20 at 00:00
+		assert_eq!(Some(35), policy.target(at(21, 0, 0)).brightness);
+
+		let simulator = Simulator::new();
+		let (config, opener) = simulator::fixture(&[("lobby", &simulator)]);
+		let mut controller = Controller::with_opener(config, vec![policy], opener).unwrap();
+
+		// Reading the brightness and one step use up the budget
+		let adjustments = controller.tick(at(6, 0, 0));
+		assert_eq!("brightness=65", adjustments[0].report.results[0].1.as_ref().unwrap()[0].to_string());
+		assert_eq!(Some(vec![65]), simulator.value(0x30));
+		assert!(controller.tick(at(6, 0, 30)).is_empty());
+
+		controller.tick(at(6, 1, 0));
+		assert_eq!(Some(vec![55]), simulator.value(0x30));
+		assert_eq!(Some(vec![0x08, 0, 0, 0]), simulator.value(0x43));
+
+		controller.tick(at(6, 2, 0));
+		assert_eq!(Some(vec![50]), simulator.value(0x30));
+		assert!(controller.tick(at(6, 3, 0)).is_empty());
+	}
+
+	#[test]
+	fn circadian_rejects_shared_monitors() {
+		let (config, _) = simulator::fixture(&[("lobby", &Simulator::new()), ("atrium", &Simulator::new())]);
+		let day = policy(&[("00:00", 20, 5000)]);
+		let night = Policy{name: String::from("night"), targets: vec![String::from("atrium"), String::from("lobby")], ..day.clone()};
+		assert!(Controller::new(config.clone(), vec![day.clone(), night.clone()]).is_err());
+
+		let night = Policy{targets: vec![String::from("atrium")], ..night};
+		assert!(Controller::new(config, vec![day, night]).is_ok());
+	}
+}
@@ -0,0 +1,218 @@
+//! Cron expressions: minute, hour, day of month, month and day of week.
+//!
+//! Fields take `*`, numbers, ranges, lists and steps, months and days of
+//! week also take English abbreviations. As in cron, when both day fields
+//! are restricted a day matching either of them matches.
+
+use std;
+use std::fmt;
+use std::str::FromStr;
+use std::convert::TryFrom;
+
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, Timelike};
+
+const MONTHS : [&str; 12] = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+const WEEKDAYS : [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+/// How far `next_after` looks ahead, enough for a February 29th.
+const HORIZON_DAYS : i64 = 8 * 366;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Cron {
+	text: String,
+	minutes: u64,
+	hours: u32,
+	days: u32,
+	months: u16,
+	weekdays: u8,
+	any_day: bool,
+	any_weekday: bool,
+}
+
+fn parse_value(x: &str, min: u32, names: &[&str]) -> Option<u32> {
+	x.parse().ok().or_else(|| {
+		names.iter().position(|name| name.eq_ignore_ascii_case(x)).map(|i| i as u32 + min)
+	})
+}
+
+/// Bit set of the values of one field.
+fn parse_field(field: &str, min: u32, max: u32, names: &[&str]) -> Option<u64> {
+	let mut bits = 0u64;
+
+	for item in field.split(',') {
+		let (range, step) = match item.find('/') {
+			Some(i) => (&item[..i], item[i + 1..].parse::<u32>().ok().filter(|&x| x > 0)?),
+			None => (item, 1),
+		};
+		let (first, last) = match (range, range.find('-')) {
+			("*", _) => (min, max),
+			(_, Some(i)) => (parse_value(&range[..i], min, names)?, parse_value(&range[i + 1..], min, names)?),
+			(_, None) if step > 1 => (parse_value(range, min, names)?, max),
+			(_, None) => {
+				let x = parse_value(range, min, names)?;
+				(x, x)
+			},
+		};
+		if first < min || last > max || first > last {
+			return None;
+		}
+		for x in (first..=last).step_by(step as usize) {
+			bits |= 1 << x;
+		}
+	}
+
+	Some(bits)
+}
+
+fn expand(text: &str) -> &str {
+	match text {
+		"@yearly" | "@annually" => "0 0 1 1 *",
+		"@monthly" => "0 0 1 * *",
+		"@weekly" => "0 0 * * 0",
+		"@daily" | "@midnight" => "0 0 * * *",
+		"@hourly" => "0 * * * *",
+		x => x,
+	}
+}
+
+impl FromStr for Cron {
+	type Err = String;
+
+	fn from_str(text: &str) -> std::result::Result<Self, Self::Err> {
+		let fields : Vec<_> = expand(text.trim()).split_whitespace().collect();
+		if fields.len() != 5 {
+			return Err(format!("{}: expected 5 fields", text));
+		}
+		let invalid = |i: usize| format!("{}: invalid field `{}`", text, fields[i]);
+
+		let weekdays = parse_field(fields[4], 0, 7, &WEEKDAYS).ok_or_else(|| invalid(4))?;
+		Ok(Cron{
+			text: String::from(text.trim()),
+			minutes: parse_field(fields[0], 0, 59, &[]).ok_or_else(|| invalid(0))?,
+			hours: parse_field(fields[1], 0, 23, &[]).ok_or_else(|| invalid(1))? as u32,
+			days: parse_field(fields[2], 1, 31, &[]).ok_or_else(|| invalid(2))? as u32,
+			months: parse_field(fields[3], 1, 12, &MONTHS).ok_or_else(|| invalid(3))? as u16,
+			// Sunday is both 0 and 7
+			weekdays: (weekdays | weekdays >> 7) as u8 & 0x7f,
+			any_day: fields[2].starts_with('*'),
+			any_weekday: fields[4].starts_with('*'),
+		})
+	}
+}
+
+impl TryFrom<String> for Cron {
+	type Error = String;
+
+	fn try_from(x: String) -> std::result::Result<Self, Self::Error> {
+		x.parse()
+	}
+}
+
+impl From<Cron> for String {
+	fn from(x: Cron) -> Self {
+		x.text
+	}
+}
+
+impl fmt::Display for Cron {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", self.text)
+	}
+}
+
+impl Cron {
+	fn matches_day(&self, date: NaiveDate) -> bool {
+		let day = self.days & 1 << date.day() != 0;
+		let weekday = self.weekdays & 1 << date.weekday().num_days_from_sunday() != 0;
+
+		self.months & 1 << date.month() != 0 && match (self.any_day, self.any_weekday) {
+			(true, true) => true,
+			(true, false) => weekday,
+			(false, true) => day,
+			(false, false) => day || weekday,
+		}
+	}
+
+	pub fn matches(&self, t: &NaiveDateTime) -> bool {
+		self.matches_day(t.date()) && self.hours & 1 << t.hour() != 0 && self.minutes & 1 << t.minute() != 0
+	}
+
+	/// First matching minute strictly after `t`.
+	pub fn next_after(&self, t: NaiveDateTime) -> Option<NaiveDateTime> {
+		let mut t = t.with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+		let end = t + Duration::days(HORIZON_DAYS);
+
+		while t < end {
+			if !self.matches_day(t.date()) {
+				t = t.date().succ_opt()?.and_hms_opt(0, 0, 0)?;
+			} else if self.hours & 1 << t.hour() == 0 {
+				t = t.with_minute(0)? + Duration::hours(1);
+			} else if self.minutes & 1 << t.minute() == 0 {
+				t += Duration::minutes(1);
+			} else {
+				return Some(t);
+			}
+		}
+
+		None
+	}
+
+	/// Last matching minute in `(from, to]`.
+	pub fn last_between(&self, from: NaiveDateTime, to: NaiveDateTime) -> Option<NaiveDateTime> {
+		let mut last = None;
+		let mut t = from;
+
+		while let Some(next) = self.next_after(t).filter(|&x| x <= to) {
+			last = Some(next);
+			t = next;
+		}
+		last
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use chrono::{NaiveDate, NaiveDateTime};
+
+	use schedule::cron::Cron;
+
+	fn at(day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+		// 2024-01-01 is a Monday
+		NaiveDate::from_ymd_opt(2024, 1, day).unwrap().and_hms_opt(hour, minute, 0).unwrap()
+	}
+
+	#[test]
+	fn cron_parse() {
+		assert!("30 7 * * 1-5".parse::<Cron>().is_ok());
+		assert!("*/15 8-18/2 1,15 jan-mar sun".parse::<Cron>().is_ok());
+		assert!("@daily".parse::<Cron>().is_ok());
+		assert!("60 * * * *".parse::<Cron>().is_err());
+		assert!("* * * *".parse::<Cron>().is_err());
+		assert!("5-1 * * * *".parse::<Cron>().is_err());
+		assert!("*/0 * * * *".parse::<Cron>().is_err());
+	}
+
+	#[test]
+	fn cron_next_after() {
+		let weekdays : Cron = "30 7 * * mon-fri".parse().unwrap();
+		assert_eq!(Some(at(1, 7, 30)), weekdays.next_after(at(1, 0, 0)));
+		assert_eq!(Some(at(2, 7, 30)), weekdays.next_after(at(1, 7, 30)));
+		// Friday to Monday
+		assert_eq!(Some(at(8, 7, 30)), weekdays.next_after(at(5, 19, 0)));
+
+		let sunday : Cron = "0 0 * * 7".parse().unwrap();
+		assert_eq!(Some(at(7, 0, 0)), sunday.next_after(at(1, 0, 0)));
+
+		// Either the 3rd or a Saturday
+		let either : Cron = "0 12 3 * sat".parse().unwrap();
+		assert_eq!(Some(at(3, 12, 0)), either.next_after(at(1, 0, 0)));
+		assert_eq!(Some(at(6, 12, 0)), either.next_after(at(3, 12, 0)));
+
+		let never : Cron = "0 0 31 2 *".parse().unwrap();
+		assert_eq!(None, never.next_after(at(1, 0, 0)));
+
+		assert_eq!(Some(at(5, 7, 30)), weekdays.last_between(at(1, 0, 0), at(7, 23, 59)));
+		assert_eq!(None, weekdays.last_between(at(6, 0, 0), at(7, 23, 59)));
+	}
+}
@@ -0,0 +1,576 @@
+//! Just enough iCalendar (RFC 5545) to plan around bookings: events with
+//! their recurrence rules, extra and excluded dates and modified or
+//! cancelled instances.
+//!
+//! Times with a time zone identifier are taken as local times, UTC ones
+//! are converted to local time. All-day events are left out.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::collections::BTreeMap;
+
+use chrono::{Datelike, Duration, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc, Weekday};
+
+/// How many periods a recurrence is followed before giving up on it.
+const MAX_PERIODS : i64 = 100_000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Frequency {
+	Daily,
+	Weekly,
+	Monthly,
+	Yearly,
+}
+
+/// A `RRULE`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Recurrence {
+	frequency: Frequency,
+	interval: i64,
+	count: Option<usize>,
+	until: Option<NaiveDateTime>,
+	/// Weekdays with their ordinal in the month, 0 for all of them.
+	by_day: Vec<(i64, Weekday)>,
+	by_month_day: Vec<i64>,
+	by_month: Vec<u32>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Event {
+	pub uid: String,
+	pub summary: String,
+	pub description: String,
+	/// Input from `X-C5517H-INPUT`, or from an `input:` line of the description.
+	pub input: Option<String>,
+	pub start: NaiveDateTime,
+	pub end: NaiveDateTime,
+	pub recurrence: Option<Recurrence>,
+	pub extra_dates: Vec<NaiveDateTime>,
+	pub excluded_dates: Vec<NaiveDateTime>,
+	/// Original start of the instance this event replaces.
+	pub recurrence_id: Option<NaiveDateTime>,
+	pub cancelled: bool,
+}
+
+/// One instance of an event.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Occurrence {
+	pub start: NaiveDateTime,
+	pub end: NaiveDateTime,
+	pub summary: String,
+	pub input: Option<String>,
+}
+
+fn invalid_data(message: String) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Content lines with folding undone.
+fn unfold(text: &str) -> Vec<String> {
+	let mut lines : Vec<String> = Vec::new();
+	for line in text.lines() {
+		match (line.chars().next(), lines.last_mut()) {
+			(Some(' '), Some(last)) | (Some('\t'), Some(last)) => last.push_str(&line[1..]),
+			_ => lines.push(String::from(line)),
+		}
+	}
+	lines
+}
+
+type Parameters = Vec<(String, String)>;
+type Properties = BTreeMap<String, Vec<(Parameters, String)>>;
+
+/// Name, parameters and value of a content line.
+fn split_line(line: &str) -> Option<(String, Parameters, &str)> {
+	let mut quoted = false;
+	let colon = line.char_indices().find(|&(_, x)| {
+		if x == '"' {
+			quoted = !quoted;
+		}
+		x == ':' && !quoted
+	})?.0;
+
+	let mut parts = line[..colon].split(';');
+	let name = parts.next()?.to_ascii_uppercase();
+	let params = parts.filter_map(|x| {
+		let eq = x.find('=')?;
+		Some((x[..eq].to_ascii_uppercase(), String::from(x[eq + 1..].trim_matches('"'))))
+	}).collect();
+
+	Some((name, params, &line[colon + 1..]))
+}
+
+fn unescape(x: &str) -> String {
+	let mut text = String::new();
+	let mut chars = x.chars();
+	while let Some(c) = chars.next() {
+		match (c, chars.clone().next()) {
+			('\\', Some('n')) | ('\\', Some('N')) => {
+				text.push('\n');
+				chars.next();
+			},
+			('\\', Some(x)) => {
+				text.push(x);
+				chars.next();
+			},
+			(c, _) => text.push(c),
+		}
+	}
+	text
+}
+
+/// A date-time, and whether it was given as a plain date.
+fn parse_time(x: &str) -> Option<(NaiveDateTime, bool)> {
+	if x.len() == 8 {
+		let date = NaiveDate::parse_from_str(x, "%Y%m%d").ok()?;
+		return Some((date.and_hms_opt(0, 0, 0)?, true));
+	}
+	let (x, utc) = match x.strip_suffix('Z') {
+		Some(x) => (x, true),
+		None => (x, false),
+	};
+	let time = NaiveDateTime::parse_from_str(x, "%Y%m%dT%H%M%S").ok()?;
+	match utc {
+		true => Some((Utc.from_utc_datetime(&time).with_timezone(&Local).naive_local(), false)),
+		false => Some((time, false)),
+	}
+}
+
+fn parse_duration(x: &str) -> Option<Duration> {
+	let (sign, x) = match x.strip_prefix('-') {
+		Some(x) => (-1, x),
+		None => (1, x.trim_start_matches('+')),
+	};
+	let x = x.strip_prefix('P')?;
+	let mut total = Duration::zero();
+	let mut number = String::new();
+
+	for c in x.chars() {
+		match c {
+			'0'..='9' => number.push(c),
+			'T' => continue,
+			_ => {
+				let n : i64 = number.parse().ok()?;
+				number.clear();
+				total += match c {
+					'W' => Duration::weeks(n),
+					'D' => Duration::days(n),
+					'H' => Duration::hours(n),
+					'M' => Duration::minutes(n),
+					'S' => Duration::seconds(n),
+					_ => return None,
+				};
+			},
+		}
+	}
+	Some(total * sign)
+}
+
+fn parse_weekday(x: &str) -> Option<Weekday> {
+	match x {
+		"MO" => Some(Weekday::Mon),
+		"TU" => Some(Weekday::Tue),
+		"WE" => Some(Weekday::Wed),
+		"TH" => Some(Weekday::Thu),
+		"FR" => Some(Weekday::Fri),
+		"SA" => Some(Weekday::Sat),
+		"SU" => Some(Weekday::Sun),
+		_ => None,
+	}
+}
+
+fn parse_recurrence(x: &str) -> Option<Recurrence> {
+	let mut recurrence = Recurrence{
+		frequency: Frequency::Daily,
+		interval: 1,
+		count: None,
+		until: None,
+		by_day: Vec::new(),
+		by_month_day: Vec::new(),
+		by_month: Vec::new(),
+	};
+	let mut frequency = None;
+
+	for part in x.split(';') {
+		let eq = part.find('=')?;
+		let (name, value) = (part[..eq].to_ascii_uppercase(), &part[eq + 1..]);
+		match name.as_str() {
+			"FREQ" => frequency = match value {
+				"DAILY" => Some(Frequency::Daily),
+				"WEEKLY" => Some(Frequency::Weekly),
+				"MONTHLY" => Some(Frequency::Monthly),
+				"YEARLY" => Some(Frequency::Yearly),
+				_ => return None,
+			},
+			"INTERVAL" => recurrence.interval = value.parse().ok().filter(|&x| x > 0)?,
+			"COUNT" => recurrence.count = Some(value.parse().ok()?),
+			"UNTIL" => recurrence.until = parse_time(value).map(|(x, date)| match date {
+				true => x + Duration::days(1) - Duration::seconds(1),
+				false => x,
+			}),
+			"BYDAY" => for day in value.split(',') {
+				let split = day.len().checked_sub(2)?;
+				let ordinal = match &day[..split] {
+					"" => 0,
+					x => x.trim_start_matches('+').parse().ok()?,
+				};
+				recurrence.by_day.push((ordinal, parse_weekday(&day[split..])?));
+			},
+			"BYMONTHDAY" => for day in value.split(',') {
+				recurrence.by_month_day.push(day.parse().ok()?);
+			},
+			"BYMONTH" => for month in value.split(',') {
+				recurrence.by_month.push(month.parse().ok()?);
+			},
+			_ => (),
+		}
+	}
+
+	recurrence.frequency = frequency?;
+	Some(recurrence)
+}
+
+fn days_in_month(year: i32, month: u32) -> i64 {
+	let next = if month == 12 { NaiveDate::from_ymd_opt(year + 1, 1, 1) } else { NaiveDate::from_ymd_opt(year, month + 1, 1) };
+	next.unwrap().signed_duration_since(NaiveDate::from_ymd_opt(year, month, 1).unwrap()).num_days()
+}
+
+impl Recurrence {
+	/// Days of the given month selected by the rule.
+	fn month_days(&self, year: i32, month: u32, start: NaiveDate) -> Vec<NaiveDate> {
+		let last = days_in_month(year, month);
+		let day = |x: i64| NaiveDate::from_ymd_opt(year, month, x as u32);
+
+		if !self.by_month_day.is_empty() {
+			self.by_month_day.iter()
+				.filter_map(|&x| if x < 0 { day(last + 1 + x) } else { day(x) })
+				.filter(|x| self.by_day.is_empty() || self.by_day.iter().any(|&(_, wd)| wd == x.weekday()))
+				.collect()
+		} else if !self.by_day.is_empty() {
+			let mut days = Vec::new();
+			for &(ordinal, weekday) in &self.by_day {
+				let matching : Vec<_> = (1..=last).filter_map(day).filter(|x| x.weekday() == weekday).collect();
+				match ordinal {
+					0 => days.extend(matching),
+					n if n > 0 => days.extend(matching.get(n as usize - 1)),
+					n => days.extend(matching.len().checked_sub((-n) as usize).and_then(|i| matching.get(i))),
+				}
+			}
+			days
+		} else {
+			day(i64::from(start.day())).into_iter().collect()
+		}
+	}
+
+	/// First day the period `k` can contain and its candidate days.
+	fn period(&self, start: NaiveDate, k: i64) -> (NaiveDate, Vec<NaiveDate>) {
+		let n = k * self.interval;
+		let months = |months: i64| {
+			let total = i64::from(start.year()) * 12 + i64::from(start.month0()) + months;
+			((total / 12) as i32, (total % 12) as u32 + 1)
+		};
+
+		let (first, mut days) = match self.frequency {
+			Frequency::Daily => {
+				let day = start + Duration::days(n);
+				let by_day = self.by_day.is_empty() || self.by_day.iter().any(|&(_, x)| x == day.weekday());
+				let by_month_day = self.by_month_day.is_empty() || self.by_month_day.iter().any(|&x| {
+					x == i64::from(day.day()) || x == i64::from(day.day()) - days_in_month(day.year(), day.month()) - 1
+				});
+				(day, if by_day && by_month_day { vec![day] } else { Vec::new() })
+			},
+			Frequency::Weekly => {
+				let monday = start - Duration::days(i64::from(start.weekday().num_days_from_monday())) + Duration::weeks(n);
+				let days = match self.by_day.is_empty() {
+					true => vec![monday + Duration::days(i64::from(start.weekday().num_days_from_monday()))],
+					false => self.by_day.iter().map(|&(_, x)| monday + Duration::days(i64::from(x.num_days_from_monday()))).collect(),
+				};
+				(monday, days)
+			},
+			Frequency::Monthly => {
+				let (year, month) = months(n);
+				(NaiveDate::from_ymd_opt(year, month, 1).unwrap(), self.month_days(year, month, start))
+			},
+			Frequency::Yearly => {
+				let year = start.year() + n as i32;
+				let months = match self.by_month.is_empty() {
+					true => vec![start.month()],
+					false => self.by_month.clone(),
+				};
+				let days = months.iter().flat_map(|&month| self.month_days(year, month, start)).collect();
+				(NaiveDate::from_ymd_opt(year, 1, 1).unwrap(), days)
+			},
+		};
+
+		days.retain(|x| self.by_month.is_empty() || self.by_month.contains(&x.month()));
+		days.sort();
+		days.dedup();
+		(first, days)
+	}
+
+	/// Starts of the instances up to `to`, the first one being `start`.
+	fn expand(&self, start: NaiveDateTime, to: NaiveDateTime) -> Vec<NaiveDateTime> {
+		let mut starts = vec![start];
+		let until = self.until.map_or(to, |x| x.min(to));
+
+		'periods: for k in 0..MAX_PERIODS {
+			let (first, days) = self.period(start.date(), k);
+			if first > until.date() {
+				break;
+			}
+			for day in days {
+				let time = day.and_time(start.time());
+				if time <= start {
+					continue;
+				}
+				if time > until || self.count.is_some_and(|x| starts.len() >= x) {
+					break 'periods;
+				}
+				starts.push(time);
+			}
+		}
+
+		starts
+	}
+}
+
+fn input_of(description: &str) -> Option<String> {
+	description.lines().find_map(|line| {
+		let line = line.trim();
+		let prefix = line.get(..6)?;
+		match prefix.eq_ignore_ascii_case("input:") || prefix.eq_ignore_ascii_case("input=") {
+			true => Some(String::from(line[6..].trim())).filter(|x| !x.is_empty()),
+			false => None,
+		}
+	})
+}
+
+/// Events of an iCalendar document, all-day ones left out.
+pub fn parse(text: &str) -> io::Result<Vec<Event>> {
+	let mut events = Vec::new();
+	let mut depth = 0;
+	let mut properties : Option<Properties> = None;
+
+	for line in unfold(text) {
+		let (name, params, value) = match split_line(&line) {
+			Some(x) => x,
+			None => continue,
+		};
+		match (name.as_str(), value.to_ascii_uppercase().as_str()) {
+			("BEGIN", "VEVENT") if properties.is_none() => {
+				properties = Some(BTreeMap::new());
+				depth = 0;
+			},
+			("BEGIN", _) => depth += 1,
+			("END", "VEVENT") if depth == 0 => {
+				if let Some(event) = properties.take().map(|x| event(&x)).transpose()? {
+					events.extend(event);
+				}
+			},
+			("END", _) => depth -= 1,
+			_ if depth == 0 => if let Some(ref mut properties) = properties {
+				properties.entry(name).or_insert_with(Vec::new).push((params, String::from(value)));
+			},
+			_ => (),
+		}
+	}
+
+	Ok(events)
+}
+
+fn event(properties: &Properties) -> io::Result<Option<Event>> {
+	let text = |name: &str| properties.get(name).and_then(|x| x.first()).map(|x| unescape(&x.1));
+	let time = |name: &str| match properties.get(name).and_then(|x| x.first()) {
+		Some((_, value)) => parse_time(value).map(Some).ok_or_else(|| invalid_data(format!("invalid {}: {}", name, value))),
+		None => Ok(None),
+	};
+	let times = |name: &str| -> io::Result<Vec<NaiveDateTime>> {
+		let mut times = Vec::new();
+		for (_, value) in properties.get(name).map(|x| &x[..]).unwrap_or(&[]) {
+			for x in value.split(',') {
+				times.push(parse_time(x).ok_or_else(|| invalid_data(format!("invalid {}: {}", name, x)))?.0);
+			}
+		}
+		Ok(times)
+	};
+
+	let (start, all_day) = match time("DTSTART")? {
+		Some(x) => x,
+		None => return Ok(None),
+	};
+	if all_day {
+		return Ok(None);
+	}
+	let end = match (time("DTEND")?, text("DURATION")) {
+		(Some((end, _)), _) => end,
+		(None, Some(duration)) => start + parse_duration(&duration).ok_or_else(|| invalid_data(format!("invalid DURATION: {}", duration)))?,
+		(None, None) => start,
+	};
+	let recurrence = match text("RRULE") {
+		Some(x) => Some(parse_recurrence(&x).ok_or_else(|| invalid_data(format!("invalid RRULE: {}", x)))?),
+		None => None,
+	};
+	let description = text("DESCRIPTION").unwrap_or_default();
+
+	Ok(Some(Event{
+		uid: text("UID").unwrap_or_default(),
+		summary: text("SUMMARY").unwrap_or_default(),
+		input: text("X-C5517H-INPUT").or_else(|| input_of(&description)),
+		description,
+		start,
+		end,
+		recurrence,
+		extra_dates: times("RDATE")?,
+		excluded_dates: times("EXDATE")?,
+		recurrence_id: time("RECURRENCE-ID")?.map(|x| x.0),
+		cancelled: text("STATUS").is_some_and(|x| x.eq_ignore_ascii_case("CANCELLED")),
+	}))
+}
+
+/// Events of `path`, a file or a directory of `.ics` files.
+pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Vec<Event>> {
+	let path = path.as_ref();
+	if !path.is_dir() {
+		return parse(&fs::read_to_string(path)?);
+	}
+
+	let mut files = fs::read_dir(path)?
+		.map(|x| x.map(|x| x.path()))
+		.collect::<io::Result<Vec<_>>>()?;
+	files.retain(|x| x.extension().is_some_and(|x| x.eq_ignore_ascii_case("ics")));
+	files.sort();
+
+	let mut events = Vec::new();
+	for file in files {
+		events.extend(parse(&fs::read_to_string(file)?)?);
+	}
+	Ok(events)
+}
+
+/// Instances of `events` overlapping `[from, to]`, in start order.
+pub fn occurrences(events: &[Event], from: NaiveDateTime, to: NaiveDateTime) -> Vec<Occurrence> {
+	let mut occurrences = Vec::new();
+
+	for event in events.iter().filter(|x| x.recurrence_id.is_none()) {
+		let length = event.end.signed_duration_since(event.start);
+		let mut starts = match event.recurrence {
+			Some(ref recurrence) => recurrence.expand(event.start, to),
+			None => vec![event.start],
+		};
+		starts.extend(event.extra_dates.iter().cloned());
+		starts.sort();
+		starts.dedup();
+
+		for start in starts {
+			if event.excluded_dates.contains(&start) {
+				continue;
+			}
+			let modified = events.iter().find(|x| x.uid == event.uid && x.recurrence_id == Some(start));
+			let occurrence = match modified {
+				Some(x) if x.cancelled => continue,
+				Some(x) => Occurrence{start: x.start, end: x.end, summary: x.summary.clone(), input: x.input.clone()},
+				None if event.cancelled => continue,
+				None => Occurrence{start, end: start + length, summary: event.summary.clone(), input: event.input.clone()},
+			};
+			if occurrence.end >= from && occurrence.start <= to {
+				occurrences.push(occurrence);
+			}
+		}
+	}
+
+	occurrences.sort_by_key(|x| x.start);
+	occurrences
+}
+
+/// Midnight of the day of `t`.
+pub fn day_start(t: NaiveDateTime) -> NaiveDateTime {
+	t.date().and_time(NaiveTime::from_hms_opt(0, 0, 0).unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+	use chrono::{NaiveDate, NaiveDateTime};
+
+	use schedule::ical;
+
+	fn at(month: u32, day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+		NaiveDate::from_ymd_opt(2024, month, day).unwrap().and_hms_opt(hour, minute, 0).unwrap()
+	}
+
+	const CALENDAR : &str = "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+BEGIN:VEVENT\r
+UID:standup\r
+SUMMARY:Stand-up\r
+DESCRIPTION:Daily sync\\nInput: HDMI2\r
+DTSTART;TZID=Europe/Berlin:20240101T090000\r
+DURATION:PT15M\r
+RRULE:FREQ=WEEKLY;BYDAY=MO,WE,FR;COUNT=6\r
+EXDATE;TZID=Europe/Berlin:20240103T090000\r
+BEGIN:VALARM\r
+TRIGGER:-PT5M\r
+END:VALARM\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:standup\r
+RECURRENCE-ID;TZID=Europe/Berlin:20240105T090000\r
+SUMMARY:Stand-up (moved)\r
+DTSTART;TZID=Europe/Berlin:20240105T110000\r
+DTEND;TZID=Europe/Berlin:20240105T111500\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:holiday\r
+DTSTART;VALUE=DATE:20240101\r
+SUMMARY:Holiday\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:review\r
+SUMMARY:Monthly rev\r
+ iew\r
+X-C5517H-INPUT:dp1\r
+DTSTART:20240101T140000\r
+DTEND:20240101T150000\r
+RRULE:FREQ=MONTHLY;BYDAY=-1FR;UNTIL=20240331T235959\r
+END:VEVENT\r
+END:VCALENDAR\r
+";
+
+	#[test]
+	fn ical_occurrences() {
+		let events = ical::parse(CALENDAR).unwrap();
+		assert_eq!(3, events.len());
+
+		let occurrences = ical::occurrences(&events, at(1, 1, 0, 0), at(12, 31, 0, 0));
+		let starts : Vec<_> = occurrences.iter().map(|x| (x.start, x.summary.as_str())).collect();
+		assert_eq!(vec![
+			(at(1, 1, 9, 0), "Stand-up"),
+			(at(1, 1, 14, 0), "Monthly review"),
+			(at(1, 5, 11, 0), "Stand-up (moved)"),
+			(at(1, 8, 9, 0), "Stand-up"),
+			(at(1, 10, 9, 0), "Stand-up"),
+			(at(1, 12, 9, 0), "Stand-up"),
+			(at(1, 26, 14, 0), "Monthly review"),
+			(at(2, 23, 14, 0), "Monthly review"),
+			(at(3, 29, 14, 0), "Monthly review"),
+		], starts);
+		assert_eq!(at(1, 1, 9, 15), occurrences[0].end);
+		assert_eq!(Some(String::from("HDMI2")), occurrences[0].input);
+		assert_eq!(Some(String::from("dp1")), occurrences[1].input);
+	}
+
+	#[test]
+	fn ical_recurrence_rules() {
+		let event = |rule: &str| format!("BEGIN:VEVENT\nUID:x\nDTSTART:20240131T080000\nRRULE:{}\nEND:VEVENT\n", rule);
+		let starts = |rule: &str| -> Vec<NaiveDateTime> {
+			ical::occurrences(&ical::parse(&event(rule)).unwrap(), at(1, 1, 0, 0), at(6, 30, 0, 0)).iter().map(|x| x.start).collect()
+		};
+
+		// Months without a 31st are skipped
+		assert_eq!(vec![at(1, 31, 8, 0), at(3, 31, 8, 0), at(5, 31, 8, 0)], starts("FREQ=MONTHLY"));
+		assert_eq!(vec![at(1, 31, 8, 0), at(2, 29, 8, 0), at(3, 31, 8, 0)], starts("FREQ=MONTHLY;BYMONTHDAY=-1;COUNT=3"));
+		assert_eq!(vec![at(1, 31, 8, 0), at(2, 2, 8, 0), at(2, 4, 8, 0)], starts("FREQ=DAILY;INTERVAL=2;COUNT=3"));
+		assert_eq!(vec![at(1, 31, 8, 0), at(2, 14, 8, 0)], starts("FREQ=WEEKLY;INTERVAL=2;UNTIL=20240220"));
+		assert!(ical::parse(&event("FREQ=HOURLY")).is_err());
+	}
+}
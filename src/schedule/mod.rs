@@ -0,0 +1,620 @@
+//! Runs property writes on monitors and groups at times given by cron
+//! expressions or by the events of iCalendar files, in local time.
+//!
+//! Runs missed while the scheduler was down are caught up at start, only
+//! the last one of every rule and in the order they were due. A calendar
+//! catches up with the last value due of every property it writes. Times are
+//! passed in rather than read, so schedules can be tested with any clock.
+
+pub mod cron;
+pub mod ical;
+pub mod circadian;
+
+use std;
+use std::fs;
+use std::io;
+use std::fmt;
+use std::error;
+use std::thread;
+use std::path::{Path, PathBuf};
+use std::collections::{BTreeMap, BTreeSet};
+
+use chrono::{Duration, Local, NaiveDateTime, Timelike};
+use serde_json;
+
+use config::Config;
+use group;
+use group::{Group, Report};
+use monitor;
+use monitor::{Monitor, Opener};
+use property;
+
+pub use self::cron::Cron;
+pub use self::circadian::Policy;
+
+#[derive(Debug)]
+pub enum Error {
+	UnknownTarget(monitor::Error),
+	UnknownProperty(String),
+	NotWritable(String),
+	InvalidPolicy(String, &'static str),
+	CalendarError(PathBuf, io::Error),
+	UnknownInput(PathBuf, String),
+	StateError(io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Error::UnknownTarget(ref monitor_error) =>
+				write!(f, "{}", monitor_error),
+			Error::UnknownProperty(ref name) =>
+				write!(f, "unknown property: {}", name),
+			Error::NotWritable(ref name) =>
+				write!(f, "{} is not writable", name),
+			Error::InvalidPolicy(ref name, reason) =>
+				write!(f, "{}: {}", name, reason),
+			Error::CalendarError(ref path, ref io_error) =>
+				write!(f, "calendar error: {}: {}", path.display(), io_error),
+			Error::UnknownInput(ref path, ref input) =>
+				write!(f, "calendar error: {}: unknown input: {}", path.display(), input),
+			Error::StateError(ref io_error) =>
+				write!(f, "state error: {}", io_error),
+		}
+	}
+}
+
+impl error::Error for Error {
+	fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+		match self {
+			Error::UnknownTarget(ref monitor_error) => Some(monitor_error),
+			Error::CalendarError(_, ref io_error) => Some(io_error),
+			Error::StateError(ref io_error) => Some(io_error),
+			_ => None,
+		}
+	}
+}
+
+/// One property write, such as `{ property = "input", value = "hdmi2" }`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Action {
+	pub property: String,
+	pub value: String,
+}
+
+impl Action {
+	pub fn new(property: &str, value: &str) -> Action {
+		Action{property: String::from(property), value: String::from(value)}
+	}
+}
+
+impl fmt::Display for Action {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}={}", self.property, self.value)
+	}
+}
+
+/// Actions planned for `time`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Transition {
+	pub time: NaiveDateTime,
+	pub actions: Vec<Action>,
+	/// What the transition is for, such as the event summary.
+	pub note: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Rule {
+	pub name: String,
+	pub cron: Cron,
+	/// Monitor names, aliases or group names.
+	pub targets: Vec<String>,
+	/// Written in order, a failed write stops the sequence for that monitor.
+	pub actions: Vec<Action>,
+}
+
+impl Rule {
+	/// Transitions in `(from, to]`.
+	pub fn transitions(&self, from: NaiveDateTime, to: NaiveDateTime) -> Vec<Transition> {
+		let mut transitions = Vec::new();
+		let mut t = from;
+
+		while let Some(time) = self.cron.next_after(t).filter(|&x| x <= to) {
+			transitions.push(Transition{time, actions: self.actions.clone(), note: self.cron.to_string()});
+			t = time;
+		}
+		transitions
+	}
+}
+
+fn default_lead_minutes() -> i64 { 10 }
+
+/// Bookings of a calendar: the monitors power on ahead of every event and
+/// switch to its input, then power off after the last event of the day.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Calendar {
+	pub name: String,
+	/// An `.ics` file or a directory of them.
+	pub path: PathBuf,
+	/// Monitor names, aliases or group names.
+	pub targets: Vec<String>,
+	/// Minutes between powering on and the start of an event.
+	#[serde(default = "default_lead_minutes")]
+	pub lead_minutes: i64,
+}
+
+impl Calendar {
+	pub fn load(&self) -> Result<Vec<ical::Event>> {
+		ical::load(&self.path).map_err(|err| Error::CalendarError(self.path.clone(), err))
+	}
+
+	/// Transitions in `(from, to]` planned for `events`.
+	pub fn transitions(&self, events: &[ical::Event], from: NaiveDateTime, to: NaiveDateTime) -> Vec<Transition> {
+		let lead = Duration::minutes(self.lead_minutes);
+		let occurrences = ical::occurrences(events,
+			ical::day_start(from) - Duration::days(1) - lead, ical::day_start(to) + Duration::days(2) + lead);
+		let is_needed = |t: NaiveDateTime| occurrences.iter().any(|x| x.start - lead <= t && t < x.end);
+		let mut transitions = Vec::new();
+		let mut last_of_day : BTreeMap<_, &ical::Occurrence> = BTreeMap::new();
+
+		for occurrence in &occurrences {
+			let mut actions = vec![Action::new("power", "on")];
+			actions.extend(occurrence.input.iter().map(|x| Action::new("input", x)));
+			transitions.push(Transition{time: occurrence.start - lead, actions, note: occurrence.summary.clone()});
+
+			let last = last_of_day.entry(occurrence.start.date()).or_insert(occurrence);
+			if occurrence.end > last.end {
+				*last = occurrence;
+			}
+		}
+		for last in last_of_day.values().filter(|x| !is_needed(x.end)) {
+			transitions.push(Transition{time: last.end, actions: vec![Action::new("power", "off")], note: format!("after {}", last.summary)});
+		}
+
+		transitions.retain(|x| from < x.time && x.time <= to);
+		transitions.sort_by_key(|x| x.time);
+		transitions
+	}
+
+	/// Summary of an event booking the monitors during `[from, to)`, its lead included.
+	pub fn booking(&self, events: &[ical::Event], from: NaiveDateTime, to: NaiveDateTime) -> Option<String> {
+		let lead = Duration::minutes(self.lead_minutes);
+		ical::occurrences(events, from, to + lead).into_iter()
+			.find(|x| x.start - lead < to && from < x.end)
+			.map(|x| x.summary)
+	}
+}
+
+/// Outcome of a rule or calendar transition due at `time`.
+#[derive(Debug)]
+pub struct Run {
+	pub rule: String,
+	pub time: NaiveDateTime,
+	pub note: String,
+	pub report: Report<(), property::Error>,
+}
+
+/// Source of the local time.
+pub trait Clock {
+	fn now(&self) -> NaiveDateTime;
+	fn sleep(&self, duration: std::time::Duration);
+}
+
+pub struct LocalClock;
+
+impl Clock for LocalClock {
+	fn now(&self) -> NaiveDateTime {
+		Local::now().naive_local()
+	}
+
+	fn sleep(&self, duration: std::time::Duration) {
+		thread::sleep(duration)
+	}
+}
+
+/// Where the last run times are kept: `$XDG_STATE_HOME/c5517h/schedule.json`.
+pub fn state_path() -> Option<PathBuf> {
+	Config::state_dir().map(|x| x.join("schedule.json"))
+}
+
+fn check_targets(config: &Config, targets: &[String]) -> Result<()> {
+	Group::resolve_all(config, targets).map(|_| ()).map_err(Error::UnknownTarget)
+}
+
+pub struct Scheduler {
+	config: Config,
+	rules: Vec<Rule>,
+	calendars: Vec<(Calendar, Vec<ical::Event>)>,
+	opener: Opener,
+	catch_up: Duration,
+	state: Option<PathBuf>,
+	last_run: BTreeMap<String, NaiveDateTime>,
+}
+
+impl Scheduler {
+	pub fn new(config: Config, rules: Vec<Rule>) -> Result<Scheduler> {
+		Scheduler::with_opener(config, rules, Box::new(Monitor::open))
+	}
+
+	/// Checks that the targets and actions of every rule exist.
+	pub fn with_opener(config: Config, rules: Vec<Rule>, opener: Opener) -> Result<Scheduler> {
+		for rule in &rules {
+			check_targets(&config, &rule.targets)?;
+			for action in &rule.actions {
+				let property = property::find(&action.property).ok_or_else(|| Error::UnknownProperty(action.property.clone()))?;
+				if !property.is_writable() {
+					return Err(Error::NotWritable(action.property.clone()));
+				}
+			}
+		}
+
+		Ok(Scheduler{
+			config,
+			rules,
+			calendars: Vec::new(),
+			opener,
+			catch_up: Duration::hours(24),
+			state: None,
+			last_run: BTreeMap::new(),
+		})
+	}
+
+	/// Events of `calendar`, checking that their inputs exist.
+	fn load_events(calendar: &Calendar) -> Result<Vec<ical::Event>> {
+		let events = calendar.load()?;
+		let inputs = property::find("input").unwrap().choices();
+		for input in events.iter().filter_map(|x| x.input.as_ref()) {
+			if !inputs.iter().any(|x| x.eq_ignore_ascii_case(input.trim())) {
+				return Err(Error::UnknownInput(calendar.path.clone(), input.clone()));
+			}
+		}
+		Ok(events)
+	}
+
+	/// Adds calendars, reading their events.
+	pub fn with_calendars(mut self, calendars: Vec<Calendar>) -> Result<Scheduler> {
+		for calendar in calendars {
+			check_targets(&self.config, &calendar.targets)?;
+			let events = Scheduler::load_events(&calendar)?;
+			self.calendars.push((calendar, events));
+		}
+		Ok(self)
+	}
+
+	/// Reads the calendars again, those that fail to read keep their events.
+	pub fn reload_calendars(&mut self) -> Result<()> {
+		let mut result = Ok(());
+		for (calendar, events) in &mut self.calendars {
+			match Scheduler::load_events(calendar) {
+				Ok(x) => *events = x,
+				Err(err) => result = Err(err),
+			}
+		}
+		result
+	}
+
+	/// Runs missed for longer than `catch_up` are dropped, 24 hours by default.
+	pub fn with_catch_up(mut self, catch_up: Duration) -> Scheduler {
+		self.catch_up = catch_up;
+		self
+	}
+
+	/// Keeps the last run times in `path`, so that runs missed while down are caught up.
+	pub fn with_state<P: AsRef<Path>>(mut self, path: P) -> Result<Scheduler> {
+		self.last_run = match fs::read(path.as_ref()) {
+			Ok(content) => serde_json::from_slice(&content)
+				.map_err(|err| Error::StateError(io::Error::new(io::ErrorKind::InvalidData, err)))?,
+			Err(ref err) if err.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+			Err(err) => return Err(Error::StateError(err)),
+		};
+		self.state = Some(path.as_ref().to_path_buf());
+		Ok(self)
+	}
+
+	pub fn rules(&self) -> &[Rule] {
+		&self.rules
+	}
+
+	/// Name, targets and transitions in `(from, to]` of every rule and calendar.
+	fn plans(&self, from: &dyn Fn(&str) -> NaiveDateTime, to: NaiveDateTime) -> Vec<(&str, &[String], Vec<Transition>)> {
+		let rules = self.rules.iter()
+			.map(|x| (x.name.as_str(), &x.targets[..], x.transitions(from(&x.name), to)));
+		let calendars = self.calendars.iter()
+			.map(|(x, events)| (x.name.as_str(), &x.targets[..], x.transitions(events, from(&x.name), to)));
+		rules.chain(calendars).collect()
+	}
+
+	/// Everything planned in `(from, to]`, in time order, with the rule or calendar name.
+	pub fn preview(&self, from: NaiveDateTime, to: NaiveDateTime) -> Vec<(String, Vec<String>, Transition)> {
+		let mut transitions : Vec<_> = self.plans(&|_| from, to).into_iter()
+			.flat_map(|(name, targets, transitions)| transitions.into_iter().map(move |x| (String::from(name), targets.to_vec(), x)))
+			.collect();
+		transitions.sort_by_key(|x| x.2.time);
+		transitions
+	}
+
+	fn save_state(&self) -> Result<()> {
+		let path = match self.state {
+			Some(ref path) => path,
+			None => return Ok(()),
+		};
+		if let Some(dir) = path.parent() {
+			fs::create_dir_all(dir).map_err(Error::StateError)?;
+		}
+		let temporary = path.with_extension("tmp");
+		fs::write(&temporary, serde_json::to_vec(&self.last_run).unwrap()).map_err(Error::StateError)?;
+		fs::rename(&temporary, path).map_err(Error::StateError)
+	}
+
+	/// Writes `actions` to `targets`, repeating them on monitors that could
+	/// not be opened as their retry policy permits.
+	fn execute(&self, targets: &[String], actions: &[Action]) -> Report<(), property::Error> {
+		let actions : Vec<_> = actions.iter()
+			.map(|x| (property::find(&x.property).unwrap(), x))
+			.collect();
+		let apply = |m: &mut Monitor| {
+			actions.iter().try_for_each(|&(property, action)| property.set(m, &action.value))
+		};
+
+		let group = Group::resolve_all(&self.config, targets).unwrap();
+		let mut report = group.run_with(&self.config, &self.opener, apply);
+
+		for attempt in 2.. {
+			let failed : Vec<_> = report.results.iter()
+				.filter(|&(name, result)| match result {
+					Err(group::Error::OpenError(_)) => self.config.monitors[name].retry.attempts >= attempt,
+					_ => false,
+				})
+				.map(|(name, _)| name.clone())
+				.collect();
+			if failed.is_empty() {
+				break;
+			}
+
+			let delay = failed.iter().map(|x| self.config.monitors[x].retry.delay_ms).max().unwrap();
+			thread::sleep(std::time::Duration::from_millis(delay));
+
+			let retry = Group::resolve_all(&self.config, &failed).unwrap().run_with(&self.config, &self.opener, apply);
+			for (name, result) in retry.results {
+				let i = report.results.iter().position(|x| x.0 == name).unwrap();
+				report.results[i].1 = result;
+			}
+		}
+
+		report
+	}
+
+	/// The last transition, writing the last value of every property of
+	/// `transitions` in the order they were first written.
+	fn merge(transitions: Vec<Transition>) -> Option<Transition> {
+		let mut actions : Vec<Action> = Vec::new();
+		for action in transitions.iter().flat_map(|x| &x.actions) {
+			match actions.iter_mut().find(|x| x.property == action.property) {
+				Some(x) => x.value = action.value.clone(),
+				None => actions.push(action.clone()),
+			}
+		}
+		transitions.into_iter().last().map(|x| Transition{actions, ..x})
+	}
+
+	/// Runs every rule and calendar transition due since the previous call,
+	/// or since the last run recorded in the state, up to `now`.
+	///
+	/// A run that failed on any of its targets stays due, and is repeated on
+	/// all of them by the next calls for as long as `catch_up` covers it.
+	pub fn run_due(&mut self, now: NaiveDateTime) -> Result<Vec<Run>> {
+		let from = |name: &str| self.last_run.get(name).cloned().unwrap_or(now).max(now - self.catch_up);
+		let rule_count = self.rules.len();
+		let mut due : Vec<_> = self.plans(&from, now).into_iter().enumerate()
+			.filter_map(|(i, (name, targets, transitions))| match i < rule_count {
+				true => transitions.into_iter().last(),
+				false => Scheduler::merge(transitions),
+			}.map(|x| (x, String::from(name), targets.to_vec())))
+			.collect();
+		due.sort_by_key(|x| x.0.time);
+
+		let runs : Vec<_> = due.into_iter().map(|(transition, rule, targets)| {
+			let report = self.execute(&targets, &transition.actions);
+			Run{rule, time: transition.time, note: transition.note, report}
+		}).collect();
+
+		let failed : BTreeSet<_> = runs.iter().filter(|x| !x.report.is_success()).map(|x| x.rule.clone()).collect();
+		let names : Vec<_> = self.rules.iter().map(|x| x.name.clone())
+			.chain(self.calendars.iter().map(|x| x.0.name.clone()))
+			.filter(|x| !failed.contains(x))
+			.collect();
+		for name in names {
+			self.last_run.insert(name, now);
+		}
+
+		self.save_state()?;
+		Ok(runs)
+	}
+
+	/// Runs the rules and calendars as they fall due, passing every run and
+	/// every error to `f`.
+	pub fn run<C: Clock, F: FnMut(std::result::Result<&Run, &Error>)>(&mut self, clock: &C, mut f: F) -> ! {
+		loop {
+			if let Err(err) = self.reload_calendars() {
+				f(Err(&err));
+			}
+			match self.run_due(clock.now()) {
+				Ok(runs) => runs.iter().for_each(|x| f(Ok(x))),
+				Err(err) => f(Err(&err)),
+			}
+
+			// Wake up just after the next minute starts
+			let second = clock.now().second();
+			clock.sleep(std::time::Duration::from_secs(u64::from(60 - second.min(59))));
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::fs;
+	use std::env;
+	use std::process;
+
+	use chrono::{Duration, NaiveDate, NaiveDateTime};
+
+	use schedule::{Action, Calendar, Error, Rule, Scheduler};
+	use schedule::ical;
+	use simulator;
+	use simulator::Simulator;
+
+	fn at(day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+		NaiveDate::from_ymd_opt(2024, 1, day).unwrap().and_hms_opt(hour, minute, 0).unwrap()
+	}
+
+	fn rule(name: &str, cron: &str, actions: &[(&str, &str)]) -> Rule {
+		Rule{
+			name: String::from(name),
+			cron: cron.parse().unwrap(),
+			targets: vec![String::from("lobby")],
+			actions: actions.iter().map(|&(property, value)| Action{property: String::from(property), value: String::from(value)}).collect(),
+		}
+	}
+
+	fn scheduler(simulator: &Simulator, rules: Vec<Rule>) -> Scheduler {
+		let (config, opener) = simulator::fixture(&[("lobby", simulator)]);
+		Scheduler::with_opener(config, rules, opener).unwrap()
+	}
+
+	fn signage() -> Vec<Rule> {
+		vec![
+			rule("on", "30 7 * * 1-5", &[("power", "on"), ("input", "hdmi2")]),
+			rule("off", "0 19 * * 1-5", &[("power", "off")]),
+		]
+	}
+
+	#[test]
+	fn scheduler_runs_due_rules() {
+		let simulator = Simulator::new();
+		let mut scheduler = scheduler(&simulator, signage());
+
+		assert!(scheduler.run_due(at(1, 7, 0)).unwrap().is_empty());
+		assert!(scheduler.run_due(at(1, 7, 29)).unwrap().is_empty());
+
+		let runs = scheduler.run_due(at(1, 7, 30)).unwrap();
+		assert_eq!(1, runs.len());
+		assert_eq!("on", runs[0].rule);
+		assert!(runs[0].report.is_success());
+		assert_eq!(Some(vec![0x02, 0, 0, 0]), simulator.value(0x62));
+
+		assert!(scheduler.run_due(at(1, 7, 31)).unwrap().is_empty());
+		assert_eq!("off", scheduler.run_due(at(1, 19, 0)).unwrap()[0].rule);
+		assert_eq!(Some(vec![0]), simulator.value(0x20));
+	}
+
+	#[test]
+	fn scheduler_catches_up() {
+		let simulator = Simulator::new();
+		let mut scheduler = scheduler(&simulator, signage());
+
+		scheduler.run_due(at(1, 6, 0)).unwrap();
+		// Down from Monday 6:00 to Tuesday 8:00, missing "on", "off" and "on" again
+		let runs = scheduler.run_due(at(2, 8, 0)).unwrap();
+		assert_eq!(vec![("off", at(1, 19, 0)), ("on", at(2, 7, 30))],
+			runs.iter().map(|x| (x.rule.as_str(), x.time)).collect::<Vec<_>>());
+		assert_eq!(Some(vec![1]), simulator.value(0x20));
+
+		// Missed runs older than the catch-up window are dropped
+		let mut scheduler = scheduler.with_catch_up(Duration::minutes(30));
+		assert!(scheduler.run_due(at(3, 8, 0)).unwrap().is_empty());
+	}
+
+	#[test]
+	fn scheduler_repeats_failed_runs() {
+		let simulator = Simulator::new();
+		let mut scheduler = scheduler(&simulator, signage()).with_catch_up(Duration::minutes(30));
+
+		scheduler.run_due(at(1, 18, 0)).unwrap();
+		simulator.set_responding(false);
+		assert!(!scheduler.run_due(at(1, 19, 0)).unwrap()[0].report.is_success());
+		assert!(!scheduler.run_due(at(1, 19, 1)).unwrap()[0].report.is_success());
+
+		// Back in time, the panel gets its "off" after all
+		simulator.set_responding(true);
+		let runs = scheduler.run_due(at(1, 19, 2)).unwrap();
+		assert_eq!(vec![("off", at(1, 19, 0))], runs.iter().map(|x| (x.rule.as_str(), x.time)).collect::<Vec<_>>());
+		assert!(runs[0].report.is_success());
+		assert_eq!(Some(vec![0]), simulator.value(0x20));
+		assert!(scheduler.run_due(at(1, 19, 3)).unwrap().is_empty());
+
+		// Not once the catch-up window is over
+		simulator.set_responding(false);
+		scheduler.run_due(at(2, 7, 30)).unwrap();
+		simulator.set_responding(true);
+		assert!(scheduler.run_due(at(2, 8, 1)).unwrap().is_empty());
+		assert_eq!(Some(vec![0]), simulator.value(0x20));
+	}
+
+	#[test]
+	fn calendar_transitions() {
+		let events = ical::parse("BEGIN:VEVENT
+UID:a
+SUMMARY:Planning
+X-C5517H-INPUT:dp1
+DTSTART:20240101T090000
+DTEND:20240101T100000
+END:VEVENT
+BEGIN:VEVENT
+UID:b
+SUMMARY:Review
+DTSTART:20240101T095500
+DTEND:20240101T110000
+RRULE:FREQ=DAILY;COUNT=2
+END:VEVENT
+").unwrap();
+		let calendar = Calendar{name: String::from("room12"), path: From::from("room12.ics"), targets: Vec::new(), lead_minutes: 10};
+		let transitions : Vec<_> = calendar.transitions(&events, at(1, 0, 0), at(3, 0, 0)).into_iter()
+			.map(|x| (x.time, x.actions.iter().map(|x| x.to_string()).collect::<Vec<_>>().join(" "), x.note))
+			.collect();
+
+		assert_eq!(vec![
+			(at(1, 8, 50), String::from("power=on input=dp1"), String::from("Planning")),
+			(at(1, 9, 45), String::from("power=on"), String::from("Review")),
+			(at(1, 11, 0), String::from("power=off"), String::from("after Review")),
+			(at(2, 9, 45), String::from("power=on"), String::from("Review")),
+			(at(2, 11, 0), String::from("power=off"), String::from("after Review")),
+		], transitions);
+	}
+
+	#[test]
+	fn scheduler_catches_up_calendars() {
+		let simulator = Simulator::new();
+		let path = env::temp_dir().join(format!("c5517h-schedule-catch-up-{}.ics", process::id()));
+		fs::write(&path, "BEGIN:VCALENDAR\nBEGIN:VEVENT\nUID:a\nSUMMARY:Planning\nX-C5517H-INPUT:dp1\nDTSTART:20240101T090000\nDTEND:20240101T100000\nEND:VEVENT\nBEGIN:VEVENT\nUID:b\nSUMMARY:Review\nDTSTART:20240101T095500\nDTEND:20240101T110000\nEND:VEVENT\nEND:VCALENDAR\n").unwrap();
+		let calendar = Calendar{name: String::from("room12"), path: path.clone(), targets: vec![String::from("lobby")], lead_minutes: 10};
+		let mut scheduler = scheduler(&simulator, Vec::new()).with_calendars(vec![calendar]).unwrap();
+		fs::remove_file(&path).unwrap();
+
+		// Down over the leads of both events, the input of the first one still applies
+		scheduler.run_due(at(1, 8, 0)).unwrap();
+		let runs = scheduler.run_due(at(1, 9, 50)).unwrap();
+		assert_eq!(vec![("room12", at(1, 9, 45), true)],
+			runs.iter().map(|x| (x.rule.as_str(), x.time, x.report.is_success())).collect::<Vec<_>>());
+		assert_eq!((Some(vec![1]), Some(vec![0x08, 0, 0, 0])), (simulator.value(0x20), simulator.value(0x62)));
+	}
+
+	#[test]
+	fn scheduler_rejects_unknown_properties() {
+		let (config, _) = simulator::fixture(&[("lobby", &Simulator::new())]);
+
+		assert!(Scheduler::new(config.clone(), vec![rule("x", "* * * * *", &[("volume", "10")])]).is_err());
+		assert!(Scheduler::new(config.clone(), vec![rule("x", "* * * * *", &[("name", "x")])]).is_err());
+		let mut unknown = rule("x", "* * * * *", &[("power", "on")]);
+		unknown.targets.push(String::from("lounge"));
+		assert!(Scheduler::new(config.clone(), vec![unknown]).is_err());
+
+		let path = env::temp_dir().join(format!("c5517h-schedule-{}.ics", process::id()));
+		fs::write(&path, "BEGIN:VCALENDAR\nBEGIN:VEVENT\nUID:a\nSUMMARY:Planning\nX-C5517H-INPUT:hdmi9\nDTSTART:20240101T090000\nDTEND:20240101T100000\nEND:VEVENT\nEND:VCALENDAR\n").unwrap();
+		let calendar = Calendar{name: String::from("room12"), path: path.clone(), targets: Vec::new(), lead_minutes: 10};
+		let result = Scheduler::new(config, Vec::new()).unwrap().with_calendars(vec![calendar]);
+		fs::remove_file(&path).unwrap();
+		assert!(matches!(result, Err(Error::UnknownInput(_, ref input)) if input == "hdmi9"));
+	}
+}
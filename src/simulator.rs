@@ -0,0 +1,273 @@
+//! A fake C5517H speaking the RS232 protocol, for tests and demos.
+//!
+//! `Simulator` is a transport: frames written to it are answered the way a
+//! monitor would. Clones share the device state but not the line, like two
+//! ports wired to the same monitor.
+
+use std::io;
+use std::io::{Read, Write};
+use std::sync::{Arc, Mutex};
+use std::collections::BTreeMap;
+
+#[cfg(test)]
+use config::{Config, MonitorConfig};
+#[cfg(test)]
+use monitor::{Monitor, Opener};
+use protocol::checksum::{CheckSum, XORCheckSum};
+use protocol::frame::{frame_size, monitor_frame, HOST_PREFIX};
+use protocol::reply::ResultCode;
+
+const READ : u8 = 0xEB;
+const WRITE : u8 = 0xEA;
+const RESET_POWER : u8 = 0x2F;
+
+/// Opcodes that can only be read.
+const READ_ONLY : [u8; 4] = [0x01, 0x02, 0x04, 0xA0];
+
+struct State {
+	values: BTreeMap<u8, Vec<u8>>,
+	fail_next: Option<ResultCode>,
+	responding: bool,
+	corrupting: bool,
+	requests: usize,
+}
+
+#[derive(Clone)]
+pub struct Simulator {
+	state: Arc<Mutex<State>>,
+	input: Vec<u8>,
+	output: Vec<u8>,
+}
+
+fn reply(result_code: u8, opcode: u8, payload: &[u8]) -> Vec<u8> {
+	monitor_frame(result_code, opcode, payload)
+}
+
+fn is_valid(opcode: u8, payload: &[u8]) -> bool {
+	match (opcode, payload) {
+		(0x30, &[x]) | (0x31, &[x]) | (0x34, &[x]) | (0x80, &[x]) => x <= 100,
+		(0x83, &[x]) => (5..=60).contains(&x),
+		(0x20, &[x]) | (0x21, &[x]) | (0x22, &[x]) | (0x60, &[x]) | (0x84, &[x]) | (0xA3, &[x]) => x <= 1,
+		(0x33, &[x]) => x == 0 || x == 2 || x == 4,
+		_ => true,
+	}
+}
+
+impl State {
+	fn process(&mut self, direction: u8, opcode: u8, payload: &[u8]) -> Vec<u8> {
+		self.requests += 1;
+
+		if let Some(result_code) = self.fail_next.take() {
+			return reply(result_code as u8, opcode, &[]);
+		}
+
+		let parameters_error = reply(ResultCode::ParametersError as u8, opcode, &[]);
+		match direction {
+			READ => match self.values.get(&opcode) {
+				Some(value) => reply(0, opcode, value),
+				None => parameters_error,
+			},
+			WRITE if opcode == RESET_POWER => {
+				self.values.insert(0x20, vec![1]);
+				reply(0, opcode, &[])
+			},
+			WRITE if READ_ONLY.contains(&opcode) || !is_valid(opcode, payload) => parameters_error,
+			WRITE => match self.values.get_mut(&opcode) {
+				Some(ref mut value) if value.len() == payload.len() => {
+					value.copy_from_slice(payload);
+					reply(0, opcode, &[])
+				},
+				_ => parameters_error,
+			},
+			_ => parameters_error,
+		}
+	}
+}
+
+impl Default for Simulator {
+	fn default() -> Self {
+		Simulator::new()
+	}
+}
+
+impl Simulator {
+	/// A monitor that is on, at factory defaults.
+	pub fn new() -> Simulator {
+		let mut values = BTreeMap::new();
+		values.insert(0x01, b"DELL C5517H".to_vec());
+		values.insert(0x02, b"CN0ABC123456".to_vec());
+		values.insert(0x04, vec![0x04, 0xd2]);
+		values.insert(0x20, vec![1]);
+		values.insert(0x21, vec![1]);
+		values.insert(0x22, vec![0]);
+		values.insert(0x30, vec![75]);
+		values.insert(0x31, vec![75]);
+		values.insert(0x33, vec![0]);
+		values.insert(0x34, vec![50]);
+		values.insert(0x43, vec![0x04, 0, 0, 0]);
+		values.insert(0x46, vec![0]);
+		values.insert(0x48, vec![0x01, 0, 0, 0]);
+		values.insert(0x60, vec![0]);
+		values.insert(0x62, vec![0x01, 0, 0, 0]);
+		values.insert(0x80, vec![20]);
+		values.insert(0x81, vec![0]);
+		values.insert(0x83, vec![20]);
+		values.insert(0x84, vec![0]);
+		values.insert(0xA0, b"M2T104".to_vec());
+		values.insert(0xA2, vec![1]);
+		values.insert(0xA3, vec![0]);
+
+		Simulator{
+			state: Arc::new(Mutex::new(State{values, fail_next: None, responding: true, corrupting: false, requests: 0})),
+			input: Vec::new(),
+			output: Vec::new(),
+		}
+	}
+
+	/// Raw payload stored for `opcode`.
+	pub fn value(&self, opcode: u8) -> Option<Vec<u8>> {
+		self.state.lock().unwrap().values.get(&opcode).cloned()
+	}
+
+	pub fn set_value(&self, opcode: u8, value: &[u8]) {
+		self.state.lock().unwrap().values.insert(opcode, value.to_vec());
+	}
+
+	/// Answers the next request with `result_code` whatever it is.
+	pub fn fail_next(&self, result_code: ResultCode) {
+		self.state.lock().unwrap().fail_next = Some(result_code);
+	}
+
+	/// A monitor that is not responding swallows requests, reads time out.
+	pub fn set_responding(&self, responding: bool) {
+		self.state.lock().unwrap().responding = responding;
+	}
+
+	/// A monitor on a bad cable, replies arrive with a wrong checksum.
+	pub fn set_corrupting(&self, corrupting: bool) {
+		self.state.lock().unwrap().corrupting = corrupting;
+	}
+
+	/// Number of well-formed requests answered so far.
+	pub fn requests(&self) -> usize {
+		self.state.lock().unwrap().requests
+	}
+
+	fn process_input(&mut self) {
+		loop {
+			match self.input.windows(2).position(|x| x == HOST_PREFIX) {
+				Some(start) => { self.input.drain(..start); },
+				None => {
+					let keep = if self.input.last() == Some(&HOST_PREFIX[0]) { 1 } else { 0 };
+					let len = self.input.len();
+					self.input.drain(..len - keep);
+					return;
+				},
+			}
+
+			if self.input.len() < 3 || self.input.len() < frame_size(self.input[2]) {
+				return;
+			}
+
+			let frame : Vec<u8> = self.input.drain(..frame_size(self.input[2])).collect();
+			let mut c = XORCheckSum::new();
+			c.consume(&frame);
+			if c.value() != 0 || frame.len() < 6 {
+				continue;
+			}
+
+			let mut state = self.state.lock().unwrap();
+			if state.responding {
+				let mut reply = state.process(frame[3], frame[4], &frame[5..frame.len() - 1]);
+				if state.corrupting {
+					*reply.last_mut().unwrap() ^= 0xff;
+				}
+				self.output.extend(reply);
+			}
+		}
+	}
+}
+
+impl Read for Simulator {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		if self.output.is_empty() {
+			return Err(io::Error::new(io::ErrorKind::TimedOut, "simulated monitor did not reply"));
+		}
+		let n = (&self.output[..]).read(buf)?;
+		self.output.drain(..n);
+		Ok(n)
+	}
+}
+
+impl Write for Simulator {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		self.input.extend_from_slice(buf);
+		self.process_input();
+		Ok(buf.len())
+	}
+	fn flush(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+/// A configuration of `monitors`, at an address nothing answers on, and
+/// an opener wiring each of them to its simulator.
+#[cfg(test)]
+pub fn fixture(monitors: &[(&str, &Simulator)]) -> (Config, Opener) {
+	let mut config = Config::default();
+	let mut simulators = BTreeMap::new();
+	for &(name, simulator) in monitors {
+		config.monitors.insert(String::from(name), MonitorConfig::new("tcp:127.0.0.1:1".parse().unwrap()));
+		simulators.insert(String::from(name), simulator.clone());
+	}
+	(config, Box::new(move |name, _, _| Ok(Monitor::new(name, Box::new(simulators[name].clone())))))
+}
+
+#[cfg(test)]
+mod tests {
+	use monitor::Monitor;
+	use protocol::decoder;
+	use protocol::types;
+	use protocol::reply::ResultCode;
+	use protocol::transaction;
+	use simulator::Simulator;
+
+	#[test]
+	fn simulator_get_set() {
+		let simulator = Simulator::new();
+		let mut m = Monitor::new("sim", Box::new(simulator.clone()));
+
+		assert_eq!(types::Brightness::from(75), m.get::<types::Brightness>().unwrap());
+		m.set(types::Brightness::new(30).unwrap()).unwrap();
+		assert_eq!(Some(vec![30]), simulator.value(0x30));
+		assert_eq!(types::VideoInput::HDMI1, m.get::<types::VideoInput>().unwrap());
+		assert_eq!(types::MonitorName::from(String::from("DELL C5517H")), m.get::<types::MonitorName>().unwrap());
+		assert_eq!(4, simulator.requests());
+	}
+
+	#[test]
+	fn simulator_errors() {
+		let simulator = Simulator::new();
+		let mut m = Monitor::new("sim", Box::new(simulator.clone()));
+
+		assert!(m.set(types::Brightness::from(101)).is_err());
+		simulator.fail_next(ResultCode::NotConnected);
+		assert!(m.get::<types::PowerState>().is_err());
+		simulator.set_responding(false);
+		assert!(m.get::<types::PowerState>().is_err());
+		simulator.set_responding(true);
+		assert_eq!(types::PowerState::On, m.get::<types::PowerState>().unwrap());
+	}
+
+	#[test]
+	fn simulator_corrupting() {
+		let simulator = Simulator::new();
+		let mut m = Monitor::new("sim", Box::new(simulator.clone()));
+
+		simulator.set_corrupting(true);
+		assert!(matches!(m.get::<types::PowerState>(),
+			Err(transaction::Error::DecodeError(decoder::Error::ChecksumError{..}, _))));
+		simulator.set_corrupting(false);
+		assert_eq!(types::PowerState::On, m.get::<types::PowerState>().unwrap());
+	}
+}
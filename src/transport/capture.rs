@@ -0,0 +1,325 @@
+//! Recording of the bytes exchanged with a monitor, and their replay.
+//!
+//! A capture is a text file with one line per read or run of writes: its
+//! local time, `>` for bytes sent to the monitor or `<` for bytes received
+//! from it, and the bytes in hexadecimal. The analyzer reads it as it is.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::collections::VecDeque;
+
+use chrono::{Local, NaiveDateTime};
+
+use protocol::frame::{frame_size, hex};
+
+const TIME_FORMAT : &str = "%Y-%m-%dT%H:%M:%S%.6f";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+	Sent,
+	Received,
+}
+
+/// Bytes of a single read or write.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Record {
+	pub time: NaiveDateTime,
+	pub direction: Direction,
+	pub bytes: Vec<u8>,
+}
+
+impl fmt::Display for Record {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let direction = match self.direction {
+			Direction::Sent => '>',
+			Direction::Received => '<',
+		};
+		write!(f, "{} {}", self.time.format(TIME_FORMAT), direction)?;
+		for x in &self.bytes {
+			write!(f, " {:02x}", x)?;
+		}
+		Ok(())
+	}
+}
+
+impl FromStr for Record {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let mut tokens = s.split_whitespace();
+		let time = tokens.next().ok_or("empty line")?;
+		let time = NaiveDateTime::parse_from_str(time, TIME_FORMAT).map_err(|err| format!("{}: {}", time, err))?;
+		let direction = match tokens.next() {
+			Some(">") => Direction::Sent,
+			Some("<") => Direction::Received,
+			Some(x) => return Err(format!("{}: not a direction", x)),
+			None => return Err(String::from("direction is missing")),
+		};
+		let bytes = tokens.map(|x| u8::from_str_radix(x, 16).map_err(|err| format!("{}: {}", x, err)))
+			.collect::<Result<_, _>>()?;
+		Ok(Record{time, direction, bytes})
+	}
+}
+
+/// Reads a capture, blank lines and `#` comments are skipped.
+pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Vec<Record>> {
+	let content = fs::read_to_string(&path)?;
+	content.lines()
+		.enumerate()
+		.map(|(i, line)| (i, line.trim()))
+		.filter(|&(_, line)| !line.is_empty() && !line.starts_with('#'))
+		.map(|(i, line)| line.parse().map_err(|err| {
+			io::Error::new(io::ErrorKind::InvalidData, format!("{}: line {}: {}", path.as_ref().display(), i + 1, err))
+		}))
+		.collect()
+}
+
+/// Passes everything through to `inner`, appending it to a capture.
+/// Consecutive writes are recorded together, at the next read or flush.
+pub struct Recorder<T> {
+	inner: T,
+	file: fs::File,
+	sent: Option<Record>,
+}
+
+impl<T> Recorder<T> {
+	/// Appends to the capture at `path`, creating it if needed.
+	pub fn create<P: AsRef<Path>>(inner: T, path: P) -> io::Result<Recorder<T>> {
+		let file = fs::OpenOptions::new().create(true).append(true).open(path)?;
+		Ok(Recorder{inner, file, sent: None})
+	}
+
+	fn record(&mut self, record: &Record) -> io::Result<()> {
+		self.file.write_all(format!("{}\n", record).as_bytes())
+	}
+
+	fn record_sent(&mut self) -> io::Result<()> {
+		match self.sent.take() {
+			Some(record) => self.record(&record),
+			None => Ok(()),
+		}
+	}
+}
+
+impl<T : Read + Write> Read for Recorder<T> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		self.record_sent()?;
+		let n = self.inner.read(buf)?;
+		if n > 0 {
+			self.record(&Record{time: Local::now().naive_local(), direction: Direction::Received, bytes: buf[..n].to_vec()})?;
+		}
+		Ok(n)
+	}
+}
+
+impl<T : Read + Write> Write for Recorder<T> {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		let n = self.inner.write(buf)?;
+		self.sent.get_or_insert_with(|| Record{time: Local::now().naive_local(), direction: Direction::Sent, bytes: Vec::new()})
+			.bytes.extend_from_slice(&buf[..n]);
+		Ok(n)
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		self.inner.flush()?;
+		self.record_sent()?;
+		self.file.flush()
+	}
+}
+
+impl<T> Drop for Recorder<T> {
+	fn drop(&mut self) {
+		let _ = self.record_sent();
+	}
+}
+
+/// How a replay answers requests.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReplayMode {
+	/// Requests must be the captured ones, in the captured order.
+	Strict,
+	/// A request gets the reply to the next captured read or write of its
+	/// opcode, or to the last one once they are all used.
+	Lenient,
+}
+
+#[derive(Debug, Default)]
+struct Exchange {
+	request: Vec<u8>,
+	reply: Vec<u8>,
+}
+
+impl Exchange {
+	fn is_complete(&self) -> bool {
+		!self.reply.is_empty() || self.request.len() >= self.request.get(2).map_or(usize::MAX, |&x| frame_size(x))
+	}
+
+	/// Whether `request` is a read or write of the same opcode.
+	fn is_like(&self, request: &[u8]) -> bool {
+		self.request.get(3..5).is_some() && self.request.get(3..5) == request.get(3..5)
+	}
+}
+
+fn invalid_data(message: String) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Plays the monitor's side of a capture back. A request without a
+/// captured reply times out, as it did on the line.
+pub struct Replay {
+	exchanges: Vec<Exchange>,
+	mode: ReplayMode,
+	next: usize,
+	request: Vec<u8>,
+	reply: VecDeque<u8>,
+}
+
+impl Replay {
+	pub fn new(records: &[Record], mode: ReplayMode) -> Replay {
+		let mut exchanges : Vec<Exchange> = Vec::new();
+
+		for record in records {
+			match record.direction {
+				Direction::Sent => {
+					if exchanges.last().is_none_or(Exchange::is_complete) {
+						exchanges.push(Exchange::default());
+					}
+					exchanges.last_mut().unwrap().request.extend_from_slice(&record.bytes);
+				},
+				Direction::Received => if let Some(exchange) = exchanges.last_mut() {
+					exchange.reply.extend_from_slice(&record.bytes);
+				},
+			}
+		}
+
+		Replay{exchanges, mode, next: 0, request: Vec::new(), reply: VecDeque::new()}
+	}
+
+	pub fn open<P: AsRef<Path>>(path: P, mode: ReplayMode) -> io::Result<Replay> {
+		Ok(Replay::new(&load(path)?, mode))
+	}
+
+	fn answer(&mut self, request: &[u8]) -> io::Result<()> {
+		let index = match self.mode {
+			ReplayMode::Strict => {
+				let exchange = self.exchanges.get(self.next)
+					.ok_or_else(|| invalid_data(format!("replay: {} was not captured, the capture is over", hex(request))))?;
+				if exchange.request != request {
+					return Err(invalid_data(format!("replay: {} differs from the captured {}", hex(request), hex(&exchange.request))));
+				}
+				self.next
+			},
+			ReplayMode::Lenient => {
+				(self.next..self.exchanges.len()).find(|&i| self.exchanges[i].is_like(request))
+					.or_else(|| self.exchanges.iter().rposition(|x| x.is_like(request)))
+					.ok_or_else(|| invalid_data(format!("replay: nothing like {} was captured", hex(request))))?
+			},
+		};
+
+		self.next = index + 1;
+		self.reply.extend(&self.exchanges[index].reply);
+		Ok(())
+	}
+}
+
+impl Read for Replay {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		if self.reply.is_empty() {
+			return Err(io::Error::new(io::ErrorKind::TimedOut, "replay: no reply was captured"));
+		}
+		let n = self.reply.len().min(buf.len());
+		for (x, y) in buf.iter_mut().zip(self.reply.drain(..n)) {
+			*x = y;
+		}
+		Ok(n)
+	}
+}
+
+impl Write for Replay {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		self.request.extend_from_slice(buf);
+		while let Some(size) = self.request.get(2).map(|&x| frame_size(x)).filter(|&x| x <= self.request.len()) {
+			let request : Vec<u8> = self.request.drain(..size).collect();
+			self.answer(&request)?;
+		}
+		Ok(buf.len())
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::env;
+	use std::fs;
+	use std::process;
+
+	use config::{Config, MonitorConfig};
+	use group::Group;
+	use monitor::{Monitor, Opener};
+	use protocol::types::{Brightness, Contrast};
+	use simulator::Simulator;
+	use transport::capture;
+	use transport::capture::{Recorder, Replay, ReplayMode};
+
+	#[test]
+	fn capture_replay() {
+		let path = env::temp_dir().join(format!("c5517h-capture-{}.txt", process::id()));
+		let _ = fs::remove_file(&path);
+		let simulator = Simulator::new();
+		simulator.set_value(0x30, &[42]);
+		simulator.set_value(0x31, &[50]);
+
+		let mut monitor = Monitor::new("x", Box::new(Recorder::create(simulator.clone(), &path).unwrap()));
+		assert_eq!(Brightness::from(42), monitor.get().unwrap());
+		monitor.set(Brightness::from(60)).unwrap();
+		assert_eq!(Contrast::from(50), monitor.get().unwrap());
+		assert_eq!(6, capture::load(&path).unwrap().len());
+
+		let mut monitor = Monitor::new("x", Box::new(Replay::open(&path, ReplayMode::Strict).unwrap()));
+		assert_eq!(Brightness::from(42), monitor.get().unwrap());
+		assert!(monitor.get::<Contrast>().is_err());
+
+		let mut monitor = Monitor::new("x", Box::new(Replay::open(&path, ReplayMode::Lenient).unwrap()));
+		assert_eq!(Contrast::from(50), monitor.get().unwrap());
+		assert_eq!(Brightness::from(42), monitor.get().unwrap());
+		assert_eq!(Brightness::from(42), monitor.get().unwrap());
+		fs::remove_file(&path).unwrap();
+	}
+
+	#[test]
+	fn capture_replay_group() {
+		let path = env::temp_dir().join(format!("c5517h-capture-group-{}.txt", process::id()));
+		let mut config = Config::default();
+		for name in ["lobby", "atrium"] {
+			config.monitors.insert(String::from(name), MonitorConfig::new("tcp:127.0.0.1:1".parse().unwrap()));
+		}
+		config.set_capture(&path);
+		let opener : Opener = Box::new(|name, monitor, _| {
+			let simulator = Simulator::new();
+			simulator.set_value(0x30, &[if name == "lobby" { 10 } else { 20 }]);
+			let recorder = Recorder::create(simulator, monitor.capture.as_ref().unwrap()).unwrap();
+			Ok(Monitor::new(name, Box::new(recorder)))
+		});
+
+		let names = vec![String::from("lobby"), String::from("atrium")];
+		let group = Group::resolve_all(&config, &names).unwrap();
+		let report = group.run_with(&config, &opener, |m| (0..5).map(|_| m.get::<Brightness>()).collect::<Result<Vec<_>, _>>());
+		assert_eq!(2, report.succeeded());
+
+		for (name, brightness) in [("lobby", 10), ("atrium", 20)] {
+			let capture = config.monitors[name].capture.clone().unwrap();
+			let mut monitor = Monitor::new(name, Box::new(Replay::open(&capture, ReplayMode::Strict).unwrap()));
+			for _ in 0..5 {
+				assert_eq!(Brightness::from(brightness), monitor.get().unwrap());
+			}
+			fs::remove_file(&capture).unwrap();
+		}
+	}
+}
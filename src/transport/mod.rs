@@ -0,0 +1,143 @@
+use std;
+use std::fmt;
+use std::io;
+use std::io::{Read, Write};
+use std::str::FromStr;
+use std::convert::TryFrom;
+use std::time::Duration;
+use std::net::{TcpStream, ToSocketAddrs};
+
+use lock;
+use lock::LockPolicy;
+use port::LineSettings;
+
+pub mod rfc2217;
+pub mod capture;
+
+use self::rfc2217::Rfc2217Stream;
+use self::capture::{Replay, ReplayMode};
+
+/// Anything a monitor can be talked to through.
+pub trait Transport : Read + Write + Send {}
+
+impl<T : Read + Write + Send> Transport for T {}
+
+/// Where a monitor is attached: `serial:/dev/ttyUSB0`, `tcp:host:port` or
+/// `rfc2217:host:port`. A string without a known scheme is a serial port path.
+/// `replay:capture.txt` and `replay-lenient:capture.txt` play a capture back.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Address {
+	Serial(String),
+	Tcp(String),
+	Rfc2217(String),
+	Replay(String, ReplayMode),
+}
+
+impl FromStr for Address {
+	type Err = String;
+
+	fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+		let (scheme, rest) = match s.find(':') {
+			Some(pos) => (&s[..pos], s[pos + 1..].trim_start_matches("//")),
+			None => ("", s),
+		};
+
+		match scheme {
+			"serial" if !rest.is_empty() => Ok(Address::Serial(String::from(rest))),
+			"tcp" | "rfc2217" if !rest.contains(':') => Err(format!("{}: port number is missing", s)),
+			"tcp" => Ok(Address::Tcp(String::from(rest))),
+			"rfc2217" => Ok(Address::Rfc2217(String::from(rest))),
+			"replay" | "replay-lenient" if rest.is_empty() => Err(format!("{}: capture file is missing", s)),
+			"replay" => Ok(Address::Replay(String::from(rest), ReplayMode::Strict)),
+			"replay-lenient" => Ok(Address::Replay(String::from(rest), ReplayMode::Lenient)),
+			_ if s.is_empty() => Err(String::from("empty address")),
+			_ => Ok(Address::Serial(String::from(s))),
+		}
+	}
+}
+
+impl TryFrom<String> for Address {
+	type Error = String;
+
+	fn try_from(s: String) -> std::result::Result<Self, Self::Error> {
+		s.parse()
+	}
+}
+
+impl From<Address> for String {
+	fn from(x: Address) -> Self {
+		x.to_string()
+	}
+}
+
+impl fmt::Display for Address {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Address::Serial(ref path) => write!(f, "serial:{}", path),
+			Address::Tcp(ref addr) => write!(f, "tcp:{}", addr),
+			Address::Rfc2217(ref addr) => write!(f, "rfc2217:{}", addr),
+			Address::Replay(ref path, ReplayMode::Strict) => write!(f, "replay:{}", path),
+			Address::Replay(ref path, ReplayMode::Lenient) => write!(f, "replay-lenient:{}", path),
+		}
+	}
+}
+
+fn connect(addr: &str, timeout: Duration) -> io::Result<TcpStream> {
+	let mut last_error = io::Error::new(io::ErrorKind::NotFound, format!("{}: no addresses to connect to", addr));
+
+	for sock_addr in addr.to_socket_addrs()? {
+		match TcpStream::connect_timeout(&sock_addr, timeout) {
+			Ok(stream) => {
+				stream.set_read_timeout(Some(timeout))?;
+				stream.set_write_timeout(Some(timeout))?;
+				stream.set_nodelay(true)?;
+				return Ok(stream);
+			},
+			Err(err) => last_error = err,
+		}
+	}
+
+	Err(last_error)
+}
+
+/// Opens `address`, `timeout` bounds every single read and write.
+///
+/// Line settings are ignored for plain TCP since the other end of the
+/// socket owns the serial line, so is the lock policy for anything but
+/// local serial ports.
+pub fn open(address: &Address, settings: &LineSettings, timeout: Duration, lock: &LockPolicy) -> io::Result<Box<dyn Transport>> {
+	match address {
+		Address::Serial(ref path) =>
+			Ok(Box::new(lock::open(path, &settings.to_serialport(timeout), lock)?)),
+		Address::Tcp(ref addr) =>
+			Ok(Box::new(connect(addr, timeout)?)),
+		Address::Rfc2217(ref addr) =>
+			Ok(Box::new(Rfc2217Stream::new(connect(addr, timeout)?, settings)?)),
+		Address::Replay(ref path, ref mode) =>
+			Ok(Box::new(Replay::open(path, *mode)?)),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use transport::Address;
+	use transport::capture::ReplayMode;
+
+	#[test]
+	fn address_from_str() {
+		assert_eq!(Ok(Address::Serial(String::from("/dev/ttyUSB0"))), "/dev/ttyUSB0".parse());
+		assert_eq!(Ok(Address::Serial(String::from("/dev/ttyUSB0"))), "serial:/dev/ttyUSB0".parse());
+		assert_eq!(Ok(Address::Tcp(String::from("10.0.0.5:4001"))), "tcp://10.0.0.5:4001".parse());
+		assert_eq!(Ok(Address::Rfc2217(String::from("moxa:950"))), "rfc2217:moxa:950".parse());
+		assert_eq!(Ok(Address::Replay(String::from("site.txt"), ReplayMode::Lenient)), "replay-lenient:site.txt".parse());
+		assert!("tcp:moxa".parse::<Address>().is_err());
+		assert!("".parse::<Address>().is_err());
+	}
+
+	#[test]
+	fn address_display() {
+		let x : Address = "rfc2217://moxa:950".parse().unwrap();
+		assert_eq!("rfc2217:moxa:950", x.to_string());
+	}
+}
@@ -0,0 +1,217 @@
+//! Minimal RFC 2217 (Telnet Com Port Control) client.
+//!
+//! Only what a remote RS232 port needs is implemented: binary transmission,
+//! line settings on connect and IAC escaping of the data stream. Any other
+//! option the server proposes is refused.
+
+use std::io;
+use std::io::{Read, Write};
+
+use port::{LineSettings, LineParity};
+
+const IAC : u8 = 255;
+const DONT : u8 = 254;
+const DO : u8 = 253;
+const WONT : u8 = 252;
+const WILL : u8 = 251;
+const SB : u8 = 250;
+const SE : u8 = 240;
+
+const BINARY : u8 = 0;
+const COM_PORT_OPTION : u8 = 44;
+
+const SET_BAUDRATE : u8 = 1;
+const SET_DATASIZE : u8 = 2;
+const SET_PARITY : u8 = 3;
+const SET_STOPSIZE : u8 = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+	Data,
+	Iac,
+	Option(u8),
+	Subnegotiation,
+	SubnegotiationIac,
+}
+
+pub struct Rfc2217Stream<S> {
+	inner: S,
+	state: State,
+}
+
+fn subnegotiation(command: u8, value: &[u8]) -> Vec<u8> {
+	let mut ret = vec![IAC, SB, COM_PORT_OPTION, command];
+	for &x in value {
+		ret.push(x);
+		if x == IAC {
+			ret.push(IAC);
+		}
+	}
+	ret.extend_from_slice(&[IAC, SE]);
+	ret
+}
+
+impl<S : Read + Write> Rfc2217Stream<S> {
+	/// Negotiates binary mode and applies `settings` to the remote port.
+	pub fn new(mut inner: S, settings: &LineSettings) -> io::Result<Rfc2217Stream<S>> {
+		let parity = match settings.parity {
+			LineParity::None => 1,
+			LineParity::Odd => 2,
+			LineParity::Even => 3,
+		};
+		let mut buf = vec![
+			IAC, WILL, BINARY,
+			IAC, DO, BINARY,
+			IAC, WILL, COM_PORT_OPTION,
+		];
+		buf.extend(subnegotiation(SET_BAUDRATE, &settings.baud_rate.to_be_bytes()));
+		buf.extend(subnegotiation(SET_DATASIZE, &[8]));
+		buf.extend(subnegotiation(SET_PARITY, &[parity]));
+		buf.extend(subnegotiation(SET_STOPSIZE, &[settings.stop_bits]));
+
+		inner.write_all(&buf)?;
+		inner.flush()?;
+
+		Ok(Rfc2217Stream{inner, state: State::Data})
+	}
+
+	pub fn inner(&mut self) -> &mut S {
+		&mut self.inner
+	}
+
+	/// Strips Telnet commands out of `buf` in place, returns the data length
+	/// and the replies owed to the server.
+	fn filter(&mut self, buf: &mut [u8]) -> (usize, Vec<u8>) {
+		let mut len = 0;
+		let mut replies = Vec::new();
+
+		for i in 0..buf.len() {
+			let x = buf[i];
+			self.state = match (self.state, x) {
+				(State::Data, IAC) => State::Iac,
+				(State::Data, _) => {
+					buf[len] = x;
+					len += 1;
+					State::Data
+				},
+				(State::Iac, IAC) => {
+					buf[len] = IAC;
+					len += 1;
+					State::Data
+				},
+				(State::Iac, SB) => State::Subnegotiation,
+				(State::Iac, DO) | (State::Iac, DONT) | (State::Iac, WILL) | (State::Iac, WONT) => State::Option(x),
+				(State::Iac, _) => State::Data,
+				(State::Option(command), option) => {
+					match (command, option) {
+						(DO, BINARY) | (DO, COM_PORT_OPTION) | (WILL, BINARY) => (),
+						(DO, _) => replies.extend_from_slice(&[IAC, WONT, option]),
+						(WILL, _) => replies.extend_from_slice(&[IAC, DONT, option]),
+						_ => (),
+					}
+					State::Data
+				},
+				(State::Subnegotiation, IAC) => State::SubnegotiationIac,
+				(State::Subnegotiation, _) => State::Subnegotiation,
+				(State::SubnegotiationIac, SE) => State::Data,
+				(State::SubnegotiationIac, _) => State::Subnegotiation,
+			};
+		}
+
+		(len, replies)
+	}
+}
+
+impl<S : Read + Write> Read for Rfc2217Stream<S> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		loop {
+			let n = self.inner.read(buf)?;
+			if n == 0 {
+				return Ok(0);
+			}
+
+			let (len, replies) = self.filter(&mut buf[..n]);
+			if !replies.is_empty() {
+				self.inner.write_all(&replies)?;
+			}
+			if len > 0 {
+				return Ok(len);
+			}
+		}
+	}
+}
+
+impl<S : Read + Write> Write for Rfc2217Stream<S> {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		let mut escaped = Vec::with_capacity(buf.len());
+		for &x in buf {
+			escaped.push(x);
+			if x == IAC {
+				escaped.push(IAC);
+			}
+		}
+		self.inner.write_all(&escaped)?;
+		Ok(buf.len())
+	}
+	fn flush(&mut self) -> io::Result<()> {
+		self.inner.flush()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io;
+	use std::io::{Read, Write};
+
+	use port::LineSettings;
+	use transport::rfc2217::Rfc2217Stream;
+
+	struct Duplex {
+		input: io::Cursor<Vec<u8>>,
+		output: Vec<u8>,
+	}
+
+	impl Read for Duplex {
+		fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> { self.input.read(buf) }
+	}
+
+	impl Write for Duplex {
+		fn write(&mut self, buf: &[u8]) -> io::Result<usize> { self.output.write(buf) }
+		fn flush(&mut self) -> io::Result<()> { Ok(()) }
+	}
+
+	#[test]
+	fn rfc2217_negotiation() {
+		let duplex = Duplex{input: io::Cursor::new(Vec::new()), output: Vec::new()};
+		let mut s = Rfc2217Stream::new(duplex, &LineSettings::default()).unwrap();
+		let out = &s.inner().output;
+		assert_eq!([255 as u8, 251, 0, 255, 253, 0, 255, 251, 44], out[..9]);
+		assert_eq!([255 as u8, 250, 44, 1, 0, 0, 0x25, 0x80, 255, 240], out[9..19]);
+	}
+
+	#[test]
+	fn rfc2217_read_filters_commands() {
+		let input = vec![
+			0x6f, 255, 251, 3,                 // WILL SUPPRESS-GO-AHEAD
+			0x37, 255, 250, 44, 101, 0, 255, 240, // NOTIFY-LINESTATE
+			255, 255, 0x02];
+		let duplex = Duplex{input: io::Cursor::new(input), output: Vec::new()};
+		let mut s = Rfc2217Stream::new(duplex, &LineSettings::default()).unwrap();
+		s.inner().output.clear();
+
+		let mut buf = Vec::new();
+		s.read_to_end(&mut buf).unwrap();
+		assert_eq!([0x6f as u8, 0x37, 255, 0x02], buf[..]);
+		assert_eq!([255 as u8, 254, 3], s.inner().output[..]);
+	}
+
+	#[test]
+	fn rfc2217_write_escapes_iac() {
+		let duplex = Duplex{input: io::Cursor::new(Vec::new()), output: Vec::new()};
+		let mut s = Rfc2217Stream::new(duplex, &LineSettings::default()).unwrap();
+		s.inner().output.clear();
+
+		assert_eq!(3, s.write(&[0x37, 255, 0x51]).unwrap());
+		assert_eq!([0x37 as u8, 255, 255, 0x51], s.inner().output[..]);
+	}
+}
@@ -0,0 +1,211 @@
+//! Change events of monitor properties, found by polling.
+//!
+//! The first poll reports every value with no previous one, and so does the
+//! first successful read after an error, so that recoveries are seen too.
+//! Polling backs off while nothing changes, from `min` up to `max`, and
+//! returns to `min` as soon as something does, since changes tend to come
+//! in bursts.
+
+use std;
+use std::fmt;
+use std::error;
+use std::thread;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::Sender;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use chrono::{DateTime, Local};
+
+use monitor::Monitor;
+use property;
+use property::{Property, Value};
+
+/// Watched when no properties are chosen.
+pub const DEFAULT_PROPERTIES : [&str; 6] = ["power", "input", "brightness", "contrast", "preset", "osd-button-lock"];
+
+#[derive(Debug)]
+pub enum Error {
+	PollError(String, &'static str, property::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Error::PollError(ref monitor, property, ref property_error) =>
+				write!(f, "{}: {}: {}", monitor, property, property_error),
+		}
+	}
+}
+
+impl error::Error for Error {
+	fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+		match self {
+			Error::PollError(_, _, ref property_error) => Some(property_error),
+		}
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Event {
+	pub monitor: String,
+	pub property: &'static str,
+	/// `None` on the first poll and after an error.
+	pub old: Option<Value>,
+	pub new: Value,
+	pub time: DateTime<Local>,
+}
+
+/// Bounds of the adaptive polling interval.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Interval {
+	pub min: Duration,
+	pub max: Duration,
+}
+
+impl Default for Interval {
+	fn default() -> Interval {
+		Interval{min: Duration::from_millis(500), max: Duration::from_secs(10)}
+	}
+}
+
+/// Polls properties of a monitor, an endless iterator of changes and errors.
+pub struct Watcher {
+	name: String,
+	monitor: Arc<Mutex<Monitor>>,
+	properties: Vec<(&'static Property, Option<Value>)>,
+	interval: Interval,
+	delay: Duration,
+	pending: VecDeque<Result<Event>>,
+	first: bool,
+}
+
+impl Watcher {
+	/// Watches the readable properties among `properties`.
+	pub fn new(monitor: Monitor, properties: &[&'static Property]) -> Watcher {
+		Watcher::shared(Arc::new(Mutex::new(monitor)), properties)
+	}
+
+	/// Same as `new` for a monitor also written to by others, which is
+	/// only locked for one read at a time.
+	pub fn shared(monitor: Arc<Mutex<Monitor>>, properties: &[&'static Property]) -> Watcher {
+		let name = String::from(monitor.lock().unwrap().name());
+		let properties = properties.iter().filter(|x| x.is_readable()).map(|&x| (x, None)).collect();
+		let interval = Interval::default();
+		Watcher{name, monitor, properties, interval, delay: interval.min, pending: VecDeque::new(), first: true}
+	}
+
+	pub fn with_interval(mut self, interval: Interval) -> Watcher {
+		self.interval = interval;
+		self.delay = interval.min;
+		self
+	}
+
+	/// Reads every property once, queueing the changes and errors.
+	pub fn poll(&mut self) {
+		let mut changed = false;
+
+		for (property, last) in self.properties.iter_mut() {
+			let value = property.get(&mut self.monitor.lock().unwrap());
+			match value {
+				Ok(value) if last.as_ref() != Some(&value) => {
+					changed = true;
+					self.pending.push_back(Ok(Event{
+						monitor: self.name.clone(),
+						property: property.name(),
+						old: last.replace(value.clone()),
+						new: value,
+						time: Local::now(),
+					}));
+				},
+				Ok(_) => (),
+				Err(err) => {
+					*last = None;
+					self.pending.push_back(Err(Error::PollError(self.name.clone(), property.name(), err)));
+				},
+			}
+		}
+
+		self.delay = match changed && !self.first {
+			true => self.interval.min,
+			false => (self.delay * 2).min(self.interval.max),
+		};
+		self.first = false;
+	}
+
+	/// Time until the next poll.
+	pub fn delay(&self) -> Duration {
+		self.delay
+	}
+
+	/// Sends the events to `sender` from a new thread, until it is disconnected.
+	pub fn spawn(self, sender: Sender<Result<Event>>) -> thread::JoinHandle<()> {
+		thread::spawn(move || {
+			for event in self {
+				if sender.send(event).is_err() {
+					break;
+				}
+			}
+		})
+	}
+}
+
+impl Iterator for Watcher {
+	type Item = Result<Event>;
+
+	/// Polls until there is something to report.
+	fn next(&mut self) -> Option<Self::Item> {
+		while self.pending.is_empty() {
+			if !self.first {
+				thread::sleep(self.delay);
+			}
+			self.poll();
+		}
+		self.pending.pop_front()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::time::Duration;
+	use std::sync::mpsc;
+
+	use monitor::Monitor;
+	use property;
+	use property::Value;
+	use simulator::Simulator;
+	use watch::{Interval, Watcher};
+
+	#[test]
+	fn watch_changes() {
+		let simulator = Simulator::new();
+		let properties = [property::find("power").unwrap(), property::find("input").unwrap()];
+		let interval = Interval{min: Duration::from_millis(1), max: Duration::from_millis(4)};
+		let mut watcher = Watcher::new(Monitor::new("lobby", Box::new(simulator.clone())), &properties).with_interval(interval);
+
+		let first : Vec<_> = watcher.by_ref().take(2).map(|x| x.unwrap()).collect();
+		assert_eq!((None, Value::Choice("on")), (first[0].old.clone(), first[0].new.clone()));
+		assert_eq!("input", first[1].property);
+
+		// Nothing changes, polling backs off
+		watcher.poll();
+		watcher.poll();
+		assert_eq!(Duration::from_millis(4), watcher.delay());
+
+		simulator.set_value(0x62, &[0x02, 0, 0, 0]);
+		let event = watcher.next().unwrap().unwrap();
+		assert_eq!(("lobby", "input"), (event.monitor.as_str(), event.property));
+		assert_eq!((Some(Value::Choice("hdmi1")), Value::Choice("hdmi2")), (event.old, event.new));
+		assert_eq!(Duration::from_millis(1), watcher.delay());
+
+		let (sender, receiver) = mpsc::channel();
+		let handle = watcher.spawn(sender);
+		simulator.set_value(0x20, &[0]);
+		assert_eq!(Value::Choice("off"), receiver.recv().unwrap().unwrap().new);
+		drop(receiver);
+		simulator.set_value(0x20, &[1]);
+		handle.join().unwrap();
+	}
+}